@@ -0,0 +1,79 @@
+//! Sharing a server's sockets with other local users
+//!
+//! By default the wrap dir, sockets and token are private to the user that
+//! started the server. With `[access]` in `mcwrap.toml` they can instead be
+//! handed to a group (e.g. a panel running as its own user):
+//!
+//! ```toml
+//! [access]
+//! group = "minecraft"
+//! mode = 0o660
+//! ```
+
+use anyhow::{bail, Context, Result};
+use crate::config::AccessConfig;
+use nix::unistd::{chown, Gid, Group};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Resolved ownership and permissions for shared files
+#[derive(Clone, Copy, Default)]
+pub struct Access {
+    gid: Option<Gid>,
+    mode: Option<u32>,
+}
+
+impl Access {
+    pub fn from_config(config: &AccessConfig) -> Result<Self> {
+        let gid = match &config.group {
+            Some(name) => {
+                let group = Group::from_name(name)
+                    .with_context(|| format!("Failed to look up group {}", name))?;
+                match group {
+                    Some(group) => Some(group.gid),
+                    None => bail!("Unknown group {}", name),
+                }
+            }
+            None => None,
+        };
+        if config.mode.is_some_and(|mode| mode > 0o777) {
+            bail!("Invalid access mode {:o}", config.mode.unwrap());
+        }
+
+        Ok(Self {
+            gid,
+            mode: config.mode,
+        })
+    }
+
+    /// Apply to a socket or FIFO
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        self.set(path, self.mode)
+    }
+
+    /// Apply to a directory, adding search permission wherever read is granted
+    pub fn apply_dir(&self, path: &Path) -> Result<()> {
+        let mode = self.mode.map(|mode| mode | ((mode & 0o444) >> 2));
+        self.set(path, mode)
+    }
+
+    /// Apply to the token file: readable by the group only if it may connect
+    pub fn apply_secret(&self, path: &Path) -> Result<()> {
+        match self.mode {
+            Some(mode) if mode & 0o060 != 0 => self.set(path, Some(0o640)),
+            _ => self.set(path, None),
+        }
+    }
+
+    fn set(&self, path: &Path, mode: Option<u32>) -> Result<()> {
+        if let Some(gid) = self.gid {
+            chown(path, None, Some(gid)).with_context(|| format!("Failed to chown {:?}", path))?;
+        }
+        if let Some(mode) = mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to chmod {:?}", path))?;
+        }
+        Ok(())
+    }
+}
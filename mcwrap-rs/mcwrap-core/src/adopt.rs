@@ -0,0 +1,107 @@
+//! Taking over servers started outside mcwrap
+//!
+//! `mcwrap adopt <dir>` registers a server that is already running, say in
+//! screen or tmux, so `status`, `top`, `list` and the memory alerts see it
+//! and `send` and `stop` reach it, without restarting it. The server's JVM is
+//! the java process working in the directory, or the one given with
+//! `--pid`. mcwrap has no hold on the server's console, so commands go over
+//! RCON (see `rcon`), which `server.properties` must enable. Its console
+//! output isn't captured either: `logs` and `attach` only work from its next
+//! start with `mcwrap start`, and the uptime counts from the adoption.
+//!
+//! ```text
+//! mcwrap adopt /srv/survival
+//! mcwrap adopt /srv/survival --pid 4242
+//! ```
+
+use anyhow::{bail, Context, Result};
+use crate::access::Access;
+use crate::config::Config;
+use crate::failure::Failure;
+use crate::platform;
+use crate::rcon::Rcon;
+use crate::registry::Registry;
+use crate::{is_running, unix_now, ServerPaths, ServerState};
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+use std::fs;
+use std::path::Path;
+
+pub async fn cmd_adopt(server_dir: &Path, pid: Option<i32>) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    if is_running(&paths).is_some() {
+        bail!(Failure::already_running());
+    }
+
+    let pid = match pid {
+        Some(pid) => {
+            if kill(Pid::from_raw(pid), None).is_err() {
+                bail!("No process with PID {}", pid);
+            }
+            if platform::cwd(pid).is_some_and(|cwd| cwd != server_dir) {
+                println!("Warning: PID {} does not run in {:?}", pid, server_dir);
+            }
+            pid
+        }
+        None => find_java(&server_dir)?,
+    };
+
+    match Rcon::connect(&server_dir).await {
+        Ok(mut rcon) => {
+            rcon.command("list").await.context("RCON does not answer")?;
+        }
+        Err(e) => println!("Warning: {:#}; send and stop won't reach the server", e),
+    }
+
+    let mut registry = Registry::load()?;
+    registry.entry(&server_dir);
+    registry.save()?;
+
+    let _lock = paths.lock()?;
+    if is_running(&paths).is_some() {
+        bail!(Failure::already_running());
+    }
+    paths.clean();
+    paths.ensure_dir()?;
+    Access::from_config(&Config::load(&server_dir)?.access)?.apply_dir(&paths.wrap_dir)?;
+    let state = ServerState {
+        pid,
+        pty_master: None,
+        started_at: unix_now(),
+        server_dir: server_dir.clone(),
+        framed: false,
+        exited_at: None,
+        exit_code: None,
+        exit_signal: None,
+        exit_reason: None,
+        adopted: true,
+    };
+    fs::write(&paths.state_file, serde_json::to_string(&state)?)?;
+
+    println!("Adopted {:?} (PID {})", server_dir, pid);
+    Ok(())
+}
+
+/// The java process working in a server directory
+fn find_java(server_dir: &Path) -> Result<i32> {
+    let processes = platform::processes().context("Failed to list processes")?;
+    let found: Vec<i32> = processes
+        .iter()
+        .filter(|process| process.name == "java")
+        .filter(|process| {
+            let cwd = platform::cwd(process.pid).and_then(|cwd| cwd.canonicalize().ok());
+            cwd.as_deref() == Some(server_dir)
+        })
+        .map(|process| process.pid)
+        .collect();
+    match found[..] {
+        [pid] => Ok(pid),
+        [] => bail!("No java process runs in {:?} (give its PID with --pid)", server_dir),
+        _ => {
+            let pids: Vec<String> = found.iter().map(i32::to_string).collect();
+            let pids = pids.join(", ");
+            bail!("Several java processes run in {:?}: {} (pick one with --pid)", server_dir, pids)
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Alerts about who is on the server
+//!
+//! ```toml
+//! [alerts]
+//! watch_players = ["Notch"]
+//! max_players = 20
+//! empty_for = "6h"
+//! ```
+//!
+//! The PTY daemon raises an `Alert` event when a watched player joins, when
+//! more than `max_players` are online (once, until the count drops back),
+//! and when nobody has been online for `empty_for` since the server started
+//! or the last player left (once per empty stretch). Like every event, alerts
+//! go to `events.jsonl`, to subscribed control clients and, whatever
+//! `[notify] events` says, to the notification services.
+
+use anyhow::{anyhow, Context, Result};
+use crate::config::AlertsConfig;
+use crate::events::{Event, EventKind};
+use crate::pty::DaemonState;
+use crate::{format_uptime, parse_duration};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the player list is checked for `empty_for`
+const EMPTY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The alerts of one server
+pub struct Alerts {
+    watch_players: Vec<String>,
+    max_players: Option<usize>,
+    empty_for: Option<Duration>,
+    /// The player count is above `max_players` and was reported
+    crowded: Mutex<bool>,
+    /// When a player last joined or left, or the daemon started
+    last_activity: Mutex<Instant>,
+}
+
+impl Alerts {
+    pub fn from_config(config: &AlertsConfig) -> Result<Self> {
+        let empty_for = config
+            .empty_for
+            .as_deref()
+            .map(|duration| parse_duration(duration).map_err(|e| anyhow!(e)))
+            .transpose()
+            .context("Invalid [alerts] empty_for")?;
+        Ok(Self {
+            watch_players: config.watch_players.clone(),
+            max_players: config.max_players,
+            empty_for,
+            crowded: Mutex::new(false),
+            last_activity: Mutex::new(Instant::now()),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watch_players.is_empty() && self.max_players.is_none() && self.empty_for.is_none()
+    }
+
+    /// Alerts raised by an event, given the number of players online after it
+    pub fn check(&self, event: &Event, online: usize) -> Vec<String> {
+        let mut alerts = Vec::new();
+        match &event.kind {
+            EventKind::PlayerJoined { player } => {
+                if self.watch_players.iter().any(|name| name.eq_ignore_ascii_case(player)) {
+                    alerts.push(format!("Watched player joined: {}", player));
+                }
+            }
+            EventKind::PlayerLeft { .. } => {}
+            _ => return alerts,
+        }
+        *self.last_activity.lock().unwrap() = Instant::now();
+        let Some(max) = self.max_players else {
+            return alerts;
+        };
+        let mut crowded = self.crowded.lock().unwrap();
+        if online > max && !*crowded {
+            alerts.push(format!("{} players online (more than {})", online, max));
+            *crowded = true;
+        } else if online <= max {
+            *crowded = false;
+        }
+        alerts
+    }
+}
+
+/// Report the server staying empty for `[alerts] empty_for` from a background thread
+pub fn watch_empty(state: Arc<DaemonState>, alerts: Arc<Alerts>) {
+    let Some(empty_for) = alerts.empty_for else {
+        return;
+    };
+    thread::spawn(move || {
+        // The empty stretch already reported, by when it began
+        let mut reported = None;
+        while state.wait_exit(EMPTY_INTERVAL).is_none() {
+            if !state.online_players().is_empty() {
+                continue;
+            }
+            let since = *alerts.last_activity.lock().unwrap();
+            if since.elapsed() >= empty_for && reported != Some(since) {
+                let idle = format_uptime(since.elapsed().as_secs());
+                let message = format!("Nobody has been online for {}", idle);
+                state.emit(EventKind::Alert { message });
+                reported = Some(since);
+            }
+        }
+    });
+}
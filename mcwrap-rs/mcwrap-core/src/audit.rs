@@ -0,0 +1,123 @@
+//! Audit trail of commands sent to a server's console
+//!
+//! Every `send`, line typed into an attached console and state-changing
+//! control call is appended to `audit.log` in the wrap dir as a JSON line,
+//! together with who sent it and how. Unlike the rest of the wrap dir the
+//! audit log survives server restarts.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+))]
+use nix::sys::socket::{getsockopt, sockopt};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write as IoWrite;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// One audited command
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// RFC 3339 local time
+    pub time: String,
+    /// User name (or "owner" for the server token)
+    pub user: String,
+    /// Unix uid of the connecting process, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    /// How the command arrived: send, attach or control
+    pub via: String,
+    pub command: String,
+}
+
+impl Entry {
+    pub fn new(user: &str, uid: Option<u32>, via: &str, command: &str) -> Self {
+        Self {
+            time: Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            user: user.to_string(),
+            uid,
+            via: via.to_string(),
+            command: command.to_string(),
+        }
+    }
+
+    /// Entry for a command sent by the local user without going through a daemon
+    pub fn local(via: &str, command: &str) -> Self {
+        let uid = nix::unistd::getuid();
+        let user = nix::unistd::User::from_uid(uid)
+            .ok()
+            .flatten()
+            .map(|u| u.name)
+            .unwrap_or_else(|| uid.to_string());
+        Self::new(&user, Some(uid.as_raw()), via, command)
+    }
+}
+
+/// Unix uid of the process on the other end of a socket
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn peer_uid(stream: &UnixStream) -> Option<u32> {
+    getsockopt(stream, sockopt::PeerCredentials).ok().map(|creds| creds.uid())
+}
+
+/// Unix uid of the process on the other end of a socket
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly"))]
+pub fn peer_uid(stream: &UnixStream) -> Option<u32> {
+    getsockopt(stream, sockopt::LocalPeerCred).ok().map(|creds| creds.uid())
+}
+
+/// Unix uid of the process on the other end of a socket, which this system doesn't tell
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+)))]
+pub fn peer_uid(_stream: &UnixStream) -> Option<u32> {
+    None
+}
+
+/// Append an entry to the audit log
+pub fn append(path: &Path, entry: &Entry) -> Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    // A single O_APPEND write keeps concurrent writers from interleaving
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&line))
+        .context("Failed to write audit log")
+}
+
+/// Show the last N audit entries
+pub fn cmd_audit(server_dir: &Path, lines: usize) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = crate::ServerPaths::new(&server_dir);
+
+    if !paths.audit_log.exists() {
+        bail!("No audit log found");
+    }
+
+    let content = fs::read_to_string(&paths.audit_log)?;
+    let entries: Vec<Entry> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let start = entries.len().saturating_sub(lines);
+
+    for entry in &entries[start..] {
+        let time = DateTime::parse_from_rfc3339(&entry.time)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|_| entry.time.clone());
+        println!("{}  {:<12} {:<8} {}", time, entry.user, entry.via, entry.command);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Per-server authentication tokens
+//!
+//! Each PTY-mode start generates a fresh secret, stored mode 0600 as `token`
+//! in the wrap dir. Clients read it and present it before anything else on
+//! `pty.sock` and `control.sock`, so only users who can read the file can
+//! drive the server. Users from the users file authenticate with their own
+//! tokens instead (see `users`).
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read as IoRead, Write as IoWrite};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Token length in bytes before hex encoding
+const TOKEN_BYTES: usize = 32;
+
+/// Generate a random hex token
+pub fn random_token() -> Result<String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .context("Failed to read random bytes")?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Generate a new token and write it to `path`
+pub fn generate(path: &Path) -> Result<String> {
+    let token = random_token()?;
+
+    let _ = fs::remove_file(path);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .context("Failed to create token file")?;
+    file.write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+/// Read the token for a running server
+pub fn load(path: &Path) -> Result<String> {
+    let token = fs::read_to_string(path).context("Cannot read server token (permission denied?)")?;
+    Ok(token.trim().to_string())
+}
+
+/// Token a client should present: `MCWRAP_TOKEN` (a user's token) or the server's own
+pub fn client_token(path: &Path) -> Result<String> {
+    match std::env::var("MCWRAP_TOKEN") {
+        Ok(token) if !token.is_empty() => Ok(token),
+        _ => load(path),
+    }
+}
+
+/// Compare a presented token without leaking where it differs
+pub fn verify(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
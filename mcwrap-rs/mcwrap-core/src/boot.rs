@@ -0,0 +1,248 @@
+//! Start-on-boot support
+//!
+//! Servers are marked boot-startable in the registry. A single `mcwrap boot`
+//! invocation is installed as a systemd unit (or a cron `@reboot` entry when
+//! systemd isn't available) and brings up every enabled server in order.
+//! `destroy` removes the hook again along with the last enabled server.
+
+use anyhow::{bail, Context, Result};
+use crate::registry::Registry;
+use std::fs;
+use std::io::Write as IoWrite;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const UNIT_NAME: &str = "mcwrap-boot.service";
+
+/// Mark a server as started on boot
+pub fn cmd_enable(server_dir: &Path, after: Vec<PathBuf>) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let after = after
+        .iter()
+        .map(|dir| dir.canonicalize().with_context(|| format!("Invalid dependency {:?}", dir)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut registry = Registry::load()?;
+    let entry = registry.entry(&server_dir);
+    entry.boot = true;
+    entry.after = after;
+    // Make sure the dependency order is still satisfiable
+    registry.boot_order()?;
+    registry.save()?;
+
+    println!("Enabled start on boot for {}", server_dir.display());
+
+    let hook = install_boot_hook()?;
+    println!("  Boot hook: {}", hook);
+    Ok(())
+}
+
+/// Stop starting a server on boot
+pub fn cmd_disable(server_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+
+    let mut registry = Registry::load()?;
+    match registry.servers.iter_mut().find(|e| e.dir == server_dir) {
+        Some(entry) if entry.boot => {
+            entry.boot = false;
+            registry.save()?;
+            println!("Disabled start on boot for {}", server_dir.display());
+        }
+        _ => println!("{} is not enabled", server_dir.display()),
+    }
+
+    Ok(())
+}
+
+/// Start every boot-enabled server in dependency order
+pub async fn cmd_boot() -> Result<()> {
+    let registry = Registry::load()?;
+    let order = registry.boot_order()?;
+
+    if order.is_empty() {
+        println!("No servers enabled for boot.");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for entry in order {
+        let paths = crate::ServerPaths::new(&entry.dir);
+        if crate::is_running(&paths).is_some() {
+            println!("● {} already running", entry.dir.display());
+            continue;
+        }
+
+        let opts = crate::StartOptions {
+            basic: entry.basic,
+            exec: entry.exec.clone(),
+            priority: entry.priority.clone(),
+            container: entry.container.clone(),
+            log_level: entry.log_level,
+            gc_log: entry.gc_log,
+            preset: entry.preset,
+            ..Default::default()
+        };
+        if let Err(e) = crate::cmd_start(&entry.dir, entry.java_args.clone(), opts).await {
+            eprintln!("Failed to start {}: {:#}", entry.dir.display(), e);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} server(s) failed to start", failed);
+    }
+    Ok(())
+}
+
+/// Install the `mcwrap boot` hook, returning a description of what was set up
+fn install_boot_hook() -> Result<String> {
+    let exe = std::env::current_exe().context("Cannot locate mcwrap binary")?;
+
+    if Path::new("/run/systemd/system").exists() {
+        install_systemd_unit(&exe)
+    } else {
+        install_cron_entry(&exe)
+    }
+}
+
+/// Remove what `install_boot_hook` set up, returning a description of what was removed
+pub fn remove_boot_hook() -> Result<Option<String>> {
+    let exe = std::env::current_exe().context("Cannot locate mcwrap binary")?;
+    let is_root = nix::unistd::geteuid().is_root();
+    let unit_dir = if is_root {
+        PathBuf::from("/etc/systemd/system")
+    } else {
+        dirs::config_dir().context("No config directory")?.join("systemd/user")
+    };
+    let unit_path = unit_dir.join(UNIT_NAME);
+    if unit_path.exists() {
+        let mut systemctl = Command::new("systemctl");
+        if !is_root {
+            systemctl.arg("--user");
+        }
+        let _ = systemctl
+            .args(["disable", UNIT_NAME])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        fs::remove_file(&unit_path).with_context(|| format!("Failed to remove {:?}", unit_path))?;
+        return Ok(Some(format!("systemd unit {:?}", unit_path)));
+    }
+
+    let line = format!("@reboot {} boot", exe.display());
+    let Ok(current) = Command::new("crontab").arg("-l").stderr(Stdio::null()).output() else {
+        return Ok(None);
+    };
+    let crontab = String::from_utf8_lossy(&current.stdout);
+    if !current.status.success() || !crontab.lines().any(|l| l.trim() == line) {
+        return Ok(None);
+    }
+    let kept: String = crontab
+        .lines()
+        .filter(|l| l.trim() != line)
+        .map(|l| format!("{}\n", l))
+        .collect();
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run crontab")?;
+    child.stdin.take().unwrap().write_all(kept.as_bytes())?;
+    if !child.wait()?.success() {
+        bail!("crontab rejected the change");
+    }
+    Ok(Some("crontab @reboot entry".to_string()))
+}
+
+/// Install a oneshot systemd unit (system-wide as root, user unit otherwise)
+fn install_systemd_unit(exe: &Path) -> Result<String> {
+    let is_root = nix::unistd::geteuid().is_root();
+    let (unit_dir, wanted_by) = if is_root {
+        (PathBuf::from("/etc/systemd/system"), "multi-user.target")
+    } else {
+        let config = dirs::config_dir().context("No config directory")?;
+        (config.join("systemd/user"), "default.target")
+    };
+
+    // KillMode=process keeps servers alive when the unit itself is stopped
+    let unit = format!(
+        "[Unit]\n\
+         Description=Start mcwrap-managed Minecraft servers\n\
+         After=network-online.target\n\
+         Wants=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         RemainAfterExit=yes\n\
+         KillMode=process\n\
+         ExecStart={} boot\n\
+         \n\
+         [Install]\n\
+         WantedBy={}\n",
+        exe.display(),
+        wanted_by
+    );
+
+    fs::create_dir_all(&unit_dir)?;
+    let unit_path = unit_dir.join(UNIT_NAME);
+    if fs::read_to_string(&unit_path).ok().as_deref() != Some(unit.as_str()) {
+        fs::write(&unit_path, unit)?;
+    }
+
+    let mut systemctl = Command::new("systemctl");
+    if !is_root {
+        systemctl.arg("--user");
+    }
+    let status = systemctl
+        .args(["enable", UNIT_NAME])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if !status.is_ok_and(|s| s.success()) {
+        bail!("Failed to enable {} (wrote {:?})", UNIT_NAME, unit_path);
+    }
+
+    if !is_root {
+        println!("  Note: run `loginctl enable-linger` so user units start without a login");
+    }
+    Ok(format!("systemd unit {:?}", unit_path))
+}
+
+/// Add an `@reboot` line to the user's crontab
+fn install_cron_entry(exe: &Path) -> Result<String> {
+    let line = format!("@reboot {} boot", exe.display());
+
+    // `crontab -l` fails when no crontab exists yet
+    let current = Command::new("crontab")
+        .arg("-l")
+        .stderr(Stdio::null())
+        .output()
+        .context("Neither systemd nor crontab is available")?;
+    let mut crontab = if current.status.success() {
+        String::from_utf8_lossy(&current.stdout).into_owned()
+    } else {
+        String::new()
+    };
+
+    if crontab.lines().any(|l| l.trim() == line) {
+        return Ok("crontab @reboot entry".to_string());
+    }
+
+    if !crontab.is_empty() && !crontab.ends_with('\n') {
+        crontab.push('\n');
+    }
+    crontab.push_str(&line);
+    crontab.push('\n');
+
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run crontab")?;
+    child.stdin.take().unwrap().write_all(crontab.as_bytes())?;
+    if !child.wait()?.success() {
+        bail!("crontab rejected the @reboot entry");
+    }
+
+    Ok("crontab @reboot entry".to_string())
+}
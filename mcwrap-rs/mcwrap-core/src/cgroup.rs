@@ -0,0 +1,113 @@
+//! cgroup v2 resource limits
+//!
+//! With a `[limits]` section in the server config, the JVM is started in its
+//! own cgroup (`<cgroup_parent>/<server id>`, under `/sys/fs/cgroup`) with the
+//! configured memory, CPU and IO limits. Creating cgroups needs root or a
+//! delegated subtree (e.g. `user@<uid>.service` with systemd); when that
+//! isn't possible, or off Linux, the server starts without limits and says why.
+
+use anyhow::{bail, Context, Result};
+use crate::config::LimitsConfig;
+use nix::libc;
+use std::fs::{self, File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const DEFAULT_PARENT: &str = "mcwrap";
+
+/// cpu.max period in microseconds
+const CPU_PERIOD: u64 = 100_000;
+
+/// A cgroup prepared for a server
+pub struct Cgroup {
+    path: PathBuf,
+    /// Open for the server process to move itself in before exec
+    procs: File,
+}
+
+impl Cgroup {
+    /// Create the server's cgroup and apply its limits, if any are configured
+    pub fn create(id: &str, limits: &LimitsConfig) -> Result<Option<Self>> {
+        if !limits.is_set() {
+            return Ok(None);
+        }
+        if !cfg!(target_os = "linux") {
+            bail!("cgroups are only available on Linux");
+        }
+        let root = Path::new(CGROUP_ROOT);
+        if !root.join("cgroup.controllers").exists() {
+            bail!("cgroup v2 is not mounted at {}", CGROUP_ROOT);
+        }
+        let parent = root.join(limits.cgroup_parent.as_deref().unwrap_or(DEFAULT_PARENT));
+        let path = parent.join(format!("mcwrap-{}", id));
+        fs::create_dir_all(&path).with_context(|| format!("Failed to create {:?}", path))?;
+
+        // Controllers have to be enabled on every level from the root down to ours
+        let controllers = ["memory", "cpu", "io"];
+        let levels: Vec<&Path> =
+            parent.ancestors().take_while(|dir| dir.starts_with(root)).collect();
+        for dir in levels.into_iter().rev() {
+            let subtree = dir.join("cgroup.subtree_control");
+            for controller in controllers {
+                let _ = fs::write(&subtree, format!("+{}", controller));
+            }
+        }
+
+        match apply(&path, limits) {
+            Ok(procs) => Ok(Some(Self { path, procs })),
+            Err(e) => {
+                let _ = fs::remove_dir(&path);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Move a running process into the cgroup
+    pub fn add(&self, pid: u32) -> Result<()> {
+        fs::write(self.path.join("cgroup.procs"), pid.to_string())
+            .context("Failed to move the server into its cgroup")
+    }
+
+    /// Move the calling process into the cgroup (between fork and exec)
+    pub fn enter(&self) {
+        // "0" means the writing process
+        unsafe { libc::write(self.procs.as_raw_fd(), b"0".as_ptr() as *const libc::c_void, 1) };
+    }
+
+    /// Remove the cgroup once the server has exited
+    pub fn remove(&self) {
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Write the limits, returning `cgroup.procs` opened for writing
+fn apply(path: &Path, limits: &LimitsConfig) -> Result<File> {
+    if let Some(memory) = &limits.memory_max {
+        write(path, "memory.max", memory)?;
+    }
+    if let Some(cpus) = limits.cpu_max {
+        let quota = (cpus * CPU_PERIOD as f64).round() as u64;
+        write(path, "cpu.max", &format!("{} {}", quota, CPU_PERIOD))?;
+    }
+    if let Some(weight) = limits.io_weight {
+        write(path, "io.weight", &weight.to_string())?;
+    }
+    for line in &limits.io_max {
+        write(path, "io.max", line)?;
+    }
+    OpenOptions::new()
+        .write(true)
+        .open(path.join("cgroup.procs"))
+        .context("Failed to open cgroup.procs")
+}
+
+fn write(cgroup: &Path, file: &str, value: &str) -> Result<()> {
+    fs::write(cgroup.join(file), value).with_context(|| {
+        format!("Failed to set {} to {:?} (is the controller enabled?)", file, value)
+    })
+}
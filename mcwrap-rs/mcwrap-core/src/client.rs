@@ -0,0 +1,161 @@
+//! A typed client for a running server's daemon
+//!
+//! Panels and other tools that talk to a server use `Client` rather than
+//! speak the control socket's JSON-RPC themselves. It connects to a local
+//! server by its directory and authenticates with the server token, or takes
+//! a stream that is already authenticated, such as a channel opened through
+//! `mcwrap serve`. Each call is a method: `status`, `send`, `exec` and
+//! `stop`. `subscribe_console` and `subscribe_events` turn the connection
+//! into a stream of console output or of events. `handshake` tells which
+//! protocol version the daemon speaks and what it supports, for features
+//! newer than the daemon.
+
+use anyhow::{bail, Context, Result};
+use crate::control::ControlClient;
+use crate::events::Event;
+use crate::failure::Failure;
+use crate::protocol::Handshake;
+use crate::{is_running, ServerPaths};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A connection to a server's daemon
+pub struct Client {
+    control: ControlClient,
+}
+
+/// What the daemon says about its server
+#[derive(Deserialize, Debug, Clone)]
+pub struct Status {
+    /// The server's process
+    pub pid: i32,
+    pub uptime_secs: u64,
+    /// How many terminals are attached
+    pub clients: usize,
+    /// The players online
+    pub players: Vec<String>,
+}
+
+impl Client {
+    /// Connect to the server in `server_dir`, which must be running in PTY mode
+    pub async fn connect(server_dir: &Path) -> Result<Self> {
+        let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+        let paths = ServerPaths::new(&server_dir);
+        match ControlClient::connect(&paths).await? {
+            Some(control) => Ok(Self { control }),
+            None => {
+                is_running(&paths).context(Failure::not_running())?;
+                bail!("Server has no control socket (is it in basic mode?)")
+            }
+        }
+    }
+
+    /// Talk over a stream that is already authenticated, to a daemon that announced
+    /// `handshake` (see `remote::connect`)
+    pub fn from_stream<S>(stream: S, handshake: Handshake) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self {
+            control: ControlClient::from_stream(stream, handshake),
+        }
+    }
+
+    /// The daemon's protocol version and capabilities
+    pub fn handshake(&self) -> &Handshake {
+        self.control.handshake()
+    }
+
+    /// Whether the daemon has a capability, such as "events" or "exec"
+    pub fn supports(&self, capability: &str) -> bool {
+        self.handshake().supports(capability)
+    }
+
+    pub async fn status(&mut self) -> Result<Status> {
+        let status = self.control.call("status", json!({})).await?;
+        serde_json::from_value(status).context("Bad status from the daemon")
+    }
+
+    /// Send a console command, which waits its turn in the command queue
+    pub async fn send(&mut self, command: &str) -> Result<()> {
+        self.control.call("send", json!({ "command": command })).await?;
+        Ok(())
+    }
+
+    /// Send a console command and return the output it produces within `timeout`
+    pub async fn exec(&mut self, command: &str, timeout: Duration) -> Result<String> {
+        if !self.supports("exec") {
+            bail!("The server's daemon can't capture command output");
+        }
+        let params = json!({ "command": command, "capture_ms": timeout.as_millis() as u64 });
+        let result = self.control.call("send", params).await?;
+        Ok(result["output"].as_str().unwrap_or_default().to_string())
+    }
+
+    /// Stop the server, giving it `timeout` to exit, and return its exit code
+    pub async fn stop(&mut self, timeout: Duration) -> Result<i32> {
+        let params = json!({ "timeout_secs": timeout.as_secs() });
+        let result = self.control.call("stop", params).await?;
+        Ok(result["exit_code"].as_i64().unwrap_or_default() as i32)
+    }
+
+    /// Receive the console output from now on
+    pub async fn subscribe_console(mut self) -> Result<ConsoleStream> {
+        self.control.call("subscribe", json!({ "console": true })).await?;
+        Ok(ConsoleStream {
+            control: self.control,
+        })
+    }
+
+    /// Receive the server's events from now on
+    pub async fn subscribe_events(mut self) -> Result<EventStream> {
+        if !self.supports("events") {
+            bail!("The server's daemon doesn't send events");
+        }
+        self.control.call("subscribe", json!({ "console": false, "events": true })).await?;
+        Ok(EventStream {
+            control: self.control,
+        })
+    }
+}
+
+/// A running server's console output
+pub struct ConsoleStream {
+    control: ControlClient,
+}
+
+impl ConsoleStream {
+    /// The next piece of output, with its colors; None once the server is gone
+    pub async fn next(&mut self) -> Result<Option<String>> {
+        while let Some(message) = self.control.next_notification().await? {
+            if message["method"] == "console" {
+                if let Some(data) = message["params"]["data"].as_str() {
+                    return Ok(Some(data.to_string()));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A running server's events
+pub struct EventStream {
+    control: ControlClient,
+}
+
+impl EventStream {
+    /// The next event; None once the server is gone
+    pub async fn next(&mut self) -> Result<Option<Event>> {
+        while let Some(message) = self.control.next_notification().await? {
+            if message["method"] == "event" {
+                let event = serde_json::from_value(message["params"].clone())
+                    .context("Bad event from the daemon")?;
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+}
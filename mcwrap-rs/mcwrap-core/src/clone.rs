@@ -0,0 +1,175 @@
+//! Copying a server
+//!
+//! `mcwrap clone lobby1 lobby2` copies a server directory (its JAR, configs,
+//! plugins and worlds, but not its logs, crash reports or backups) and makes
+//! the copy safe to run next to the original: it gets ports no other
+//! registered server uses and nothing else listens on, a Velocity proxy gets
+//! a new `forwarding.secret`, and the copy is registered to start the way the
+//! original does. A running original has saving paused while its worlds are
+//! copied. With `--without-worlds`, the copy generates new worlds.
+//!
+//! Backends behind a proxy keep the proxy's forwarding secret in their
+//! configs as it is, since they must share it.
+
+use anyhow::{bail, Context, Result};
+use crate::control::ControlClient;
+use crate::hibernate::DEFAULT_PORT;
+use crate::ports;
+use crate::properties::Properties;
+use crate::registry::Registry;
+use crate::templates::copy_tree;
+use crate::{is_running, send_command, ServerPaths};
+use regex::Regex;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Top-level entries that belong to the original only
+const NOT_COPIED: &[&str] = &["logs", "crash-reports", "backups"];
+
+/// Longest to wait for a running original to write its worlds out
+const FLUSH_TIMEOUT_MS: u64 = 30_000;
+
+/// `bind = "0.0.0.0:25577"` in a Velocity proxy's config
+static VELOCITY_BIND: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?m)^(bind\s*=\s*"[^"]*:)(\d+)(")"#).unwrap());
+
+pub async fn cmd_clone(
+    src: &Path,
+    dst: &Path,
+    without_worlds: bool,
+    port: Option<u16>,
+) -> Result<()> {
+    let src = src.canonicalize().context("Invalid server directory")?;
+    if fs::read_dir(dst).is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!("{} already exists and is not empty", dst.display());
+    }
+    if std::path::absolute(dst)?.starts_with(&src) {
+        bail!("Can't clone a server into itself");
+    }
+    fs::create_dir_all(dst).with_context(|| format!("Failed to create {}", dst.display()))?;
+    let dst = dst.canonicalize()?;
+
+    let worlds: BTreeSet<PathBuf> = crate::disk::world_dirs(&src).into_iter().collect();
+    let skip = |path: &Path| {
+        let top_level = path.parent() == Some(src.as_path());
+        let name = path.file_name().unwrap_or_default();
+        (top_level && NOT_COPIED.iter().any(|n| name == *n))
+            || (without_worlds && worlds.contains(path))
+            || name == "session.lock"
+    };
+    let running = is_running(&ServerPaths::new(&src)).is_some();
+    if running && !without_worlds {
+        println!("Pausing saving on the running server...");
+        pause_saving(&src).await?;
+    }
+    let copied = copy_tree(&src, &dst, &skip);
+    if running && !without_worlds {
+        send_command(&src, "save-on", true).await?;
+    }
+    copied?;
+
+    let ports = rewrite_ports(&dst, port)?;
+    let secret = dst.join("forwarding.secret");
+    if secret.exists() {
+        let token = crate::auth::random_token()?;
+        fs::write(&secret, &token[..24]).context("Failed to write forwarding.secret")?;
+    }
+
+    let mut registry = Registry::load()?;
+    let original = registry.get(&src).cloned();
+    let entry = registry.entry(&dst);
+    if let Some(original) = original {
+        entry.java_args = original.java_args;
+        entry.basic = original.basic;
+        entry.legacy_raw = original.legacy_raw;
+        entry.exec = original.exec;
+        entry.priority = original.priority;
+        entry.container = original.container;
+        entry.log_level = original.log_level;
+        entry.gc_log = original.gc_log;
+        entry.preset = original.preset;
+    }
+    registry.save()?;
+
+    println!("Cloned {} to {}", src.display(), dst.display());
+    for (key, old, new) in ports {
+        println!("  {}: {} (was {})", key, new, old);
+    }
+    if secret.exists() {
+        println!("  forwarding.secret: regenerated (update the backends to match)");
+    }
+    if without_worlds {
+        println!("  Worlds: not copied");
+    }
+    println!("Start it with: mcwrap start {}", dst.display());
+    Ok(())
+}
+
+/// Have a running server write its worlds out and stop saving until `save-on`
+async fn pause_saving(server_dir: &Path) -> Result<()> {
+    send_command(server_dir, "save-off", true).await?;
+    match ControlClient::connect(&ServerPaths::new(server_dir)).await? {
+        // Waits for the server's answer, so that the worlds are complete on disk
+        Some(mut control) => {
+            let params = json!({ "command": "save-all flush", "capture_ms": FLUSH_TIMEOUT_MS });
+            control.call("send", params).await?;
+        }
+        None => {
+            send_command(server_dir, "save-all flush", true).await?;
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        }
+    }
+    Ok(())
+}
+
+/// Give the copy free ports, returning (setting, old port, new port) for each change
+fn rewrite_ports(dst: &Path, port: Option<u16>) -> Result<Vec<(String, u16, u16)>> {
+    let mut taken = ports::registered(dst);
+    let mut free_port =
+        |after: u16| ports::next_free(after.saturating_add(1)..=u16::MAX, &mut taken);
+    let mut changes = Vec::new();
+
+    if dst.join("server.properties").exists() {
+        let mut properties = Properties::load(dst)?;
+        let parse = |value: Option<&str>| value.and_then(|value| value.parse::<u16>().ok());
+        let old_port = parse(properties.get("server-port")).unwrap_or(DEFAULT_PORT);
+        let new_port = match port {
+            Some(port) => port,
+            None => free_port(old_port)?,
+        };
+        properties.set("server-port", &new_port.to_string());
+        changes.push(("server-port".to_string(), old_port, new_port));
+        if let Some(old) = parse(properties.get("query.port")) {
+            // Query usually shares the game port (over UDP)
+            let new = if old == old_port { new_port } else { free_port(old)? };
+            properties.set("query.port", &new.to_string());
+            changes.push(("query.port".to_string(), old, new));
+        }
+        if let Some(old) = parse(properties.get("rcon.port")) {
+            let new = free_port(old)?;
+            properties.set("rcon.port", &new.to_string());
+            changes.push(("rcon.port".to_string(), old, new));
+        }
+        properties.save()?;
+    }
+
+    // A Velocity proxy listens where its own config says
+    let velocity = dst.join("velocity.toml");
+    if let Ok(config) = fs::read_to_string(&velocity) {
+        if let Some(old) = VELOCITY_BIND.captures(&config).and_then(|c| c[2].parse().ok()) {
+            let new = match port {
+                Some(port) => port,
+                None => free_port(old)?,
+            };
+            let config = VELOCITY_BIND.replace(&config, |c: &regex::Captures| {
+                format!("{}{}{}", &c[1], new, &c[3])
+            });
+            fs::write(&velocity, config.as_ref()).context("Failed to write velocity.toml")?;
+            changes.push(("velocity bind".to_string(), old, new));
+        }
+    }
+    Ok(changes)
+}
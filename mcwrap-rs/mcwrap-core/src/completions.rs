@@ -0,0 +1,133 @@
+//! Shell completion for mcwrap
+//!
+//! `mcwrap completions bash|zsh|fish` prints the script clap_complete
+//! generates from mcwrap's command line, followed by a few lines of our own:
+//! where a subcommand expects a server directory, they also offer the
+//! servers mcwrap has started, which they ask for with the hidden
+//! `mcwrap __servers`. Load it with e.g. `source <(mcwrap completions bash)`.
+
+use anyhow::Result;
+use crate::registry::Registry;
+use clap::{Command, ValueEnum};
+use std::fmt::Write;
+use std::io;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// `mcwrap completions`: print the script for `shell`
+///
+/// `cli` is mcwrap's command line.
+pub fn cmd_completions(cli: &Command, shell: Shell) -> Result<()> {
+    let mut cli = cli.clone();
+    let name = cli.get_name().to_string();
+    let generator = match shell {
+        Shell::Bash => clap_complete::Shell::Bash,
+        Shell::Zsh => clap_complete::Shell::Zsh,
+        Shell::Fish => clap_complete::Shell::Fish,
+    };
+    clap_complete::generate(generator, &mut cli, &name, &mut io::stdout());
+
+    let mut paths = Vec::new();
+    server_paths(&cli, "", &mut paths);
+    let servers = match shell {
+        Shell::Bash => bash_servers(&name, &paths),
+        Shell::Zsh => zsh_servers(&name, &paths),
+        Shell::Fish => fish_servers(&name, &paths),
+    };
+    print!("{}", servers);
+    Ok(())
+}
+
+/// `mcwrap __servers`: the directories of the servers mcwrap has started, one per line
+pub fn cmd_servers() -> Result<()> {
+    for entry in Registry::load()?.servers {
+        println!("{}", entry.dir.display());
+    }
+    Ok(())
+}
+
+/// The subcommands, e.g. "world list", whose first argument is a server directory
+fn server_paths(cmd: &Command, path: &str, paths: &mut Vec<String>) {
+    for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let path = format!("{}{}", path, sub.get_name());
+        if sub.get_positionals().next().is_some_and(|arg| arg.get_id() == "dir") {
+            paths.push(path.clone());
+        }
+        server_paths(sub, &format!("{} ", path), paths);
+    }
+}
+
+/// Shell code setting `path` to the subcommands typed so far, skipping
+/// options: the `count` words of the shell's `words` after the command name
+fn typed_path(words: &str, count: &str) -> String {
+    let mut out = String::new();
+    writeln!(out, "    local path=\"\" word").unwrap();
+    writeln!(out, "    for word in \"${{{}[@]:1:{}}}\"; do", words, count).unwrap();
+    writeln!(out, "        [[ $word == -* ]] || path=\"${{path:+$path }}$word\"").unwrap();
+    writeln!(out, "    done").unwrap();
+    out
+}
+
+/// The alternatives of a `case` pattern matching `paths`
+fn case_patterns(paths: &[String]) -> String {
+    let paths: Vec<String> = paths.iter().map(|path| format!("\"{}\"", path)).collect();
+    paths.join("|")
+}
+
+fn bash_servers(name: &str, paths: &[String]) -> String {
+    let function = format!("_{}", name.replace('-', "__"));
+    let mut out = String::new();
+    writeln!(out).unwrap();
+    writeln!(out, "{}_servers() {{", function).unwrap();
+    writeln!(out, "    {} \"$@\"", function).unwrap();
+    writeln!(out, "    local cur=${{COMP_WORDS[COMP_CWORD]}}").unwrap();
+    out.push_str(&typed_path("COMP_WORDS", "COMP_CWORD-1"));
+    writeln!(out, "    case \"$path\" in").unwrap();
+    writeln!(out, "        {})", case_patterns(paths)).unwrap();
+    writeln!(out, "            local IFS=$'\\n'").unwrap();
+    let servers = format!("$({} __servers 2>/dev/null)", name);
+    writeln!(out, "            COMPREPLY+=($(compgen -W \"{}\" -- \"$cur\")) ;;", servers)
+        .unwrap();
+    writeln!(out, "    esac").unwrap();
+    writeln!(out, "}}").unwrap();
+    let options = "-o bashdefault -o default";
+    writeln!(out, "complete -F {}_servers {} {}", function, options, name).unwrap();
+    out
+}
+
+fn zsh_servers(name: &str, paths: &[String]) -> String {
+    let function = format!("_{}", name);
+    let mut out = String::new();
+    writeln!(out).unwrap();
+    writeln!(out, "{}_servers() {{", function).unwrap();
+    writeln!(out, "    {} \"$@\"", function).unwrap();
+    out.push_str(&typed_path("words", "CURRENT-2"));
+    writeln!(out, "    case \"$path\" in").unwrap();
+    writeln!(out, "        ({})", case_patterns(paths).replace('|', " | ")).unwrap();
+    writeln!(out, "            local -a servers").unwrap();
+    writeln!(out, "            servers=(${{(f)\"$({} __servers 2>/dev/null)\"}})", name).unwrap();
+    writeln!(out, "            compadd -X 'server' -- $servers ;;").unwrap();
+    writeln!(out, "    esac").unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out, "compdef {}_servers {}", function, name).unwrap();
+    out
+}
+
+fn fish_servers(name: &str, paths: &[String]) -> String {
+    let mut out = String::new();
+    for path in paths {
+        let condition: Vec<String> = path
+            .split(' ')
+            .map(|sub| format!("__fish_seen_subcommand_from {}", sub))
+            .collect();
+        let condition = condition.join("; and ");
+        let servers = format!("({} __servers 2>/dev/null)", name);
+        writeln!(out, "complete -c {} -n '{}' -a '{}'", name, condition, servers).unwrap();
+    }
+    out
+}
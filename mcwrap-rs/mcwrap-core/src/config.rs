@@ -0,0 +1,565 @@
+//! Server configuration
+//!
+//! Settings are read from `mcwrap.toml` in the server directory, layered over
+//! the global `~/.config/mcwrap/config.toml`. Every section is optional.
+
+use anyhow::{Context, Result};
+use crate::presets::Preset;
+use crate::records::Level;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Command that runs the server instead of `java -jar <jar>` (e.g. ["./run.sh"])
+    pub command: Vec<String>,
+    pub java: JavaConfig,
+    pub supervisor: SupervisorConfig,
+    pub hibernate: HibernateConfig,
+    pub watchdog: WatchdogConfig,
+    pub oom: OomConfig,
+    pub limits: LimitsConfig,
+    pub access: AccessConfig,
+    pub remote: RemoteConfig,
+    pub disk: DiskConfig,
+    pub ports: PortsConfig,
+    pub attach: AttachConfig,
+    pub log: LogConfig,
+    pub queue: QueueConfig,
+    pub events: EventsConfig,
+    pub alerts: AlertsConfig,
+    pub notify: NotifyConfig,
+    /// Console commands run on a cron schedule
+    pub schedule: Vec<ScheduledTask>,
+    /// Named command sequences for `mcwrap macro`
+    pub macros: BTreeMap<String, MacroConfig>,
+    /// Actions taken when console output matches a pattern
+    pub triggers: Vec<TriggerConfig>,
+    /// Rhai automation scripts, relative to the server directory
+    pub scripts: Vec<PathBuf>,
+    /// Extra environment variables for the server process
+    pub env: BTreeMap<String, String>,
+}
+
+/// The JVM when `start` builds the Java arguments itself
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct JavaConfig {
+    /// Heap size, used for both -Xms and -Xmx (e.g. "6G"; default: -Xms2G -Xmx4G)
+    pub memory: Option<String>,
+    /// Extra JVM flags, before `-jar`
+    pub flags: Vec<String>,
+    /// Collector flags added before `flags` (aikar, zgc, generational-zgc or shenandoah)
+    pub preset: Option<Preset>,
+}
+
+/// How mcwrapd supervises the server
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SupervisorConfig {
+    /// When to restart the server after it exits
+    pub restart: RestartPolicy,
+    /// Give up after this many consecutive failed restarts (0 = never give up)
+    pub max_restarts: u32,
+    /// Daily restart time in local time ("HH:MM")
+    pub restart_at: Option<String>,
+    /// Hold the daily restart until no players are online
+    pub restart_when_empty: bool,
+    /// Restart anyway once players have held it back this long (minutes)
+    pub restart_max_delay_mins: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            restart: RestartPolicy::OnFailure,
+            max_restarts: 5,
+            restart_at: None,
+            restart_when_empty: false,
+            restart_max_delay_mins: 120,
+        }
+    }
+}
+
+/// Stopping the server while nobody is playing (needs mcwrapd and PTY mode)
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct HibernateConfig {
+    /// Hibernate after the server has been empty this long (minutes)
+    pub idle_mins: Option<u64>,
+    /// Server list description while hibernating
+    pub motd: Option<String>,
+    /// Disconnect message for the player whose join wakes the server
+    pub message: Option<String>,
+}
+
+/// Detecting a server that froze without exiting (PTY mode)
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// Probe the server after this long without console output (minutes)
+    pub silent_mins: Option<u64>,
+    /// How long the server has to answer the probe (seconds)
+    pub probe_timeout_secs: u64,
+    /// Save a thread dump and kill a frozen server, for mcwrapd to restart
+    pub restart: bool,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            silent_mins: None,
+            probe_timeout_secs: 30,
+            restart: false,
+        }
+    }
+}
+
+/// What to do when the server runs out of memory
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct OomConfig {
+    /// Stop the server and have mcwrapd restart it, whatever the restart policy
+    pub restart: bool,
+    /// Raise -Xmx by this much on each such restart (MB; 0 keeps the same heap)
+    pub grow_heap_mb: u64,
+    /// Never raise -Xmx above this (MB)
+    pub max_heap_mb: Option<u64>,
+}
+
+/// Pacing of commands sent to a PTY-mode server
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// Write at most this many commands a second (0 = no limit)
+    pub max_per_sec: u32,
+    /// Write a command only once the server has answered the previous one
+    pub wait_for_answer: bool,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_per_sec: 10,
+            wait_for_answer: true,
+        }
+    }
+}
+
+/// Picking ports for new servers
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PortsConfig {
+    /// Ports `init --port auto` picks from (e.g. "25565-25664", the default)
+    pub range: Option<String>,
+}
+
+/// Attached terminals
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AttachConfig {
+    /// Key sequence that detaches (e.g. "ctrl-a d" or "ctrl-p,ctrl-q")
+    pub detach_keys: String,
+    /// What Ctrl+C does
+    pub ctrl_c: CtrlC,
+    /// Console output queued for a client that reads slower than it comes (e.g. "1M")
+    pub buffer: String,
+    /// What happens to a client once its buffer is full
+    pub slow_client: SlowClient,
+}
+
+impl Default for AttachConfig {
+    fn default() -> Self {
+        Self {
+            detach_keys: "ctrl-a d".to_string(),
+            ctrl_c: CtrlC::ClearLine,
+            buffer: "1M".to_string(),
+            slow_client: SlowClient::DropOldest,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CtrlC {
+    /// Clear the line being typed (sends Ctrl+U)
+    ClearLine,
+    /// Send Ctrl+C to the server as is
+    Forward,
+    /// Detach, like the detach keys
+    Detach,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SlowClient {
+    /// Drop the oldest output it hasn't been sent
+    DropOldest,
+    /// Disconnect it
+    Disconnect,
+}
+
+/// Disk space warnings in `mcwrap status`
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct DiskConfig {
+    /// Warn when free space drops below this (e.g. "10G")
+    pub min_free: Option<String>,
+}
+
+/// Events the daemon reports on its own
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct EventsConfig {
+    /// Report a HighMemory event when the server uses more than this (e.g. "6G")
+    pub high_memory: Option<String>,
+}
+
+/// How the console output is written to the console log
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// "text" (default), or "jsonl" for a JSON object per line
+    pub format: LogFormat,
+    /// Start every line with the date and time it was written
+    pub timestamps: bool,
+    /// Time zone of the timestamps: "local" (default), "UTC" or an offset such as "+02:00"
+    pub timezone: Option<String>,
+    /// Collapse runs of a repeated line into one with a count
+    pub dedupe: bool,
+    /// Also keep an index of the log by time, for `log --since` on big logs
+    pub index: bool,
+    /// Also send console lines to syslog or the systemd journal
+    pub forward: Option<ForwardConfig>,
+    /// Also push records to Grafana Loki or Elasticsearch
+    pub ship: Option<ShipConfig>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Jsonl,
+}
+
+/// Where console lines are forwarded, and which
+#[derive(Deserialize)]
+pub struct ForwardConfig {
+    pub to: ForwardTarget,
+    /// Identifier the lines are logged under (default: the directory name)
+    pub identifier: Option<String>,
+    /// Only lines at this level or above (e.g. "warn")
+    pub level: Option<Level>,
+    /// Only lines matching this regex
+    pub pattern: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardTarget {
+    Syslog,
+    Journald,
+}
+
+/// Where log records are pushed
+#[derive(Deserialize)]
+pub struct ShipConfig {
+    pub to: ShipTarget,
+    /// Base URL of the service (e.g. "http://loki:3100")
+    pub url: String,
+    /// Server name in the labels (default: the directory name)
+    pub name: Option<String>,
+    /// Elasticsearch index (default: "mcwrap")
+    pub index: Option<String>,
+    /// How often a batch is sent (default: "5s")
+    pub interval: Option<String>,
+    /// Extra HTTP headers, e.g. for authentication
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ShipTarget {
+    Loki,
+    /// Elasticsearch or OpenSearch
+    Elasticsearch,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            timestamps: false,
+            timezone: None,
+            dedupe: true,
+            index: false,
+            forward: None,
+            ship: None,
+        }
+    }
+}
+
+/// Alerts about who is on the server, sent through `[notify]`
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AlertsConfig {
+    /// Players whose joining is announced
+    pub watch_players: Vec<String>,
+    /// Alert when more players than this are online
+    pub max_players: Option<usize>,
+    /// Alert when nobody has been online for this long (e.g. "6h")
+    pub empty_for: Option<String>,
+}
+
+/// Notifications about server events
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// Server name shown in notifications (default: the directory name)
+    pub name: Option<String>,
+    /// Events to notify about
+    pub events: Vec<NotifyEvent>,
+    /// Online player counts to announce when they are reached
+    pub player_milestones: Vec<usize>,
+    pub discord: Option<DiscordConfig>,
+    pub telegram: Option<TelegramConfig>,
+    pub slack: Option<SlackConfig>,
+    pub webhook: Option<WebhookConfig>,
+    pub exec: Option<ExecConfig>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            events: vec![
+                NotifyEvent::Start,
+                NotifyEvent::Stop,
+                NotifyEvent::Crash,
+                NotifyEvent::BackupFailed,
+                NotifyEvent::PlayerMilestone,
+            ],
+            player_milestones: Vec::new(),
+            discord: None,
+            telegram: None,
+            slack: None,
+            webhook: None,
+            exec: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotifyEvent {
+    Start,
+    Stop,
+    Crash,
+    Join,
+    Leave,
+    Backup,
+    BackupFailed,
+    HighMemory,
+    /// The online player count reached one of `player_milestones`
+    PlayerMilestone,
+}
+
+/// Discord channel webhook
+#[derive(Deserialize, Clone)]
+pub struct DiscordConfig {
+    pub webhook: String,
+}
+
+/// Telegram bot posting to a chat
+#[derive(Deserialize, Clone)]
+pub struct TelegramConfig {
+    /// Bot token from @BotFather
+    pub token: String,
+    pub chat_id: ChatId,
+}
+
+/// A Telegram chat, by numeric ID or as "@channelname"
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum ChatId {
+    Id(i64),
+    Name(String),
+}
+
+/// Slack incoming webhook
+#[derive(Deserialize, Clone)]
+pub struct SlackConfig {
+    pub webhook: String,
+}
+
+/// URLs the event JSON is POSTed to as is
+#[derive(Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+}
+
+/// Script run for every notification, with the event in `MCWRAP_*` variables
+#[derive(Deserialize, Clone)]
+pub struct ExecConfig {
+    /// Program and arguments, run in the server directory (e.g. ["./notify.sh"])
+    pub command: Vec<String>,
+}
+
+/// A console command run by the daemon on a cron schedule
+#[derive(Deserialize, Clone)]
+pub struct ScheduledTask {
+    /// Cron expression in local time (e.g. "0 */6 * * *" or "@daily")
+    pub cron: String,
+    pub command: String,
+}
+
+/// A named sequence of console commands
+#[derive(Deserialize, Clone)]
+pub struct MacroConfig {
+    pub description: Option<String>,
+    /// Default values of `${name}` variables
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    pub steps: Vec<MacroStep>,
+}
+
+/// A command, or a pause like `{ wait = "5s" }`
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum MacroStep {
+    Command(String),
+    Wait { wait: String },
+}
+
+/// A rule acting on console lines that match a regex
+#[derive(Deserialize, Clone)]
+pub struct TriggerConfig {
+    /// Regex matched against each console line, without colors
+    pub pattern: String,
+    /// Console command to send; `$1` or `${name}` insert capture groups
+    pub send: Option<String>,
+    /// Script to run in the server directory, with the line in `MCWRAP_LINE`
+    #[serde(default)]
+    pub exec: Vec<String>,
+    /// Notification to send through `[notify]`, with capture groups like `send`
+    pub notify: Option<String>,
+    /// Restart the server (needs mcwrapd)
+    #[serde(default)]
+    pub restart: bool,
+    /// Ignore further matches for this long after firing
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_cooldown_secs() -> u64 {
+    60
+}
+
+/// cgroup v2 resource limits for the server
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// memory.max (e.g. "6G")
+    pub memory_max: Option<String>,
+    /// CPU time as a number of cores (e.g. 2.5), for cpu.max
+    pub cpu_max: Option<f64>,
+    /// io.weight (1-10000)
+    pub io_weight: Option<u16>,
+    /// io.max lines (e.g. "8:0 wbps=52428800")
+    pub io_max: Vec<String>,
+    /// Where server cgroups are created, relative to /sys/fs/cgroup (default "mcwrap")
+    pub cgroup_parent: Option<String>,
+}
+
+impl LimitsConfig {
+    /// Whether any limit is configured
+    pub fn is_set(&self) -> bool {
+        self.memory_max.is_some()
+            || self.cpu_max.is_some()
+            || self.io_weight.is_some()
+            || !self.io_max.is_empty()
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// Who besides the owner may use the server's sockets
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// Group given ownership of the wrap dir and sockets
+    pub group: Option<String>,
+    /// Permission bits for the sockets (e.g. 0o660)
+    pub mode: Option<u32>,
+}
+
+/// TLS files for `mcwrap serve` and `--host` (global config only)
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// CA that signs the certificates of the other side
+    pub tls_ca: Option<PathBuf>,
+}
+
+impl Config {
+    /// Location of the global configuration file
+    pub fn global_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("mcwrap").join("config.toml"))
+    }
+
+    /// Load the configuration for a server
+    pub fn load(server_dir: &Path) -> Result<Self> {
+        let sources = Self::global_path()
+            .into_iter()
+            .chain(std::iter::once(server_dir.join("mcwrap.toml")));
+        Self::load_from(sources)
+    }
+
+    /// Load only the global configuration
+    pub fn load_global() -> Result<Self> {
+        Self::load_from(Self::global_path().into_iter())
+    }
+
+    fn load_from(sources: impl Iterator<Item = PathBuf>) -> Result<Self> {
+        let mut merged = toml::Table::new();
+
+        for path in sources {
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let table: toml::Table =
+                toml::from_str(&content).with_context(|| format!("Invalid {:?}", path))?;
+            merge(&mut merged, table);
+        }
+
+        toml::Value::Table(merged)
+            .try_into()
+            .context("Invalid mcwrap configuration")
+    }
+}
+
+/// Recursively overlay `overlay` onto `base`, with `overlay` winning
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge(base_table, overlay_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
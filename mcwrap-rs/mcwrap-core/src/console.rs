@@ -0,0 +1,219 @@
+//! Line-editing console
+//!
+//! `mcwrap console` is a friendlier alternative to a raw `attach`: commands
+//! are edited locally at a readline prompt (with the server's command history,
+//! and Ctrl+C clearing the line instead of detaching) and sent whole over the
+//! control socket, while console output is printed above the prompt. Tab is
+//! passed through to the server's own completion and the candidates it prints
+//! are offered locally. Ctrl+D leaves the console; the server keeps running.
+
+use anyhow::{bail, Context, Result};
+use crate::control::ControlClient;
+use crate::failure::Failure;
+use crate::{history, jsonl, ServerPaths};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Editor, ExternalPrinter, Helper};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+
+const PROMPT: &str = "> ";
+
+/// Console lines shown when the console opens
+const RECENT_LINES: usize = 20;
+
+pub async fn cmd_console(server_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    let state = crate::is_running(&paths).context(Failure::not_running())?;
+    if state.pty_master.is_none() {
+        bail!("The console needs a PTY-mode server (use attach instead)");
+    }
+    // Separate connections for commands, completion and console output
+    let mut control = ControlClient::connect(&paths).await?.context("Control socket unavailable")?;
+    let completion = ControlClient::connect(&paths).await?.context("Control socket unavailable")?;
+    let mut output = ControlClient::connect(&paths).await?.context("Control socket unavailable")?;
+    output.call("subscribe", json!({ "console": true })).await?;
+
+    if let Ok(content) = jsonl::read(&paths.log_file) {
+        let lines: Vec<&str> = content.lines().collect();
+        for line in &lines[lines.len().saturating_sub(RECENT_LINES)..] {
+            println!("{}", line);
+        }
+    }
+    println!("Connected to {} (Ctrl+D to leave)", server_dir.display());
+
+    let config = Config::builder().completion_type(CompletionType::List).build();
+    let mut editor = Editor::with_config(config)?;
+    let completing = Arc::new(AtomicBool::new(false));
+    editor.set_helper(Some(ServerCompletion {
+        control: Mutex::new(completion),
+        runtime: Handle::current(),
+        active: completing.clone(),
+    }));
+    for command in history::load(&paths.history_file) {
+        editor.add_history_entry(command)?;
+    }
+
+    // Commands whose terminal echo should not be printed again
+    let echoes = Arc::new(Mutex::new(VecDeque::<String>::new()));
+    let mut printer = editor.create_external_printer()?;
+    let pending = echoes.clone();
+    tokio::spawn(async move {
+        let mut partial = String::new();
+        while let Ok(Some(message)) = output.next_notification().await {
+            let Some(data) = message["params"]["data"].as_str() else {
+                continue;
+            };
+            // The console's own completion display is shown by the editor instead
+            if completing.load(Ordering::SeqCst) {
+                partial.clear();
+                continue;
+            }
+            partial.push_str(data);
+            while let Some(end) = partial.find('\n') {
+                let line: String = partial.drain(..=end).collect();
+                let line = line.trim_end_matches(['\r', '\n']);
+                let mut pending = pending.lock().unwrap();
+                if pending.front().is_some_and(|echo| echo == line.trim()) {
+                    pending.pop_front();
+                    continue;
+                }
+                drop(pending);
+                printer.print(format!("{}\n", line)).ok();
+            }
+        }
+        printer.print("Server stopped (Ctrl+D to leave)\n".to_string()).ok();
+    });
+
+    loop {
+        let (returned, result) = tokio::task::spawn_blocking(move || {
+            let result = editor.readline(PROMPT);
+            (editor, result)
+        })
+        .await?;
+        editor = returned;
+
+        let line = match result {
+            Ok(line) => line,
+            // Ctrl+C only abandons the line being edited
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(command)?;
+
+        echoes.lock().unwrap().push_back(command.to_string());
+        if let Err(e) = control.call("send", json!({ "command": command })).await {
+            echoes.lock().unwrap().clear();
+            eprintln!("{:#}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Completes commands by asking the server's console
+struct ServerCompletion {
+    control: Mutex<ControlClient>,
+    /// Completion runs on the blocking readline thread
+    runtime: Handle,
+    /// Set while the server is answering a completion request
+    active: Arc<AtomicBool>,
+}
+
+impl Completer for ServerCompletion {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        // The server completes the whole line, so only the end can be completed
+        if pos < line.len() {
+            return Ok((pos, Vec::new()));
+        }
+        let mut control = self.control.lock().unwrap();
+        self.active.store(true, Ordering::SeqCst);
+        let reply = self.runtime.block_on(control.call("complete", json!({ "input": line })));
+        self.active.store(false, Ordering::SeqCst);
+        Ok(match reply {
+            Ok(reply) => parse_completion(line, reply["output"].as_str().unwrap_or("")),
+            Err(_) => (pos, Vec::new()),
+        })
+    }
+}
+
+impl Hinter for ServerCompletion {
+    type Hint = String;
+}
+
+impl Highlighter for ServerCompletion {}
+
+impl Validator for ServerCompletion {}
+
+impl Helper for ServerCompletion {}
+
+/// Turn the console's response to Tab into a replacement start and candidates
+///
+/// JLine either finishes the word in place, echoing the rest of it, or prints
+/// the candidates below the prompt and then redraws the line.
+fn parse_completion(input: &str, output: &str) -> (usize, Vec<String>) {
+    let output = strip_ansi(output).replace('\r', "");
+    let rest = output.strip_prefix(input).unwrap_or(&output);
+
+    if !rest.contains('\n') {
+        let suffix = rest.trim_start_matches('\t');
+        if suffix.trim().is_empty() {
+            return (input.len(), Vec::new());
+        }
+        return (input.len(), vec![suffix.to_string()]);
+    }
+
+    let start = input.rfind(' ').map_or(0, |i| i + 1);
+    let word = &input[start..];
+    // The last line is the prompt being redrawn
+    let lines: Vec<&str> = rest.lines().collect();
+    let candidates = lines[..lines.len().saturating_sub(1)]
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .filter(|candidate| candidate.starts_with(word))
+        .map(str::to_string)
+        .collect();
+    (start, candidates)
+}
+
+/// Remove ANSI escape sequences
+pub fn strip_ansi(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            result.push(c);
+            continue;
+        }
+        if chars.next() == Some('[') {
+            // CSI sequences end with a byte in @..~
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    result
+}
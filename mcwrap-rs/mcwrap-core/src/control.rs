@@ -0,0 +1,475 @@
+//! Control socket for a PTY-mode server
+//!
+//! Next to `pty.sock` every daemon listens on `control.sock`, which speaks
+//! JSON-RPC 2.0 with one message per line. It answers status queries, stops
+//! the server gracefully, sends commands (optionally capturing their output),
+//! reads the TPS of Bukkit servers for `mcwrap top`, proxies Tab completion
+//! to the server's console and streams console output and events as
+//! `console` and `event` notifications to subscribers.
+//! Clients must call `auth` with the server token or a user's token before
+//! anything else; what they may do afterwards depends on their role. Its
+//! result carries the daemon's protocol version and capabilities, which
+//! clients from before the handshake ignore.
+
+use anyhow::{bail, Context, Result};
+use crate::audit;
+use crate::auth;
+use crate::daemon_log;
+use crate::protocol::{self, Handshake};
+use crate::pty::{Capture, DaemonState};
+use crate::users::{Identity, Role};
+use crate::ServerPaths;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write as IoWrite};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// Write half of a control connection, shared with the console broadcaster
+pub type ControlWriter = Arc<Mutex<UnixStream>>;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+pub const UNAUTHORIZED: i64 = -32001;
+pub const FORBIDDEN: i64 = -32003;
+/// `stop` gave up waiting for the server to exit
+pub const STOP_TIMEOUT: i64 = -32002;
+
+/// Output is considered complete once the server has been quiet this long
+const CAPTURE_IDLE: Duration = Duration::from_millis(250);
+
+/// How long to wait for the answer to `tps`
+const TPS_CAPTURE: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct SendParams {
+    command: String,
+    /// Capture console output for up to this long
+    #[serde(default)]
+    capture_ms: Option<u64>,
+    /// Wait in the command queue (skipping it needs the admin role)
+    #[serde(default = "default_true")]
+    queue: bool,
+}
+
+#[derive(Deserialize)]
+struct CompleteParams {
+    /// Partially typed command line
+    input: String,
+    /// Wait at most this long for the server to answer
+    #[serde(default = "default_complete_ms")]
+    capture_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct StopParams {
+    #[serde(default = "default_stop_timeout")]
+    timeout_secs: u64,
+}
+
+#[derive(Deserialize)]
+struct AuthParams {
+    token: String,
+    /// User a relay holding the server token acts for
+    #[serde(default)]
+    user: Option<String>,
+    /// Protocol version the client speaks, if it knows of the handshake
+    #[serde(default)]
+    protocol: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct SubscribeParams {
+    #[serde(default = "default_true")]
+    console: bool,
+    #[serde(default)]
+    events: bool,
+}
+
+fn default_stop_timeout() -> u64 {
+    60
+}
+
+fn default_complete_ms() -> u64 {
+    1000
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Error returned by a control method
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Serve control connections on a background thread
+pub fn serve(listener: UnixListener, state: Arc<DaemonState>) {
+    thread::spawn(move || {
+        let next_id = AtomicU64::new(0);
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    daemon_log::warn(format!("Failed to accept a control connection: {}", e));
+                    continue;
+                }
+            };
+            let id = next_id.fetch_add(1, Ordering::SeqCst);
+            let state = state.clone();
+            thread::spawn(move || handle_connection(id, stream, state));
+        }
+    });
+}
+
+fn handle_connection(id: u64, stream: UnixStream, state: Arc<DaemonState>) {
+    let Ok(read_half) = stream.try_clone() else {
+        return;
+    };
+    let uid = audit::peer_uid(&stream);
+    let peer = uid.map_or(String::new(), |uid| format!(" (uid {})", uid));
+    daemon_log::debug(format!("Control connection {}{} opened", id, peer));
+    // Don't let a stalled client hold up console output
+    stream.set_write_timeout(Some(Duration::from_secs(1))).ok();
+    let writer: ControlWriter = Arc::new(Mutex::new(stream));
+    let mut identity = None;
+
+    for line in BufReader::new(read_half).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                daemon_log::warn(format!("Failed to read control connection {}: {}", id, e));
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match serde_json::from_str::<RpcRequest>(&line) {
+            Ok(request) => match &identity {
+                Some(identity) => {
+                    let result = dispatch(id, identity, &request, &writer, &state);
+                    response(request.id, result)
+                }
+                // Only `auth` is accepted until the client has presented credentials
+                None => {
+                    let result = match request.method.as_str() {
+                        "auth" => params::<AuthParams>(&request.params).and_then(|params| {
+                            let user = state
+                                .authenticate(&params.token, params.user.as_deref())
+                                .map_err(|e| {
+                                    let message = format!("Control connection {}: {}", id, e);
+                                    daemon_log::warn(message);
+                                    RpcError::new(UNAUTHORIZED, e)
+                                })?;
+                            let version = params.protocol.unwrap_or(1);
+                            let message = format!(
+                                "Control connection {} authenticated as {} (protocol {})",
+                                id, user.name, version
+                            );
+                            daemon_log::debug(message);
+                            let reply = json!({
+                                "user": user.name,
+                                "role": user.role.name(),
+                                "protocol": protocol::VERSION,
+                                "capabilities": protocol::CAPABILITIES,
+                            });
+                            identity = Some(Identity { uid, ..user });
+                            Ok(reply)
+                        }),
+                        _ => Err(RpcError::new(UNAUTHORIZED, "Not authenticated")),
+                    };
+                    response(request.id, result)
+                }
+            },
+            Err(e) => response(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+        };
+        if let Err(e) = write_line(&writer, &reply) {
+            daemon_log::warn(format!("Failed to answer control connection {}: {}", id, e));
+            break;
+        }
+    }
+
+    state.unsubscribe_console(id);
+    state.unsubscribe_events(id);
+    daemon_log::debug(format!("Control connection {} closed", id));
+}
+
+fn dispatch(
+    id: u64,
+    identity: &Identity,
+    request: &RpcRequest,
+    writer: &ControlWriter,
+    state: &DaemonState,
+) -> Result<Value, RpcError> {
+    let required = match request.method.as_str() {
+        "send" | "complete" | "tps" => Role::Operator,
+        "stop" => Role::Admin,
+        _ => Role::Viewer,
+    };
+    if !identity.role.includes(required) {
+        let message = format!("{} requires the {} role", request.method, required.name());
+        return Err(RpcError::new(FORBIDDEN, message));
+    }
+
+    match request.method.as_str() {
+        "status" => Ok(state.status()),
+        "send" => {
+            let params: SendParams = params(&request.params)?;
+            identity
+                .may_send(&params.command, params.queue)
+                .map_err(|e| RpcError::new(FORBIDDEN, e))?;
+            let capture = state.send_command(
+                &params.command,
+                params.queue,
+                params.capture_ms.is_some(),
+            );
+            state.record_command(identity, "control", &params.command);
+
+            let Some(capture) = capture else {
+                return Ok(json!({}));
+            };
+            wait_for_output(&capture, Duration::from_millis(params.capture_ms.unwrap()));
+            state.finish_capture(&capture);
+            Ok(json!({ "output": reply(&capture, &params.command) }))
+        }
+        "tps" => {
+            // Vanilla would only log "Unknown command" every time it is asked
+            if !state.is_bukkit() {
+                return Err(RpcError::new(SERVER_ERROR, "The server doesn't answer tps"));
+            }
+            // Polled by `mcwrap top`, so kept out of the audit log and history
+            let capture = state.send_command("tps", true, true).unwrap();
+            wait_for_output(&capture, TPS_CAPTURE);
+            state.finish_capture(&capture);
+            Ok(json!({ "output": reply(&capture, "tps") }))
+        }
+        "complete" => {
+            let params: CompleteParams = params(&request.params)?;
+            if params.input.contains(['\n', '\r']) {
+                return Err(RpcError::new(INVALID_PARAMS, "input must be a single line"));
+            }
+            // Type the line and Tab, then let the console's own completion answer
+            let capture = state.start_capture();
+            state.write_input(format!("{}\t", params.input).as_bytes());
+            wait_for_output(&capture, Duration::from_millis(params.capture_ms));
+            state.finish_capture(&capture);
+            // Ctrl+U clears the half-typed line again
+            state.write_input(b"\x15");
+
+            let output = String::from_utf8_lossy(&capture.output()).into_owned();
+            Ok(json!({ "output": output }))
+        }
+        "stop" => {
+            let params: StopParams = params(&request.params)?;
+            state.write_input(b"stop\n");
+            state.audit(identity, "control", "stop");
+            match state.wait_exit(Duration::from_secs(params.timeout_secs)) {
+                Some(code) => Ok(json!({ "exit_code": code })),
+                None => Err(RpcError::new(STOP_TIMEOUT, "Server did not stop in time")),
+            }
+        }
+        "subscribe" => {
+            let params: SubscribeParams = params(&request.params)?;
+            if params.console {
+                state.subscribe_console(id, writer.clone());
+            } else {
+                state.unsubscribe_console(id);
+            }
+            if params.events {
+                state.subscribe_events(id, writer.clone());
+            } else {
+                state.unsubscribe_events(id);
+            }
+            Ok(json!({}))
+        }
+        "unsubscribe" => {
+            state.unsubscribe_console(id);
+            state.unsubscribe_events(id);
+            Ok(json!({}))
+        }
+        other => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method {}", other))),
+    }
+}
+
+/// The output captured after `command`, without the terminal's echo of it
+fn reply(capture: &Capture, command: &str) -> String {
+    let output = String::from_utf8_lossy(&capture.output()).into_owned();
+    match output.strip_prefix(&format!("{}\n", command)) {
+        Some(rest) => rest.to_string(),
+        None => output,
+    }
+}
+
+/// Wait until the server goes quiet after answering, or `timeout` passes
+fn wait_for_output(capture: &Capture, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if capture.idle_for().is_some_and(|idle| idle >= CAPTURE_IDLE) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn params<T: for<'de> Deserialize<'de>>(params: &Value) -> Result<T, RpcError> {
+    // Omitted params are treated as an empty object
+    let params = if params.is_null() { json!({}) } else { params.clone() };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": e.code, "message": e.message },
+        }),
+    }
+}
+
+/// Build a JSON-RPC notification
+pub fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// Write one message to a control connection
+pub fn write_line(writer: &ControlWriter, message: &Value) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.lock().unwrap().write_all(&line)
+}
+
+/// Error returned by the daemon for a call
+#[derive(Debug)]
+pub struct RpcFailure {
+    pub code: i64,
+    pub message: String,
+}
+
+impl std::fmt::Display for RpcFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RpcFailure {}
+
+/// Client side of a control connection
+pub struct ControlClient {
+    reader: Box<dyn AsyncBufRead + Unpin + Send>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+    next_id: u64,
+    handshake: Handshake,
+}
+
+impl ControlClient {
+    /// Connect to a server's control socket and authenticate
+    ///
+    /// Returns None if there is no control socket to connect to (basic mode,
+    /// or the server isn't running).
+    pub async fn connect(paths: &ServerPaths) -> Result<Option<Self>> {
+        let Ok(stream) = tokio::net::UnixStream::connect(&paths.control_socket).await else {
+            return Ok(None);
+        };
+        let mut client = Self::from_stream(stream, Handshake::legacy());
+
+        let token = auth::client_token(&paths.token_file)?;
+        let params = json!({ "token": token, "protocol": protocol::VERSION });
+        let reply = client.call("auth", params).await?;
+        client.handshake = serde_json::from_value(reply).context("Bad handshake")?;
+        Ok(Some(client))
+    }
+
+    /// Wrap an already-authenticated stream (e.g. a remote channel) to a daemon that
+    /// announced `handshake`
+    pub fn from_stream<S>(stream: S, handshake: Handshake) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            reader: Box::new(tokio::io::BufReader::new(reader)),
+            writer: Box::new(writer),
+            next_id: 1,
+            handshake,
+        }
+    }
+
+    /// What the daemon said about itself when the connection opened
+    pub fn handshake(&self) -> &Handshake {
+        &self.handshake
+    }
+
+    /// Call a method and wait for its result, skipping notifications
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let mut line = serde_json::to_vec(&request)?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await? == 0 {
+                bail!("Control socket closed");
+            }
+            let message: Value = serde_json::from_str(&line).context("Bad control response")?;
+            if message.get("id") != Some(&json!(id)) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                return Err(RpcFailure {
+                    code: error["code"].as_i64().unwrap_or(SERVER_ERROR),
+                    message: error["message"].as_str().unwrap_or("Unknown error").to_string(),
+                }
+                .into());
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+
+    /// Wait for the next notification, skipping responses; None once the socket closes
+    pub async fn next_notification(&mut self) -> Result<Option<Value>> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await? == 0 {
+                return Ok(None);
+            }
+            let message: Value = serde_json::from_str(&line).context("Bad control message")?;
+            if message.get("id").is_none() {
+                return Ok(Some(message));
+            }
+        }
+    }
+}
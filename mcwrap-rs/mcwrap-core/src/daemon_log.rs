@@ -0,0 +1,115 @@
+//! The PTY daemon's own log
+//!
+//! The daemon runs detached from any terminal, so what goes wrong inside it
+//! (a socket it can't bind, a client dropped after a failed write, a console
+//! log it can't write) would otherwise go unseen. It logs to `daemon.log` in
+//! the wrap dir instead, at the level given with `start --log-level`: the
+//! default `info` records the daemon's lifecycle and errors, `debug` adds
+//! every client connecting and disconnecting, which is what "attach just
+//! hangs" reports need. mcwrapd notes restarts in the same file.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// Size past which the log is moved to `daemon.log.old` when a daemon starts
+const MAX_SIZE: u64 = 1 << 20;
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN ",
+            Level::Info => "INFO ",
+            Level::Debug => "DEBUG",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+struct Log {
+    file: Mutex<File>,
+    level: Level,
+}
+
+static LOG: OnceLock<Log> = OnceLock::new();
+
+/// Log to `path` from now on, in this process
+pub fn init(path: &Path, level: Level) -> io::Result<()> {
+    if fs::metadata(path).is_ok_and(|m| m.len() > MAX_SIZE) {
+        let _ = fs::rename(path, path.with_extension("log.old"));
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = LOG.set(Log {
+        file: Mutex::new(file),
+        level,
+    });
+    Ok(())
+}
+
+/// Send stdout and stderr to the log too, so that panics end up in it
+pub fn capture_stdio() {
+    if let Some(log) = LOG.get() {
+        let fd = log.file.lock().unwrap().as_raw_fd();
+        nix::unistd::dup2(fd, 1).ok();
+        nix::unistd::dup2(fd, 2).ok();
+    }
+}
+
+pub fn error(message: impl Display) {
+    log(Level::Error, message);
+}
+
+pub fn warn(message: impl Display) {
+    log(Level::Warn, message);
+}
+
+pub fn info(message: impl Display) {
+    log(Level::Info, message);
+}
+
+pub fn debug(message: impl Display) {
+    log(Level::Debug, message);
+}
+
+pub fn log(level: Level, message: impl Display) {
+    let Some(log) = LOG.get().filter(|log| level <= log.level) else {
+        return;
+    };
+    write_line(&mut log.file.lock().unwrap(), level, message);
+}
+
+/// Add a line to a server's log from outside its daemon
+pub fn append(path: &Path, level: Level, message: impl Display) {
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+        write_line(&mut file, level, message);
+    }
+}
+
+fn write_line(file: &mut File, level: Level, message: impl Display) {
+    let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+    let _ = writeln!(file, "{} {} {}", now, level.label(), message);
+}
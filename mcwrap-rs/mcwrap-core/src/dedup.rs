@@ -0,0 +1,75 @@
+//! Collapsing repeated lines in the console log
+//!
+//! A struggling server prints "Can't keep up!" over and over, and a broken
+//! plugin can repeat the same warning every tick. Lines that differ from the
+//! one before only in their numbers (times, tick counts, coordinates) are left
+//! out of `console.log`, and once something else is printed the last of them
+//! is written with how many there were: `<line> (repeated 423 times)`.
+//! Attached consoles still see every line. `[log] dedupe = false` keeps them
+//! all in the log too.
+
+/// Drops repeats from console output on its way to the log
+#[derive(Default)]
+pub struct Dedup {
+    /// Output after the last complete line
+    partial: Vec<u8>,
+    /// What the last line written looks like without its numbers
+    last: Option<String>,
+    /// The latest repeat left out, and how many there were
+    repeated: Option<(Vec<u8>, usize)>,
+}
+
+impl Dedup {
+    /// The complete lines of `data` that belong in the log
+    pub fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        self.partial.extend_from_slice(data);
+        let mut result = Vec::new();
+        while let Some(end) = self.partial.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let key = key(&line);
+            if self.last.as_ref() == Some(&key) {
+                let (latest, count) = self.repeated.get_or_insert_with(Default::default);
+                *latest = line;
+                *count += 1;
+                continue;
+            }
+            self.write_repeated(&mut result);
+            result.extend_from_slice(&line);
+            self.last = Some(key);
+        }
+        result
+    }
+
+    /// Whatever is held back, once the server has exited
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut result = Vec::new();
+        self.write_repeated(&mut result);
+        result.append(&mut self.partial);
+        result
+    }
+
+    fn write_repeated(&mut self, result: &mut Vec<u8>) {
+        match self.repeated.take() {
+            Some((line, 1)) => result.extend_from_slice(&line),
+            Some((line, count)) => {
+                result.extend_from_slice(line.strip_suffix(b"\n").unwrap_or(&line));
+                result.extend_from_slice(format!(" (repeated {} times)\n", count).as_bytes());
+            }
+            None => {}
+        }
+    }
+}
+
+/// A line without its colors, and with each number as `#`
+fn key(line: &[u8]) -> String {
+    let text = crate::console::strip_ansi(&String::from_utf8_lossy(line));
+    let mut key = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !c.is_ascii_digit() {
+            key.push(c);
+        } else if !key.ends_with('#') {
+            key.push('#');
+        }
+    }
+    key
+}
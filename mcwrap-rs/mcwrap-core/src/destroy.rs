@@ -0,0 +1,89 @@
+//! Decommissioning a server
+//!
+//! `mcwrap destroy <dir>` stops the server if it runs (terminating it if it
+//! doesn't stop in time), forgets it in the registry, also as another
+//! server's boot dependency, and removes its wrap dir with the console logs,
+//! events and history kept there. When it was the last server started on
+//! boot, the boot hook goes too. The server directory itself stays, unless
+//! `--purge` is given and confirmed.
+
+use anyhow::{bail, Context, Result};
+use crate::registry::Registry;
+use crate::{cmd_stop, is_running, ServerPaths, StopOptions};
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long the server gets to exit after `stop`, before it is terminated
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub async fn cmd_destroy(server_dir: &Path, purge: bool, yes: bool) -> Result<()> {
+    // The directory may already be gone, leaving only mcwrap's state behind
+    let server_dir = match server_dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(_) => std::path::absolute(server_dir).context("Invalid server directory")?,
+    };
+    let paths = ServerPaths::new(&server_dir);
+
+    if purge && server_dir.exists() {
+        let home = dirs::home_dir();
+        if server_dir.parent().is_none() || home.as_deref() == Some(server_dir.as_path()) {
+            bail!("Refusing to remove {}", server_dir.display());
+        }
+        if !yes {
+            if !std::io::stdin().is_terminal() {
+                bail!("Removing {} needs confirming; add --yes", server_dir.display());
+            }
+            let question = format!("Remove {} and everything in it?", server_dir.display());
+            if !crate::init::confirm(&question, false)? {
+                bail!("Nothing was removed");
+            }
+        }
+    }
+
+    if is_running(&paths).is_some() || crate::supervisor::is_hibernating(&server_dir).await? {
+        let stop = StopOptions {
+            warn: None,
+            kick: None,
+            timeout: STOP_TIMEOUT,
+            then_kill: true,
+        };
+        cmd_stop(&server_dir, stop).await?;
+    }
+
+    let mut registry = Registry::load()?;
+    let registered = registry.get(&server_dir).is_some();
+    let was_boot = registry.get(&server_dir).is_some_and(|entry| entry.boot);
+    registry.servers.retain(|entry| entry.dir != server_dir);
+    for entry in &mut registry.servers {
+        entry.after.retain(|dir| *dir != server_dir);
+    }
+    registry.save()?;
+    if registered {
+        println!("Removed {} from the registry", server_dir.display());
+    }
+    if was_boot && !registry.servers.iter().any(|entry| entry.boot) {
+        match crate::boot::remove_boot_hook() {
+            Ok(Some(hook)) => println!("Removed the boot hook ({})", hook),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to remove the boot hook: {:#}", e),
+        }
+    }
+
+    let had_state = paths.wrap_dir.exists();
+    if had_state {
+        fs::remove_dir_all(&paths.wrap_dir)
+            .with_context(|| format!("Failed to remove {:?}", paths.wrap_dir))?;
+        println!("Removed the state in {}", paths.wrap_dir.display());
+    }
+    if !registered && !had_state && !purge {
+        println!("mcwrap has nothing about {}", server_dir.display());
+    }
+    if purge && server_dir.exists() {
+        fs::remove_dir_all(&server_dir)
+            .with_context(|| format!("Failed to remove {}", server_dir.display()))?;
+        println!("Removed {}", server_dir.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Keys handled by an attached terminal
+//!
+//! An attached PTY session puts the terminal in raw mode, so every key goes
+//! to the server. A screen-style sequence (`[attach] detach_keys`, default
+//! Ctrl+A d) detaches instead, and Ctrl+C is handled according to
+//! `[attach] ctrl_c`: by default it clears the line being typed, like it does
+//! in a shell, rather than interrupting the server.
+
+use anyhow::{bail, Result};
+use crate::config::{AttachConfig, CtrlC};
+
+/// Ctrl+C as typed
+const CTRL_C: u8 = 0x03;
+/// Ctrl+U, which clears the line in the server's console
+const CTRL_U: u8 = 0x15;
+
+/// Watches typed input for the detach sequence
+pub struct DetachKeys {
+    sequence: Vec<u8>,
+    /// How the sequence is shown to the user, e.g. "Ctrl+A d"
+    description: String,
+    ctrl_c: CtrlC,
+    /// Bytes of the sequence typed so far, held back from the server
+    matched: usize,
+}
+
+impl DetachKeys {
+    pub fn new(config: &AttachConfig) -> Result<Self> {
+        let (sequence, description) = parse(&config.detach_keys)?;
+        Ok(Self {
+            sequence,
+            description,
+            ctrl_c: config.ctrl_c,
+            matched: 0,
+        })
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Filter typed input, returning what to send on and whether to detach
+    pub fn feed(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut output = Vec::with_capacity(input.len());
+        for &byte in input {
+            if byte == self.sequence[self.matched] {
+                self.matched += 1;
+                if self.matched == self.sequence.len() {
+                    self.matched = 0;
+                    return (output, true);
+                }
+                continue;
+            }
+            // Not the sequence after all: let the held bytes through
+            output.extend_from_slice(&self.sequence[..self.matched]);
+            self.matched = 0;
+            if byte == self.sequence[0] {
+                self.matched = 1;
+                continue;
+            }
+
+            match (byte, self.ctrl_c) {
+                (CTRL_C, CtrlC::Detach) => return (output, true),
+                (CTRL_C, CtrlC::ClearLine) => output.push(CTRL_U),
+                _ => output.push(byte),
+            }
+        }
+        (output, false)
+    }
+}
+
+/// Parse a key sequence such as "ctrl-a d" or "ctrl-p,ctrl-q"
+fn parse(keys: &str) -> Result<(Vec<u8>, String)> {
+    let mut sequence = Vec::new();
+    let mut names = Vec::new();
+    for key in keys.split([' ', ',']).filter(|key| !key.is_empty()) {
+        let lower = key.to_ascii_lowercase();
+        let control = lower.strip_prefix("ctrl-").or_else(|| lower.strip_prefix("ctrl+"));
+        let mut chars = control.unwrap_or(key).chars();
+        match (chars.next(), chars.next(), control.is_some()) {
+            // Control keys are the caret notation letters, ^@ to ^_
+            (Some(c), None, true) if ('@'..='_').contains(&c.to_ascii_uppercase()) => {
+                let c = c.to_ascii_uppercase();
+                sequence.push(c as u8 ^ 0x40);
+                names.push(format!("Ctrl+{}", c));
+            }
+            (Some(c), None, false) if c.is_ascii() => {
+                sequence.push(c as u8);
+                names.push(c.to_string());
+            }
+            _ => bail!("Invalid key {:?} in detach_keys (use e.g. \"ctrl-a d\")", key),
+        }
+    }
+    if sequence.is_empty() {
+        bail!("detach_keys is empty");
+    }
+    Ok((sequence, names.join(" ")))
+}
@@ -0,0 +1,131 @@
+//! Disk usage of a server
+//!
+//! `mcwrap status` shows how much space the world and the whole server
+//! directory take, and how much is left on the filesystem. Walking a large
+//! world takes a while, so the sizes are measured on a blocking thread while
+//! status talks to the server, and cached in `disk.json` in the wrap dir for
+//! a few minutes.
+
+use crate::properties::Properties;
+use nix::sys::statvfs::statvfs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// How long measured sizes are reused
+const CACHE_SECS: u64 = 300;
+
+/// Dimensions Bukkit-style servers keep next to the main world
+pub const DIMENSION_SUFFIXES: &[&str] = &["", "_nether", "_the_end"];
+
+#[derive(Serialize, Deserialize)]
+pub struct Usage {
+    /// Unix time of the measurement
+    pub measured_at: u64,
+    /// Bytes used by the world (with its nether and end, if separate)
+    pub world: u64,
+    /// Bytes used by the whole server directory
+    pub total: u64,
+}
+
+impl Usage {
+    /// Cached sizes if they are recent enough, otherwise measure them again
+    pub async fn get(server_dir: &Path, cache_file: &Path) -> Option<Self> {
+        let cached = fs::read_to_string(cache_file)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Self>(&content).ok())
+            .filter(|usage| crate::unix_now().saturating_sub(usage.measured_at) < CACHE_SECS);
+        if cached.is_some() {
+            return cached;
+        }
+
+        let dir = server_dir.to_path_buf();
+        let usage = tokio::task::spawn_blocking(move || Self::measure(&dir)).await.ok()?;
+        if let Ok(json) = serde_json::to_string_pretty(&usage) {
+            let _ = fs::write(cache_file, json);
+        }
+        Some(usage)
+    }
+
+    fn measure(server_dir: &Path) -> Self {
+        let world = world_dirs(server_dir).iter().map(|dir| du(dir)).sum();
+        Self {
+            measured_at: crate::unix_now(),
+            world,
+            total: du(server_dir),
+        }
+    }
+}
+
+/// The world directories, from `level-name` in `server.properties`
+pub fn world_dirs(server_dir: &Path) -> Vec<PathBuf> {
+    let properties = Properties::load(server_dir).ok();
+    let level = properties.as_ref().and_then(|properties| properties.get("level-name"));
+    level_dirs(server_dir, level.unwrap_or("world"))
+}
+
+/// The directories of a world and its separate dimensions that exist
+pub fn level_dirs(server_dir: &Path, level: &str) -> Vec<PathBuf> {
+    DIMENSION_SUFFIXES
+        .iter()
+        .map(|suffix| server_dir.join(format!("{}{}", level, suffix)))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+/// Space taken on disk by a file or directory tree, like `du`
+///
+/// Symlinks are not followed, and unreadable entries are skipped.
+pub fn du(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    // st_blocks is always in 512-byte units
+    let mut size = metadata.blocks() * 512;
+    if metadata.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            size += entries.flatten().map(|entry| du(&entry.path())).sum::<u64>();
+        }
+    }
+    size
+}
+
+/// Free and total bytes on the filesystem holding `path`
+pub fn free_space(path: &Path) -> Option<(u64, u64)> {
+    let stat = statvfs(path).ok()?;
+    let block = stat.fragment_size() as u64;
+    Some((stat.blocks_available() as u64 * block, stat.blocks() as u64 * block))
+}
+
+/// Parse a size such as "10G", "512M" or a number of bytes
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid size {:?}", s))?;
+    let scale: u64 = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size {:?} (use K, M, G or T)", s)),
+    };
+    number.checked_mul(scale).ok_or_else(|| format!("size {:?} is too large", s))
+}
+
+/// Human-readable size, e.g. "1.5 GB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
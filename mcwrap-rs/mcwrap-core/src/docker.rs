@@ -0,0 +1,135 @@
+//! Running the server in a Docker container
+//!
+//! `mcwrap start --docker [--image eclipse-temurin:21]` runs the server's
+//! command in a container rather than on the host. The server directory is
+//! mounted at the same path and is the working directory, the server runs as
+//! the calling user so its files keep their owner, and the ports in
+//! `server.properties` are published. mcwrap starts the `docker run` client
+//! in its PTY as it would start java, so the console, `attach`, `send` and
+//! the sockets work as usual. `[limits]` become the container's memory and
+//! CPU limits instead of a cgroup.
+//!
+//! The container is named `mcwrap-<id>` after the wrap dir, and removed once
+//! the server exits, or at the next start if it was left behind. Processes
+//! in the container aren't in the server's process group, so `top` and
+//! `status` show the docker client's memory, and `dump` and `jfr` can't
+//! reach the JVM.
+
+use anyhow::{bail, Result};
+use crate::config::LimitsConfig;
+use crate::hibernate::DEFAULT_PORT;
+use crate::ports;
+use crate::ServerPaths;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+pub const DEFAULT_IMAGE: &str = "eclipse-temurin:21";
+
+/// Container settings as given on the command line
+#[derive(Args, Serialize, Deserialize, Clone, Default)]
+pub struct Container {
+    /// Run the server in a Docker container, with the server directory mounted
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub docker: bool,
+    /// Image of the container [default: eclipse-temurin:21]
+    #[arg(long, requires = "docker")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+impl Container {
+    pub fn image(&self) -> &str {
+        self.image.as_deref().unwrap_or(DEFAULT_IMAGE)
+    }
+
+    /// The settings as `mcwrap start` arguments
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.docker {
+            args.push("--docker".to_string());
+        }
+        if let Some(image) = &self.image {
+            args.extend(["--image".to_string(), image.clone()]);
+        }
+        args
+    }
+
+    /// The `docker run` command that runs `command` in the container
+    pub fn wrap(&self, run: Run, command: Vec<String>) -> Result<Vec<String>> {
+        if crate::find_program("docker").is_none() {
+            bail!("docker not found on the PATH");
+        }
+        let dir = run.server_dir.to_string_lossy();
+        let mut args: Vec<String> =
+            ["docker", "run", "--rm", "--interactive"].map(String::from).into();
+        if run.tty {
+            args.push("--tty".to_string());
+        }
+        let user = format!("{}:{}", nix::unistd::getuid(), nix::unistd::getgid());
+        args.extend(["--name".to_string(), run.name.to_string(), "--user".to_string(), user]);
+        for volume in std::iter::once(run.server_dir).chain(run.volumes.iter().copied()) {
+            let volume = volume.to_string_lossy();
+            args.extend(["--volume".to_string(), format!("{0}:{0}", volume)]);
+        }
+        args.extend(["--workdir".to_string(), dir.to_string()]);
+        // Without a value, docker passes on the client's, which the server's environment sets
+        let env = ["TERM", "COLORTERM"].into_iter().chain(run.env.keys().map(String::as_str));
+        for key in env {
+            args.extend(["--env".to_string(), key.to_string()]);
+        }
+        for port in published_ports(run.server_dir)? {
+            args.extend(["--publish".to_string(), port]);
+        }
+        if let Some(memory) = &run.limits.memory_max {
+            args.extend(["--memory".to_string(), memory.clone()]);
+        }
+        if let Some(cpus) = run.limits.cpu_max {
+            args.extend(["--cpus".to_string(), cpus.to_string()]);
+        }
+        args.push(self.image().to_string());
+        args.extend(command);
+        Ok(args)
+    }
+}
+
+/// How a server's container is run
+pub struct Run<'a> {
+    /// Container name, from `name`
+    pub name: &'a str,
+    pub server_dir: &'a Path,
+    /// Other directories the server writes to, mounted at the same path
+    pub volumes: &'a [&'a Path],
+    /// Give the container a terminal, which needs the client to run in one
+    pub tty: bool,
+    /// Environment from the server config
+    pub env: &'a BTreeMap<String, String>,
+    pub limits: &'a LimitsConfig,
+}
+
+/// Name of a server's container, after its wrap dir
+pub fn name(server_dir: &Path) -> String {
+    let wrap_dir = ServerPaths::new(server_dir).wrap_dir;
+    format!("mcwrap-{}", wrap_dir.file_name().unwrap().to_string_lossy())
+}
+
+/// Remove a container, running or not, if it exists
+pub fn remove(name: &str) {
+    let _ = Command::new("docker")
+        .args(["rm", "--force", name])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// `--publish` values for the ports the server listens on
+fn published_ports(server_dir: &Path) -> Result<Vec<String>> {
+    if !server_dir.join("server.properties").exists() {
+        return Ok(vec![format!("{0}:{0}", DEFAULT_PORT)]);
+    }
+    let (ports, _) = ports::configured(server_dir)?;
+    Ok(ports.iter().map(ports::Port::publish).collect())
+}
@@ -0,0 +1,105 @@
+//! Thread and heap dumps of a running server
+//!
+//! `mcwrap dump threads <dir>` saves what every thread of the server's JVM
+//! is doing, to find what a lagging or frozen server is stuck on. It asks
+//! `jcmd` for it, or without a JDK sends the JVM SIGQUIT and picks the dump
+//! out of the console. `mcwrap dump heap <dir>` has `jcmd` write the live
+//! objects to an `.hprof` file for a heap analyzer such as Eclipse MAT; the
+//! server pauses while that is written, and it is as big as the heap in use.
+//! Dumps are saved to `dumps/` in the wrap dir, next to the ones the
+//! watchdog takes, named after when they were taken.
+
+use anyhow::{bail, Context, Result};
+use crate::control::ControlClient;
+use crate::disk::format_size;
+use crate::{jcmd, ServerPaths};
+use clap::Subcommand;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Quiet on the console after which a SIGQUIT thread dump is taken to be complete
+const DUMP_IDLE: Duration = Duration::from_secs(1);
+
+/// Longest the JVM gets to start printing a SIGQUIT thread dump
+const DUMP_WAIT: Duration = Duration::from_secs(10);
+
+#[derive(Subcommand)]
+pub enum DumpAction {
+    /// Save a thread dump
+    Threads {
+        /// Server directory
+        dir: PathBuf,
+    },
+    /// Save a heap dump (pauses the server while it is written)
+    Heap {
+        /// Server directory
+        dir: PathBuf,
+    },
+}
+
+pub async fn cmd_dump(action: DumpAction) -> Result<()> {
+    match action {
+        DumpAction::Threads { dir } => threads(&dir).await,
+        DumpAction::Heap { dir } => heap(&dir),
+    }
+}
+
+async fn threads(server_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let pid = jcmd::jvm_pid(&server_dir)?;
+    let dump = if jcmd::is_available(pid) {
+        jcmd::run(pid, &["Thread.print".to_string()])?
+    } else {
+        signal_dump(&server_dir, pid).await?
+    };
+    let path = jcmd::output_file(&ServerPaths::new(&server_dir).dump_dir, "threaddump", "txt")?;
+    fs::write(&path, format!("{}\n", dump))
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    let count = dump.lines().filter(|line| line.starts_with('"')).count();
+    println!("Saved {} ({} threads)", path.display(), count);
+    Ok(())
+}
+
+/// Have the JVM print a thread dump to its console, and collect it from there
+async fn signal_dump(server_dir: &Path, pid: i32) -> Result<String> {
+    let paths = ServerPaths::new(server_dir);
+    let Some(mut client) = ControlClient::connect(&paths).await? else {
+        bail!("jcmd not found, and without it thread dumps need a server in PTY mode");
+    };
+    client.call("subscribe", json!({ "console": true })).await?;
+    kill(Pid::from_raw(pid), Signal::SIGQUIT).context("Failed to signal the JVM")?;
+
+    let mut output = String::new();
+    loop {
+        let wait = if output.is_empty() { DUMP_WAIT } else { DUMP_IDLE };
+        let Ok(message) = tokio::time::timeout(wait, client.next_notification()).await else {
+            break;
+        };
+        let Some(message) = message? else {
+            break;
+        };
+        output.push_str(message["params"]["data"].as_str().unwrap_or_default());
+    }
+    // Leave out what the server logged before the dump started
+    let Some(start) = output.find("Full thread dump") else {
+        bail!("The JVM didn't print a thread dump");
+    };
+    Ok(crate::console::strip_ansi(&output[start..].replace('\r', "")).trim_end().to_string())
+}
+
+fn heap(server_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let pid = jcmd::jvm_pid(&server_dir)?;
+    let path = jcmd::output_file(&ServerPaths::new(&server_dir).dump_dir, "heapdump", "hprof")?;
+    println!("Dumping the heap; the server pauses until it is written...");
+    let output = jcmd::run(pid, &["GC.heap_dump".to_string(), path.display().to_string()])?;
+    let Ok(metadata) = fs::metadata(&path) else {
+        bail!("The JVM didn't write the heap dump: {}", output);
+    };
+    println!("Saved {} ({})", path.display(), format_size(metadata.len()));
+    Ok(())
+}
@@ -0,0 +1,259 @@
+//! A summary of the exceptions in a server's console logs
+//!
+//! `mcwrap errors <dir>` reads the current console log and the archived runs
+//! and counts the exceptions in them, grouped by exception class and the top
+//! frame of the stack trace, so that a plugin throwing on every tick shows
+//! up as one line with a count rather than pages of traces. Each group shows
+//! when it first and last happened: from the `[log] timestamps` when there
+//! are some, or else from the run's start and the server's time of day.
+//! `--since 24h` looks only at what happened lately.
+//!
+//! Each exception is put down to the plugin whose code is nearest the top of
+//! its stack trace, going by the package of the main class in the
+//! `plugin.yml` of each jar in `plugins/` (read with `unzip`), or else to the
+//! plugin Paper names in "Could not pass event ... to <plugin>". The summary
+//! starts with each plugin's share of the exceptions.
+
+use anyhow::{Context, Result};
+use crate::config::Config;
+use crate::records::{self, Record};
+use crate::timestamps::{self, Zone};
+use crate::{jsonl, logs, read_state, ServerPaths};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, NaiveTime, TimeDelta, TimeZone};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::LazyLock;
+
+/// The plugin Paper blames, e.g. `Could not pass event PlayerJoinEvent to FancyShops v1.2`
+static PASS_EVENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^Could not pass event \S+ to (\S+) v").unwrap());
+
+/// Where a plugin describes itself in its jar
+const PLUGIN_FILES: [&str; 2] = ["plugin.yml", "paper-plugin.yml"];
+
+/// An exception class, with its package: `java.lang.NullPointerException`
+static EXCEPTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b((?:[A-Za-z_$][\w$]*\.)+[\w$]*(?:Exception|Error|Throwable))\b").unwrap()
+});
+
+/// Exceptions of one kind
+struct Group {
+    class: String,
+    frame: Option<String>,
+    count: usize,
+    first: Option<DateTime<FixedOffset>>,
+    last: Option<DateTime<FixedOffset>>,
+    /// The message logged with the latest one
+    message: String,
+    /// The plugin the latest one was put down to
+    plugin: Option<String>,
+}
+
+/// An installed plugin, known by the package of its main class
+struct Plugin {
+    name: String,
+    package: String,
+}
+
+pub fn cmd_errors(server_dir: &Path, since: Option<&str>) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    let zone = Zone::from_config(&Config::load(&server_dir)?.log)?;
+    let since = since.map(|since| timestamps::parse_since(since, zone)).transpose()?;
+
+    // Oldest first, ending with the run going on now
+    let mut runs: Vec<(String, Option<NaiveDateTime>)> = Vec::new();
+    for run in logs::runs(&paths.logs_dir).into_iter().rev() {
+        if let Ok(content) = jsonl::read(&run) {
+            runs.push((content, logs::started(&run)));
+        }
+    }
+    if let Ok(content) = jsonl::read(&paths.log_file) {
+        let started = read_state(&paths)
+            .and_then(|state| DateTime::from_timestamp(state.started_at as i64, 0))
+            .map(|at| at.with_timezone(&Local).naive_local());
+        runs.push((content, started));
+    }
+
+    let plugins = plugins(&server_dir);
+    let mut groups: Vec<Group> = Vec::new();
+    let mut by_plugin: Vec<(Option<String>, usize)> = Vec::new();
+    let mut index: HashMap<(String, Option<String>), usize> = HashMap::new();
+    for (content, started) in &runs {
+        let mut clock = Clock::new(*started);
+        for record in records::parse(content.lines()) {
+            let at = occurred(&record, &mut clock);
+            let Some((class, frame)) = exception(&record) else {
+                continue;
+            };
+            if since.is_some_and(|since| at.is_none_or(|at| at < since)) {
+                continue;
+            }
+            let key = (class.clone(), frame.clone());
+            let i = *index.entry(key).or_insert_with(|| {
+                groups.push(Group {
+                    class,
+                    frame,
+                    count: 0,
+                    first: None,
+                    last: None,
+                    message: String::new(),
+                    plugin: None,
+                });
+                groups.len() - 1
+            });
+            let group = &mut groups[i];
+            group.count += 1;
+            group.first = group.first.or(at);
+            group.last = at.or(group.last);
+            group.message = record.message.clone();
+            group.plugin = blame(&record, &plugins);
+            match by_plugin.iter_mut().find(|(plugin, _)| *plugin == group.plugin) {
+                Some((_, count)) => *count += 1,
+                None => by_plugin.push((group.plugin.clone(), 1)),
+            }
+        }
+    }
+
+    if groups.is_empty() {
+        println!("No exceptions found");
+        return Ok(());
+    }
+    groups.sort_by(|a, b| b.count.cmp(&a.count).then(b.last.cmp(&a.last)));
+    let total: usize = groups.iter().map(|group| group.count).sum();
+    println!("{} exception(s) of {} kind(s)", total, groups.len());
+    if by_plugin.iter().any(|(plugin, _)| plugin.is_some()) {
+        by_plugin.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        for (plugin, count) in by_plugin {
+            let share = count as f64 * 100.0 / total as f64;
+            let origin = plugin.map_or("outside any plugin".to_string(), |p| format!("in {}", p));
+            println!("  {:>3.0}% originate {} ({})", share, origin, count);
+        }
+    }
+    for group in &groups {
+        println!();
+        match &group.plugin {
+            Some(plugin) => println!("{:>6}x {} [{}]", group.count, group.class, plugin),
+            None => println!("{:>6}x {}", group.count, group.class),
+        }
+        if let Some(frame) = &group.frame {
+            println!("        at {}", frame);
+        }
+        println!("        first {}, last {}", time(group.first), time(group.last));
+        if !group.message.is_empty() {
+            println!("        {}", group.message);
+        }
+    }
+    Ok(())
+}
+
+/// The exception class in a record and the top frame of its stack trace
+fn exception(record: &Record) -> Option<(String, Option<String>)> {
+    let lines = std::iter::once(&record.message).chain(&record.trace);
+    let class = lines.clone().find_map(|line| EXCEPTION.captures(line))?[1].to_string();
+    let frame = lines
+        .filter_map(|line| line.trim_start().strip_prefix("at "))
+        .next()
+        .map(String::from);
+    Some((class, frame))
+}
+
+/// The plugin nearest the top of a record's stack trace, or else the one its message names
+fn blame(record: &Record, plugins: &[Plugin]) -> Option<String> {
+    let frames = record.trace.iter().filter_map(|line| line.trim_start().strip_prefix("at "));
+    for frame in frames {
+        // A plugin may sit inside another's package, so the longest package wins
+        let plugin = plugins
+            .iter()
+            .filter(|plugin| {
+                let rest = frame.strip_prefix(plugin.package.as_str());
+                rest.is_some_and(|rest| rest.starts_with('.'))
+            })
+            .max_by_key(|plugin| plugin.package.len());
+        if let Some(plugin) = plugin {
+            return Some(plugin.name.clone());
+        }
+    }
+    Some(PASS_EVENT.captures(&record.message)?[1].to_string())
+}
+
+/// The plugins in `plugins/`, from the description in each jar
+fn plugins(server_dir: &Path) -> Vec<Plugin> {
+    let Ok(entries) = fs::read_dir(server_dir.join("plugins")) else {
+        return Vec::new();
+    };
+    let mut plugins = Vec::new();
+    for jar in entries.flatten().map(|entry| entry.path()) {
+        if jar.extension().is_none_or(|ext| ext != "jar") {
+            continue;
+        }
+        // unzip prints whichever of the files the jar has, and fails only for the other
+        let Ok(output) = Command::new("unzip").arg("-p").arg(&jar).args(PLUGIN_FILES).output()
+        else {
+            eprintln!("unzip not found, exceptions won't be put down to plugins");
+            return Vec::new();
+        };
+        let yml = String::from_utf8_lossy(&output.stdout);
+        let value = |key: &str| {
+            yml.lines().find_map(|line| {
+                let value = line.strip_prefix(key)?.strip_prefix(':')?;
+                Some(value.trim().trim_matches(['"', '\'']).to_string())
+            })
+        };
+        let (Some(name), Some(main)) = (value("name"), value("main")) else {
+            continue;
+        };
+        if let Some((package, _)) = main.rsplit_once('.') {
+            plugins.push(Plugin {
+                name,
+                package: package.to_string(),
+            });
+        }
+    }
+    plugins
+}
+
+/// When a record was logged: its timestamp, or the time of day in its header on the day the
+/// run had got to
+pub fn occurred(record: &Record, clock: &mut Clock) -> Option<DateTime<FixedOffset>> {
+    // The clock follows every header, timestamped or not
+    let estimate = record.clock.as_deref().and_then(|time| clock.at(time));
+    match record.time.as_deref() {
+        Some(time) => DateTime::parse_from_rfc3339(time).ok(),
+        None => Some(Local.from_local_datetime(&estimate?).earliest()?.fixed_offset()),
+    }
+}
+
+/// Follows the date through a run from the times of day in its headers
+pub struct Clock {
+    /// The date and time of the latest header
+    now: Option<NaiveDateTime>,
+}
+
+impl Clock {
+    pub fn new(started: Option<NaiveDateTime>) -> Self {
+        Self { now: started }
+    }
+
+    fn at(&mut self, time: &str) -> Option<NaiveDateTime> {
+        let time = NaiveTime::parse_from_str(time, "%H:%M:%S").ok()?;
+        let now = self.now?;
+        let mut at = now.date().and_time(time);
+        // A time of day earlier than the last one means midnight has passed
+        if at < now - TimeDelta::minutes(1) {
+            at += TimeDelta::days(1);
+        }
+        self.now = Some(at.max(now));
+        Some(at)
+    }
+}
+
+fn time(at: Option<DateTime<FixedOffset>>) -> String {
+    at.map_or_else(
+        || "unknown".to_string(),
+        |at| at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+    )
+}
@@ -0,0 +1,186 @@
+//! Structured events about a server
+//!
+//! The daemon notes what happens to its server (started, stopped, crashed,
+//! players joining and leaving, memory running high) as events. Each one is
+//! appended to `events.jsonl` in the wrap dir, together with events recorded
+//! while no daemon runs (e.g. the backup `upgrade` takes), and sent as an
+//! `event` notification to control clients that subscribed with
+//! `{"events": true}`. `mcwrap events` shows them, and with `--follow` keeps
+//! printing new ones, so scripts and panels needn't poll the console log.
+
+use anyhow::{bail, Context, Result};
+use crate::control::ControlClient;
+use crate::failure::Failure;
+use crate::pty::DaemonState;
+use crate::{is_running, local_time, unix_now, ServerPaths};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Size past which the events are moved to `events.jsonl.old` when a daemon starts
+const MAX_SIZE: u64 = 1 << 20;
+
+/// How often memory use is checked against `[events] high_memory`
+const MEMORY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Memory use must fall below this share of the threshold before it is reported again
+const MEMORY_REARM: f64 = 0.9;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Event {
+    /// Unix time it happened
+    pub at: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum EventKind {
+    /// The server finished starting
+    ServerStarted { pid: i32 },
+    /// The server exited after a stop, or was terminated on purpose
+    ServerStopped { exit_code: i32 },
+    /// The server exited with an error or ran out of memory
+    Crashed { exit_code: i32, out_of_memory: bool },
+    PlayerJoined { player: String },
+    PlayerLeft { player: String },
+    BackupCompleted { archive: PathBuf },
+    BackupFailed { reason: String },
+    /// The server's processes use more memory than `[events] high_memory`
+    HighMemory { bytes: u64, threshold: u64 },
+    /// A trigger rule's `notify` message, a script's alert or one from `[alerts]`
+    Alert { message: String },
+}
+
+impl Event {
+    pub fn now(kind: EventKind) -> Self {
+        Self {
+            at: unix_now(),
+            kind,
+        }
+    }
+
+    /// Append the event to an events file
+    pub fn record(&self, path: &Path) -> Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The event as a line for people, e.g. "Player joined: Steve"
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            EventKind::ServerStarted { pid } => format!("Server started (PID {})", pid),
+            EventKind::ServerStopped { exit_code } => {
+                format!("Server stopped (exit code {})", exit_code)
+            }
+            EventKind::Crashed {
+                out_of_memory: true,
+                ..
+            } => "Server ran out of memory".to_string(),
+            EventKind::Crashed { exit_code, .. } => {
+                format!("Server crashed (exit code {})", exit_code)
+            }
+            EventKind::PlayerJoined { player } => format!("Player joined: {}", player),
+            EventKind::PlayerLeft { player } => format!("Player left: {}", player),
+            EventKind::BackupCompleted { archive } => {
+                format!("Backup completed: {}", archive.display())
+            }
+            EventKind::BackupFailed { reason } => format!("Backup failed: {}", reason),
+            EventKind::HighMemory { bytes, threshold } => format!(
+                "High memory use: {} (threshold {})",
+                crate::disk::format_size(*bytes),
+                crate::disk::format_size(*threshold)
+            ),
+            EventKind::Alert { message } => message.clone(),
+        }
+    }
+}
+
+/// Move a big events file aside so it doesn't grow forever
+pub fn rotate(path: &Path) {
+    if fs::metadata(path).is_ok_and(|m| m.len() > MAX_SIZE) {
+        let _ = fs::rename(path, path.with_extension("jsonl.old"));
+    }
+}
+
+/// The recorded events, oldest first
+fn load(path: &Path) -> Vec<Event> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Report memory use above `threshold` bytes from a background thread
+pub fn watch_memory(state: Arc<DaemonState>, threshold: u64) {
+    thread::spawn(move || {
+        let mut reported = false;
+        loop {
+            thread::sleep(MEMORY_INTERVAL);
+            let Some((_, bytes)) = crate::top::group_usage(state.child_pid.as_raw()) else {
+                return;
+            };
+            if bytes > threshold && !reported {
+                state.emit(EventKind::HighMemory { bytes, threshold });
+                reported = true;
+            } else if (bytes as f64) < threshold as f64 * MEMORY_REARM {
+                reported = false;
+            }
+        }
+    });
+}
+
+/// Print the last `lines` events, then with `follow` new ones as they happen
+pub async fn cmd_events(
+    server_dir: &Path,
+    follow: bool,
+    as_json: bool,
+    lines: usize,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    let print = |event: &Event| {
+        if as_json {
+            println!("{}", serde_json::to_string(event).unwrap_or_default());
+        } else {
+            println!("{}  {}", local_time(event.at), event.describe());
+        }
+    };
+
+    // Subscribe before reading the history so that nothing falls in between
+    let mut control = None;
+    if follow {
+        let Some(mut client) = ControlClient::connect(&paths).await? else {
+            is_running(&paths).context(Failure::not_running())?;
+            bail!("Following events needs a PTY-mode server");
+        };
+        client.call("subscribe", json!({ "console": false, "events": true })).await?;
+        control = Some(client);
+    }
+
+    let history = load(&paths.events_file);
+    for event in &history[history.len().saturating_sub(lines)..] {
+        print(event);
+    }
+
+    let Some(mut control) = control else {
+        return Ok(());
+    };
+    while let Some(message) = control.next_notification().await? {
+        let Ok(event) = serde_json::from_value::<Event>(message["params"].clone()) else {
+            continue;
+        };
+        if history.last() != Some(&event) {
+            print(&event);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,202 @@
+//! Exporting a server's setup for container infrastructure
+//!
+//! `mcwrap export compose <dir>` prints a docker-compose service and
+//! `mcwrap export containerfile <dir>` a Containerfile (Dockerfile) that run
+//! the server the way `mcwrap start` does: the same command and JVM flags,
+//! an `eclipse-temurin` image of the Java version the server runs on (or the
+//! image it was started in with `--docker`), its ports from
+//! `server.properties`, its `[env]` and, for compose, its `[limits]`. The
+//! server directory is the `/data` volume and the working directory.
+//!
+//! ```text
+//! mcwrap export compose /srv/survival > compose.yaml
+//! ```
+
+use anyhow::{Context, Result};
+use crate::config::Config;
+use crate::docker::DEFAULT_IMAGE;
+use crate::ports::{self, Port};
+use crate::registry::Registry;
+use crate::{build_command, presets, server_name};
+use clap::Subcommand;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Where the server directory is mounted in the container
+const DATA_DIR: &str = "/data";
+
+#[derive(Subcommand)]
+pub enum ExportAction {
+    /// Print a docker-compose service that runs the server
+    Compose {
+        /// Server directory
+        dir: PathBuf,
+    },
+    /// Print a Containerfile (Dockerfile) for an image that runs the server
+    Containerfile {
+        /// Server directory
+        dir: PathBuf,
+    },
+}
+
+pub fn cmd_export(action: ExportAction) -> Result<()> {
+    match action {
+        ExportAction::Compose { dir } => print!("{}", Setup::load(&dir)?.compose()),
+        ExportAction::Containerfile { dir } => print!("{}", Setup::load(&dir)?.containerfile()),
+    }
+    Ok(())
+}
+
+/// What a container needs to run a server like mcwrap does
+struct Setup {
+    server_dir: PathBuf,
+    image: String,
+    /// Whether the image was guessed, as the Java version couldn't be found
+    guessed: bool,
+    /// The command, with paths in the server directory moved to `DATA_DIR`
+    command: Vec<String>,
+    ports: Vec<Port>,
+    env: BTreeMap<String, String>,
+    memory_max: Option<String>,
+    cpu_max: Option<f64>,
+}
+
+impl Setup {
+    fn load(server_dir: &Path) -> Result<Self> {
+        let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+        let config = Config::load(&server_dir)?;
+        let entry = Registry::load()?.get(&server_dir).cloned();
+        let (java_args, exec, preset, container) = entry
+            .map(|entry| (entry.java_args, entry.exec, entry.preset, entry.container))
+            .unwrap_or_default();
+        let launcher = exec.map(|program| vec![program]).unwrap_or(config.command);
+        let mut java = config.java;
+        presets::apply(&mut java, preset, &launcher, &java_args);
+        let (command, _) = build_command(&server_dir, &launcher, &java, java_args, None)?;
+
+        let prefix = server_dir.to_string_lossy().into_owned();
+        let command = command
+            .into_iter()
+            .map(|arg| match arg.strip_prefix(&prefix) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                    format!("{}{}", DATA_DIR, rest)
+                }
+                _ => arg,
+            })
+            .collect();
+
+        let (image, guessed) = if container.docker {
+            (container.image().to_string(), false)
+        } else {
+            match presets::java_version(&[]) {
+                Some(version) => (format!("eclipse-temurin:{}", version), false),
+                None => (DEFAULT_IMAGE.to_string(), true),
+            }
+        };
+        let ports = if server_dir.join("server.properties").exists() {
+            ports::configured(&server_dir)?.0
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            server_dir,
+            image,
+            guessed,
+            command,
+            ports,
+            env: config.env,
+            memory_max: config.limits.memory_max,
+            cpu_max: config.limits.cpu_max,
+        })
+    }
+
+    /// A compose file with the server as its only service
+    fn compose(&self) -> String {
+        let mut out = String::new();
+        let mut line = |text: String| {
+            out.push_str(&text);
+            out.push('\n');
+        };
+        line(format!("# {}, exported by mcwrap", self.server_dir.display()));
+        line("services:".to_string());
+        line(format!("  {}:", service_name(&self.server_dir)));
+        if self.guessed {
+            line("    # java could not be run to find its version; check the image".to_string());
+        }
+        line(format!("    image: {}", quote(&self.image)));
+        line(format!("    working_dir: {}", DATA_DIR));
+        line(format!("    command: {}", serde_json::to_string(&self.command).unwrap()));
+        line("    volumes:".to_string());
+        let volume = format!("{}:{}", self.server_dir.display(), DATA_DIR);
+        line(format!("      - {}", quote(&volume)));
+        if !self.ports.is_empty() {
+            line("    ports:".to_string());
+            for port in &self.ports {
+                line(format!("      - {}", quote(&port.publish())));
+            }
+        }
+        if !self.env.is_empty() {
+            line("    environment:".to_string());
+            for (key, value) in &self.env {
+                line(format!("      {}: {}", key, quote(value)));
+            }
+        }
+        if let Some(memory) = &self.memory_max {
+            line(format!("    mem_limit: {}", quote(memory)));
+        }
+        if let Some(cpus) = self.cpu_max {
+            line(format!("    cpus: {}", cpus));
+        }
+        // The console, for `docker attach`
+        line("    stdin_open: true".to_string());
+        line("    tty: true".to_string());
+        line("    restart: unless-stopped".to_string());
+        out
+    }
+
+    /// A Containerfile for an image that runs the server in its `/data` volume
+    fn containerfile(&self) -> String {
+        let mut out = String::new();
+        let mut line = |text: String| {
+            out.push_str(&text);
+            out.push('\n');
+        };
+        line(format!("# {}, exported by mcwrap", self.server_dir.display()));
+        let volume = format!("{}:{}", self.server_dir.display(), DATA_DIR);
+        line(format!("# Build it, then run with: docker run -it -v {} <image>", quote(&volume)));
+        if self.guessed {
+            line("# java could not be run to find its version; check the image".to_string());
+        }
+        line(format!("FROM {}", self.image));
+        line(format!("WORKDIR {}", DATA_DIR));
+        line(format!("VOLUME {}", DATA_DIR));
+        for (key, value) in &self.env {
+            line(format!("ENV {}={}", key, quote(value)));
+        }
+        for port in &self.ports {
+            let protocol = if port.udp { "udp" } else { "tcp" };
+            line(format!("EXPOSE {}/{}", port.port, protocol));
+        }
+        line(format!("CMD {}", serde_json::to_string(&self.command).unwrap()));
+        out
+    }
+}
+
+/// A compose service name for a server: its directory name, lowercased, with
+/// only the characters compose allows
+fn service_name(server_dir: &Path) -> String {
+    let name: String = server_name(server_dir)
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    match name.trim_matches('-') {
+        "" => "minecraft".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// A double-quoted string, as both YAML and Containerfiles read it
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
@@ -0,0 +1,131 @@
+//! Kinds of failure that scripts can tell apart
+//!
+//! mcwrap exits with 1 when something goes wrong, and with a code of its own
+//! for the failures a script may want to handle: the server is not running
+//! (3), is already running (4), has no JAR to start (5), access was denied
+//! (6), something timed out (7) or the server failed to start (8). Commands
+//! run with `--json` print the failure as JSON too:
+//! `{"error": {"code": "not_running", "exit_code": 3, "message": "…"}}`.
+//!
+//! Errors carry their kind as a `Failure` somewhere in their context chain;
+//! denied and timed-out I/O and control calls are recognized as they are.
+
+use anyhow::Error;
+use crate::control::{self, RpcFailure};
+use serde_json::{json, Value};
+use std::fmt;
+use std::io;
+
+/// Exit code of failures without a kind
+pub const GENERIC: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    NotRunning,
+    AlreadyRunning,
+    NoJar,
+    PermissionDenied,
+    Timeout,
+    StartFailed,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::NotRunning => 3,
+            ErrorKind::AlreadyRunning => 4,
+            ErrorKind::NoJar => 5,
+            ErrorKind::PermissionDenied => 6,
+            ErrorKind::Timeout => 7,
+            ErrorKind::StartFailed => 8,
+        }
+    }
+
+    /// Name in JSON output
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::NotRunning => "not_running",
+            ErrorKind::AlreadyRunning => "already_running",
+            ErrorKind::NoJar => "no_jar",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::StartFailed => "start_failed",
+        }
+    }
+}
+
+/// An error of a known kind
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: ErrorKind,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_running() -> Self {
+        Self::new(ErrorKind::NotRunning, "Server is not running")
+    }
+
+    pub fn already_running() -> Self {
+        Self::new(ErrorKind::AlreadyRunning, "Server is already running")
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The kind of an error, if it has one
+pub fn kind(error: &Error) -> Option<ErrorKind> {
+    if let Some(failure) = error.downcast_ref::<Failure>() {
+        return Some(failure.kind);
+    }
+    error.chain().find_map(|cause| {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return Some(failure.kind);
+        }
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            return match e.kind() {
+                io::ErrorKind::PermissionDenied => Some(ErrorKind::PermissionDenied),
+                io::ErrorKind::TimedOut => Some(ErrorKind::Timeout),
+                _ => None,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<RpcFailure>() {
+            return match e.code {
+                control::UNAUTHORIZED | control::FORBIDDEN => Some(ErrorKind::PermissionDenied),
+                control::STOP_TIMEOUT => Some(ErrorKind::Timeout),
+                _ => None,
+            };
+        }
+        cause.is::<tokio::time::error::Elapsed>().then_some(ErrorKind::Timeout)
+    })
+}
+
+/// The process exit code for an error
+pub fn exit_code(error: &Error) -> i32 {
+    kind(error).map_or(GENERIC, ErrorKind::exit_code)
+}
+
+/// An error as `--json` commands print it
+pub fn to_json(error: &Error) -> Value {
+    let kind = kind(error);
+    json!({
+        "error": {
+            "code": kind.map_or("error", ErrorKind::name),
+            "exit_code": kind.map_or(GENERIC, ErrorKind::exit_code),
+            "message": format!("{:#}", error),
+        }
+    })
+}
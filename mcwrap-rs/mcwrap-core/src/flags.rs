@@ -0,0 +1,295 @@
+//! Checking and comparing the JVM flags servers are launched with
+//!
+//! `mcwrap flags check <dir>` works out the command `mcwrap start` runs, the
+//! way it was last started and with `@` argument files such as Forge's
+//! `user_jvm_args.txt` read in, and points out common mistakes: flags given
+//! twice, a heap that G1 has to resize, collectors and flags that newer Java
+//! versions no longer have, and a missing `--nogui`. `mcwrap flags diff
+//! <dir> <dir>` shows where two servers' launch commands differ.
+
+use anyhow::{bail, Context, Result};
+use crate::{build_command, presets};
+use crate::config::Config;
+use crate::disk::{format_size, parse_size};
+use crate::platform;
+use crate::registry::Registry;
+use clap::Subcommand;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Flags that take their value as the next argument
+const WITH_VALUE: &[&str] = &[
+    "-cp",
+    "-classpath",
+    "--class-path",
+    "-p",
+    "--module-path",
+    "--add-modules",
+    "--add-opens",
+    "--add-exports",
+    "--add-reads",
+];
+
+/// Flags current Java versions refuse or ignore, and why
+const OBSOLETE: &[(&str, &str)] = &[
+    ("-XX:+UseConcMarkSweepGC", "the CMS collector was removed in Java 14; G1 replaces it"),
+    ("-XX:+UseParNewGC", "ParNew was removed in Java 10"),
+    ("-XX:+CMSIncrementalMode", "the CMS collector was removed in Java 14"),
+    ("-XX:+CMSClassUnloadingEnabled", "the CMS collector was removed in Java 14"),
+    ("-XX:CMSInitiatingOccupancyFraction", "the CMS collector was removed in Java 14"),
+    ("-XX:PermSize", "the permanent generation was removed in Java 8"),
+    ("-XX:MaxPermSize", "the permanent generation was removed in Java 8"),
+    ("-XX:+AggressiveOpts", "it was removed in Java 12"),
+    ("-XX:+UseFastAccessorMethods", "it was removed in Java 9"),
+    ("-XX:+UseCompressedStrings", "it was removed in Java 7"),
+    ("-Xincgc", "incremental CMS was removed in Java 9"),
+];
+
+/// Flags that pick a garbage collector
+const COLLECTORS: &[&str] = &[
+    "-XX:+UseG1GC",
+    "-XX:+UseZGC",
+    "-XX:+UseShenandoahGC",
+    "-XX:+UseParallelGC",
+    "-XX:+UseSerialGC",
+    "-XX:+UseConcMarkSweepGC",
+];
+
+#[derive(Subcommand)]
+pub enum FlagsAction {
+    /// Point out common mistakes in a server's JVM flags
+    Check {
+        /// Server directory
+        dir: PathBuf,
+    },
+    /// Compare the launch commands of two servers
+    Diff {
+        /// Server directory
+        dir: PathBuf,
+        /// Server directory to compare with
+        other: PathBuf,
+    },
+}
+
+pub fn cmd_flags(action: FlagsAction) -> Result<()> {
+    match action {
+        FlagsAction::Check { dir } => check(&dir),
+        FlagsAction::Diff { dir, other } => diff(&dir, &other),
+    }
+}
+
+/// A server's launch command, split the way the JVM reads it
+struct Launch {
+    /// What the command was derived from, e.g. "JAR: paper.jar"
+    source: String,
+    /// Options for the JVM, with argument files read in
+    jvm: Vec<String>,
+    /// The JAR or main class and its arguments
+    program: Vec<String>,
+}
+
+impl Launch {
+    fn load(server_dir: &Path) -> Result<Self> {
+        let config = Config::load(server_dir)?;
+        let entry = Registry::load()?.get(server_dir).cloned();
+        let (java_args, exec, preset) = entry
+            .map(|entry| (entry.java_args, entry.exec, entry.preset))
+            .unwrap_or_default();
+        let launcher = exec.map(|program| vec![program]).unwrap_or(config.command);
+        let mut java = config.java;
+        presets::apply(&mut java, preset, &launcher, &java_args);
+        let (command, source) = build_command(server_dir, &launcher, &java, java_args, None)?;
+        // A configured command may still run java itself
+        let runs_java = Path::new(&command[0]).file_name().is_some_and(|name| name == "java");
+        let source = match source {
+            Some(source) => source.to_string(),
+            None if runs_java => format!("Command: {}", command[0]),
+            None => bail!(
+                "{} runs {}, not java, so its flags are up to it",
+                server_dir.display(),
+                command[0]
+            ),
+        };
+
+        let mut args = Vec::new();
+        for arg in &command[1..] {
+            match arg.strip_prefix('@') {
+                Some(file) => args.extend(read_args_file(&server_dir.join(file))?),
+                None => args.push(arg.clone()),
+            }
+        }
+        // The JVM's options end at -jar or at the main class
+        let mut jvm = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-jar" || !arg.starts_with('-') {
+                let program = std::iter::once(arg).chain(args).collect();
+                return Ok(Self { source, jvm, program });
+            }
+            if WITH_VALUE.contains(&arg.as_str()) {
+                let value = args.next().unwrap_or_default();
+                jvm.push(format!("{} {}", arg, value));
+            } else {
+                jvm.push(arg);
+            }
+        }
+        Ok(Self {
+            source,
+            jvm,
+            program: Vec::new(),
+        })
+    }
+
+    /// The value of the last `-Xmx`-style flag
+    fn value(&self, prefix: &str) -> Option<&str> {
+        self.jvm.iter().rev().find_map(|arg| arg.strip_prefix(prefix))
+    }
+}
+
+/// The arguments in an `@` file, one or more per line, without comments
+fn read_args_file(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .map(String::from)
+        .collect())
+}
+
+/// What makes two flags the same setting: `-Xmx`, `-XX:Foo`, `-Dfoo`...
+fn key(arg: &str) -> String {
+    for prefix in ["-Xmx", "-Xms", "-Xss", "-Xmn"] {
+        if arg.starts_with(prefix) {
+            return prefix.to_string();
+        }
+    }
+    if let Some(option) = arg.strip_prefix("-XX:") {
+        let option = option.trim_start_matches(['+', '-']);
+        return format!("-XX:{}", option.split('=').next().unwrap_or(option));
+    }
+    if arg.starts_with("-D") {
+        return arg.split('=').next().unwrap_or(arg).to_string();
+    }
+    arg.to_string()
+}
+
+fn check(server_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let launch = Launch::load(&server_dir)?;
+    println!("{}", launch.source);
+    println!("  {}", launch.jvm.join(" "));
+    let mut problems = Vec::new();
+
+    let mut seen: Vec<&String> = Vec::new();
+    for arg in &launch.jvm {
+        let key = key(arg);
+        let Some(earlier) = seen.iter().find(|earlier| self::key(earlier) == key) else {
+            seen.push(arg);
+            continue;
+        };
+        if *earlier == arg {
+            problems.push(format!("{} is given twice", arg));
+        } else {
+            problems.push(format!("{} and {} are both given; the last one wins", earlier, arg));
+        }
+        seen.push(arg);
+    }
+
+    for (flag, reason) in OBSOLETE {
+        if launch.jvm.iter().any(|arg| key(arg) == key(flag)) {
+            problems.push(format!("{}: {}", flag, reason));
+        }
+    }
+
+    let collectors: Vec<&str> =
+        COLLECTORS.iter().copied().filter(|gc| launch.jvm.iter().any(|arg| arg == gc)).collect();
+    if collectors.len() > 1 {
+        problems.push(format!("Several collectors are chosen: {}", collectors.join(", ")));
+    }
+    // G1 is the default collector
+    let g1 = collectors.is_empty() || collectors == ["-XX:+UseG1GC"];
+    match (launch.value("-Xms"), launch.value("-Xmx")) {
+        (_, None) => {
+            problems.push("No -Xmx: the JVM takes a quarter of the machine's memory".into())
+        }
+        (Some(min), Some(max)) if g1 && !min.eq_ignore_ascii_case(max) => problems.push(format!(
+            "-Xms{} differs from -Xmx{}: with G1, the same size for both saves resizing the heap",
+            min, max
+        )),
+        _ => {}
+    }
+    let max = launch.value("-Xmx");
+    let bytes = max.and_then(|max| parse_size(max).ok());
+    if let (Some(max), Some(bytes), Some(total)) = (max, bytes, platform::total_memory()) {
+        if bytes > total {
+            problems.push(format!(
+                "-Xmx{} is more than the machine's {} of memory",
+                max,
+                format_size(total)
+            ));
+        }
+    }
+
+    let proxy = ["velocity", "bungee", "waterfall"]
+        .iter()
+        .any(|name| launch.source.to_lowercase().contains(name));
+    let nogui = launch.program.iter().any(|arg| arg == "--nogui" || arg == "nogui");
+    if !proxy && !nogui {
+        problems.push("No --nogui: the server opens its status window where it can".into());
+    }
+
+    if problems.is_empty() {
+        println!("No problems found");
+    }
+    for problem in problems {
+        println!("  ! {}", problem);
+    }
+    Ok(())
+}
+
+fn diff(server_dir: &Path, other_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let other_dir = other_dir.canonicalize().context("Invalid server directory")?;
+    let ours = Launch::load(&server_dir)?;
+    let theirs = Launch::load(&other_dir)?;
+
+    let mut lines = Vec::new();
+    if ours.source != theirs.source {
+        lines.push(format!("- {}", ours.source));
+        lines.push(format!("+ {}", theirs.source));
+    }
+    // Flags in the order they first appear, each compared by setting
+    let mut keys: Vec<String> = Vec::new();
+    for arg in ours.jvm.iter().chain(&theirs.jvm) {
+        if !keys.contains(&key(arg)) {
+            keys.push(key(arg));
+        }
+    }
+    for key in keys {
+        let with_key = |launch: &Launch| -> Vec<String> {
+            launch.jvm.iter().filter(|arg| self::key(arg) == key).cloned().collect()
+        };
+        let (a, b) = (with_key(&ours), with_key(&theirs));
+        if a != b {
+            lines.extend(a.iter().map(|arg| format!("- {}", arg)));
+            lines.extend(b.iter().map(|arg| format!("+ {}", arg)));
+        }
+    }
+    if ours.program != theirs.program {
+        lines.push(format!("- {}", ours.program.join(" ")));
+        lines.push(format!("+ {}", theirs.program.join(" ")));
+    }
+
+    if lines.is_empty() {
+        println!("{} and {} launch the same way", server_dir.display(), other_dir.display());
+        return Ok(());
+    }
+    println!("--- {}", server_dir.display());
+    println!("+++ {}", other_dir.display());
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
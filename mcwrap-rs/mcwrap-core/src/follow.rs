@@ -0,0 +1,309 @@
+//! Following the console log as it is written
+//!
+//! `tail` and `attach` to a basic-mode server read the console log as it
+//! grows. Rather than checking it every 100ms, they sleep until the kernel
+//! says it changed: through inotify on Linux and kqueue on the BSDs and
+//! macOS, so output shows up at once and an idle tail costs nothing. Where
+//! neither can be set up they go back to checking every 100ms. A log that is
+//! truncated is read again from the start, and one that is replaced, as when
+//! a restarted server's log is archived to `logs/`, is followed from the
+//! start of the new file once the end of the old one has been read.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often the file is checked when the kernel can't tell when it changes
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads what is appended to a file
+pub struct Follower {
+    path: PathBuf,
+    file: Option<File>,
+    /// Where reading continues in `file`
+    pos: u64,
+    watch: Option<Watch>,
+}
+
+impl Follower {
+    /// Follow a file from its start
+    pub fn new(path: &Path) -> Self {
+        // Watch before the first read, so that no write falls in between
+        let watch = Watch::new(path);
+        let mut follower = Self {
+            path: path.to_path_buf(),
+            file: None,
+            pos: 0,
+            watch,
+        };
+        follower.open();
+        follower
+    }
+
+    /// Wait for more of the file and return it
+    pub async fn next(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            let data = self.read()?;
+            if !data.is_empty() {
+                return Ok(data);
+            }
+            match &mut self.watch {
+                Some(watch) => watch.wait(self.file.as_ref()).await?,
+                None => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+
+    fn open(&mut self) {
+        let Ok(file) = File::open(&self.path) else {
+            return;
+        };
+        self.pos = 0;
+        self.file = Some(file);
+    }
+
+    /// What has been written since the last read, following truncation and replacement
+    fn read(&mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let Some(file) = &mut self.file else {
+            self.open();
+            return match self.file {
+                Some(_) => self.read(),
+                None => Ok(data),
+            };
+        };
+        let current = file.metadata()?;
+        if current.len() < self.pos {
+            self.pos = 0;
+        }
+        file.seek(SeekFrom::Start(self.pos))?;
+        self.pos += file.read_to_end(&mut data)? as u64;
+        let same = |metadata: fs::Metadata| {
+            (metadata.dev(), metadata.ino()) == (current.dev(), current.ino())
+        };
+        if fs::metadata(&self.path).map_or(true, |metadata| !same(metadata)) {
+            // The old file has been read to its end, so the new one can start
+            self.file = None;
+            if data.is_empty() {
+                return self.read();
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Wakes a follower when its file may have changed
+enum Watch {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::Watch),
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    Kqueue(kqueue::Watch),
+}
+
+impl Watch {
+    /// Watch `path` through inotify
+    #[cfg(target_os = "linux")]
+    fn new(path: &Path) -> Option<Self> {
+        inotify::Watch::new(path).ok().map(Watch::Inotify)
+    }
+
+    /// Watch `path` through kqueue
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    fn new(path: &Path) -> Option<Self> {
+        kqueue::Watch::new(path).ok().map(Watch::Kqueue)
+    }
+
+    /// Nothing to watch with here, so the file is checked every `POLL_INTERVAL`
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    )))]
+    fn new(_path: &Path) -> Option<Self> {
+        None
+    }
+
+    /// Wait for a change to the file
+    #[cfg(target_os = "linux")]
+    async fn wait(&mut self, _file: Option<&File>) -> io::Result<()> {
+        let Watch::Inotify(watch) = self;
+        watch.wait().await
+    }
+
+    /// Wait for a change to the file, which is `file` if it is open
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    async fn wait(&mut self, file: Option<&File>) -> io::Result<()> {
+        let Watch::Kqueue(watch) = self;
+        watch.wait(file).await
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    )))]
+    async fn wait(&mut self, _file: Option<&File>) -> io::Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use nix::errno::Errno;
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+    use std::ffi::OsString;
+    use std::io;
+    use std::os::fd::{AsFd, AsRawFd, RawFd};
+    use std::path::Path;
+    use tokio::io::unix::AsyncFd;
+
+    /// Watches the file's directory, so that the file being replaced or created is seen too
+    pub struct Watch {
+        /// Declared before `inotify`, so that it is deregistered before the descriptor closes
+        ready: AsyncFd<RawFd>,
+        inotify: Inotify,
+        name: OsString,
+    }
+
+    impl Watch {
+        pub fn new(path: &Path) -> io::Result<Self> {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?.to_os_string();
+            let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+            let flags = AddWatchFlags::IN_MODIFY
+                | AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_DELETE
+                | AddWatchFlags::IN_MOVED_FROM
+                | AddWatchFlags::IN_MOVED_TO;
+            inotify.add_watch(dir, flags)?;
+            Ok(Self {
+                ready: AsyncFd::new(inotify.as_fd().as_raw_fd())?,
+                inotify,
+                name,
+            })
+        }
+
+        pub async fn wait(&mut self) -> io::Result<()> {
+            loop {
+                let mut ready = self.ready.readable().await?;
+                let events = match self.inotify.read_events() {
+                    Ok(events) => events,
+                    Err(Errno::EAGAIN) => {
+                        ready.clear_ready();
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                // Other files in the directory change too
+                if events.iter().any(|event| event.name.as_ref() == Some(&self.name)) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+mod kqueue {
+    use nix::libc;
+    use nix::sys::event::{EventFilter, EventFlag, FilterFlag, KEvent, Kqueue};
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::{AsFd, AsRawFd, RawFd};
+    use std::path::Path;
+    use tokio::io::unix::AsyncFd;
+
+    /// Watches the file for writes and for being moved or deleted, and its directory for a
+    /// new file appearing
+    pub struct Watch {
+        /// Declared before `kqueue`, so that it is deregistered before the descriptor closes
+        ready: AsyncFd<RawFd>,
+        kqueue: Kqueue,
+        /// Kept open for its events
+        _dir: File,
+    }
+
+    impl Watch {
+        pub fn new(path: &Path) -> io::Result<Self> {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let dir = File::open(dir)?;
+            let kqueue = Kqueue::new()?;
+            kqueue.kevent(&[event(dir.as_raw_fd(), FilterFlag::NOTE_WRITE)], &mut [], None)?;
+            Ok(Self {
+                ready: AsyncFd::new(kqueue.as_fd().as_raw_fd())?,
+                kqueue,
+                _dir: dir,
+            })
+        }
+
+        pub async fn wait(&mut self, file: Option<&File>) -> io::Result<()> {
+            // A file's events go when it is closed, so the one open now is (re)registered
+            if let Some(file) = file {
+                let flags = FilterFlag::NOTE_WRITE
+                    | FilterFlag::NOTE_EXTEND
+                    | FilterFlag::NOTE_DELETE
+                    | FilterFlag::NOTE_RENAME
+                    | FilterFlag::NOTE_ATTRIB;
+                self.kqueue.kevent(&[event(file.as_raw_fd(), flags)], &mut [], None)?;
+            }
+            let mut ready = self.ready.readable().await?;
+            let mut events = [event(0, FilterFlag::empty()); 8];
+            let now = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            };
+            self.kqueue.kevent(&[], &mut events, Some(now))?;
+            ready.clear_ready();
+            Ok(())
+        }
+    }
+
+    fn event(fd: RawFd, flags: FilterFlag) -> KEvent {
+        KEvent::new(
+            fd as usize,
+            EventFilter::EVFILT_VNODE,
+            EventFlag::EV_ADD | EventFlag::EV_CLEAR,
+            flags,
+            0,
+            0,
+        )
+    }
+}
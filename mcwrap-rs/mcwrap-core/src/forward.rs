@@ -0,0 +1,179 @@
+//! Forwarding the console to syslog or the systemd journal
+//!
+//! ```toml
+//! [log.forward]
+//! to = "journald"        # or "syslog"
+//! level = "warn"
+//! pattern = "(?i)lag|exception"
+//! ```
+//!
+//! Hosts that collect the journal or syslog centrally pick up the server's
+//! console this way without tailing `console.log`. Each line goes out as it
+//! is written to the log (after `[log] dedupe`, without colors) under the
+//! server directory's name, or `identifier`, with a priority taken from the
+//! line's level: stack traces share the level of the line they belong to.
+//! `level` and `pattern` keep only some lines; with `level`, lines that have
+//! no level of their own are left out. In the global config this covers every
+//! server, each under its own name.
+
+use anyhow::{Context, Result};
+use crate::config::{ForwardConfig, ForwardTarget};
+use crate::console::strip_ansi;
+use crate::daemon_log;
+use crate::records::{self, Level};
+use chrono::Local;
+use regex::Regex;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+/// The journal's native protocol socket
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The local syslog socket
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The syslog facility lines are sent with (user-level messages)
+const FACILITY_USER: u8 = 1;
+
+/// Which console lines go where
+#[derive(Clone)]
+pub struct Forwarding {
+    to: ForwardTarget,
+    identifier: String,
+    level: Option<Level>,
+    pattern: Option<Regex>,
+}
+
+impl Forwarding {
+    pub fn from_config(config: &ForwardConfig, server_dir: &Path) -> Result<Self> {
+        let pattern = config
+            .pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context("Invalid [log.forward] pattern")?;
+        let identifier =
+            config.identifier.clone().unwrap_or_else(|| crate::server_name(server_dir));
+        Ok(Self {
+            to: config.to,
+            identifier,
+            level: config.level,
+            pattern,
+        })
+    }
+
+    /// Where the lines go, e.g. "journald as survival"
+    pub fn describe(&self) -> String {
+        match self.to {
+            ForwardTarget::Syslog => format!("syslog as {}", self.identifier),
+            ForwardTarget::Journald => format!("journald as {}", self.identifier),
+        }
+    }
+}
+
+/// Sends console lines to syslog or the journal
+pub struct Forwarder {
+    forwarding: Forwarding,
+    /// Opened with the first line sent
+    socket: Option<UnixDatagram>,
+    /// Output after the last complete line
+    partial: Vec<u8>,
+    /// The level of the latest line that had one, for the stack trace after it
+    last_level: Option<Level>,
+    /// Set while sending fails, so that it is reported once
+    failing: bool,
+}
+
+impl Forwarder {
+    pub fn new(forwarding: Forwarding) -> Self {
+        Self {
+            forwarding,
+            socket: None,
+            partial: Vec::new(),
+            last_level: None,
+            failing: false,
+        }
+    }
+
+    /// Forward the complete lines of console output, which may end mid-line
+    pub fn push(&mut self, data: &[u8]) {
+        self.partial.extend_from_slice(data);
+        while let Some(end) = self.partial.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = strip_ansi(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']));
+            let level = if records::is_trace(&line) {
+                self.last_level
+            } else {
+                self.last_level = records::level(&line);
+                self.last_level
+            };
+            let forwarding = &self.forwarding;
+            if line.trim().is_empty()
+                || forwarding.level.is_some_and(|min| level.is_none_or(|level| level < min))
+                || forwarding.pattern.as_ref().is_some_and(|pattern| !pattern.is_match(&line))
+            {
+                continue;
+            }
+            self.send(&line, level);
+        }
+    }
+
+    fn send(&mut self, line: &str, level: Option<Level>) {
+        let severity = severity(level);
+        let forwarding = &self.forwarding;
+        let (message, path) = match forwarding.to {
+            ForwardTarget::Journald => (
+                format!(
+                    "MESSAGE={}\nPRIORITY={}\nSYSLOG_IDENTIFIER={}\n",
+                    line, severity, forwarding.identifier
+                ),
+                JOURNALD_SOCKET,
+            ),
+            ForwardTarget::Syslog => (
+                format!(
+                    "<{}>{} {}[{}]: {}",
+                    FACILITY_USER * 8 + severity,
+                    Local::now().format("%b %e %H:%M:%S"),
+                    forwarding.identifier,
+                    std::process::id(),
+                    line
+                ),
+                SYSLOG_SOCKET,
+            ),
+        };
+        match self.deliver(message.as_bytes(), path) {
+            Ok(_) if self.failing => {
+                let to = self.forwarding.describe();
+                daemon_log::info(format!("Forwarding to {} works again", to));
+                self.failing = false;
+            }
+            Ok(_) => {}
+            Err(e) if !self.failing => {
+                let to = self.forwarding.describe();
+                daemon_log::error(format!("Failed to forward to {}: {}", to, e));
+                self.failing = true;
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn deliver(&mut self, message: &[u8], path: &str) -> io::Result<usize> {
+        let socket = match &mut self.socket {
+            Some(socket) => socket,
+            socket @ None => socket.insert(UnixDatagram::unbound()?),
+        };
+        socket.send_to(message, path)
+    }
+}
+
+/// The syslog severity of a line, with lines without a level as informational
+fn severity(level: Option<Level>) -> u8 {
+    match level {
+        Some(Level::Fatal) => 2,
+        Some(Level::Error) => 3,
+        Some(Level::Warn) => 4,
+        Some(Level::Info) | None => 6,
+        Some(Level::Debug | Level::Trace) => 7,
+    }
+}
@@ -0,0 +1,311 @@
+//! Garbage collection logs
+//!
+//! `mcwrap start --gc-log` has the JVM log its collections to `gc/gc.log`
+//! in the wrap dir (rotated at 20 MB, five files kept, and across restarts).
+//! `mcwrap gc <dir>` reads the log of the current or last run and sums it
+//! up: how often and how long the server paused, how full the heap stays
+//! after collections and whether that is growing, and what that says about
+//! the heap size. It understands the unified logging of Java 9 and later for
+//! G1, Parallel, ZGC and Shenandoah.
+
+use anyhow::{bail, Context, Result};
+use crate::disk::{format_size, parse_size};
+use crate::{format_uptime, ServerPaths};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// Share of the maximum heap still in use after collections that means it is too small
+const TOO_SMALL: f64 = 0.7;
+
+/// Share below which the heap is mostly unused
+const TOO_LARGE: f64 = 0.3;
+
+/// Growth of the heap in use after collections between the first and last quarter of a run
+/// that is worth pointing out
+const GROWING: f64 = 1.25;
+
+/// A stop-the-world pause, with the heap before and after it when the collector logs it:
+/// `GC(12) Pause Young (Normal) (G1 Evacuation Pause) 1024M->256M(4096M) 12.345ms`,
+/// `GC(3) Pause Init Mark 0.262ms`, or `GC(0) Y: Pause Mark Start 0.020ms` for
+/// generational ZGC
+static PAUSE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"GC\(\d+\) (?:[YO]: )?(Pause [A-Za-z ]+?)(?: \([^)]*\))*",
+        r"(?: (\d+[KMG])->(\d+[KMG])\((\d+[KMG])\))? ([\d.]+)ms$"
+    ))
+    .unwrap()
+});
+
+/// A ZGC cycle, which has no pause with the heap in it: `GC(3) Garbage Collection
+/// (Warmup) 406M(10%)->78M(2%)`, or `Minor Collection` and `Major Collection`
+static CYCLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"GC\(\d+\) (?:Garbage|Major|Minor) Collection \([^)]*\) ",
+        r"\d+[KMG]\(\d+%\)->(\d+[KMG])"
+    ))
+    .unwrap()
+});
+
+/// The JVM's uptime decoration, e.g. `[12.345s]`
+static UPTIME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[([\d.]+)s\]").unwrap());
+
+/// The collector, e.g. `Using G1`
+static COLLECTOR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\] Using (.+)$").unwrap());
+
+/// The largest the heap may grow, from `-Xmx`: `Heap Max Capacity: 4G` (`Max Capacity` for ZGC)
+static MAX_HEAP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\] (?:Heap )?Max Capacity: (\d+[KMG])").unwrap());
+
+/// The `-Xlog` flag that logs collections to `path`
+pub fn log_flag(path: &Path) -> String {
+    format!(
+        "-Xlog:gc*:file={}:time,uptime,level,tags:filecount=5,filesize=20M",
+        path.display()
+    )
+}
+
+/// What the log says about one run
+#[derive(Default)]
+struct Summary {
+    collector: Option<String>,
+    max_heap: Option<u64>,
+    /// Pause kind, e.g. "Pause Young", to their lengths in milliseconds
+    pauses: BTreeMap<String, Vec<f64>>,
+    /// Heap in use after each collection
+    heap_after: Vec<u64>,
+    /// Heap size the collector reported last
+    capacity: Option<u64>,
+    uptime: f64,
+}
+
+impl Summary {
+    fn parse(log: &str) -> Self {
+        let mut summary = Self::default();
+        for line in log.lines() {
+            if let Some(uptime) = UPTIME.captures(line).and_then(|caps| caps[1].parse().ok()) {
+                summary.uptime = uptime;
+            }
+            if let Some(caps) = PAUSE.captures(line) {
+                let Ok(ms) = caps[5].parse() else {
+                    continue;
+                };
+                summary.pauses.entry(caps[1].to_string()).or_default().push(ms);
+                if let (Some(after), Some(capacity)) = (caps.get(3), caps.get(4)) {
+                    if let Ok(after) = parse_size(after.as_str()) {
+                        summary.heap_after.push(after);
+                    }
+                    summary.capacity = parse_size(capacity.as_str()).ok();
+                }
+            } else if let Some(caps) = CYCLE.captures(line) {
+                if let Ok(after) = parse_size(&caps[1]) {
+                    summary.heap_after.push(after);
+                }
+            } else if let Some(caps) = COLLECTOR.captures(line) {
+                summary.collector = Some(caps[1].to_string());
+            } else if let Some(caps) = MAX_HEAP.captures(line) {
+                summary.max_heap = parse_size(&caps[1]).ok();
+            }
+        }
+        summary
+    }
+
+    /// Average heap in use after collections in the first and the last quarter of the run
+    fn trend(&self) -> Option<(u64, u64)> {
+        if self.heap_after.len() < 8 {
+            return None;
+        }
+        let quarter = self.heap_after.len() / 4;
+        let average = |samples: &[u64]| samples.iter().sum::<u64>() / samples.len() as u64;
+        let last = &self.heap_after[self.heap_after.len() - quarter..];
+        Some((average(&self.heap_after[..quarter]), average(last)))
+    }
+}
+
+pub fn cmd_gc(server_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let path = ServerPaths::new(&server_dir).gc_log;
+    let Ok(log) = fs::read_to_string(&path) else {
+        bail!(
+            "No GC log for this server (start it with: mcwrap start --gc-log {})",
+            server_dir.display()
+        );
+    };
+    let summary = Summary::parse(&log);
+
+    let collector = summary.collector.as_deref().unwrap_or("unknown collector");
+    println!("{}", path.display());
+    println!("  {}, {} of uptime", collector, format_uptime(summary.uptime as u64));
+    if summary.pauses.is_empty() {
+        println!("  No collections yet");
+        return Ok(());
+    }
+
+    let mut all: Vec<f64> = summary.pauses.values().flatten().copied().collect();
+    all.sort_by(f64::total_cmp);
+    let total: f64 = all.iter().sum();
+    let kinds: Vec<String> = summary
+        .pauses
+        .iter()
+        .map(|(kind, pauses)| format!("{} {}", pauses.len(), kind.trim_start_matches("Pause ")))
+        .collect();
+    println!("Pauses: {} ({})", all.len(), kinds.join(", "));
+    let share = if summary.uptime > 0.0 {
+        format!(" ({:.2}% of the time)", total / 10.0 / summary.uptime)
+    } else {
+        String::new()
+    };
+    println!("  Total {}{}", millis(total), share);
+    println!(
+        "  Average {}, 99th percentile {}, longest {}",
+        millis(total / all.len() as f64),
+        millis(all[(all.len() - 1) * 99 / 100]),
+        millis(all[all.len() - 1])
+    );
+
+    let max_heap = summary.max_heap.or(summary.capacity);
+    let Some(&latest) = summary.heap_after.last() else {
+        return Ok(());
+    };
+    let lowest = summary.heap_after.iter().copied().min().unwrap_or(latest);
+    let of_max = max_heap.map(|max| format!(" (maximum {})", format_size(max))).unwrap_or_default();
+    println!(
+        "Heap after collections: {} now, lowest {}{}",
+        format_size(latest),
+        format_size(lowest),
+        of_max
+    );
+    let trend = summary.trend();
+    if let Some((first, last)) = trend {
+        println!(
+            "  Average {} early in the run, {} lately",
+            format_size(first),
+            format_size(last)
+        );
+    }
+
+    let mut advice = Vec::new();
+    let full = summary.pauses.get("Pause Full").map_or(0, Vec::len);
+    if full > 0 {
+        advice.push(format!(
+            "{} full collection(s): the heap ran out; give it more memory or find what fills it",
+            full
+        ));
+    }
+    if let Some(max) = max_heap {
+        let used = trend.map_or(latest, |(_, last)| last) as f64 / max as f64;
+        if used > TOO_SMALL {
+            advice.push(format!(
+                "The heap is still {:.0}% full after collections: it is too small",
+                used * 100.0
+            ));
+        } else if used < TOO_LARGE && full == 0 {
+            advice.push(format!(
+                "Only {:.0}% of the heap is in use after collections: it could be smaller",
+                used * 100.0
+            ));
+        }
+    }
+    let growing = trend.filter(|(first, last)| *last as f64 > *first as f64 * GROWING);
+    if let Some((first, last)) = growing {
+        advice.push(format!(
+            "The heap in use grew from {} to {}: expect it to fill up if that continues",
+            format_size(first),
+            format_size(last)
+        ));
+    }
+    if advice.is_empty() {
+        advice.push("The heap size looks right for this load".to_string());
+    }
+    println!("Heap size:");
+    for line in advice {
+        println!("  {}", line);
+    }
+    Ok(())
+}
+
+/// A pause length, e.g. "12.3 ms" or "1.20 s"
+fn millis(ms: f64) -> String {
+    if ms >= 1000.0 {
+        format!("{:.2} s", ms / 1000.0)
+    } else {
+        format!("{:.1} ms", ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1 << 20;
+
+    const G1: &str = include_str!("../../tests/fixtures/gc-g1.log");
+
+    const ZGC: &str = include_str!("../../tests/fixtures/gc-zgc.log");
+
+    #[test]
+    fn g1() {
+        let summary = Summary::parse(G1);
+        assert_eq!(summary.collector.as_deref(), Some("G1"));
+        assert_eq!(summary.max_heap, Some(4096 * MB));
+        let kinds: Vec<&str> = summary.pauses.keys().map(String::as_str).collect();
+        assert_eq!(kinds, ["Pause Cleanup", "Pause Remark", "Pause Young"]);
+        assert_eq!(summary.pauses["Pause Young"], [12.345, 8.0]);
+        assert_eq!(summary.heap_after, [256 * MB, 300 * MB, 305 * MB, 305 * MB]);
+        assert_eq!(summary.capacity, Some(2048 * MB));
+        assert_eq!(summary.uptime, 61.0);
+        // Too few collections to tell
+        assert_eq!(summary.trend(), None);
+    }
+
+    #[test]
+    fn zgc() {
+        let summary = Summary::parse(ZGC);
+        assert_eq!(summary.collector.as_deref(), Some("The Z Garbage Collector"));
+        assert_eq!(summary.max_heap, Some(4096 * MB));
+        assert_eq!(summary.pauses["Pause Mark Start"], [0.012, 0.016]);
+        assert_eq!(summary.pauses["Pause Mark End"], [0.020]);
+        assert_eq!(summary.pauses["Pause Relocate Start"], [0.008]);
+        assert_eq!(summary.heap_after, [78 * MB, 128 * MB, 200 * MB]);
+        assert_eq!(summary.capacity, None);
+        assert_eq!(summary.uptime, 20.0);
+    }
+
+    #[test]
+    fn trend() {
+        let pause =
+            |i| format!("[{}.0s][info][gc] GC({}) Pause Full 2G->{}M(4G) 1ms\n", i, i, i * 100);
+        let pauses: String = (1..=8).map(pause).collect();
+        assert_eq!(Summary::parse(&pauses).trend(), Some((150 * MB, 750 * MB)));
+    }
+
+    #[test]
+    fn unparseable() {
+        let summary = Summary::parse(
+            "\
+not a gc log
+\0\u{fffd}binary
+[abc s][info][gc] Using
+[1.0s][info][gc] GC(0) Pause Young (Normal) 1024M->256M(4096M) 1.2.3ms
+[2.0s][info][gc] GC(1) Pause Young (Normal) 99999999999G->1M(99999999999G) 5.000ms
+[3.0s][info][gc] GC(2) Garbage Collection (Warmup) 1M(1%)->99999999999G(1%)
+[4.0s][info][gc,init] Heap Max Capacity: 99999999999G
+[5.0s][info][gc] GC(3) Pause Young 10M->5M
+",
+        );
+        assert_eq!(summary.collector, None);
+        assert_eq!(summary.max_heap, None);
+        // Only the pause with a readable length counts, and only sizes that fit in a u64
+        assert_eq!(summary.pauses["Pause Young"], [5.0]);
+        assert_eq!(summary.heap_after, [MB]);
+        assert_eq!(summary.capacity, None);
+        assert_eq!(summary.uptime, 5.0);
+
+        let summary = Summary::parse("");
+        assert!(summary.pauses.is_empty());
+        assert!(summary.heap_after.is_empty());
+        assert_eq!(summary.uptime, 0.0);
+    }
+}
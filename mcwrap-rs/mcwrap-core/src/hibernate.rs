@@ -0,0 +1,306 @@
+//! Hibernation: stopping idle servers and waking them on connection
+//!
+//! When a server's `[hibernate]` config sets `idle_mins`, mcwrapd stops it
+//! after nobody has been online for that long and listens on its port in its
+//! place. The server list shows a "sleeping" status, and a player who tries to
+//! join is told to come back shortly while the real server boots.
+
+use anyhow::{bail, Context, Result};
+use crate::config::HibernateConfig;
+use crate::properties::Properties;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+pub const DEFAULT_PORT: u16 = 25565;
+const DEFAULT_MOTD: &str = "Sleeping - join to wake the server up";
+const DEFAULT_MESSAGE: &str = "Starting, try again in 30s";
+
+/// Largest packet accepted from a client; handshakes are far smaller
+const MAX_PACKET: usize = 4096;
+
+/// Time a client gets to finish its exchange
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Hold the server's port until a player tries to join
+pub async fn sleep_until_join(server_dir: &Path, config: &HibernateConfig) -> Result<()> {
+    let addr = server_address(server_dir)?;
+    let listener = bind(addr).await?;
+    let motd = Arc::new(config.motd.clone().unwrap_or_else(|| DEFAULT_MOTD.to_string()));
+    let message = Arc::new(config.message.clone().unwrap_or_else(|| DEFAULT_MESSAGE.to_string()));
+    let joined = Arc::new(Notify::new());
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, _)) = accepted else {
+                    continue;
+                };
+                let (motd, message, joined) = (motd.clone(), message.clone(), joined.clone());
+                tokio::spawn(async move {
+                    let answered = answer(stream, &motd, &message);
+                    if let Ok(Ok(true)) = tokio::time::timeout(CLIENT_TIMEOUT, answered).await {
+                        joined.notify_one();
+                    }
+                });
+            }
+            _ = joined.notified() => return Ok(()),
+        }
+    }
+}
+
+/// Bind the port, allowing a little time for the server to release it
+async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    for _ in 0..10 {
+        if let Ok(listener) = TcpListener::bind(addr).await {
+            return Ok(listener);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))
+}
+
+/// Address the server listens on, from `server.properties`
+fn server_address(server_dir: &Path) -> Result<SocketAddr> {
+    let properties = Properties::load(server_dir)?;
+    let port = properties
+        .get("server-port")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(DEFAULT_PORT);
+    let ip = properties
+        .get("server-ip")
+        .and_then(|ip| ip.parse().ok())
+        .unwrap_or([0, 0, 0, 0].into());
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// What a client said in its handshake
+#[derive(Debug, PartialEq)]
+struct Handshake {
+    protocol: i32,
+    /// 1 for a server list ping, 2 to log in, 3 for a transfer
+    next_state: i32,
+}
+
+/// Answer one client, returning whether it tried to log in
+async fn answer(mut stream: TcpStream, motd: &str, message: &str) -> Result<bool> {
+    let (id, handshake) = read_packet(&mut stream).await?;
+    if id != 0 {
+        bail!("Expected a handshake");
+    }
+    let Handshake { protocol, next_state } = parse_handshake(&handshake)?;
+
+    match next_state {
+        // Server list ping
+        1 => {
+            read_packet(&mut stream).await?;
+            let status = json!({
+                // The client's own protocol, so the entry isn't shown as incompatible
+                "version": { "name": "Sleeping", "protocol": protocol },
+                "players": { "max": 0, "online": 0 },
+                "description": { "text": motd },
+            });
+            write_packet(&mut stream, 0, &string(&status.to_string())).await?;
+            if let Ok((1, payload)) = read_packet(&mut stream).await {
+                write_packet(&mut stream, 1, &payload).await?;
+            }
+            Ok(false)
+        }
+        // Login (or a transfer from another server)
+        2 | 3 => {
+            let disconnect = json!({ "text": message });
+            write_packet(&mut stream, 0, &string(&disconnect.to_string())).await?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// The body of a handshake packet: protocol, address, port and next state
+fn parse_handshake(mut body: &[u8]) -> Result<Handshake> {
+    let protocol = take_varint(&mut body)?;
+    take_string(&mut body)?;
+    // Port the client connected to
+    body = body.get(2..).context("Truncated handshake")?;
+    let next_state = take_varint(&mut body)?;
+    Ok(Handshake { protocol, next_state })
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(stream: &mut R) -> Result<(i32, Vec<u8>)> {
+    let mut length = 0u32;
+    for i in 0..5 {
+        let byte = stream.read_u8().await?;
+        length |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            break;
+        } else if i == 4 {
+            bail!("VarInt too long");
+        }
+    }
+    let length = length as usize;
+    if length == 0 || length > MAX_PACKET {
+        bail!("Invalid packet length {}", length);
+    }
+    let mut packet = vec![0; length];
+    stream.read_exact(&mut packet).await?;
+
+    let mut body = packet.as_slice();
+    let id = take_varint(&mut body)?;
+    Ok((id, body.to_vec()))
+}
+
+async fn write_packet(stream: &mut TcpStream, id: i32, body: &[u8]) -> Result<()> {
+    let mut packet = Vec::new();
+    put_varint(&mut packet, id);
+    packet.extend_from_slice(body);
+    let mut out = Vec::new();
+    put_varint(&mut out, packet.len() as i32);
+    out.extend(packet);
+    stream.write_all(&out).await?;
+    Ok(())
+}
+
+fn take_varint(body: &mut &[u8]) -> Result<i32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        let (&byte, rest) = body.split_first().context("Truncated packet")?;
+        *body = rest;
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    bail!("VarInt too long")
+}
+
+fn take_string(body: &mut &[u8]) -> Result<String> {
+    let length = take_varint(body)? as usize;
+    if length > body.len() {
+        bail!("Truncated packet");
+    }
+    let (text, rest) = body.split_at(length);
+    *body = rest;
+    Ok(String::from_utf8_lossy(text).into_owned())
+}
+
+fn put_varint(out: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value < 0x80 {
+            out.push(value as u8);
+            return;
+        }
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+/// A protocol string: length-prefixed UTF-8
+fn string(text: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    put_varint(&mut out, text.len() as i32);
+    out.extend_from_slice(text.as_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(protocol: i32, address: &str, port: u16, next_state: i32) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_varint(&mut body, protocol);
+        body.extend(string(address));
+        body.extend(port.to_be_bytes());
+        put_varint(&mut body, next_state);
+        body
+    }
+
+    async fn read(packet: &[u8]) -> Result<(i32, Vec<u8>)> {
+        read_packet(&mut &packet[..]).await
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 127, 128, 300, 25565, i32::MAX, -1, i32::MIN] {
+            let mut out = Vec::new();
+            put_varint(&mut out, value);
+            let mut body = out.as_slice();
+            assert_eq!(take_varint(&mut body).unwrap(), value);
+            assert!(body.is_empty());
+        }
+        assert_eq!(take_varint(&mut &[0xff, 0x01][..]).unwrap(), 255);
+    }
+
+    #[test]
+    fn truncated_varints() {
+        assert!(take_varint(&mut &[][..]).is_err());
+        assert!(take_varint(&mut &[0x80][..]).is_err());
+        assert!(take_varint(&mut &[0xff, 0xff, 0xff, 0xff][..]).is_err());
+    }
+
+    #[test]
+    fn oversized_varints() {
+        let err = take_varint(&mut &[0xff; 5][..]).unwrap_err();
+        assert_eq!(err.to_string(), "VarInt too long");
+        assert!(take_varint(&mut &[0x80, 0x80, 0x80, 0x80, 0x80, 0x01][..]).is_err());
+    }
+
+    #[test]
+    fn handshakes() {
+        let body = handshake(767, "mc.example.com", 25565, 2);
+        let expected = Handshake {
+            protocol: 767,
+            next_state: 2,
+        };
+        assert_eq!(parse_handshake(&body).unwrap(), expected);
+    }
+
+    #[test]
+    fn malformed_handshakes() {
+        let body = handshake(767, "mc.example.com", 25565, 1);
+        // Cut short anywhere, including inside the address and the port
+        for end in 0..body.len() {
+            assert!(parse_handshake(&body[..end]).is_err(), "{} bytes", end);
+        }
+        // An address longer than the packet, or of negative length
+        let mut body = Vec::new();
+        put_varint(&mut body, 767);
+        put_varint(&mut body, 1000);
+        body.extend(b"short");
+        assert!(parse_handshake(&body).is_err());
+        let mut body = Vec::new();
+        put_varint(&mut body, 767);
+        put_varint(&mut body, -1);
+        assert!(parse_handshake(&body).is_err());
+        assert!(parse_handshake(&[0xff; 5]).is_err());
+    }
+
+    #[tokio::test]
+    async fn packets() {
+        let mut packet = Vec::new();
+        put_varint(&mut packet, 4);
+        packet.extend([0x00, 0xaa, 0xbb, 0xcc]);
+        assert_eq!(read(&packet).await.unwrap(), (0, vec![0xaa, 0xbb, 0xcc]));
+    }
+
+    #[tokio::test]
+    async fn malformed_packets() {
+        // Empty, oversized, cut short, or with a length that never ends
+        assert!(read(&[]).await.is_err());
+        assert!(read(&[0x00]).await.is_err());
+        assert!(read(&[0x80, 0x40]).await.is_err());
+        assert!(read(&[0x05, 0x00, 0x01]).await.is_err());
+        assert!(read(&[0xff; 5]).await.is_err());
+        assert!(read(&[0xff; 8]).await.is_err());
+        // A packet id that is itself a truncated VarInt
+        assert!(read(&[0x01, 0x80]).await.is_err());
+    }
+}
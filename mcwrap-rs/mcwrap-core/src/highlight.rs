@@ -0,0 +1,85 @@
+//! Colors for warnings and errors in `attach` and `tail`
+//!
+//! Paper and most plugins color their own output, but vanilla servers and
+//! many mods print plain text. `attach` and `tail` color the lines that have
+//! no colors of their own: yellow for warnings, red for errors and stack
+//! traces. `--highlight <regex>` (which may be repeated) shows what a pattern
+//! matches in reverse video, on any line. Output that reaches the terminal a
+//! piece of a line at a time, such as a prompt, is shown as it is.
+
+use anyhow::{Context, Result};
+use crate::console::strip_ansi;
+use crate::records;
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// A warning, e.g. `[12:00:00 WARN]:` or `[Server thread/WARN]:`
+static WARN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(?:WARN|WARNING)\b").unwrap());
+
+/// An error, e.g. `[12:00:00 ERROR]:` or Forge's `SEVERE`
+static ERROR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(?:ERROR|SEVERE|FATAL)\b").unwrap());
+
+const YELLOW: &str = "33";
+const RED: &str = "31";
+
+pub struct Highlighter {
+    patterns: Vec<Regex>,
+    line_start: bool,
+}
+
+impl Highlighter {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).with_context(|| format!("Invalid --highlight {:?}", pattern))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            patterns,
+            line_start: true,
+        })
+    }
+
+    /// Color console output, which may end mid-line
+    pub fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(data.len());
+        for piece in data.split_inclusive(|&byte| byte == b'\n') {
+            let whole = self.line_start && piece.ends_with(b"\n");
+            match std::str::from_utf8(piece) {
+                Ok(line) if whole => result.extend_from_slice(self.line(line).as_bytes()),
+                _ => result.extend_from_slice(piece),
+            }
+            self.line_start = piece.ends_with(b"\n");
+        }
+        result
+    }
+
+    /// Color one line, with or without its line ending
+    pub fn line(&self, line: &str) -> String {
+        let body = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[body.len()..];
+        let plain = strip_ansi(body);
+        let flagged = self.patterns.iter().any(|pattern| pattern.is_match(&plain));
+        // The server's own colors win, unless a pattern has to be shown
+        if plain.len() != body.len() && !flagged {
+            return line.to_string();
+        }
+        let color = if ERROR.is_match(&plain) || records::is_trace(&plain) {
+            Some(RED)
+        } else if WARN.is_match(&plain) {
+            Some(YELLOW)
+        } else {
+            None
+        };
+        let mut text = plain;
+        for pattern in &self.patterns {
+            text = pattern.replace_all(&text, "\x1b[7m${0}\x1b[27m").into_owned();
+        }
+        match color {
+            Some(color) => format!("\x1b[{}m{}\x1b[0m{}", color, text, ending),
+            None => format!("{}{}", text, ending),
+        }
+    }
+}
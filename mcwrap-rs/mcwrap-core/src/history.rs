@@ -0,0 +1,192 @@
+//! Per-server command history
+//!
+//! Commands sent with `send` or typed into an attached console are appended
+//! to `history` in the wrap dir, which survives restarts. `mcwrap history`
+//! lists and re-sends entries, and interactive attach sessions recall them
+//! with the up and down arrows.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write as IoWrite;
+use std::path::Path;
+
+/// Most entries kept when loading history
+pub const MAX_ENTRIES: usize = 1000;
+
+/// Append a command to the history file
+pub fn append(path: &Path, command: &str) -> Result<()> {
+    let command = command.trim();
+    if command.is_empty() || command.contains('\n') {
+        return Ok(());
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", command))
+        .context("Failed to write history")
+}
+
+/// Load history, oldest first, without consecutive duplicates
+pub fn load(path: &Path) -> Vec<String> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let mut entries: Vec<String> = Vec::new();
+    for line in content.lines().filter(|l| !l.is_empty()) {
+        if entries.last().map(String::as_str) != Some(line) {
+            entries.push(line.to_string());
+        }
+    }
+    let start = entries.len().saturating_sub(MAX_ENTRIES);
+    entries.split_off(start)
+}
+
+/// Reassembles lines typed into an attached console from raw keystrokes
+///
+/// Backspace and Ctrl+U are applied and escape sequences (arrow keys etc.)
+/// dropped, so lines edited with the server's own history are approximate.
+#[derive(Default)]
+pub struct LineBuffer {
+    line: Vec<u8>,
+    escape: Escape,
+}
+
+#[derive(Default, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    /// After ESC
+    Start,
+    /// Inside a CSI sequence
+    Csi,
+}
+
+impl LineBuffer {
+    /// Feed keystrokes, returning any lines completed by Enter
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            match self.escape {
+                Escape::Start => {
+                    self.escape = if byte == b'[' { Escape::Csi } else { Escape::None };
+                    continue;
+                }
+                Escape::Csi => {
+                    if (0x40..=0x7e).contains(&byte) {
+                        self.escape = Escape::None;
+                    }
+                    continue;
+                }
+                Escape::None => {}
+            }
+
+            match byte {
+                b'\r' | b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).trim().to_string();
+                    if !line.is_empty() {
+                        lines.push(line);
+                    }
+                    self.line.clear();
+                }
+                0x7f | 0x08 => {
+                    self.line.pop();
+                }
+                0x15 => self.line.clear(),
+                0x1b => self.escape = Escape::Start,
+                b if b < 0x20 => {}
+                b => self.line.push(b),
+            }
+        }
+        lines
+    }
+
+    /// Whether nothing has been typed on the current line
+    pub fn is_empty(&self) -> bool {
+        self.line.is_empty()
+    }
+
+    /// Replace the current line (e.g. after recalling history)
+    pub fn set(&mut self, line: &str) {
+        self.line = line.as_bytes().to_vec();
+        self.escape = Escape::None;
+    }
+}
+
+/// Up/down arrow history recall for a raw attach session
+///
+/// Arrows are only intercepted while the current line is empty or showing a
+/// recalled entry; otherwise they go to the server's own line editor.
+pub struct Recall {
+    entries: Vec<String>,
+    /// Index of the entry currently shown
+    pos: Option<usize>,
+    line: LineBuffer,
+}
+
+impl Recall {
+    pub fn new(entries: Vec<String>) -> Self {
+        Self {
+            entries,
+            pos: None,
+            line: LineBuffer::default(),
+        }
+    }
+
+    /// Translate a chunk of keystrokes into what should be sent to the server
+    pub fn filter(&mut self, input: &[u8]) -> Vec<u8> {
+        let up = matches!(input, b"\x1b[A" | b"\x1bOA");
+        let down = matches!(input, b"\x1b[B" | b"\x1bOB");
+        let recalling = self.pos.is_some() || self.line.is_empty();
+
+        if (up || down) && recalling && !self.entries.is_empty() {
+            let last = self.entries.len() - 1;
+            self.pos = match (self.pos, up) {
+                (None, true) => Some(last),
+                (Some(pos), true) => Some(pos.saturating_sub(1)),
+                (Some(pos), false) if pos < last => Some(pos + 1),
+                (_, false) => None,
+            };
+            // Ctrl+U clears the server's line before the entry is typed in
+            let mut out = vec![0x15];
+            if let Some(pos) = self.pos {
+                out.extend_from_slice(self.entries[pos].as_bytes());
+            }
+            return out;
+        }
+
+        // Any other key edits the recalled entry as if it had been typed
+        if let Some(pos) = self.pos.take() {
+            self.line.set(&self.entries[pos]);
+        }
+        for line in self.line.push(input) {
+            if self.entries.last() != Some(&line) {
+                self.entries.push(line);
+            }
+        }
+        input.to_vec()
+    }
+}
+
+/// List recent history, or re-send entry `run`
+pub async fn cmd_history(server_dir: &Path, lines: usize, run: Option<usize>) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = crate::ServerPaths::new(&server_dir);
+    let entries = load(&paths.history_file);
+
+    if let Some(n) = run {
+        let Some(command) = n.checked_sub(1).and_then(|i| entries.get(i)) else {
+            bail!("No history entry {}", n);
+        };
+        println!("{}", command);
+        return crate::cmd_send(&server_dir, command).await;
+    }
+
+    if entries.is_empty() {
+        println!("No history yet.");
+    }
+    let start = entries.len().saturating_sub(lines);
+    for (i, command) in entries.iter().enumerate().skip(start) {
+        println!("{:>5}  {}", i + 1, command);
+    }
+
+    Ok(())
+}
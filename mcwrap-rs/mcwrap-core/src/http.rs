@@ -0,0 +1,116 @@
+//! HTTP through curl
+//!
+//! Webhooks, log shipping and downloads (`init`, `upgrade`, `pregen`) run
+//! curl rather than carrying an HTTP client and TLS stack of their own.
+//! Failed requests, HTTP errors included, come back as curl's message.
+//!
+//! URLs and headers of posts can hold secrets, such as a Telegram bot token
+//! or an `Authorization` header, so they reach curl through its config on
+//! stdin rather than its command line, where any user could read them with
+//! `ps`. The body then goes through a file under `~/.mcwrap` that only its
+//! owner can read.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Longest a service gets to accept a POST
+const POST_TIMEOUT_SECS: &str = "10";
+
+/// Fail on HTTP errors and print nothing but the error
+const QUIET: [&str; 3] = ["--fail", "--silent", "--show-error"];
+
+/// POST `body`, returning the response
+pub fn post(url: &str, content_type: &str, body: &[u8]) -> Result<Vec<u8>> {
+    post_with_headers(url, content_type, &[], body)
+}
+
+/// POST `body` with extra `Name: value` headers, returning the response
+pub fn post_with_headers(
+    url: &str,
+    content_type: &str,
+    headers: &[String],
+    body: &[u8],
+) -> Result<Vec<u8>> {
+    let mut curl = Command::new("curl");
+    curl.args(QUIET)
+        .args(["--max-time", POST_TIMEOUT_SECS])
+        .args(["--header", &format!("Content-Type: {}", content_type)]);
+    let body = BodyFile::write(body)?;
+    let mut config = config_line("url", url);
+    for header in headers {
+        config.push_str(&config_line("header", header));
+    }
+    config.push_str(&config_line("data-binary", &format!("@{}", body.0.display())));
+    let mut curl = curl
+        .args(["--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl")?;
+    curl.stdin.take().unwrap().write_all(config.as_bytes())?;
+    response(curl.wait_with_output()?)
+}
+
+/// An option for curl's config, with its value quoted
+fn config_line(name: &str, value: &str) -> String {
+    let value = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("{} = \"{}\"\n", name, value)
+}
+
+/// A request body in a file under `~/.mcwrap`, removed on drop
+struct BodyFile(PathBuf);
+
+impl BodyFile {
+    fn write(body: &[u8]) -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let next = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = crate::wrap_base();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("post-{}-{}", process::id(), next));
+        let _ = fs::remove_file(&path);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .with_context(|| format!("Failed to create {:?}", path))?;
+        let body_file = Self(path);
+        file.write_all(body)?;
+        Ok(body_file)
+    }
+}
+
+impl Drop for BodyFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Fetch with curl, following redirects, returning what it wrote to stdout
+pub async fn get(args: &[&str]) -> Result<Vec<u8>> {
+    let output = tokio::process::Command::new("curl")
+        .args(QUIET)
+        .arg("--location")
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run curl")?;
+    response(output).map_err(|e| anyhow!("Download failed: {}", e))
+}
+
+fn response(output: Output) -> Result<Vec<u8>> {
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,134 @@
+//! The icon shown next to a server in the multiplayer list
+//!
+//! The client only shows a `server-icon.png` that is a PNG of exactly 64x64
+//! pixels, and the server reads it once at startup. `mcwrap icon set <dir>
+//! image` installs a PNG, JPEG, GIF or WebP image as the icon, converting
+//! and resizing it when it isn't one already (non-square images are cropped
+//! to their center); `mcwrap icon show <dir>` checks the one in place.
+
+use anyhow::{bail, Context, Result};
+use crate::{is_running, ServerPaths};
+use clap::Subcommand;
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const ICON_FILE: &str = "server-icon.png";
+
+/// Width and height the client requires
+const ICON_SIZE: u32 = 64;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Subcommand)]
+pub enum IconAction {
+    /// Install an image as the server icon, resizing it to 64x64
+    Set {
+        /// Server directory
+        dir: PathBuf,
+        /// Image file (PNG, JPEG, GIF or WebP)
+        image: PathBuf,
+    },
+    /// Show the server icon in place and whether the client will show it
+    Show {
+        /// Server directory
+        dir: PathBuf,
+    },
+}
+
+pub fn cmd_icon(action: IconAction) -> Result<()> {
+    match action {
+        IconAction::Set { dir, image } => set(&dir, &image),
+        IconAction::Show { dir } => show(&dir),
+    }
+}
+
+fn set(server_dir: &Path, image: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    if !image.is_file() {
+        bail!("{} is not a file", image.display());
+    }
+    let icon = server_dir.join(ICON_FILE);
+    // Written next to the icon first, so that a failed conversion leaves the old one
+    let staged = server_dir.join(format!("{}.new", ICON_FILE));
+
+    let original = png_size(image);
+    match original {
+        Some((ICON_SIZE, ICON_SIZE)) => {
+            fs::copy(image, &staged).with_context(|| format!("Failed to copy {:?}", image))?;
+        }
+        _ => {
+            if let Err(e) = resize(image, &staged) {
+                let _ = fs::remove_file(&staged);
+                return Err(e);
+            }
+        }
+    }
+    if png_size(&staged) != Some((ICON_SIZE, ICON_SIZE)) {
+        let _ = fs::remove_file(&staged);
+        bail!("Converting {} didn't give a 64x64 PNG", image.display());
+    }
+    fs::rename(&staged, &icon).with_context(|| format!("Failed to write {:?}", icon))?;
+
+    match original {
+        Some((ICON_SIZE, ICON_SIZE)) => println!("Installed {}", icon.display()),
+        Some((width, height)) => {
+            println!("Installed {} (resized from {}x{})", icon.display(), width, height);
+        }
+        None => println!("Installed {} (converted to a 64x64 PNG)", icon.display()),
+    }
+    if is_running(&ServerPaths::new(&server_dir)).is_some() {
+        println!("The server reads its icon at startup; it shows after the next restart.");
+    }
+    Ok(())
+}
+
+fn show(server_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let icon = server_dir.join(ICON_FILE);
+    let Ok(metadata) = fs::metadata(&icon) else {
+        println!("No server icon (set one with: mcwrap icon set {} <image>)", server_dir.display());
+        return Ok(());
+    };
+    let size = crate::disk::format_size(metadata.len());
+    println!("{}", icon.display());
+    match png_size(&icon) {
+        Some((ICON_SIZE, ICON_SIZE)) => println!("  64x64 PNG, {}", size),
+        Some((width, height)) => {
+            println!("  {}x{} PNG, {}", width, height, size);
+            println!("  The client only shows 64x64 icons; fix it with: mcwrap icon set");
+        }
+        None => {
+            println!("  Not a PNG file ({})", size);
+            println!("  The client only shows 64x64 PNG icons; fix it with: mcwrap icon set");
+        }
+    }
+    Ok(())
+}
+
+/// Width and height of a PNG image, from its header
+fn png_size(path: &Path) -> Option<(u32, u32)> {
+    let mut header = [0u8; 24];
+    File::open(path).ok()?.read_exact(&mut header).ok()?;
+    // The signature, then the IHDR chunk's length and type, then width and height
+    if &header[..8] != PNG_SIGNATURE || &header[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(header[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(header[20..24].try_into().ok()?);
+    Some((width, height))
+}
+
+/// Convert an image to a 64x64 PNG
+fn resize(image: &Path, dest: &Path) -> Result<()> {
+    let decoded = ImageReader::open(image)
+        .and_then(|reader| reader.with_guessed_format())
+        .with_context(|| format!("Failed to read {}", image.display()))?
+        .decode()
+        .with_context(|| format!("Failed to decode {}", image.display()))?;
+    let icon = decoded.resize_to_fill(ICON_SIZE, ICON_SIZE, FilterType::Lanczos3);
+    icon.save_with_format(dest, ImageFormat::Png)
+        .with_context(|| format!("Failed to write {:?}", dest))
+}
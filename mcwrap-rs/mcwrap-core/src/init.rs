@@ -0,0 +1,449 @@
+//! Setting up a new server
+//!
+//! `mcwrap init <dir>` turns an empty (or missing) directory into a server:
+//! it asks for the server type (Paper, Fabric or vanilla), the Minecraft
+//! version, the heap size and the port, has the EULA accepted, downloads the
+//! server JAR and writes `eula.txt`, `server.properties` and a `mcwrap.toml`
+//! with the heap as `[java] memory`, then offers to start the server.
+//!
+//! Every question has a flag (`--type`, `--version`...), and without a
+//! terminal or with `--yes` the unanswered ones take their defaults, except
+//! for the EULA, which must be accepted with `--accept-eula`. With
+//! `--template`, the answers come from a template (see `templates`).
+
+use anyhow::{bail, Context, Result};
+use crate::hibernate::DEFAULT_PORT;
+use crate::http;
+use crate::ports::PortChoice;
+use crate::templates::{copy_tree, Template};
+use crate::upgrade::{download_paper, is_jar};
+use crate::{cmd_start, StartOptions};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+/// Where the Minecraft EULA can be read
+const EULA_URL: &str = "https://aka.ms/MinecraftEULA";
+
+/// Minecraft versions with Paper builds, from the PaperMC API
+const PAPER_PROJECT: &str = "https://api.papermc.io/v2/projects/paper";
+
+/// Game versions, loaders and installers, from Fabric's meta API
+const FABRIC_META: &str = "https://meta.fabricmc.net/v2/versions";
+
+/// Every vanilla release, from Mojang
+const VANILLA_MANIFEST: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+
+const DEFAULT_MEMORY: &str = "4G";
+
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerType {
+    Paper,
+    Fabric,
+    Vanilla,
+}
+
+/// Options for `mcwrap init`; what is left out is asked for
+pub struct InitOptions {
+    pub server_type: Option<ServerType>,
+    /// Minecraft version (default: the latest release)
+    pub version: Option<String>,
+    /// Heap size, e.g. "4G"
+    pub memory: Option<String>,
+    /// Port, or `auto` for the next free one
+    pub port: Option<PortChoice>,
+    pub accept_eula: bool,
+    /// Template in `~/.config/mcwrap/templates` to take the answers from
+    pub template: Option<String>,
+    /// Start the server once it is set up
+    pub start: bool,
+    /// Take the defaults instead of asking
+    pub yes: bool,
+}
+
+impl ServerType {
+    fn name(self) -> &'static str {
+        match self {
+            ServerType::Paper => "paper",
+            ServerType::Fabric => "fabric",
+            ServerType::Vanilla => "vanilla",
+        }
+    }
+
+    /// The name `start` finds the server JAR under
+    fn jar_name(self) -> &'static str {
+        match self {
+            ServerType::Paper => "paper.jar",
+            ServerType::Fabric => "fabric-server-launch.jar",
+            ServerType::Vanilla => "server.jar",
+        }
+    }
+
+    /// Where plugins (or mods) go
+    fn plugin_dir(self) -> &'static str {
+        match self {
+            ServerType::Fabric => "mods",
+            ServerType::Paper | ServerType::Vanilla => "plugins",
+        }
+    }
+
+    /// The newest release this type of server is available for
+    async fn latest_version(self) -> Result<String> {
+        match self {
+            ServerType::Paper => {
+                let project: PaperProject = fetch_json(PAPER_PROJECT).await?;
+                project.versions.last().cloned().context("PaperMC lists no versions")
+            }
+            ServerType::Fabric => {
+                let url = format!("{}/game", FABRIC_META);
+                let versions: Vec<FabricVersion> = fetch_json(&url).await?;
+                latest_stable(&versions).context("Fabric lists no stable game versions")
+            }
+            ServerType::Vanilla => {
+                let manifest: VanillaManifest = fetch_json(VANILLA_MANIFEST).await?;
+                Ok(manifest.latest.release)
+            }
+        }
+    }
+
+    async fn download(self, version: &str, dest: &Path) -> Result<()> {
+        match self {
+            ServerType::Paper => download_paper(version, dest).await,
+            ServerType::Fabric => download_fabric(version, dest).await,
+            ServerType::Vanilla => download_vanilla(version, dest).await,
+        }
+    }
+}
+
+pub async fn cmd_init(server_dir: &Path, opts: InitOptions) -> Result<()> {
+    for existing in ["mcwrap.toml", "server.properties"] {
+        if server_dir.join(existing).exists() {
+            bail!("{} already has a {}", server_dir.display(), existing);
+        }
+    }
+    if let Ok(jar) = crate::find_jar(server_dir) {
+        bail!("{} already has a server JAR ({})", server_dir.display(), jar.display());
+    }
+    let template = match &opts.template {
+        Some(name) => Template::load(name)?,
+        None => Template::default(),
+    };
+    let interactive = !opts.yes && std::io::stdin().is_terminal();
+    if !opts.accept_eula && !interactive {
+        bail!("Accept the Minecraft EULA ({}) with --accept-eula", EULA_URL);
+    }
+    // A template answers every question but the EULA
+    let ask_setup = interactive && opts.template.is_none();
+
+    let server_type = match opts.server_type.or(template.server_type) {
+        Some(server_type) => server_type,
+        None if ask_setup => ask_server_type()?,
+        None => ServerType::Paper,
+    };
+    let source = match &template.jar {
+        Some(jar) => Source::Jar(template.source(jar)),
+        None => Source::Download(match opts.version.or(template.version.clone()) {
+            Some(version) => version,
+            None => {
+                let latest = server_type.latest_version().await.with_context(|| {
+                    format!("Failed to look up the latest {} version", server_type.name())
+                })?;
+                if ask_setup {
+                    ask("Minecraft version", &latest)?
+                } else {
+                    latest
+                }
+            }
+        }),
+    };
+    let memory = match opts.memory.or(template.memory.clone()) {
+        Some(memory) => memory,
+        None if ask_setup => ask("Memory (heap size)", DEFAULT_MEMORY)?,
+        None => DEFAULT_MEMORY.to_string(),
+    };
+    if crate::oom::parse_size_mb(&memory).is_none_or(|mb| mb == 0) {
+        bail!("Invalid memory {:?} (expected a size like 4G or 3072M)", memory);
+    }
+    let port = match opts.port.or(template.port) {
+        Some(port) => port.resolve(server_dir)?,
+        None if ask_setup => {
+            let free = PortChoice::Auto.resolve(server_dir).unwrap_or(DEFAULT_PORT);
+            loop {
+                match ask("Port", &free.to_string())?.parse::<PortChoice>() {
+                    Ok(port) => break port.resolve(server_dir)?,
+                    Err(e) => println!("{}", e),
+                }
+            }
+        }
+        None => DEFAULT_PORT,
+    };
+    if !opts.accept_eula {
+        println!("Running a server requires accepting the Minecraft EULA: {}", EULA_URL);
+        if !confirm("Do you accept it?", false)? {
+            bail!("The EULA was not accepted; nothing was set up");
+        }
+    }
+
+    fs::create_dir_all(server_dir)
+        .with_context(|| format!("Failed to create {}", server_dir.display()))?;
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let jar = server_dir.join(server_type.jar_name());
+    let fetched = match &source {
+        Source::Jar(jar_source) => fetch(jar_source, &jar).await,
+        Source::Download(version) => server_type.download(version, &jar).await,
+    };
+    if let Err(e) = fetched {
+        let _ = fs::remove_file(&jar);
+        return Err(e.context(format!("Failed to get {} {}", server_type.name(), source)));
+    }
+    if !is_jar(&jar) {
+        let _ = fs::remove_file(&jar);
+        bail!("The server JAR is not a JAR file");
+    }
+    if !template.plugins.is_empty() {
+        let plugins = server_dir.join(server_type.plugin_dir());
+        fs::create_dir_all(&plugins)?;
+        for plugin in &template.plugins {
+            let plugin = template.source(plugin);
+            let name = plugin.rsplit('/').next().unwrap_or_default();
+            println!("Adding {}...", name);
+            fetch(&plugin, &plugins.join(name))
+                .await
+                .with_context(|| format!("Failed to get plugin {}", plugin))?;
+        }
+    }
+
+    let accepted = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    fs::write(
+        server_dir.join("eula.txt"),
+        format!("# Accepted through mcwrap init on {}\neula=true\n", accepted),
+    )?;
+    // The server fills in the rest of its properties on the first start
+    let mut properties: Vec<String> = template
+        .properties
+        .iter()
+        .filter(|(key, _)| *key != "server-port")
+        .map(|(key, value)| match value {
+            toml::Value::String(value) => format!("{}={}", key, value),
+            // Numbers and booleans, without the quotes strings would get
+            value => format!("{}={}", key, value),
+        })
+        .collect();
+    properties.push(format!("server-port={}", port));
+    fs::write(server_dir.join("server.properties"), properties.join("\n") + "\n")?;
+    let config = match &opts.template {
+        Some(name) => template_config(name, &template, &memory)?,
+        None => starter_config(&memory),
+    };
+    fs::write(server_dir.join("mcwrap.toml"), config)?;
+    if template.files.is_dir() {
+        copy_tree(&template.files, &server_dir, &|_| false)?;
+    }
+    println!(
+        "Set up {} {} in {} (port {}, {} heap)",
+        server_type.name(),
+        source,
+        server_dir.display(),
+        port,
+        memory
+    );
+
+    let start = opts.start || (interactive && confirm("Start the server now?", true)?);
+    if !start {
+        println!("Start it with: mcwrap start {}", server_dir.display());
+        return Ok(());
+    }
+    cmd_start(&server_dir, Vec::new(), StartOptions::default()).await
+}
+
+/// Where the server JAR comes from
+enum Source {
+    /// The server type's download for a Minecraft version
+    Download(String),
+    /// A template's JAR, as a path or URL
+    Jar(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Download(version) => write!(f, "{}", version),
+            Source::Jar(jar) => write!(f, "{}", jar.rsplit('/').next().unwrap_or(jar)),
+        }
+    }
+}
+
+/// Copy a local file or download a URL
+async fn fetch(source: &str, dest: &Path) -> Result<()> {
+    if source.contains("://") {
+        http::get(&["-o", &dest.to_string_lossy(), source]).await?;
+    } else {
+        fs::copy(source, dest).with_context(|| format!("Failed to copy {}", source))?;
+    }
+    Ok(())
+}
+
+/// The `mcwrap.toml` of a new server
+fn starter_config(memory: &str) -> String {
+    format!(
+        r#"# mcwrap settings for this server, layered over ~/.config/mcwrap/config.toml
+
+[java]
+memory = "{}"
+# Tuned garbage collector flags: aikar, or zgc, generational-zgc or shenandoah for large heaps
+# preset = "aikar"
+
+# Restart the server whenever it crashes (needs mcwrapd)
+# [supervisor]
+# restart = "on-failure"
+
+# Save the worlds every hour
+# schedule = [{{ cron = "@hourly", command = "save-all" }}]
+"#,
+        memory
+    )
+}
+
+/// The `mcwrap.toml` of a server set up from a template: its config, with the heap and flags
+fn template_config(name: &str, template: &Template, memory: &str) -> Result<String> {
+    let mut config = template.config.clone();
+    let java = config
+        .entry("java")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        .as_table_mut()
+        .context("Invalid [config] java in the template")?;
+    java.insert("memory".to_string(), memory.into());
+    if !template.java_flags.is_empty() {
+        java.insert("flags".to_string(), template.java_flags.clone().into());
+    }
+    let config = toml::to_string(&config).context("Invalid [config] in the template")?;
+    Ok(format!("# Set up from the {} template\n\n{}", name, config))
+}
+
+fn ask_server_type() -> Result<ServerType> {
+    loop {
+        match ask("Server type (paper, fabric, vanilla)", "paper")?.to_lowercase().as_str() {
+            "paper" => return Ok(ServerType::Paper),
+            "fabric" => return Ok(ServerType::Fabric),
+            "vanilla" => return Ok(ServerType::Vanilla),
+            _ => println!("Choose paper, fabric or vanilla"),
+        }
+    }
+}
+
+/// Ask a question on the terminal; an empty answer takes the default
+fn ask(question: &str, default: &str) -> Result<String> {
+    print!("{} [{}]: ", question, default);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        bail!("No answer to {:?}", question);
+    }
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
+}
+
+/// Ask a yes or no question on the terminal
+pub fn confirm(question: &str, default: bool) -> Result<bool> {
+    let choices = if default { "Y/n" } else { "y/N" };
+    loop {
+        match ask(question, choices)?.to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            answer if answer == choices.to_lowercase() => return Ok(default),
+            _ => println!("Answer yes or no"),
+        }
+    }
+}
+
+pub async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T> {
+    let response = http::get(&[url]).await?;
+    serde_json::from_slice(&response).with_context(|| format!("Unexpected answer from {}", url))
+}
+
+#[derive(Deserialize)]
+struct PaperProject {
+    versions: Vec<String>,
+}
+
+/// A game version, loader or installer in Fabric's meta API
+#[derive(Deserialize)]
+struct FabricVersion {
+    version: String,
+    stable: bool,
+}
+
+fn latest_stable(versions: &[FabricVersion]) -> Option<String> {
+    versions.iter().find(|v| v.stable).map(|v| v.version.clone())
+}
+
+/// Download Fabric's server launcher, with the latest stable loader and installer
+async fn download_fabric(version: &str, dest: &Path) -> Result<()> {
+    let loaders: Vec<FabricVersion> = fetch_json(&format!("{}/loader", FABRIC_META)).await?;
+    let loader = latest_stable(&loaders).context("Fabric lists no stable loader")?;
+    let installers: Vec<FabricVersion> =
+        fetch_json(&format!("{}/installer", FABRIC_META)).await?;
+    let installer = latest_stable(&installers).context("Fabric lists no stable installer")?;
+
+    println!("Downloading the Fabric server launcher (loader {})...", loader);
+    let url = format!(
+        "{}/loader/{}/{}/{}/server/jar",
+        FABRIC_META, version, loader, installer
+    );
+    http::get(&["-o", &dest.to_string_lossy(), &url]).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct VanillaManifest {
+    latest: VanillaLatest,
+    versions: Vec<VanillaVersion>,
+}
+
+#[derive(Deserialize)]
+struct VanillaLatest {
+    release: String,
+}
+
+#[derive(Deserialize)]
+struct VanillaVersion {
+    id: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct VanillaPackage {
+    downloads: VanillaDownloads,
+}
+
+#[derive(Deserialize)]
+struct VanillaDownloads {
+    server: Option<VanillaDownload>,
+}
+
+#[derive(Deserialize)]
+struct VanillaDownload {
+    url: String,
+}
+
+/// Download Mojang's server JAR for a version
+async fn download_vanilla(version: &str, dest: &Path) -> Result<()> {
+    let manifest: VanillaManifest = fetch_json(VANILLA_MANIFEST).await?;
+    let entry = manifest
+        .versions
+        .iter()
+        .find(|v| v.id == version)
+        .with_context(|| format!("No Minecraft version {}", version))?;
+    let package: VanillaPackage = fetch_json(&entry.url).await?;
+    let server = package
+        .downloads
+        .server
+        .with_context(|| format!("Minecraft {} has no server download", version))?;
+
+    println!("Downloading the Minecraft {} server...", version);
+    http::get(&["-o", &dest.to_string_lossy(), &server.url]).await?;
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Diagnostic commands sent to a server's JVM with the JDK's `jcmd`
+//!
+//! The JVM is found among the server's processes, since a start script may
+//! run it as a child. The `jcmd` next to that JVM's `java` is preferred over
+//! the one on the PATH so their versions match; a JRE has none, in which
+//! case a JDK needs to be installed. `jcmd` must run as the user the server
+//! runs as.
+
+use anyhow::{bail, Context, Result};
+use crate::failure::Failure;
+use crate::platform::{self, Process};
+use crate::{is_running, unix_now, ServerPaths};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// PID of the running server's JVM
+pub fn jvm_pid(server_dir: &Path) -> Result<i32> {
+    let Some(state) = is_running(&ServerPaths::new(server_dir)) else {
+        bail!(Failure::not_running());
+    };
+    let processes = platform::processes().context("Failed to list processes")?;
+    let java: Vec<&Process> = processes.iter().filter(|p| p.name == "java").collect();
+    if java.iter().any(|p| p.pid == state.pid) {
+        return Ok(state.pid);
+    }
+    // The server runs in its own process group, led by the process mcwrap started
+    let found = java.iter().find(|p| p.pgid == state.pid).map(|p| p.pid);
+    found.with_context(|| format!("No Java process found under PID {}", state.pid))
+}
+
+/// Run a diagnostic command (e.g. `Thread.print`) in a JVM, returning its output
+pub fn run(pid: i32, args: &[String]) -> Result<String> {
+    let output = Command::new(program(pid)).arg(pid.to_string()).args(args).output();
+    let output = match output {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            bail!("jcmd not found; it comes with a JDK, not with a JRE")
+        }
+        output => output.context("Failed to run jcmd")?,
+    };
+    // jcmd repeats the PID on the first line
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let text = stdout.split_once('\n').map_or("", |(_, rest)| rest).trim().to_string();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = if text.is_empty() { stderr.trim() } else { text.as_str() };
+        bail!("jcmd failed: {}", reason);
+    }
+    Ok(text)
+}
+
+/// Whether there is a jcmd to run against the JVM
+pub fn is_available(pid: i32) -> bool {
+    let program = program(pid);
+    program.is_absolute() || crate::find_program(&program.to_string_lossy()).is_some()
+}
+
+/// A new file in `dir` for the JVM to write to, named after the time, e.g.
+/// `threaddump-1700000000.txt`
+pub fn output_file(dir: &Path, kind: &str, extension: &str) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let now = unix_now();
+    let path = (1..)
+        .map(|n| match n {
+            1 => dir.join(format!("{}-{}.{}", kind, now, extension)),
+            n => dir.join(format!("{}-{}-{}.{}", kind, now, n, extension)),
+        })
+        .find(|path| !path.exists())
+        .unwrap();
+    Ok(path)
+}
+
+/// The jcmd of the JVM's own installation, or the one on the PATH
+fn program(pid: i32) -> PathBuf {
+    platform::executable(pid)
+        .and_then(|java| Some(java.parent()?.join("jcmd")))
+        .filter(|jcmd| jcmd.is_file())
+        .unwrap_or_else(|| PathBuf::from("jcmd"))
+}
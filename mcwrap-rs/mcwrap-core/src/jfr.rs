@@ -0,0 +1,111 @@
+//! Java Flight Recorder recordings of a running server
+//!
+//! `mcwrap jfr start <dir>` starts a recording in the server's JVM (with
+//! `--profile` in more detail, at a little more overhead, and with
+//! `--duration` ending by itself), `mcwrap jfr dump <dir>` saves what it
+//! has recorded so far while it goes on, and `mcwrap jfr stop <dir>` saves
+//! it and ends it. Recordings are saved to `jfr/` in the wrap dir, to be
+//! opened in JDK Mission Control or summarized with `jfr summary`.
+
+use anyhow::{bail, Context, Result};
+use crate::{jcmd, parse_duration, ServerPaths};
+use clap::Subcommand;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the recording mcwrap manages, so it can be found again
+const RECORDING: &str = "mcwrap";
+
+#[derive(Subcommand)]
+pub enum JfrAction {
+    /// Start recording
+    Start {
+        /// Server directory
+        dir: PathBuf,
+        /// Record with the more detailed `profile` settings
+        #[arg(long)]
+        profile: bool,
+        /// Stop and save the recording after this long
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Save what has been recorded so far, and keep recording
+    Dump {
+        /// Server directory
+        dir: PathBuf,
+    },
+    /// Save the recording and stop it
+    Stop {
+        /// Server directory
+        dir: PathBuf,
+    },
+}
+
+pub fn cmd_jfr(action: JfrAction) -> Result<()> {
+    match action {
+        JfrAction::Start {
+            dir,
+            profile,
+            duration,
+        } => start(&dir, profile, duration),
+        JfrAction::Dump { dir } => save(&dir, "JFR.dump"),
+        JfrAction::Stop { dir } => save(&dir, "JFR.stop"),
+    }
+}
+
+fn start(server_dir: &Path, profile: bool, duration: Option<Duration>) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let pid = jcmd::jvm_pid(&server_dir)?;
+    // Recording names needn't be unique, so starting twice would record twice
+    let check = jcmd::run(pid, &["JFR.check".to_string(), format!("name={}", RECORDING)]);
+    if check.is_ok_and(|check| check.contains("(running)")) {
+        bail!("A recording is already running (save it with: mcwrap jfr stop)");
+    }
+    let path = jcmd::output_file(&ServerPaths::new(&server_dir).jfr_dir, "recording", "jfr")?;
+    let settings = if profile { "profile" } else { "default" };
+    let mut args = vec![
+        "JFR.start".to_string(),
+        format!("name={}", RECORDING),
+        format!("settings={}", settings),
+    ];
+    match duration {
+        // The JVM writes the file when the time is up
+        Some(duration) => {
+            args.push(format!("duration={}s", duration.as_secs().max(1)));
+            args.push(format!("filename={}", path.display()));
+        }
+        None => args.push("disk=true".to_string()),
+    }
+    jcmd::run(pid, &args)?;
+    match duration {
+        Some(_) => println!("Recording; it will be saved to {}", path.display()),
+        None => println!(
+            "Recording; save it with: mcwrap jfr dump|stop {}",
+            server_dir.display()
+        ),
+    }
+    Ok(())
+}
+
+/// Write the recording to a new file with `JFR.dump` or `JFR.stop`
+fn save(server_dir: &Path, command: &str) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let pid = jcmd::jvm_pid(&server_dir)?;
+    let path = jcmd::output_file(&ServerPaths::new(&server_dir).jfr_dir, "recording", "jfr")?;
+    let args = [
+        command.to_string(),
+        format!("name={}", RECORDING),
+        format!("filename={}", path.display()),
+    ];
+    let output = jcmd::run(pid, &args)?;
+    if output.contains("Could not find") || output.contains("No recordings") {
+        bail!("No recording running (start one with: mcwrap jfr start)");
+    }
+    if !path.exists() {
+        bail!("The JVM didn't save the recording: {}", output);
+    }
+    let size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+    println!("Saved {} ({})", path.display(), crate::disk::format_size(size));
+    Ok(())
+}
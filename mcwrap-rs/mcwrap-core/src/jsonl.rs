@@ -0,0 +1,190 @@
+//! The console log as JSON Lines
+//!
+//! With `[log] format = "jsonl"` the daemon writes each line of console
+//! output to `console.log` as a JSON object, for tools that would rather not
+//! parse log4j headers:
+//!
+//! ```json
+//! {"ts":"2024-05-01T12:00:00.123+02:00","level":"WARN","message":"Hi","raw":"[12:00:00 WARN]: Hi"}
+//! ```
+//!
+//! `ts` is in the `[log] timezone`, `level`, `thread` and (on Forge) `logger`
+//! come from the header when there is one, and the lines of a stack trace
+//! carry those of the message they belong to. `message` is the text after the
+//! header, without colors, and `raw` the line as the server printed it. Every
+//! command that reads the log (`log`, `tail`, `attach`, `errors`, `logs`...)
+//! shows it as text again, with the timestamp in front as `[log] timestamps`
+//! would have it. Notes mcwrap adds itself, such as the watchdog's, stay
+//! plain lines.
+
+use crate::console::strip_ansi;
+use crate::records::{self, Level};
+use crate::timestamps::Zone;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A line as written to the log
+#[derive(Serialize)]
+struct Entry<'a> {
+    ts: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level: Option<Level>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logger: Option<&'a str>,
+    message: &'a str,
+    raw: &'a str,
+}
+
+/// What is read back from a line
+#[derive(Deserialize)]
+struct Logged {
+    ts: String,
+    raw: String,
+}
+
+/// The header of the latest line that had one, which a stack trace shares
+#[derive(Default)]
+struct Header {
+    level: Option<Level>,
+    thread: Option<String>,
+    logger: Option<String>,
+}
+
+/// Turns console output into JSON lines, across reads that may end mid-line
+pub struct Encoder {
+    zone: Zone,
+    /// Output after the last complete line
+    partial: Vec<u8>,
+    header: Header,
+}
+
+impl Encoder {
+    pub fn new(zone: Zone) -> Self {
+        Self {
+            zone,
+            partial: Vec::new(),
+            header: Header::default(),
+        }
+    }
+
+    /// The complete lines of `data`, one object each
+    pub fn encode(&mut self, data: &[u8]) -> Vec<u8> {
+        self.partial.extend_from_slice(data);
+        let mut result = Vec::new();
+        while let Some(end) = self.partial.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            self.line(&line, &mut result);
+        }
+        result
+    }
+
+    /// A line left without its line ending, once the server has exited
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut result = Vec::new();
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.line(&line, &mut result);
+        }
+        result
+    }
+
+    fn line(&mut self, line: &[u8], result: &mut Vec<u8>) {
+        let raw = String::from_utf8_lossy(line);
+        let raw = raw.trim_end_matches(['\r', '\n']);
+        let text = strip_ansi(raw);
+        let record = records::parse([text.as_str()]).pop();
+        let message = match record {
+            Some(record) if record.level.is_some() => {
+                self.header = Header {
+                    level: record.level,
+                    thread: record.thread,
+                    logger: record.logger,
+                };
+                record.message
+            }
+            _ => {
+                if !records::is_trace(&text) {
+                    self.header = Header::default();
+                }
+                text
+            }
+        };
+        let entry = Entry {
+            ts: self.zone.now(),
+            level: self.header.level,
+            thread: self.header.thread.as_deref(),
+            logger: self.header.logger.as_deref(),
+            message: &message,
+            raw,
+        };
+        if let Ok(json) = serde_json::to_string(&entry) {
+            result.extend_from_slice(json.as_bytes());
+            result.push(b'\n');
+        }
+    }
+}
+
+/// A log line as text: a JSON line as its timestamp and raw line, anything else as it is
+pub fn render(line: &str) -> Cow<'_, str> {
+    if !line.starts_with('{') {
+        return Cow::Borrowed(line);
+    }
+    match serde_json::from_str::<Logged>(line) {
+        Ok(logged) => Cow::Owned(format!("{} {}", logged.ts, logged.raw)),
+        Err(_) => Cow::Borrowed(line),
+    }
+}
+
+/// Read a console log as text, whichever format it was written in
+pub fn read(path: &Path) -> io::Result<String> {
+    Ok(text(String::from_utf8_lossy(&fs::read(path)?).into_owned()))
+}
+
+/// Console log content as text, whichever format it is in
+pub fn text(content: String) -> String {
+    if !content.lines().any(|line| line.starts_with('{')) {
+        return content;
+    }
+    let mut text = String::with_capacity(content.len());
+    for line in content.lines() {
+        text.push_str(&render(line));
+        text.push('\n');
+    }
+    text
+}
+
+/// Renders a console log as text while it is being written
+#[derive(Default)]
+pub struct Renderer {
+    /// The start of a JSON line, until the rest of it is written
+    partial: String,
+}
+
+impl Renderer {
+    /// Text appended to the log, as text; plain output that ends mid-line is passed on as is
+    pub fn push(&mut self, text: &str) -> String {
+        self.partial.push_str(text);
+        let mut result = String::with_capacity(self.partial.len());
+        let mut used = 0;
+        for piece in self.partial.split_inclusive('\n') {
+            if piece.starts_with('{') && !piece.ends_with('\n') {
+                break;
+            }
+            match render(piece.trim_end_matches(['\r', '\n'])) {
+                Cow::Owned(text) => {
+                    result.push_str(&text);
+                    result.push('\n');
+                }
+                Cow::Borrowed(_) => result.push_str(piece),
+            }
+            used += piece.len();
+        }
+        self.partial.drain(..used);
+        result
+    }
+}
@@ -0,0 +1,2478 @@
+//! mcwrap - Minecraft server wrapper with PTY support
+//!
+//! Provides session persistence and proper terminal emulation for
+//! interactive console features like tab completion.
+//!
+//! This crate is all of mcwrap but its command line, which the `mcwrap`
+//! binary parses and hands over to the `cmd_*` functions here. Programs that
+//! manage servers, such as a panel's backend, can link it rather than run
+//! `mcwrap` and read what it prints: `Server` starts a server or connects to
+//! one that is running, and `Client` talks to a running server's daemon.
+//!
+//! mcwrap is at home on Linux and also runs on macOS and FreeBSD (jails
+//! included), without the Linux-only cgroup limits, CPU affinity and IO
+//! priority.
+
+use anyhow::{anyhow, bail, Context, Result};
+use access::Access;
+use alerts::Alerts;
+use cgroup::Cgroup;
+use config::{Config, JavaConfig, LogFormat, QueueConfig, SlowClient};
+use control::{ControlClient, RpcFailure};
+use dedup::Dedup;
+use detach::DetachKeys;
+use failure::{ErrorKind, Failure};
+use follow::Follower;
+use forward::{Forwarder, Forwarding};
+use highlight::Highlighter;
+use log_index::Indexer;
+use notify::Notifier;
+use priority::{Priority, Scheduling};
+use progress::{Progress, Step};
+use protocol::{Credentials, Frame, FrameDecoder};
+use rcon::Rcon;
+use registry::Registry;
+use scripting::Scripts;
+use scrollback::Pager;
+use ship::{Shipper, Shipping};
+use stats::Stats;
+use timestamps::Zone;
+use triggers::Triggers;
+use watchdog::Watchdog;
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::stat::Mode;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+use nix::unistd::Pid;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, IsTerminal, Read as IoRead, Write as IoWrite};
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::signal::unix::{signal, SignalKind};
+
+pub use client::{Client, ConsoleStream, EventStream, Status};
+pub use protocol::Handshake;
+pub use server::{Server, ServerEvent, ServerHandle};
+
+mod access;
+pub mod adopt;
+mod alerts;
+pub mod audit;
+mod auth;
+pub mod boot;
+mod cgroup;
+pub mod client;
+pub mod clone;
+pub mod completions;
+mod config;
+pub mod console;
+mod control;
+pub mod daemon_log;
+mod dedup;
+pub mod destroy;
+mod detach;
+pub mod disk;
+pub mod docker;
+pub mod dump;
+pub mod errors;
+pub mod events;
+pub mod export;
+pub mod failure;
+pub mod flags;
+mod follow;
+mod forward;
+pub mod gc;
+mod hibernate;
+pub mod highlight;
+pub mod history;
+mod http;
+pub mod icon;
+pub mod init;
+mod jcmd;
+pub mod jfr;
+mod jsonl;
+mod log_index;
+pub mod logs;
+pub mod macros;
+pub mod motd;
+mod notify;
+mod oom;
+mod outbox;
+mod platform;
+mod protocol;
+pub mod players;
+pub mod playtime;
+pub mod pregen;
+pub mod ports;
+pub mod presets;
+pub mod priority;
+pub mod progress;
+mod properties;
+mod pty;
+mod queue;
+mod rcon;
+pub mod records;
+mod registry;
+pub mod remote;
+pub mod replay;
+pub mod schedule;
+mod scripting;
+mod scrollback;
+pub mod server;
+mod ship;
+pub mod ssh;
+mod startup;
+mod stats;
+pub mod supervisor;
+mod templates;
+mod timestamps;
+pub mod top;
+mod triggers;
+pub mod upgrade;
+pub mod users;
+mod watchdog;
+pub mod world;
+
+/// Options controlling how a server is started
+#[derive(Clone, Default)]
+pub struct StartOptions {
+    /// Use basic pipe mode instead of a PTY
+    pub basic: bool,
+    /// Manage the server from this process instead of a detached daemon
+    pub foreground: bool,
+    /// Unframed socket protocol for older clients
+    pub legacy_raw: bool,
+    /// Program run instead of java
+    pub exec: Option<String>,
+    /// CPU affinity and scheduling priority
+    pub priority: Priority,
+    /// Docker container to run the server in
+    pub container: docker::Container,
+    /// Detail of the PTY daemon's own log
+    pub log_level: daemon_log::Level,
+    /// Have the JVM log garbage collections
+    pub gc_log: bool,
+    /// Garbage collector preset, instead of the one in [java] preset
+    pub preset: Option<presets::Preset>,
+    /// Report the start as it is printed, or as JSON steps
+    pub progress: Progress,
+}
+
+/// Server state persisted to disk
+#[derive(Serialize, Deserialize)]
+struct ServerState {
+    pid: i32,
+    pty_master: Option<String>, // Path to PTY master (for basic mode: None)
+    started_at: u64,
+    server_dir: PathBuf,
+    /// Socket speaks the framed protocol (absent for daemons predating it)
+    #[serde(default)]
+    framed: bool,
+    /// Unix time the server was found to have exited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exited_at: Option<u64>,
+    /// Exit status, when whoever reaped the server recorded it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    /// Signal that ended the server, if one did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exit_signal: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exit_reason: Option<ExitReason>,
+    /// Started outside mcwrap and adopted, so commands go over RCON
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    adopted: bool,
+}
+
+impl ServerState {
+    /// How mcwrap talks to the server
+    fn mode(&self) -> &'static str {
+        match self.pty_master {
+            Some(_) => "PTY",
+            None if self.adopted => "adopted (RCON)",
+            None => "basic",
+        }
+    }
+
+    /// When the run ended in a crash or out of memory, if it did
+    fn crashed_at(&self) -> Option<u64> {
+        match self.exit_reason {
+            Some(ExitReason::Crashed | ExitReason::OutOfMemory) => self.exited_at,
+            _ => None,
+        }
+    }
+
+    /// Status symbol for a server that has exited
+    fn exit_symbol(&self) -> &'static str {
+        if self.crashed_at().is_some() {
+            "✗"
+        } else {
+            "○"
+        }
+    }
+
+    /// How the last run ended, e.g. "crashed (exit code 1)"
+    fn describe_exit(&self) -> String {
+        let reason = match self.exit_reason {
+            Some(ExitReason::Stopped) => "stopped",
+            Some(ExitReason::Crashed) => "crashed",
+            Some(ExitReason::OutOfMemory) => "ran out of memory",
+            Some(ExitReason::Killed) => "killed",
+            None => "exited",
+        };
+        match (&self.exit_signal, self.exit_code) {
+            (Some(signal), _) => format!("{} ({})", reason, signal),
+            (None, Some(code)) if code != 0 => format!("{} (exit code {})", reason, code),
+            _ => reason.to_string(),
+        }
+    }
+}
+
+/// How a server run ended
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ExitReason {
+    /// Exited cleanly, normally after `stop`
+    Stopped,
+    /// Exited with an error status or a fatal signal
+    Crashed,
+    /// Ran out of memory, whether the JVM noticed or the kernel killed it
+    OutOfMemory,
+    /// Terminated with SIGTERM, SIGKILL, SIGINT or SIGHUP
+    Killed,
+}
+
+/// What is known about how the server exited
+#[derive(Default)]
+struct Exit {
+    reason: Option<ExitReason>,
+    code: Option<i32>,
+    signal: Option<Signal>,
+}
+
+impl Exit {
+    /// Classify the status of a server that was reaped
+    fn reaped(code: Option<i32>, signal: Option<Signal>, out_of_memory: bool) -> Self {
+        let reason = match signal {
+            _ if out_of_memory => ExitReason::OutOfMemory,
+            Some(Signal::SIGTERM | Signal::SIGKILL | Signal::SIGINT | Signal::SIGHUP) => {
+                ExitReason::Killed
+            }
+            Some(_) => ExitReason::Crashed,
+            None if code == Some(0) => ExitReason::Stopped,
+            None => ExitReason::Crashed,
+        };
+        Self {
+            reason: Some(reason),
+            code,
+            signal,
+        }
+    }
+
+    fn is_killed(&self) -> bool {
+        self.reason == Some(ExitReason::Killed)
+    }
+
+    fn is_crash(&self) -> bool {
+        matches!(self.reason, Some(ExitReason::Crashed | ExitReason::OutOfMemory))
+    }
+
+    /// An exit caused by mcwrap itself, whose status it didn't see
+    fn because(reason: ExitReason) -> Self {
+        Self {
+            reason: Some(reason),
+            ..Default::default()
+        }
+    }
+}
+
+/// Base directory holding all mcwrap state
+fn wrap_base() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join(".mcwrap")
+}
+
+/// Get the wrap directory for a server
+fn get_wrap_dir(server_dir: &Path) -> PathBuf {
+    // Create unique ID from server path
+    let id = format!("{:x}", md5::compute(server_dir.to_string_lossy().as_bytes()));
+    let short_id = &id[..12];
+
+    wrap_base().join(short_id)
+}
+
+/// Paths for a server's state files
+struct ServerPaths {
+    wrap_dir: PathBuf,
+    state_file: PathBuf,
+    log_file: PathBuf,
+    socket_path: PathBuf,
+    control_socket: PathBuf,
+    token_file: PathBuf,
+    audit_log: PathBuf,
+    history_file: PathBuf,
+    failure_file: PathBuf,
+    dump_dir: PathBuf,
+    oom_file: PathBuf,
+    logs_dir: PathBuf,
+    lock_file: PathBuf,
+    stats_file: PathBuf,
+    disk_file: PathBuf,
+    daemon_log: PathBuf,
+    events_file: PathBuf,
+    playtime_file: PathBuf,
+    gc_log: PathBuf,
+    jfr_dir: PathBuf,
+}
+
+impl ServerPaths {
+    fn new(server_dir: &Path) -> Self {
+        let wrap_dir = get_wrap_dir(server_dir);
+        Self {
+            state_file: wrap_dir.join("state.json"),
+            log_file: wrap_dir.join("console.log"),
+            socket_path: wrap_dir.join("pty.sock"),
+            control_socket: wrap_dir.join("control.sock"),
+            token_file: wrap_dir.join("token"),
+            audit_log: wrap_dir.join("audit.log"),
+            history_file: wrap_dir.join("history"),
+            failure_file: wrap_dir.join("failure.json"),
+            dump_dir: wrap_dir.join("dumps"),
+            oom_file: wrap_dir.join("oom.json"),
+            logs_dir: wrap_dir.join("logs"),
+            lock_file: wrap_dir.join("lock"),
+            stats_file: wrap_dir.join("stats.json"),
+            disk_file: wrap_dir.join("disk.json"),
+            daemon_log: wrap_dir.join("daemon.log"),
+            events_file: wrap_dir.join("events.jsonl"),
+            playtime_file: wrap_dir.join("playtime.json"),
+            gc_log: wrap_dir.join("gc").join("gc.log"),
+            jfr_dir: wrap_dir.join("jfr"),
+            wrap_dir,
+        }
+    }
+
+    fn ensure_dir(&self) -> Result<()> {
+        fs::create_dir_all(&self.wrap_dir)?;
+        Ok(())
+    }
+
+    /// Take the lock that serializes starting and stopping the server
+    ///
+    /// Dropping it releases the lock, even if a process forked meanwhile still has the file open.
+    fn lock(&self) -> Result<Flock<File>> {
+        self.ensure_dir()?;
+        // Read access is enough for flock, and all a group member may have
+        let file = File::open(&self.lock_file)
+            .or_else(|_| File::create(&self.lock_file))
+            .context("Failed to open the lock file")?;
+        Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(|(_, errno)| match errno {
+            Errno::EWOULDBLOCK => anyhow!("Another mcwrap is starting or stopping this server"),
+            errno => anyhow!("Failed to lock {:?}: {}", self.lock_file, errno),
+        })
+    }
+
+    /// Remove runtime state, keeping files that outlive a server run
+    fn clean(&self) {
+        // The state file stays behind as the record of how the run ended
+        mark_exited(&self.state_file, Exit::default());
+        let started_at = read_state(self).map(|state| state.started_at);
+        logs::archive(&self.log_file, &self.logs_dir, started_at);
+
+        let Ok(entries) = fs::read_dir(&self.wrap_dir) else {
+            return;
+        };
+        let mut kept = false;
+        for entry in entries.flatten() {
+            if PERSISTENT_FILES.iter().any(|name| entry.file_name() == *name) {
+                kept = true;
+                continue;
+            }
+            let path = entry.path();
+            let _ = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        }
+        if !kept {
+            let _ = fs::remove_dir(&self.wrap_dir);
+        }
+    }
+}
+
+/// Files in the wrap dir that are kept when a server stops
+const PERSISTENT_FILES: &[&str] = &[
+    "state.json",
+    "stats.json",
+    "audit.log",
+    "history",
+    "failure.json",
+    "dumps",
+    "oom.json",
+    "logs",
+    "lock",
+    "disk.json",
+    "daemon.log",
+    "daemon.log.old",
+    "events.jsonl",
+    "events.jsonl.old",
+    "playtime.json",
+    "gc",
+    "jfr",
+];
+
+/// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Format a Unix time as local date and time
+fn local_time(unix: u64) -> String {
+    chrono::DateTime::from_timestamp(unix as i64, 0)
+        .map(|at| at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// What a server is called in notifications and forwarded logs when not configured: its
+/// directory name
+fn server_name(server_dir: &Path) -> String {
+    server_dir.file_name().map_or_else(
+        || server_dir.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+/// Read the saved server state without checking whether it is current
+fn read_state(paths: &ServerPaths) -> Option<ServerState> {
+    serde_json::from_reader(File::open(&paths.state_file).ok()?).ok()
+}
+
+/// Check if a server is running
+fn is_running(paths: &ServerPaths) -> Option<ServerState> {
+    let state = read_state(paths)?;
+    if state.exited_at.is_some() {
+        return None;
+    }
+
+    // Check if process is still alive
+    if kill(Pid::from_raw(state.pid), None).is_ok() {
+        Some(state)
+    } else {
+        // Keep the last run's files around until the next start or stop cleans them up
+        mark_exited(&paths.state_file, Exit::default());
+        None
+    }
+}
+
+/// Record in the state file that the server has exited
+///
+/// What is known replaces what was recorded before.
+fn mark_exited(state_file: &Path, exit: Exit) {
+    let Some(mut state) = File::open(state_file)
+        .ok()
+        .and_then(|file| serde_json::from_reader::<_, ServerState>(file).ok())
+    else {
+        return;
+    };
+    state.exited_at.get_or_insert_with(unix_now);
+    if exit.reason.is_some() {
+        state.exit_reason = exit.reason;
+    }
+    if exit.code.is_some() || exit.signal.is_some() {
+        state.exit_code = exit.code;
+        state.exit_signal = exit.signal.map(|signal| signal.as_str().to_string());
+    }
+    if let Ok(json) = serde_json::to_string(&state) {
+        let _ = fs::write(state_file, json);
+    }
+}
+
+/// Java arguments used when none are given
+fn default_java_args(jar_name: &str, java: &JavaConfig) -> Vec<String> {
+    let mut args = vec!["-Dnet.kyori.ansi.colorLevel=truecolor".to_string()];
+    args.extend(heap_args(java));
+    args.extend(java.flags.iter().cloned());
+    args.extend(["-jar".to_string(), jar_name.to_string(), "--nogui".to_string()]);
+    args
+}
+
+/// `-Xms` and `-Xmx`, from `[java] memory` or the defaults
+fn heap_args(java: &JavaConfig) -> [String; 2] {
+    match &java.memory {
+        Some(memory) => [format!("-Xms{}", memory), format!("-Xmx{}", memory)],
+        None => ["-Xms2G".to_string(), "-Xmx4G".to_string()],
+    }
+}
+
+/// Make a relative program path such as `./run.sh` relative to the server directory
+fn resolve_program(server_dir: &Path, program: &str) -> String {
+    if program.contains('/') {
+        let program = program.strip_prefix("./").unwrap_or(program);
+        server_dir.join(program).to_string_lossy().to_string()
+    } else {
+        program.to_string()
+    }
+}
+
+/// Java arguments for a Forge server when none are given
+///
+/// Like Forge's own run.sh, but with a default heap unless `user_jvm_args.txt` sets one.
+fn forge_java_args(server_dir: &Path, args_file: &str, java: &JavaConfig) -> Vec<String> {
+    let user_args = server_dir.join("user_jvm_args.txt");
+    let user_heap = fs::read_to_string(&user_args)
+        .is_ok_and(|content| content.lines().any(|line| line.trim().starts_with("-Xmx")));
+
+    let mut args = Vec::new();
+    if !user_heap {
+        args.extend(heap_args(java));
+    }
+    args.extend(java.flags.iter().cloned());
+    if user_args.exists() {
+        args.push("@user_jvm_args.txt".to_string());
+    }
+    args.push(format!("@{}", args_file));
+    args.push("--nogui".to_string());
+    args
+}
+
+static RUN_SH_ARGS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"@(libraries/\S+/unix_args\.txt)").unwrap());
+
+/// Find the `unix_args.txt` of a modern Forge or NeoForge server, which has no runnable JAR
+///
+/// Returns the path relative to the server directory. The one run.sh uses wins when
+/// several versions are installed, then the most recently installed.
+fn find_forge_args(server_dir: &Path) -> Option<String> {
+    if let Ok(run_sh) = fs::read_to_string(server_dir.join("run.sh")) {
+        let referenced = RUN_SH_ARGS.captures(&run_sh).map(|caps| caps[1].to_string());
+        if let Some(args_file) = referenced.filter(|file| server_dir.join(file).exists()) {
+            return Some(args_file);
+        }
+    }
+
+    let loaders = [
+        "libraries/net/minecraftforge/forge",
+        "libraries/net/neoforged/neoforge",
+        "libraries/net/neoforged/forge",
+    ];
+    loaders
+        .iter()
+        .filter_map(|loader| fs::read_dir(server_dir.join(loader)).ok())
+        .flatten()
+        .filter_map(|version| {
+            let path = version.ok()?.path().join("unix_args.txt");
+            let installed = path.metadata().ok()?.modified().ok()?;
+            let relative = path.strip_prefix(server_dir).ok()?.to_string_lossy().to_string();
+            Some((installed, relative))
+        })
+        .max()
+        .map(|(_, relative)| relative)
+}
+
+/// Find the server JAR file
+fn find_jar(server_dir: &Path) -> Result<PathBuf> {
+    // Look for common jar names; Fabric's launcher before the vanilla server.jar it downloads
+    let candidates = [
+        "paper.jar",
+        "fabric-server-launch.jar",
+        "server.jar",
+        "spigot.jar",
+        "bukkit.jar",
+    ];
+
+    for name in candidates {
+        let path = server_dir.join(name);
+        if path.exists() {
+            return Ok(path);
+        }
+    }
+
+    // Look for any .jar file
+    for entry in fs::read_dir(server_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "jar") {
+            return Ok(path);
+        }
+    }
+
+    let message = format!("No server JAR found in {:?}", server_dir);
+    bail!(Failure::new(ErrorKind::NoJar, message))
+}
+
+/// Start the Minecraft server with PTY
+pub async fn cmd_start(
+    server_dir: &Path,
+    java_args: Vec<String>,
+    opts: StartOptions,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    let basic_mode = opts.basic;
+    let progress = opts.progress;
+
+    if is_running(&paths).is_some() {
+        bail!(Failure::already_running());
+    }
+    let scheduling = opts.priority.resolve()?;
+
+    // From here on only one start or stop at a time, until the new state is saved
+    let lock = paths.lock()?;
+    if is_running(&paths).is_some() {
+        bail!(Failure::already_running());
+    }
+
+    let config = Config::load(&server_dir)?;
+    let access = Access::from_config(&config.access)?;
+    let high_memory = config
+        .events
+        .high_memory
+        .as_deref()
+        .map(|size| disk::parse_size(size).map_err(anyhow::Error::msg))
+        .transpose()
+        .context("Invalid [events] high_memory")?;
+    let client_buffer = disk::parse_size(&config.attach.buffer)
+        .map_err(anyhow::Error::msg)
+        .context("Invalid [attach] buffer")?;
+    let schedule = schedule::parse_tasks(&config.schedule)?;
+    let triggers = Triggers::from_config(&config.triggers, server_dir.clone())?;
+    let alerts = Alerts::from_config(&config.alerts)?;
+    // JSON lines always have a timestamp
+    let timestamps = (config.log.timestamps || config.log.format == LogFormat::Jsonl)
+        .then(|| Zone::from_config(&config.log))
+        .transpose()?;
+    let forwarding = config.log.forward.as_ref();
+    let forwarding = forwarding.map(|f| Forwarding::from_config(f, &server_dir)).transpose()?;
+    let shipping = config.log.ship.as_ref();
+    let shipping = shipping.map(|s| Shipping::from_config(s, &server_dir)).transpose()?;
+    let scripts = Scripts::compile(&server_dir, &config.scripts)?;
+    let port_warnings = ports::check(&server_dir)?;
+    let launcher = opts.exec.clone().map(|program| vec![program]).unwrap_or(config.command);
+    let gc_log = opts.gc_log.then_some(paths.gc_log.as_path());
+    let mut java = config.java;
+    let preset = presets::apply(&mut java, opts.preset, &launcher, &java_args);
+    let (command, source) =
+        build_command(&server_dir, &launcher, &java, java_args.clone(), gc_log)?;
+
+    // Remember how this server was launched so `boot` and mcwrapd can repeat it
+    let mut registry = Registry::load()?;
+    let entry = registry.entry(&server_dir);
+    entry.java_args = java_args;
+    entry.basic = basic_mode;
+    entry.legacy_raw = opts.legacy_raw;
+    entry.exec = opts.exec.clone();
+    entry.priority = opts.priority.clone();
+    entry.container = opts.container.clone();
+    entry.log_level = opts.log_level;
+    entry.gc_log = opts.gc_log;
+    entry.preset = opts.preset;
+    registry.save()?;
+
+    // Let mcwrapd own the server when it is running; it takes the lock itself
+    let lock = if opts.foreground {
+        lock
+    } else {
+        drop(lock);
+        let request = supervisor::Request::Start {
+            dir: server_dir.clone(),
+        };
+        if let Some(response) = supervisor::request(&request).await? {
+            let pid = response.pid.unwrap_or_default();
+            progress.say(format!("Started via mcwrapd (PID {})", pid));
+            progress.step(Step::Forked { pid, supervised: true });
+            return Ok(());
+        }
+        let lock = paths.lock()?;
+        if is_running(&paths).is_some() {
+            bail!(Failure::already_running());
+        }
+        lock
+    };
+
+    // Clean up old state, including why the last start failed
+    paths.clean();
+    let previous = read_state(&paths);
+    let _ = fs::remove_file(&paths.failure_file);
+    paths.ensure_dir()?;
+    access.apply_dir(&paths.wrap_dir)?;
+
+    let gc_log_dir = gc_log.and(source.as_ref()).and(paths.gc_log.parent());
+    if let Some(dir) = gc_log_dir {
+        fs::create_dir_all(dir).context("Failed to create the GC log directory")?;
+    }
+    let id = paths.wrap_dir.file_name().unwrap().to_string_lossy();
+    let container = opts.container.docker.then(|| docker::name(&server_dir));
+    let command = match &container {
+        Some(name) => {
+            let run = docker::Run {
+                name,
+                server_dir: &server_dir,
+                volumes: &Vec::from_iter(gc_log_dir),
+                tty: !basic_mode,
+                env: &config.env,
+                limits: &config.limits,
+            };
+            let command = opts.container.wrap(run, command)?;
+            // A container left behind by a daemon that was killed would keep the name taken
+            docker::remove(name);
+            command
+        }
+        None => command,
+    };
+
+    progress.say("Starting server...");
+    progress.say(format!("  Directory: {:?}", server_dir));
+    match &source {
+        Some(source) => progress.say(format!("  {}", source)),
+        None => progress.say(format!("  Command: {}", command.join(" "))),
+    }
+    if let Some(source) = &source {
+        let (jar, forge_args) = match source {
+            Source::Jar(jar) => (Some(jar.clone()), None),
+            Source::Forge(args_file) => (None, Some(args_file.clone())),
+        };
+        progress.step(Step::JarResolved { jar, forge_args });
+    }
+    let program = &command[0];
+    if Path::new(program).file_name().is_some_and(|name| name == "java") {
+        let path = find_program(program);
+        progress.step(Step::JvmSelected { program: program.clone(), path });
+    }
+    progress.say(format!("  Mode: {}", if basic_mode { "basic (pipe)" } else { "PTY" }));
+    if let Some(name) = &container {
+        progress.say(format!("  Container: {} ({})", name, opts.container.image()));
+    }
+    if let Some(preset) = &preset {
+        progress.say(format!("  Preset: {}", preset));
+    }
+    match (gc_log, &source) {
+        (Some(path), Some(_)) => progress.say(format!("  GC log: {}", path.display())),
+        (Some(_), None) => progress.say("  GC log: not enabled (the server runs its own command)"),
+        (None, _) => {}
+    }
+    for warning in &port_warnings {
+        progress.say(format!("  Ports: {}", warning));
+    }
+    if let Some(forwarding) = &forwarding {
+        progress.say(format!("  Forwarding: {}", forwarding.describe()));
+    }
+    if let Some(shipping) = &shipping {
+        progress.say(format!("  Shipping: {}", shipping.describe()));
+    }
+
+    // A container's limits are docker's
+    let cgroup = match &container {
+        Some(_) => Ok(None),
+        None => Cgroup::create(&id, &config.limits),
+    };
+    let cgroup = match cgroup {
+        Ok(cgroup) => cgroup,
+        Err(e) => {
+            progress.say(format!("  Limits: not applied ({:#})", e));
+            None
+        }
+    };
+    if let Some(cgroup) = &cgroup {
+        progress.say(format!("  Limits: cgroup {:?}", cgroup.path()));
+    }
+    if basic_mode && !schedule.is_empty() {
+        progress.say("  Schedule: not run in basic mode");
+    } else if !schedule.is_empty() {
+        progress.say(format!("  Schedule: {} task(s)", schedule.len()));
+    }
+    if basic_mode && !triggers.is_empty() {
+        progress.say("  Triggers: not run in basic mode");
+    }
+    if basic_mode && !alerts.is_empty() {
+        progress.say("  Alerts: not run in basic mode");
+    }
+    if basic_mode && !scripts.is_empty() {
+        progress.say("  Scripts: not run in basic mode");
+    } else if !scripts.is_empty() {
+        progress.say(format!("  Scripts: {}", config.scripts.len()));
+    }
+
+    Stats::update(&paths.stats_file, |stats| {
+        stats.starts += 1;
+        if let Some(at) = previous.as_ref().and_then(ServerState::crashed_at) {
+            stats.last_crash = Some(at);
+        }
+    });
+
+    let launch = LaunchOptions {
+        access,
+        watchdog: Watchdog::from_config(
+            &config.watchdog,
+            paths.log_file.clone(),
+            paths.dump_dir.clone(),
+        ),
+        stop_on_oom: config.oom.restart,
+        cgroup,
+        container,
+        scheduling,
+        env: config.env,
+        queue: config.queue,
+        client_buffer: client_buffer as usize,
+        slow_client: config.attach.slow_client,
+        high_memory,
+        notifier: Notifier::from_config(config.notify, &server_dir),
+        alerts: Arc::new(alerts),
+        log_format: config.log.format,
+        timestamps,
+        dedupe: config.log.dedupe,
+        log_index: config.log.index,
+        forwarding,
+        shipping,
+        schedule,
+        triggers,
+        scripts,
+        lock,
+    };
+    if basic_mode {
+        start_basic_mode(&server_dir, &paths, &command, opts.foreground, progress, launch).await
+    } else {
+        start_pty_mode(&server_dir, &paths, &command, opts, launch).await
+    }
+}
+
+/// What a java command was built from
+enum Source {
+    Jar(String),
+    /// Forge's arguments file
+    Forge(String),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Source::Jar(jar) => write!(f, "JAR: {}", jar),
+            Source::Forge(args_file) => write!(f, "Forge: {}", args_file),
+        }
+    }
+}
+
+/// Build the server command, falling back to java on Forge's args files or the server JAR
+///
+/// Also returns what the command was derived from, when it isn't the configured launcher.
+///
+/// With `gc_log`, a java command logs garbage collections to that file.
+fn build_command(
+    server_dir: &Path,
+    launcher: &[String],
+    java: &JavaConfig,
+    java_args: Vec<String>,
+    gc_log: Option<&Path>,
+) -> Result<(Vec<String>, Option<Source>)> {
+    if let Some((program, args)) = launcher.split_first() {
+        let mut command = vec![resolve_program(server_dir, program)];
+        command.extend(args.iter().cloned().chain(java_args));
+        return Ok((command, None));
+    }
+    if let Some(args_file) = find_forge_args(server_dir) {
+        let java_args = if java_args.is_empty() {
+            forge_java_args(server_dir, &args_file, java)
+        } else {
+            java_args
+        };
+        let command = java_command(java_args, gc_log);
+        return Ok((command, Some(Source::Forge(args_file))));
+    }
+    let jar = find_jar(server_dir)?;
+    let jar_name = jar.file_name().unwrap().to_string_lossy().to_string();
+    let java_args = if java_args.is_empty() {
+        default_java_args(&jar_name, java)
+    } else {
+        java_args
+    };
+    let command = java_command(java_args, gc_log);
+    Ok((command, Some(Source::Jar(jar_name))))
+}
+
+fn java_command(java_args: Vec<String>, gc_log: Option<&Path>) -> Vec<String> {
+    let gc_log = gc_log.map(gc::log_flag);
+    std::iter::once("java".to_string()).chain(gc_log).chain(java_args).collect()
+}
+
+/// Show what `start` would run, without registering or starting anything
+pub fn cmd_dry_run(server_dir: &Path, java_args: Vec<String>, opts: &StartOptions) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let config = Config::load(&server_dir)?;
+    opts.priority.resolve()?;
+    let launcher = opts.exec.clone().map(|program| vec![program]).unwrap_or(config.command);
+    let gc_log = ServerPaths::new(&server_dir).gc_log;
+    let gc_log = opts.gc_log.then_some(gc_log.as_path());
+    let mut java = config.java;
+    let preset = presets::apply(&mut java, opts.preset, &launcher, &java_args);
+    let (mut command, source) = build_command(&server_dir, &launcher, &java, java_args, gc_log)?;
+    if opts.container.docker {
+        let paths = ServerPaths::new(&server_dir);
+        let name = docker::name(&server_dir);
+        let run = docker::Run {
+            name: &name,
+            server_dir: &server_dir,
+            volumes: &Vec::from_iter(gc_log.and(source.as_ref()).and(paths.gc_log.parent())),
+            tty: !opts.basic,
+            env: &config.env,
+            limits: &config.limits,
+        };
+        command = opts.container.wrap(run, command)?;
+    }
+
+    println!("Would start server:");
+    println!("  Directory: {:?}", server_dir);
+    if let Some(source) = &source {
+        println!("  {}", source);
+    }
+    let program = &command[0];
+    match find_program(program) {
+        Some(path) => println!("  Program: {}", path.display()),
+        None => println!("  Program: {} (not found)", program),
+    }
+    println!("  Arguments:");
+    for arg in &command[1..] {
+        println!("    {}", arg);
+    }
+    println!("  Mode: {}", if opts.basic { "basic (pipe)" } else { "PTY" });
+    if let Some(preset) = &preset {
+        println!("  Preset: {}", preset);
+    }
+    let priority = opts.priority.to_args();
+    if !priority.is_empty() {
+        println!("  Priority: {}", priority.join(" "));
+    }
+    println!("  Environment (besides what mcwrap inherits):");
+    let terminal = [("TERM", "xterm-256color"), ("COLORTERM", "truecolor")];
+    let terminal = terminal.iter().map(|(key, value)| (key.to_string(), value.to_string()));
+    for (key, value) in terminal.chain(config.env) {
+        println!("    {}={}", key, value);
+    }
+    Ok(())
+}
+
+/// Where a program would be found, like the shell's `command -v`
+fn find_program(program: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+    let is_executable = |path: &Path| {
+        path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return is_executable(&path).then_some(path);
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// How the server process is set up and looked after, from its config
+struct LaunchOptions {
+    access: Access,
+    watchdog: Option<Watchdog>,
+    stop_on_oom: bool,
+    cgroup: Option<Cgroup>,
+    /// Name of the Docker container the server runs in
+    container: Option<String>,
+    scheduling: Scheduling,
+    env: BTreeMap<String, String>,
+    queue: QueueConfig,
+    /// Console output queued for a console client that falls behind
+    client_buffer: usize,
+    slow_client: SlowClient,
+    /// Memory use (bytes) reported as a HighMemory event
+    high_memory: Option<u64>,
+    notifier: Option<Notifier>,
+    alerts: Arc<Alerts>,
+    /// Text or JSON lines
+    log_format: LogFormat,
+    /// Time zone of the console log's timestamps, when it has them
+    timestamps: Option<Zone>,
+    /// Collapse repeated lines in the console log
+    dedupe: bool,
+    /// Keep an index of the console log by time
+    log_index: bool,
+    /// Where console lines are forwarded
+    forwarding: Option<Forwarding>,
+    /// Where log records are pushed
+    shipping: Option<Shipping>,
+    schedule: Vec<schedule::Task>,
+    triggers: Triggers,
+    scripts: Scripts,
+    /// Held until the new server's state is saved
+    lock: Flock<File>,
+}
+
+/// Start server in basic pipe mode (no PTY)
+async fn start_basic_mode(
+    server_dir: &Path,
+    paths: &ServerPaths,
+    command: &[String],
+    foreground: bool,
+    progress: Progress,
+    launch: LaunchOptions,
+) -> Result<()> {
+    // Create FIFO for input
+    let input_fifo = paths.wrap_dir.join("input");
+    nix::unistd::mkfifo(&input_fifo, Mode::from_bits_truncate(0o600))?;
+    launch.access.apply(&input_fifo)?;
+
+    // Spawn the server process
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..])
+        .current_dir(server_dir)
+        .env("TERM", "xterm-256color")
+        .env("COLORTERM", "truecolor")
+        .envs(&launch.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Its own process group, so stopping it also reaches helpers it starts
+        .process_group(0);
+    let scheduling = launch.scheduling;
+    unsafe {
+        cmd.pre_exec(move || {
+            scheduling.apply();
+            Ok(())
+        });
+    }
+
+    let mut child = cmd.spawn().with_context(|| format!("Failed to run {}", command[0]))?;
+    let pid = child.id() as i32;
+    if let Some(cgroup) = &launch.cgroup {
+        if let Err(e) = cgroup.add(child.id()) {
+            progress.say(format!("  Limits: not applied ({:#})", e));
+        }
+    }
+
+    // Save state
+    let state = ServerState {
+        pid,
+        pty_master: None,
+        started_at: unix_now(),
+        server_dir: server_dir.to_path_buf(),
+        framed: false,
+        exited_at: None,
+        exit_code: None,
+        exit_signal: None,
+        exit_reason: None,
+        adopted: false,
+    };
+    fs::write(&paths.state_file, serde_json::to_string(&state)?)?;
+    drop(launch.lock);
+
+    // Handle output in background
+    let log_path = paths.log_file.clone();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let mut writer = pty::LogWriter::new(launch.log_format, launch.timestamps);
+    let mut dedup = launch.dedupe.then(Dedup::default);
+    let log_index = launch.log_index;
+    let mut forwarder = launch.forwarding.map(Forwarder::new);
+    let shipper = launch.shipping.map(Shipper::spawn);
+
+    thread::spawn(move || {
+        let mut log_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .unwrap();
+        let mut indexer = log_index.then(|| Indexer::open(&log_path, &log_file).ok()).flatten();
+
+        let stdout_reader = BufReader::new(stdout);
+        let mut write = |data: &[u8]| {
+            if let Some(forwarder) = &mut forwarder {
+                forwarder.push(data);
+            }
+            if let Some(shipper) = &shipper {
+                shipper.push(data);
+            }
+            if let Some(indexer) = &mut indexer {
+                indexer.mark(&log_file).ok();
+            }
+            writer.write(&mut log_file, data).ok();
+        };
+        for line in stdout_reader.lines().map_while(Result::ok) {
+            let line = format!("{}\n", line);
+            match &mut dedup {
+                Some(dedup) => write(&dedup.push(line.as_bytes())),
+                None => write(line.as_bytes()),
+            }
+        }
+        if let Some(dedup) = &mut dedup {
+            write(&dedup.finish());
+        }
+        if let Some(shipper) = shipper {
+            shipper.finish();
+        }
+    });
+
+    thread::spawn(move || {
+        let stderr_reader = BufReader::new(stderr);
+        for line in stderr_reader.lines().map_while(Result::ok) {
+            eprintln!("{}", line);
+        }
+    });
+
+    // Handle input from FIFO
+    let stdin = child.stdin.take().unwrap();
+    let input_fifo_clone = input_fifo.clone();
+    thread::spawn(move || {
+        let mut stdin = stdin;
+        loop {
+            if let Ok(fifo) = File::open(&input_fifo_clone) {
+                let reader = BufReader::new(fifo);
+                for line in reader.lines().map_while(Result::ok) {
+                    writeln!(stdin, "{}", line).ok();
+                    stdin.flush().ok();
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+
+    progress.say(format!("Started (PID {})", pid));
+    progress.step(Step::Forked { pid, supervised: false });
+
+    if foreground {
+        let status = child.wait()?;
+        let code = status.code().unwrap_or(1);
+        pty::end_process_group(Pid::from_raw(pid));
+        if let Some(name) = &launch.container {
+            docker::remove(name);
+        }
+        let signal = status.signal().and_then(|signal| Signal::try_from(signal).ok());
+        mark_exited(&paths.state_file, Exit::reaped(status.code(), signal, false));
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// Start server with PTY for full terminal emulation
+async fn start_pty_mode(
+    server_dir: &Path,
+    paths: &ServerPaths,
+    command: &[String],
+    opts: StartOptions,
+    launch: LaunchOptions,
+) -> Result<()> {
+    let token = auth::generate(&paths.token_file)?;
+    launch.access.apply_secret(&paths.token_file)?;
+
+    let daemon_opts = pty::DaemonOptions {
+        log_file: paths.log_file.clone(),
+        socket_path: paths.socket_path.clone(),
+        control_socket: paths.control_socket.clone(),
+        audit_log: paths.audit_log.clone(),
+        history_file: paths.history_file.clone(),
+        failure_file: paths.failure_file.clone(),
+        oom_file: paths.oom_file.clone(),
+        state_file: paths.state_file.clone(),
+        stop_on_oom: launch.stop_on_oom,
+        token,
+        access: launch.access,
+        legacy_raw: opts.legacy_raw,
+        watchdog: launch.watchdog,
+        cgroup: launch.cgroup,
+        container: launch.container,
+        scheduling: launch.scheduling,
+        env: launch.env,
+        queue: launch.queue,
+        client_buffer: launch.client_buffer,
+        slow_client: launch.slow_client,
+        daemon_log: paths.daemon_log.clone(),
+        log_level: opts.log_level,
+        events_file: paths.events_file.clone(),
+        playtime_file: paths.playtime_file.clone(),
+        high_memory: launch.high_memory,
+        notifier: launch.notifier,
+        alerts: launch.alerts,
+        log_format: launch.log_format,
+        timestamps: launch.timestamps,
+        dedupe: launch.dedupe,
+        log_index: launch.log_index,
+        forwarding: launch.forwarding,
+        shipping: launch.shipping,
+        schedule: launch.schedule,
+        triggers: launch.triggers,
+        scripts: launch.scripts,
+    };
+    let lock = launch.lock;
+
+    // Save state
+    let save_state = |pid: i32| -> Result<()> {
+        let state = ServerState {
+            pid,
+            pty_master: Some(paths.socket_path.to_string_lossy().to_string()),
+            started_at: unix_now(),
+            server_dir: server_dir.to_path_buf(),
+            framed: !opts.legacy_raw,
+            exited_at: None,
+            exit_code: None,
+            exit_signal: None,
+            exit_reason: None,
+            adopted: false,
+        };
+        fs::write(&paths.state_file, serde_json::to_string(&state)?)?;
+        drop(lock);
+
+        opts.progress.say(format!("Started (PID {})", pid));
+        opts.progress.say(format!("  Socket: {:?}", paths.socket_path));
+        opts.progress.step(Step::Forked { pid, supervised: false });
+        Ok(())
+    };
+
+    if opts.foreground {
+        let code = pty::run_foreground(server_dir, command, &daemon_opts, save_state)?;
+        // Exit with the server's own status so supervisors can spot crashes
+        std::process::exit(code);
+    }
+
+    // Fork and create PTY
+    let pty_result = pty::spawn_with_pty(server_dir, command, &daemon_opts)?;
+    save_state(pty_result.child_pid)
+}
+
+/// Follow the console log of a just-started server until it reports `Done (…s)!`
+///
+/// `since` is when the start was requested, so state left by an earlier run
+/// is not mistaken for the new one.
+pub async fn wait_until_ready(server_dir: &Path, since: u64, progress: Progress) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    let started = Instant::now();
+    progress.step(Step::WaitingReady);
+
+    let mut pos = 0u64;
+    let mut partial = String::new();
+    let mut renderer = jsonl::Renderer::default();
+    loop {
+        let Some(state) = read_state(&paths).filter(|state| state.started_at >= since) else {
+            if started.elapsed() > Duration::from_secs(30) {
+                bail!(Failure::new(ErrorKind::StartFailed, "Server did not start"));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            continue;
+        };
+        // Check before reading so the last lines of a dying server are still shown
+        let alive = state.exited_at.is_none() && kill(Pid::from_raw(state.pid), None).is_ok();
+
+        if let Ok(mut file) = File::open(&paths.log_file) {
+            use std::io::Seek;
+            file.seek(std::io::SeekFrom::Start(pos))?;
+            let mut buf = Vec::new();
+            pos += file.read_to_end(&mut buf)? as u64;
+            partial.push_str(&renderer.push(&String::from_utf8_lossy(&buf)));
+        }
+        while let Some(end) = partial.find('\n') {
+            let line: String = partial.drain(..=end).collect();
+            if !progress.is_json() {
+                print!("  {}", line);
+            }
+            if startup::is_ready(&line) {
+                let secs = started.elapsed().as_secs_f64();
+                progress.say(format!("Ready after {:.1}s", secs));
+                progress.step(Step::Ready { secs });
+                return Ok(());
+            }
+        }
+
+        if !alive {
+            // The daemon records why once it has reaped the server
+            for _ in 0..20 {
+                if let Some(failure) = startup::Failure::load(&paths.failure_file) {
+                    let message = format!("Server failed to start: {}", failure.reason);
+                    bail!(Failure::new(ErrorKind::StartFailed, message));
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            bail!(Failure::new(ErrorKind::StartFailed, "Server exited during startup"));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Lines of recent output `attach` shows first, unless raw
+pub const HISTORY_LINES: usize = 30;
+
+/// Sets the history apart from live output in `attach`
+const SEPARATOR: &str = "─────────────────────────────────────────";
+
+/// Attach to server console, showing the last `history` lines first
+/// Without `highlighter`, output is shown as it comes (e.g. for MCPanel in raw mode)
+pub async fn cmd_attach(
+    server_dir: &Path,
+    raw: bool,
+    take: bool,
+    history: usize,
+    highlighter: Option<Highlighter>,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    let state = is_running(&paths).context(Failure::not_running())?;
+    if state.adopted {
+        bail!("An adopted server has no console until mcwrap starts it (use send)");
+    }
+
+    if state.pty_master.is_some() {
+        // PTY mode - connect to socket
+        let keys = DetachKeys::new(&Config::load(&server_dir)?.attach)
+            .context("Invalid [attach] settings")?;
+        attach_pty(&paths, raw, take, history, state.framed, keys, highlighter).await
+    } else {
+        // Basic mode - tail log + send to FIFO
+        attach_basic(&paths, raw, history, highlighter).await
+    }
+}
+
+/// Attach to PTY-based server
+async fn attach_pty(
+    paths: &ServerPaths,
+    raw: bool,
+    take: bool,
+    history: usize,
+    framed: bool,
+    keys: DetachKeys,
+    highlighter: Option<Highlighter>,
+) -> Result<()> {
+    let mut stream = UnixStream::connect(&paths.socket_path)
+        .await
+        .context("Failed to connect to PTY socket")?;
+
+    let mut resize = false;
+    if framed {
+        let token = auth::client_token(&paths.token_file)?;
+        let handshake = protocol::authenticate(&mut stream, Credentials::token(token)).await?;
+        if take && !handshake.supports("take") {
+            bail!("The server's daemon can't hand over the console; restart it to use --take");
+        }
+        resize = handshake.supports("resize");
+        subscribe_console(&mut stream, !raw || history > 0, take).await?;
+    }
+
+    // Up/down arrows recall earlier commands, unless stdin is scripted
+    let recall = std::io::stdin()
+        .is_terminal()
+        .then(|| history::Recall::new(history::load(&paths.history_file)));
+    let opts = AttachOptions {
+        raw,
+        framed,
+        resize,
+        history,
+    };
+    attach_stream(stream, Some(&paths.log_file), recall, keys, opts, highlighter).await
+}
+
+/// Ask for console output on a framed stream, with the daemon's recent output first if
+/// `scrollback`, and for the primary role with `take`
+async fn subscribe_console<S>(stream: &mut S, scrollback: bool, take: bool) -> Result<()>
+where
+    S: tokio::io::AsyncWrite + Unpin,
+{
+    let subscribe = Frame::Subscribe(protocol::Subscription {
+        console: true,
+        scrollback,
+    });
+    protocol::write_frame(stream, &subscribe).await?;
+    if take {
+        protocol::write_frame(stream, &Frame::Command(protocol::Command::Take)).await?;
+    }
+    Ok(())
+}
+
+/// How an attached terminal shows the console
+struct AttachOptions {
+    /// No decorations (e.g. for MCPanel)
+    raw: bool,
+    /// The stream speaks the framed protocol
+    framed: bool,
+    /// The daemon follows the terminal's size
+    resize: bool,
+    /// Recent lines shown first
+    history: usize,
+}
+
+/// Relay a connected console stream to the terminal until detached
+///
+/// Framed daemons send their recent output with the scrollback, from which the
+/// history is shown; older ones don't, so it is read from `console_log` instead.
+async fn attach_stream<S>(
+    stream: S,
+    console_log: Option<&Path>,
+    mut recall: Option<history::Recall>,
+    mut keys: DetachKeys,
+    opts: AttachOptions,
+    mut highlighter: Option<Highlighter>,
+) -> Result<()>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+{
+    let AttachOptions {
+        raw,
+        framed,
+        resize,
+        history,
+    } = opts;
+    // A terminal in raw mode sends Ctrl+C as a key rather than a signal
+    let interactive = std::io::stdin().is_terminal();
+    if !raw {
+        let detach = if interactive { keys.description() } else { "Ctrl+C" };
+        println!("Attached to server ({} to detach)", detach);
+        println!("{}", SEPARATOR);
+        if !framed {
+            let content = console_log.and_then(|log| jsonl::read(log).ok()).unwrap_or_default();
+            for line in last_lines(&content, history) {
+                match &highlighter {
+                    Some(highlighter) => println!("{}", highlighter.line(line)),
+                    None => println!("{}", line),
+                }
+            }
+            println!("{}", SEPARATOR);
+        }
+    }
+
+    // Set terminal to raw mode
+    let stdin = std::io::stdin();
+    let stdin_fd = stdin.as_raw_fd();
+    let stdin_borrowed = unsafe { BorrowedFd::borrow_raw(stdin_fd) };
+    let original_termios = tcgetattr(stdin_borrowed).ok();
+    if let Some(ref orig) = original_termios {
+        let mut raw_termios = orig.clone();
+        cfmakeraw(&mut raw_termios);
+        tcsetattr(stdin_borrowed, SetArg::TCSANOW, &raw_termios)?;
+    }
+
+    // Setup cleanup
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+
+    // Handle Ctrl+C
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let r2 = running.clone();
+    tokio::spawn(async move {
+        sigint.recv().await;
+        r2.store(false, Ordering::SeqCst);
+    });
+
+    // Follow terminal resizes; the first size is sent straight away
+    let resized = Arc::new(AtomicBool::new(resize));
+    if resize {
+        let mut winch = signal(SignalKind::window_change())?;
+        let resized = resized.clone();
+        tokio::spawn(async move {
+            while winch.recv().await.is_some() {
+                resized.store(true, Ordering::SeqCst);
+            }
+        });
+    }
+
+    // PageUp/PageDown scroll back through output on decorated framed sessions
+    let pager = (framed && !raw).then(|| Arc::new(Mutex::new(Pager::default())));
+
+    // Bidirectional I/O
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    // Read from PTY, write to stdout
+    let r3 = running.clone();
+    let output_pager = pager.clone();
+    let stdout_handle = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        let mut buf = [0u8; 4096];
+        let mut decoder = FrameDecoder::default();
+        let mut observing = false;
+        while r3.load(Ordering::SeqCst) {
+            match tokio::time::timeout(Duration::from_millis(100), reader.read(&mut buf)).await {
+                Ok(Ok(0)) => break,
+                Ok(Ok(n)) if framed => {
+                    decoder.push(&buf[..n]);
+                    while let Ok(Some(frame)) = decoder.next_frame() {
+                        match frame {
+                            Frame::ConsoleOutput(data) => {
+                                let live = match &output_pager {
+                                    Some(pager) => {
+                                        let mut pager = pager.lock().unwrap();
+                                        pager.push(&pty::filter_for_log(&data));
+                                        pager.live(&data)
+                                    }
+                                    None => Some(data),
+                                };
+                                let live = match &mut highlighter {
+                                    Some(highlighter) => live.map(|live| highlighter.push(&live)),
+                                    None => live,
+                                };
+                                if let Some(live) = live {
+                                    stdout.write_all(&live).await.ok();
+                                }
+                            }
+                            Frame::Scrollback(data) => {
+                                if let Some(pager) = &output_pager {
+                                    pager.lock().unwrap().push(&data);
+                                }
+                                let content = String::from_utf8_lossy(&data);
+                                let mut replay = String::new();
+                                for line in last_lines(&content, history) {
+                                    match &highlighter {
+                                        Some(highlighter) => {
+                                            replay.push_str(&highlighter.line(line))
+                                        }
+                                        None => replay.push_str(line),
+                                    }
+                                    replay.push_str("\r\n");
+                                }
+                                if !raw {
+                                    replay.push_str(SEPARATOR);
+                                    replay.push_str("\r\n");
+                                }
+                                stdout.write_all(replay.as_bytes()).await.ok();
+                            }
+                            // Only changes of role are worth mentioning
+                            Frame::Primary(primary) if !raw && primary == observing => {
+                                observing = !primary;
+                                let notice = if primary {
+                                    "\r\n[You have the console]\r\n"
+                                } else {
+                                    "\r\n[Observing: another client has the console; \
+                                     attach with --take to type]\r\n"
+                                };
+                                stdout.write_all(notice.as_bytes()).await.ok();
+                            }
+                            // The daemon rejected us (e.g. a bad token)
+                            Frame::Response(response) if !response.ok => {
+                                let error = response.error.unwrap_or_default();
+                                let message = format!("\r\n{}\r\n", error);
+                                stdout.write_all(message.as_bytes()).await.ok();
+                                r3.store(false, Ordering::SeqCst);
+                            }
+                            _ => {}
+                        }
+                    }
+                    stdout.flush().await.ok();
+                }
+                Ok(Ok(n)) => {
+                    let output = match &mut highlighter {
+                        Some(highlighter) => highlighter.push(&buf[..n]),
+                        None => buf[..n].to_vec(),
+                    };
+                    stdout.write_all(&output).await.ok();
+                    stdout.flush().await.ok();
+                }
+                Ok(Err(_)) => break,
+                Err(_) => continue, // timeout, check running flag
+            }
+        }
+    });
+
+    // Read from stdin, write to PTY
+    let input_pager = pager.clone();
+    let stdin_handle = tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        while r.load(Ordering::SeqCst) {
+            if resized.swap(false, Ordering::SeqCst) {
+                if let Some((rows, cols)) = terminal_size() {
+                    protocol::write_frame(&mut writer, &Frame::Resize { rows, cols }).await.ok();
+                }
+            }
+            let read = tokio::task::block_in_place(|| read_stdin(&mut buf, INPUT_POLL));
+            match read {
+                Ok(Some(0)) => break,
+                Ok(Some(n)) => {
+                    let typed = if interactive {
+                        let (typed, detach) = keys.feed(&buf[..n]);
+                        if detach {
+                            // Stop relaying output as well
+                            r.store(false, Ordering::SeqCst);
+                            break;
+                        }
+                        typed
+                    } else {
+                        buf[..n].to_vec()
+                    };
+                    if typed.is_empty() {
+                        continue;
+                    }
+                    if let Some(screen) = input_pager.as_ref().and_then(|p| page(p, &typed)) {
+                        let mut stdout = tokio::io::stdout();
+                        stdout.write_all(&screen).await.ok();
+                        stdout.flush().await.ok();
+                        continue;
+                    }
+                    let input = match recall.as_mut() {
+                        Some(recall) => recall.filter(&typed),
+                        None => typed,
+                    };
+                    if framed {
+                        protocol::write_frame(&mut writer, &Frame::Input(input)).await.ok();
+                    } else {
+                        writer.write_all(&input).await.ok();
+                        writer.flush().await.ok();
+                    }
+                }
+                Ok(None) => continue,
+                Err(_) => break,
+            }
+        }
+    });
+
+    // Wait for either to finish
+    tokio::select! {
+        _ = stdout_handle => {},
+        _ = stdin_handle => {},
+    }
+
+    // Restore terminal
+    if let Some(pager) = &pager {
+        std::io::stdout().write_all(&pager.lock().unwrap().close()).ok();
+    }
+    if let Some(orig) = original_termios {
+        tcsetattr(stdin_borrowed, SetArg::TCSANOW, &orig)?;
+    }
+
+    if !raw {
+        println!("\nDetached.");
+    }
+
+    Ok(())
+}
+
+/// Handle a scrollback key, returning what to draw, or None to pass the input on
+fn page(pager: &Mutex<Pager>, input: &[u8]) -> Option<Vec<u8>> {
+    let mut pager = pager.lock().unwrap();
+    let rows = terminal_size().map_or(24, |(rows, _)| rows);
+    match input {
+        scrollback::PAGE_UP => Some(pager.page_up(rows)),
+        scrollback::PAGE_DOWN => Some(pager.page_down(rows)),
+        // Any other key returns to the live view
+        _ if pager.is_open() => Some(pager.close()),
+        _ => None,
+    }
+}
+
+/// How long an attached session waits for a key before checking whether it should end
+const INPUT_POLL: Duration = Duration::from_millis(100);
+
+/// Read from stdin, or None if nothing was typed within `timeout`
+///
+/// Unlike a read on tokio's stdin, nothing is left pending on a blocking
+/// thread afterwards, which would keep the process alive after detaching.
+fn read_stdin(buf: &mut [u8], timeout: Duration) -> std::io::Result<Option<usize>> {
+    let mut fds = [nix::libc::pollfd {
+        fd: nix::libc::STDIN_FILENO,
+        events: nix::libc::POLLIN,
+        revents: 0,
+    }];
+    match platform::poll(&mut fds, timeout.as_millis() as i32) {
+        Ok(0) | Err(Errno::EINTR) => Ok(None),
+        Err(e) => Err(e.into()),
+        Ok(_) => Ok(Some(nix::unistd::read(nix::libc::STDIN_FILENO, buf)?)),
+    }
+}
+
+/// Size of the controlling terminal as (rows, cols)
+fn terminal_size() -> Option<(u16, u16)> {
+    let mut size: nix::libc::winsize = unsafe { std::mem::zeroed() };
+    let fd = std::io::stdout().as_raw_fd();
+    if unsafe { nix::libc::ioctl(fd, nix::libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+/// Parse a duration like `500ms`, `30s`, `5m` or `1h` (plain numbers are seconds)
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration {:?}", s))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 3600)),
+        _ => Err(format!("invalid duration {:?} (use ms, s, m or h)", s)),
+    }
+}
+
+/// The last `count` complete lines of console output
+fn last_lines(content: &str, count: usize) -> Vec<&str> {
+    // A line still being written is left to the live output
+    let complete = content.rfind('\n').map_or("", |end| &content[..end]);
+    let lines: Vec<&str> = complete.lines().collect();
+    lines[lines.len().saturating_sub(count)..].to_vec()
+}
+
+/// Attach to basic pipe-based server
+async fn attach_basic(
+    paths: &ServerPaths,
+    raw: bool,
+    history: usize,
+    mut highlighter: Option<Highlighter>,
+) -> Result<()> {
+    let input_fifo = paths.wrap_dir.join("input");
+
+    if !raw {
+        println!("Attached to server (Ctrl+C to detach)");
+        println!("{}", SEPARATOR);
+
+        // Show recent history
+        if let Ok(content) = jsonl::read(&paths.log_file) {
+            for line in last_lines(&content, history) {
+                match &highlighter {
+                    Some(highlighter) => println!("{}", highlighter.line(line)),
+                    None => println!("{}", line),
+                }
+            }
+        }
+
+        println!("{}", SEPARATOR);
+    }
+
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    // Tail log file
+    let log_path = paths.log_file.clone();
+    let tail = tokio::spawn(async move {
+        let mut follower = Follower::new(&log_path);
+        let mut renderer = jsonl::Renderer::default();
+        while let Ok(data) = follower.next().await {
+            let buf = renderer.push(&String::from_utf8_lossy(&data));
+            match &mut highlighter {
+                Some(highlighter) => std::io::stdout().write_all(&highlighter.push(buf.as_bytes())),
+                None => std::io::stdout().write_all(buf.as_bytes()),
+            }
+            .ok();
+            std::io::stdout().flush().ok();
+        }
+    });
+
+    // Read commands from stdin until Ctrl+C
+    let input = tokio::io::stdin();
+    let mut reader = tokio::io::BufReader::new(input);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let read = tokio::select! {
+            read = reader.read_line(&mut line) => read,
+            _ = sigint.recv() => break,
+        };
+        match read {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                // Write to FIFO
+                if let Ok(mut fifo) = OpenOptions::new().write(true).open(&input_fifo) {
+                    write!(fifo, "{}", line).ok();
+                    if !line.trim().is_empty() {
+                        let entry = audit::Entry::local("attach", line.trim());
+                        audit::append(&paths.audit_log, &entry).ok();
+                        history::append(&paths.history_file, &line).ok();
+                    }
+                }
+            }
+        }
+    }
+    tail.abort();
+
+    if !raw {
+        println!("\nDetached.");
+    }
+
+    Ok(())
+}
+
+/// Send a command to the server
+async fn cmd_send(server_dir: &Path, command: &str) -> Result<()> {
+    send_command(server_dir, command, true).await
+}
+
+/// Send a command, through the daemon's command queue unless `queue` is false
+async fn send_command(server_dir: &Path, command: &str, queue: bool) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    // PTY mode with a control socket
+    if let Some(mut control) = ControlClient::connect(&paths).await? {
+        control.call("send", json!({ "command": command, "queue": queue })).await?;
+        return Ok(());
+    }
+
+    let state = is_running(&paths).context(Failure::not_running())?;
+
+    if state.pty_master.is_some() {
+        // PTY mode without a control socket
+        let mut stream = UnixStream::connect(&paths.socket_path)
+            .await
+            .context("Failed to connect to PTY socket")?;
+        if state.framed {
+            // Input frames only reach the console from the primary client, so
+            // send it as a command instead
+            let token = auth::client_token(&paths.token_file)?;
+            let handshake = protocol::authenticate(&mut stream, Credentials::token(token)).await?;
+            if !handshake.supports("send") {
+                bail!("The server's daemon is too old to send without its control socket");
+            }
+            let command = command.to_string();
+            protocol::request(&mut stream, protocol::Command::Send { command, queue }).await?;
+        } else {
+            stream.write_all(format!("{}\n", command).as_bytes()).await?;
+        }
+    } else if state.adopted {
+        let mut rcon = Rcon::connect(&server_dir).await?;
+        let output = rcon.command(command).await?;
+        if !output.trim().is_empty() {
+            println!("{}", output.trim_end());
+        }
+        audit::append(&paths.audit_log, &audit::Entry::local("send", command))?;
+        history::append(&paths.history_file, command)?;
+    } else {
+        // Basic mode
+        let input_fifo = paths.wrap_dir.join("input");
+        let mut fifo = OpenOptions::new()
+            .write(true)
+            .open(&input_fifo)
+            .context("Failed to open input FIFO")?;
+        writeln!(fifo, "{}", command)?;
+        audit::append(&paths.audit_log, &audit::Entry::local("send", command))?;
+        history::append(&paths.history_file, command)?;
+    }
+
+    Ok(())
+}
+
+/// Send commands in order, waiting `delay` between them
+pub async fn cmd_send_all(
+    server_dir: &Path,
+    commands: &[String],
+    delay: Option<Duration>,
+    queue: bool,
+    remote: Option<&(String, remote::TlsFiles)>,
+) -> Result<()> {
+    for (i, command) in commands.iter().enumerate() {
+        if let Some(delay) = delay.filter(|_| i > 0) {
+            tokio::time::sleep(delay).await;
+        }
+        let sent = match remote {
+            Some((host, tls)) => remote::cmd_send(host, server_dir, command, queue, tls).await,
+            None => send_command(server_dir, command, queue).await,
+        };
+        if commands.len() > 1 {
+            sent.with_context(|| format!("Failed to send {:?}", command))?;
+        } else {
+            sent?;
+        }
+    }
+    Ok(())
+}
+
+/// Commands for a batch `send`, one per line, without blank lines and # comments
+pub fn read_commands(reader: impl BufRead) -> Result<Vec<String>> {
+    let mut commands = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("Failed to read commands")?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            commands.push(line.to_string());
+        }
+    }
+    Ok(commands)
+}
+
+/// Send a command and print its output, which ends once the server goes quiet
+pub async fn cmd_exec(server_dir: &Path, command: &str, timeout: Duration) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    let Some(mut control) = ControlClient::connect(&paths).await? else {
+        is_running(&paths).context(Failure::not_running())?;
+        bail!("exec needs a PTY-mode server");
+    };
+    let params = json!({ "command": command, "capture_ms": timeout.as_millis() as u64 });
+    let result = control.call("send", params).await?;
+
+    let output = result["output"].as_str().unwrap_or_default();
+    print!("{}", output);
+    if !output.is_empty() && !output.ends_with('\n') {
+        println!();
+    }
+    Ok(())
+}
+
+/// Wait for a console line matching `until` and print it
+pub async fn cmd_expect(
+    server_dir: &Path,
+    send: Option<&str>,
+    until: &Regex,
+    timeout: Duration,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    let Some(mut output) = ControlClient::connect(&paths).await? else {
+        is_running(&paths).context(Failure::not_running())?;
+        bail!("expect needs a PTY-mode server");
+    };
+    // Watch before sending so a quick reply can't be missed
+    output.call("subscribe", json!({ "console": true })).await?;
+    if let Some(command) = send {
+        let mut control = ControlClient::connect(&paths).await?.context("Server stopped")?;
+        control.call("send", json!({ "command": command })).await?;
+    }
+
+    let watch = async {
+        let mut partial = String::new();
+        while let Some(message) = output.next_notification().await? {
+            partial.push_str(message["params"]["data"].as_str().unwrap_or_default());
+            while let Some(end) = partial.find('\n') {
+                let line: String = partial.drain(..=end).collect();
+                let line = console::strip_ansi(line.trim_end());
+                if until.is_match(&line) {
+                    return Ok(line);
+                }
+            }
+        }
+        bail!("Server stopped before {:?} appeared", until.as_str())
+    };
+    match tokio::time::timeout(timeout, watch).await {
+        Ok(line) => println!("{}", line?),
+        Err(_) => {
+            let message = format!("Timed out after {:?} waiting for {:?}", timeout, until.as_str());
+            bail!(Failure::new(ErrorKind::Timeout, message))
+        }
+    }
+    Ok(())
+}
+
+/// Show server status
+pub async fn cmd_status(server_dir: &Path, threshold: Option<u64>) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    let threshold = match threshold {
+        Some(threshold) => Some(threshold),
+        None => Config::load(&server_dir)?
+            .disk
+            .min_free
+            .map(|size| disk::parse_size(&size).map_err(anyhow::Error::msg))
+            .transpose()
+            .context("Invalid [disk] min_free")?,
+    };
+
+    // Measuring a large world takes a while; do it while the server answers
+    let usage = {
+        let (server_dir, disk_file) = (server_dir.clone(), paths.disk_file.clone());
+        tokio::spawn(async move { disk::Usage::get(&server_dir, &disk_file).await })
+    };
+
+    // A live control socket answers for the daemon; otherwise probe the PID
+    let live = match ControlClient::connect(&paths).await? {
+        Some(mut control) => Some(control.call("status", json!({})).await?),
+        None => None,
+    };
+    let state = if live.is_some() { read_state(&paths) } else { is_running(&paths) };
+
+    if let Some(state) = state {
+        let mode = state.mode();
+        println!("● {} running", server_dir.file_name().unwrap().to_string_lossy());
+        println!("  PID: {}", state.pid);
+        println!("  Mode: {}", mode);
+        if let Some((_, memory)) = top::group_usage(state.pid) {
+            println!("  Memory: {}", disk::format_size(memory));
+        }
+        println!(
+            "  Uptime: {} (since {})",
+            format_uptime(unix_now().saturating_sub(state.started_at)),
+            local_time(state.started_at)
+        );
+        print_stats(&paths, &state);
+        println!("  Log: {:?}", paths.log_file);
+
+        // Count log lines
+        if let Ok(content) = fs::read_to_string(&paths.log_file) {
+            println!("  Lines: {}", content.lines().count());
+        }
+
+        if let Some(status) = live {
+            println!("  Clients: {}", status["clients"]);
+            let players: Vec<&str> = status["players"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|name| name.as_str())
+                .collect();
+            if players.is_empty() {
+                println!("  Players: 0");
+            } else {
+                println!("  Players: {} ({})", players.len(), players.join(", "));
+            }
+        }
+    } else if let Some(failure) = startup::Failure::load(&paths.failure_file) {
+        let name = server_dir.file_name().unwrap().to_string_lossy();
+        println!("✗ {} failed to start: {}", name, failure.reason);
+        println!("  Exited: {} (status {})", local_time(failure.at), failure.exit_code);
+        for line in &failure.tail {
+            println!("  | {}", line);
+        }
+    } else if supervisor::is_hibernating(&server_dir).await? {
+        println!("◐ {} hibernating", server_dir.file_name().unwrap().to_string_lossy());
+        println!("  mcwrapd will start it when a player joins");
+    } else if let Some(state) = read_state(&paths).filter(|state| state.exited_at.is_some()) {
+        let name = server_dir.file_name().unwrap().to_string_lossy();
+        println!("{} {} {}", state.exit_symbol(), name, state.describe_exit());
+        println!("  Exited: {}", local_time(state.exited_at.unwrap_or_default()));
+        print_stats(&paths, &state);
+        if paths.log_file.exists() {
+            println!("  Log: {:?}", paths.log_file);
+        }
+    } else {
+        println!("○ {} not running", server_dir.file_name().unwrap().to_string_lossy());
+    }
+    if let Some(oom) = oom::Oom::load(&paths.oom_file) {
+        println!("  Last out of memory: {}", oom.describe(&server_dir));
+    }
+
+    if let Ok(Some(usage)) = usage.await {
+        println!("  World size: {}", disk::format_size(usage.world));
+        println!("  Directory size: {}", disk::format_size(usage.total));
+    }
+    if let Some((free, total)) = disk::free_space(&server_dir) {
+        println!("  Free space: {} of {}", disk::format_size(free), disk::format_size(total));
+        if let Some(threshold) = threshold.filter(|&threshold| free < threshold) {
+            println!("  ⚠ Free space is below {}", disk::format_size(threshold));
+        }
+    }
+
+    Ok(())
+}
+
+/// Default redraw interval of `status --watch`
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Redraw the status in place every `interval`, like `watch mcwrap status`
+pub async fn watch_status(
+    server_dir: &Path,
+    threshold: Option<u64>,
+    interval: Duration,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    loop {
+        // Home the cursor and clear the screen
+        print!("\x1b[H\x1b[2J");
+        println!(
+            "Every {:?}: mcwrap status {}    {}",
+            interval,
+            server_dir.display(),
+            local_time(unix_now())
+        );
+        println!();
+        if let Err(e) = cmd_status(&server_dir, threshold).await {
+            println!("Error: {:#}", e);
+        }
+        std::io::stdout().flush()?;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Stop the server gracefully
+pub async fn cmd_stop(server_dir: &Path, opts: StopOptions) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    if is_running(&paths).is_none() {
+        if supervisor::is_hibernating(&server_dir).await? {
+            let request = supervisor::Request::Stopping { dir: server_dir };
+            supervisor::request(&request).await?;
+            println!("Server was hibernating; it will no longer be woken.");
+            return Ok(());
+        }
+        paths.clean();
+        bail!(Failure::not_running());
+    }
+    let _lock = paths.lock()?;
+    // Another stop or a restart may have run in between; signal only what runs now
+    let state = is_running(&paths).context(Failure::not_running())?;
+
+    if let Some(warn) = opts.warn {
+        println!("Warning players, stopping in {}...", describe_secs(warn.as_secs()));
+        stop_countdown(&server_dir, warn.as_secs()).await?;
+    }
+    if let Some(message) = &opts.kick {
+        cmd_send(&server_dir, &format!("kick @a {}", message)).await?;
+    }
+
+    println!("Stopping server...");
+
+    // Keep mcwrapd from restarting it
+    let request = supervisor::Request::Stopping {
+        dir: server_dir.clone(),
+    };
+    supervisor::request(&request).await?;
+
+    if let Some(mut control) = ControlClient::connect(&paths).await? {
+        // The daemon replies once the server has exited
+        let params = json!({ "timeout_secs": opts.timeout.as_secs() });
+        match control.call("stop", params).await {
+            Ok(_) => {
+                println!("Server stopped.");
+                paths.clean();
+                return Ok(());
+            }
+            Err(e) if is_stop_timeout(&e) => println!("{}", e),
+            Err(e) => return Err(e),
+        }
+    } else {
+        // Send stop command
+        cmd_send(&server_dir, "stop").await?;
+
+        if wait_for_exit(Pid::from_raw(state.pid), opts.timeout).await {
+            println!("Server stopped.");
+            mark_exited(&paths.state_file, Exit::because(ExitReason::Stopped));
+            paths.clean();
+            return Ok(());
+        }
+    }
+
+    if !opts.then_kill {
+        let message = format!(
+            "Server did not stop within {:?} (use --then-kill or mcwrap kill)",
+            opts.timeout
+        );
+        bail!(Failure::new(ErrorKind::Timeout, message));
+    }
+    terminate(Pid::from_raw(state.pid), KILL_GRACE).await?;
+    mark_exited(&paths.state_file, Exit::because(ExitReason::Killed));
+    paths.clean();
+
+    Ok(())
+}
+
+/// Options for `mcwrap stop`
+pub struct StopOptions {
+    /// Count down in chat for this long first
+    pub warn: Option<Duration>,
+    /// Kick message for players still online
+    pub kick: Option<String>,
+    /// How long the server gets to exit after `stop`
+    pub timeout: Duration,
+    /// Terminate the server if it doesn't exit in time
+    pub then_kill: bool,
+}
+
+/// Time a server gets to exit after SIGTERM when `stop` escalates
+const KILL_GRACE: Duration = Duration::from_secs(10);
+
+/// Terminate the server without asking it to stop first
+pub async fn cmd_kill(server_dir: &Path, grace: Duration) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    is_running(&paths).context(Failure::not_running())?;
+    let _lock = paths.lock()?;
+    // Another stop or a restart may have run in between; signal only what runs now
+    let state = is_running(&paths).context(Failure::not_running())?;
+
+    // Keep mcwrapd from restarting it
+    let request = supervisor::Request::Stopping {
+        dir: server_dir.clone(),
+    };
+    supervisor::request(&request).await?;
+
+    terminate(Pid::from_raw(state.pid), grace).await?;
+    mark_exited(&paths.state_file, Exit::because(ExitReason::Killed));
+    paths.clean();
+    Ok(())
+}
+
+/// Send SIGTERM, then SIGKILL if the process outlives `grace`
+async fn terminate(pid: Pid, grace: Duration) -> Result<()> {
+    println!("Sending SIGTERM...");
+    if pty::signal_group(pid, Signal::SIGTERM).is_ok() && !wait_for_exit(pid, grace).await {
+        println!("Still running after {:?}, sending SIGKILL...", grace);
+        pty::signal_group(pid, Signal::SIGKILL)?;
+        if !wait_for_exit(pid, Duration::from_secs(5)).await {
+            bail!("Process {} survived SIGKILL", pid);
+        }
+    }
+    tokio::task::spawn_blocking(move || pty::end_process_group(pid)).await?;
+    println!("Server killed.");
+    Ok(())
+}
+
+/// Wait up to `timeout` for a process to exit, returning whether it did
+async fn wait_for_exit(pid: Pid, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while kill(pid, None).is_ok() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    true
+}
+
+/// Seconds before a stop at which players are reminded
+const STOP_WARNINGS: &[u64] = &[600, 300, 120, 60, 30, 10, 5, 4, 3, 2, 1];
+
+/// Announce an upcoming stop in chat, returning when it is due
+async fn stop_countdown(server_dir: &Path, total: u64) -> Result<()> {
+    let start = tokio::time::Instant::now();
+    let reminders = STOP_WARNINGS.iter().copied().filter(|&secs| secs < total);
+    for remaining in std::iter::once(total).chain(reminders) {
+        tokio::time::sleep_until(start + Duration::from_secs(total - remaining)).await;
+        let message = format!("say Server stopping in {}", describe_secs(remaining));
+        cmd_send(server_dir, &message).await?;
+    }
+    tokio::time::sleep_until(start + Duration::from_secs(total)).await;
+    Ok(())
+}
+
+/// "1 second", "30 seconds", "5 minutes"
+fn describe_secs(secs: u64) -> String {
+    let (n, unit) = if secs >= 60 && secs.is_multiple_of(60) {
+        (secs / 60, "minute")
+    } else {
+        (secs, "second")
+    };
+    format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" })
+}
+
+fn is_stop_timeout(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<RpcFailure>().is_some_and(|f| f.code == control::STOP_TIMEOUT)
+}
+
+/// Show last N lines of log
+pub fn cmd_log(
+    server_dir: &Path,
+    lines: Option<usize>,
+    since: Option<&str>,
+    plain: bool,
+    level: Option<records::Level>,
+    json: bool,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    if !paths.log_file.exists() {
+        bail!("No log file found");
+    }
+
+    let since = match since {
+        Some(since) => {
+            let zone = Zone::from_config(&Config::load(&server_dir)?.log)?;
+            Some(timestamps::parse_since(since, zone)?)
+        }
+        None => None,
+    };
+    // With an index only the end of the log is read
+    let found = since.and_then(|since| log_index::find(&paths.log_file, since));
+    let mut content = match &found {
+        Some(found) => log_index::read(&paths.log_file, found.before)?,
+        None => jsonl::read(&paths.log_file)?,
+    };
+    let stamped = content.lines().any(|line| timestamps::line_time(line).is_some());
+    if let (Some(found), false) = (&found, stamped) {
+        content = log_index::read(&paths.log_file, found.after)?;
+    }
+    let mut all_lines: Vec<&str> = content.lines().collect();
+    if let Some(since) = since {
+        if stamped {
+            // Lines without a timestamp go with the one before them
+            let mut time = None;
+            all_lines.retain(|line| {
+                time = timestamps::line_time(line).or(time);
+                time.is_some_and(|time| time >= since)
+            });
+        } else if found.is_none() {
+            bail!("The log has no timestamps (turn them on with [log] timestamps = true)");
+        }
+    }
+    let lines = lines.unwrap_or(if since.is_some() { usize::MAX } else { 100 });
+    let plain = plain || !std::io::stdout().is_terminal();
+
+    if level.is_some() || json {
+        let mut records = records::parse(all_lines);
+        if let Some(level) = level {
+            records.retain(|record| record.level >= Some(level));
+        }
+        let start = records.len().saturating_sub(lines);
+        for record in &records[start..] {
+            if json {
+                println!("{}", serde_json::to_string(record)?);
+                continue;
+            }
+            for line in &record.lines {
+                if plain {
+                    println!("{}", console::strip_ansi(line));
+                } else {
+                    println!("{}", line);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let start = all_lines.len().saturating_sub(lines);
+    for line in &all_lines[start..] {
+        if plain {
+            println!("{}", console::strip_ansi(line));
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+/// Tail the log file, with only the lines `grep` matches and `exclude` doesn't
+pub async fn cmd_tail(
+    server_dir: &Path,
+    plain: bool,
+    mut highlighter: Highlighter,
+    grep: Option<Regex>,
+    exclude: Option<Regex>,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    if !paths.log_file.exists() {
+        bail!("No log file found");
+    }
+
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let plain = plain || !std::io::stdout().is_terminal();
+    // Filtering needs whole lines, and so does stripping colors, so no color code is cut in two
+    let by_line = plain || grep.is_some() || exclude.is_some();
+    let mut partial = String::new();
+    let mut renderer = jsonl::Renderer::default();
+    let mut follower = Follower::new(&paths.log_file);
+    loop {
+        let data = tokio::select! {
+            data = follower.next() => data?,
+            _ = sigint.recv() => break,
+        };
+        let buf = renderer.push(&String::from_utf8_lossy(&data));
+        if by_line {
+            partial.push_str(&buf);
+            let end = partial.rfind('\n').map_or(0, |end| end + 1);
+            for line in partial[..end].lines() {
+                let text = console::strip_ansi(line);
+                let wanted = grep.as_ref().is_none_or(|grep| grep.is_match(&text))
+                    && !exclude.as_ref().is_some_and(|exclude| exclude.is_match(&text));
+                if !wanted {
+                    continue;
+                }
+                if plain {
+                    println!("{}", text);
+                } else {
+                    println!("{}", highlighter.line(line));
+                }
+            }
+            partial.drain(..end);
+        } else {
+            std::io::stdout().write_all(&highlighter.push(buf.as_bytes()))?;
+        }
+        std::io::stdout().flush()?;
+    }
+
+    Ok(())
+}
+
+/// Print the start counts and last crash for `status`
+fn print_stats(paths: &ServerPaths, state: &ServerState) {
+    let stats = Stats::load(&paths.stats_file);
+    println!("  Starts: {} ({} restarts by mcwrapd)", stats.starts, stats.restarts);
+    if let Some(at) = state.crashed_at().or(stats.last_crash) {
+        println!("  Last crash: {}", local_time(at));
+    }
+}
+
+/// Compact duration for uptimes, e.g. "3d 4h" or "5m 10s"
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m {}s", mins, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// List all managed servers
+pub fn cmd_list(verbose: bool) -> Result<()> {
+    let wrap_base = wrap_base();
+
+    if !wrap_base.exists() {
+        println!("No servers managed.");
+        return Ok(());
+    }
+
+    let mut found = false;
+    for entry in fs::read_dir(&wrap_base)? {
+        let entry = entry?;
+        let state_file = entry.path().join("state.json");
+        if let Ok(file) = File::open(&state_file) {
+            if let Ok(state) = serde_json::from_reader::<_, ServerState>(file) {
+                let is_alive =
+                    state.exited_at.is_none() && kill(Pid::from_raw(state.pid), None).is_ok();
+                let mode = state.mode();
+                if verbose {
+                    if !found {
+                        println!(
+                            "  {:>7}  {:<5}  {:>7}  {:>6}  {:>8}  {:<19}  SERVER",
+                            "PID", "MODE", "UPTIME", "STARTS", "RESTARTS", "LAST CRASH"
+                        );
+                    }
+                    let stats = Stats::load(&entry.path().join("stats.json"));
+                    let last_crash = state.crashed_at().or(stats.last_crash);
+                    let (symbol, pid, uptime, note) = if is_alive {
+                        let uptime = format_uptime(unix_now().saturating_sub(state.started_at));
+                        ("●", state.pid.to_string(), uptime, String::new())
+                    } else {
+                        let note = format!(" ({})", state.describe_exit());
+                        (state.exit_symbol(), "-".to_string(), "-".to_string(), note)
+                    };
+                    println!(
+                        "{} {:>7}  {:<5}  {:>7}  {:>6}  {:>8}  {:<19}  {}{}",
+                        symbol,
+                        pid,
+                        mode,
+                        uptime,
+                        stats.starts,
+                        stats.restarts,
+                        last_crash.map_or("-".to_string(), local_time),
+                        state.server_dir.display(),
+                        note
+                    );
+                } else if is_alive {
+                    println!("● {} (PID: {}, {})", state.server_dir.display(), state.pid, mode);
+                } else {
+                    println!(
+                        "{} {} ({})",
+                        state.exit_symbol(),
+                        state.server_dir.display(),
+                        state.describe_exit()
+                    );
+                }
+                found = true;
+            }
+        }
+    }
+
+    if !found {
+        println!("No servers managed.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,244 @@
+//! An index of the console log by time
+//!
+//! A busy server's console log runs to hundreds of MB, all of which `mcwrap
+//! log --since 2h` would otherwise read to find the last two hours. With
+//! `[log] index = true` the server also writes `console.idx` next to the log:
+//! a record of the time and of how long the log was then, at most once a
+//! second while there is output. `log --since` looks the time up in it and
+//! reads the log only from there. It also places the lines of a log without
+//! `[log] timestamps`, to within a second. The index moves to `logs/` with
+//! the log when the run is archived.
+
+use crate::jsonl;
+use chrono::{DateTime, FixedOffset, Utc};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Size of a record: the time in milliseconds since the epoch, then the log's length, both
+/// little-endian
+const RECORD: u64 = 16;
+
+/// The least time between two records
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the index of a log is kept
+pub fn path(log_file: &Path) -> PathBuf {
+    log_file.with_extension("idx")
+}
+
+/// Adds records to a log's index as the log is written
+pub struct Indexer {
+    file: File,
+    last: Option<Instant>,
+}
+
+impl Indexer {
+    /// Open the index of `log`, which is at `log_file`, starting it over if the log is new
+    pub fn open(log_file: &Path, log: &File) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path(log_file))?;
+        if log.metadata()?.len() == 0 {
+            file.set_len(0)?;
+        }
+        Ok(Self { file, last: None })
+    }
+
+    /// Note that what is written to `log` from now on is output from now on
+    pub fn mark(&mut self, log: &File) -> io::Result<()> {
+        if self.last.is_some_and(|last| last.elapsed() < INTERVAL) {
+            return Ok(());
+        }
+        self.last = Some(Instant::now());
+        let mut record = [0u8; RECORD as usize];
+        record[..8].copy_from_slice(&Utc::now().timestamp_millis().to_le_bytes());
+        record[8..].copy_from_slice(&log.metadata()?.len().to_le_bytes());
+        self.file.write_all(&record)
+    }
+}
+
+/// Where in a log the output of a given time starts
+pub struct Found {
+    /// Everything after this was logged at that time or later
+    pub after: u64,
+    /// Everything before this was logged earlier, and what lies between the two within a second
+    /// of it
+    pub before: u64,
+}
+
+/// Look `since` up in the index of the log at `log_file`, if it has one that fits the log
+pub fn find(log_file: &Path, since: DateTime<FixedOffset>) -> Option<Found> {
+    let mut index = File::open(path(log_file)).ok()?;
+    let count = index.metadata().ok()?.len() / RECORD;
+    let len = log_file.metadata().ok()?.len();
+    if count == 0 {
+        return None;
+    }
+    let since = since.timestamp_millis();
+    let mut record = |i: u64| -> Option<(i64, u64)> {
+        let mut bytes = [0u8; RECORD as usize];
+        index.seek(SeekFrom::Start(i * RECORD)).ok()?;
+        index.read_exact(&mut bytes).ok()?;
+        let time = i64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let offset = u64::from_le_bytes(bytes[8..].try_into().unwrap());
+        Some((time, offset))
+    };
+    // The first record at `since` or later
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if record(middle)?.0 < since {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    let after = if low < count { record(low)?.1 } else { len };
+    let before = if low > 0 { record(low - 1)?.1 } else { 0 };
+    // An index longer than its log belongs to some other log
+    if after > len || before > after {
+        return None;
+    }
+    Some(Found { after, before })
+}
+
+/// The log at `log_file` as text from `offset` on, without the line `offset` falls inside of
+pub fn read(log_file: &Path, offset: u64) -> io::Result<String> {
+    let mut file = File::open(log_file)?;
+    file.seek(SeekFrom::Start(offset.saturating_sub(1)))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    if offset > 0 {
+        // The byte before `offset` says whether a line starts there
+        let start = data.iter().position(|&byte| byte == b'\n').map_or(data.len(), |i| i + 1);
+        data.drain(..start);
+    }
+    Ok(jsonl::text(String::from_utf8_lossy(&data).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs;
+    use std::fs;
+
+    /// A scratch directory, removed on drop
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let name = format!("mcwrap-test-log-index-{}-{}", name, std::process::id());
+            let dir = std::env::temp_dir().join(name);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    /// Write a log and an index of (time, offset) records for it
+    fn write(log_file: &Path, log: &str, records: &[(&str, u64)]) {
+        fs::write(log_file, log).unwrap();
+        let mut index = Vec::new();
+        for (time, offset) in records {
+            index.extend(at(time).timestamp_millis().to_le_bytes());
+            index.extend(offset.to_le_bytes());
+        }
+        fs::write(path(log_file), index).unwrap();
+    }
+
+    fn found(log_file: &Path, since: &str) -> Option<(u64, u64)> {
+        find(log_file, at(since)).map(|found| (found.before, found.after))
+    }
+
+    const LOG: &str = "[10:00:00] one\n[10:00:01] two\n[10:00:02] three\n";
+
+    const RECORDS: [(&str, u64); 3] = [
+        ("2026-01-14T10:00:00Z", 0),
+        ("2026-01-14T10:00:01Z", 15),
+        ("2026-01-14T10:00:02Z", 30),
+    ];
+
+    #[test]
+    fn lookup() {
+        let scratch = Scratch::new("lookup");
+        let log_file = scratch.0.join("console.log");
+        write(&log_file, LOG, &RECORDS);
+
+        assert_eq!(found(&log_file, "2026-01-14T09:00:00Z"), Some((0, 0)));
+        assert_eq!(found(&log_file, "2026-01-14T10:00:01Z"), Some((0, 15)));
+        assert_eq!(found(&log_file, "2026-01-14T10:00:01.500Z"), Some((15, 30)));
+        assert_eq!(found(&log_file, "2026-01-14T11:00:00Z"), Some((30, 47)));
+        assert_eq!(read(&log_file, 15).unwrap(), "[10:00:01] two\n[10:00:02] three\n");
+        // From inside a line, the next whole one
+        assert_eq!(read(&log_file, 20).unwrap(), "[10:00:02] three\n");
+        assert_eq!(read(&log_file, 47).unwrap(), "");
+    }
+
+    #[test]
+    fn indexer() {
+        let scratch = Scratch::new("indexer");
+        let log_file = scratch.0.join("console.log");
+        fs::write(&log_file, "one\n").unwrap();
+        let log = File::open(&log_file).unwrap();
+        let mut indexer = Indexer::open(&log_file, &log).unwrap();
+        indexer.mark(&log).unwrap();
+        // At most one record a second
+        indexer.mark(&log).unwrap();
+        let index = fs::read(path(&log_file)).unwrap();
+        assert_eq!(index.len() as u64, RECORD);
+        assert_eq!(index[8..], 4u64.to_le_bytes());
+
+        // A new log starts a new index
+        fs::write(&log_file, "").unwrap();
+        Indexer::open(&log_file, &log).unwrap();
+        assert_eq!(fs::metadata(path(&log_file)).unwrap().len(), 0);
+        assert!(find(&log_file, at("2026-01-14T10:00:00Z")).is_none());
+    }
+
+    #[test]
+    fn rotation() {
+        let scratch = Scratch::new("rotation");
+        let log_file = scratch.0.join("console.log");
+        let logs_dir = scratch.0.join("logs");
+        write(&log_file, LOG, &RECORDS);
+
+        // The index goes along with its log into the archive
+        logs::archive(&log_file, &logs_dir, Some(1_768_384_800));
+        let run = logs::runs(&logs_dir).pop().unwrap();
+        assert!(!path(&log_file).exists());
+        assert_eq!(found(&run, "2026-01-14T10:00:01.500Z"), Some((15, 30)));
+
+        // The next run's log has no index yet
+        fs::write(&log_file, "[11:00:00] new\n").unwrap();
+        assert!(found(&log_file, "2026-01-14T10:00:01Z").is_none());
+
+        // Nor does it fit the last run's, should that be left behind
+        fs::copy(path(&run), path(&log_file)).unwrap();
+        assert!(found(&log_file, "2026-01-14T10:00:01.500Z").is_none());
+        assert!(found(&log_file, "2026-01-14T11:00:00Z").is_none());
+    }
+
+    #[test]
+    fn partial_last_line() {
+        let scratch = Scratch::new("partial");
+        let log_file = scratch.0.join("console.log");
+        // The server is halfway through a line, and the index through a record
+        write(&log_file, "[10:00:00] one\n[10:00:01] tw", &RECORDS[..2]);
+        let mut index = fs::OpenOptions::new().append(true).open(path(&log_file)).unwrap();
+        index.write_all(&[0xff; 7]).unwrap();
+
+        assert_eq!(found(&log_file, "2026-01-14T10:00:01Z"), Some((0, 15)));
+        assert_eq!(found(&log_file, "2026-01-14T10:00:05Z"), Some((15, 28)));
+        assert_eq!(read(&log_file, 15).unwrap(), "[10:00:01] tw");
+        assert_eq!(read(&log_file, 20).unwrap(), "");
+    }
+}
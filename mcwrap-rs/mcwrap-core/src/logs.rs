@@ -0,0 +1,111 @@
+//! Console logs of previous runs
+//!
+//! When a server's runtime state is cleaned up, its console.log is moved to
+//! `logs/run-<start time>.log` in the wrap dir instead of being deleted, so
+//! the output of a crashed or stopped server is still there for a post-mortem,
+//! along with its index if it has one.
+//! `mcwrap logs` lists the archived runs and shows one of them.
+
+use anyhow::{bail, Context, Result};
+use crate::{jsonl, log_index};
+use chrono::{DateTime, Local, NaiveDateTime};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Number of archived runs kept per server
+const KEEP_RUNS: usize = 20;
+
+const NAME_FORMAT: &str = "run-%Y%m%d-%H%M%S";
+
+/// Move a finished run's console log into the archive
+///
+/// `started_at` names the archive; without it the log's modification time is used.
+pub fn archive(log_file: &Path, logs_dir: &Path, started_at: Option<u64>) {
+    let Ok(metadata) = fs::metadata(log_file) else {
+        return;
+    };
+    if metadata.len() == 0 {
+        return;
+    }
+    let started_at = started_at.or_else(|| {
+        let modified = metadata.modified().ok()?;
+        Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+    });
+    let Some(started) = started_at.and_then(|at| DateTime::from_timestamp(at as i64, 0)) else {
+        return;
+    };
+    if fs::create_dir_all(logs_dir).is_err() {
+        return;
+    }
+
+    let name = started.with_timezone(&Local).format(NAME_FORMAT).to_string();
+    let mut path = logs_dir.join(format!("{}.log", name));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = logs_dir.join(format!("{}-{}.log", name, n));
+    }
+    if fs::rename(log_file, &path).is_ok() {
+        let _ = fs::rename(log_index::path(log_file), log_index::path(&path));
+        prune(logs_dir);
+    }
+}
+
+/// Archived runs, newest first
+pub fn runs(logs_dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(logs_dir) else {
+        return Vec::new();
+    };
+    let mut runs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+    // The names sort chronologically
+    runs.sort();
+    runs.reverse();
+    runs
+}
+
+fn prune(logs_dir: &Path) {
+    for old in runs(logs_dir).into_iter().skip(KEEP_RUNS) {
+        let _ = fs::remove_file(log_index::path(&old));
+        let _ = fs::remove_file(old);
+    }
+}
+
+/// Start time of an archived run, from its name
+pub fn started(run: &Path) -> Option<NaiveDateTime> {
+    let stem = run.file_stem()?.to_string_lossy();
+    // Runs that started within the same second have a suffix
+    let (started, _) = NaiveDateTime::parse_and_remainder(&stem, NAME_FORMAT).ok()?;
+    Some(started)
+}
+
+/// List the archived runs, or show run `run` (1 = the most recent)
+pub fn cmd_logs(server_dir: &Path, run: Option<usize>) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = crate::ServerPaths::new(&server_dir);
+    let runs = runs(&paths.logs_dir);
+
+    if let Some(n) = run {
+        let Some(path) = n.checked_sub(1).and_then(|i| runs.get(i)) else {
+            bail!("No archived run {}", n);
+        };
+        let content = jsonl::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        print!("{}", content);
+        return Ok(());
+    }
+
+    if runs.is_empty() {
+        println!("No archived runs yet.");
+    }
+    for (i, path) in runs.iter().enumerate() {
+        let started = started(path).map_or_else(String::new, |at| at.to_string());
+        let kb = fs::metadata(path).map_or(0, |m| m.len()).div_ceil(1024);
+        println!("{:>3}  {:<19}  {:>8} KB  {}", i + 1, started, kb, path.display());
+    }
+
+    Ok(())
+}
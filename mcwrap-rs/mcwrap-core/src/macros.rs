@@ -0,0 +1,155 @@
+//! Named sequences of console commands
+//!
+//! Macros are defined in the config and run with `mcwrap macro`:
+//!
+//! ```toml
+//! [macros.prep-event]
+//! description = "Clear and announce the event arena"
+//! vars = { arena = "1" }
+//! steps = [
+//!     "say Event starting in arena ${arena} in 30 seconds",
+//!     { wait = "30s" },
+//!     "fill ${x1} 64 ${z1} ${x2} 80 ${z2} air",
+//!     "say Go!",
+//! ]
+//! ```
+//!
+//! `mcwrap macro <dir> prep-event --var arena=3 --var x1=0 ...` fills in
+//! `${name}` from `--var`, falling back to the macro's `vars`, and sends the
+//! steps in order through the command queue. Every variable must have a value
+//! before anything is sent, so a typo can't leave a macro half run.
+
+use anyhow::{bail, Context, Result};
+use crate::config::{Config, MacroConfig, MacroStep};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+static VARIABLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{([A-Za-z0-9_-]+)\}").unwrap());
+
+/// A macro step with its variables filled in
+enum Step {
+    Command(String),
+    Wait(Duration),
+}
+
+/// List the macros, or run one
+pub async fn cmd_macro(
+    server_dir: &Path,
+    name: Option<&str>,
+    vars: Vec<(String, String)>,
+    dry_run: bool,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let macros = Config::load(&server_dir)?.macros;
+
+    let Some(name) = name else {
+        list(&macros);
+        return Ok(());
+    };
+    let Some(definition) = macros.get(name) else {
+        bail!("No macro named {:?} (see `mcwrap macro {}`)", name, server_dir.display());
+    };
+    let steps = expand(name, definition, vars.into_iter().collect())?;
+
+    for step in steps {
+        match step {
+            Step::Command(command) => {
+                println!("> {}", command);
+                if !dry_run {
+                    crate::send_command(&server_dir, &command, true)
+                        .await
+                        .with_context(|| format!("Failed to send {:?}", command))?;
+                }
+            }
+            Step::Wait(duration) => {
+                println!("(waiting {:?})", duration);
+                if !dry_run {
+                    tokio::time::sleep(duration).await;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list(macros: &BTreeMap<String, MacroConfig>) {
+    if macros.is_empty() {
+        println!("No macros. Define them in mcwrap.toml, e.g.:");
+        println!("  [macros.restart-warning]");
+        println!("  steps = [\"say Restarting in 1 minute\", {{ wait = \"1m\" }}, \"stop\"]");
+        return;
+    }
+    let width = macros.keys().map(String::len).max().unwrap_or(0);
+    for (name, definition) in macros {
+        let vars = variables(definition);
+        let vars = if vars.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", vars.into_iter().collect::<Vec<_>>().join(", "))
+        };
+        let description = definition.description.as_deref().unwrap_or("");
+        let line = format!("{:<width$}  {}{}", name, description, vars, width = width);
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Names of the variables a macro uses
+fn variables(definition: &MacroConfig) -> BTreeSet<String> {
+    definition
+        .steps
+        .iter()
+        .filter_map(|step| match step {
+            MacroStep::Command(command) => Some(command),
+            MacroStep::Wait { .. } => None,
+        })
+        .flat_map(|command| VARIABLE.captures_iter(command).map(|c| c[1].to_string()))
+        .collect()
+}
+
+/// Check a macro and fill in its variables
+fn expand(
+    name: &str,
+    definition: &MacroConfig,
+    vars: BTreeMap<String, String>,
+) -> Result<Vec<Step>> {
+    let used = variables(definition);
+    if let Some(unknown) = vars.keys().find(|var| !used.contains(*var)) {
+        bail!("Macro {} has no variable {:?}", name, unknown);
+    }
+    let mut values = definition.vars.clone();
+    values.extend(vars);
+    let missing: Vec<&String> = used.iter().filter(|var| !values.contains_key(*var)).collect();
+    if !missing.is_empty() {
+        let flags: Vec<String> = missing.iter().map(|var| format!("--var {}=...", var)).collect();
+        bail!("Macro {} needs {}", name, flags.join(" "));
+    }
+
+    definition
+        .steps
+        .iter()
+        .map(|step| match step {
+            MacroStep::Command(command) => {
+                let command = VARIABLE.replace_all(command, |c: &regex::Captures| {
+                    values[&c[1]].clone()
+                });
+                Ok(Step::Command(command.into_owned()))
+            }
+            MacroStep::Wait { wait } => crate::parse_duration(wait)
+                .map(Step::Wait)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Invalid wait in macro {}", name)),
+        })
+        .collect()
+}
+
+/// Parse a `--var NAME=VALUE` argument
+pub fn parse_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("expected NAME=VALUE, got {:?}", arg)),
+    }
+}
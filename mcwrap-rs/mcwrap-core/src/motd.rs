@@ -0,0 +1,277 @@
+//! The message of the day shown under a server in the multiplayer list
+//!
+//! `mcwrap motd <dir> "Welcome\n&bSurvival"` takes `\n` for the line break
+//! and `&` color and format codes (`&a`, `&l`, ...; `\&` for a plain `&`),
+//! writes the MOTD to `server.properties` escaped the way the server reads
+//! it, and shows how the client will render it. Without a message, it shows
+//! the current one.
+
+use anyhow::{Context, Result};
+use crate::properties::Properties;
+use crate::{is_running, ServerPaths};
+use std::io::IsTerminal;
+use std::path::Path;
+
+/// Characters of a line the server list shows before cutting it off, roughly
+const LINE_WIDTH: usize = 45;
+
+/// The section sign that starts a code in Minecraft text
+const SECTION: char = '§';
+
+/// The MOTD's color until a code changes it
+const DEFAULT_COLOR: &str = "\x1b[37m";
+
+pub fn cmd_motd(server_dir: &Path, text: Option<&str>, dry_run: bool) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let mut properties = Properties::load(&server_dir)?;
+
+    let Some(text) = text else {
+        let motd = unescape(properties.get("motd").unwrap_or("A Minecraft Server"));
+        preview(&motd);
+        return Ok(());
+    };
+    let motd = parse(text);
+    preview(&motd);
+
+    let lines: Vec<&str> = motd.lines().collect();
+    if lines.len() > 2 {
+        println!("Only the first two lines show in the server list.");
+    }
+    for (n, line) in lines.iter().take(2).enumerate() {
+        let width = plain(line).chars().count();
+        if width > LINE_WIDTH {
+            println!("Line {} is {} characters long and may be cut off.", n + 1, width);
+        }
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    properties.set("motd", &escape(&motd));
+    properties.save()?;
+    if is_running(&ServerPaths::new(&server_dir)).is_some() {
+        println!("Saved; the server reads its MOTD at startup, so restart it to show the new one.");
+    } else {
+        println!("Saved to server.properties");
+    }
+    Ok(())
+}
+
+/// Turn `\n` into line breaks and `&` codes into section-sign codes
+fn parse(text: &str) -> String {
+    let mut motd = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek().copied()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                motd.push('\n');
+            }
+            ('\\', Some('&')) => {
+                chars.next();
+                motd.push('&');
+            }
+            ('&', Some(code)) if is_code(code) => {
+                chars.next();
+                motd.push(SECTION);
+                motd.push(code.to_ascii_lowercase());
+            }
+            _ => motd.push(c),
+        }
+    }
+    motd
+}
+
+fn is_code(c: char) -> bool {
+    matches!(c.to_ascii_lowercase(), '0'..='9' | 'a'..='f' | 'k'..='o' | 'r')
+}
+
+/// A value as `server.properties` stores it: escaped, with everything but ASCII as `\uXXXX`
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            // Leading spaces would be taken as part of the separator
+            ' ' if i == 0 => escaped.push_str("\\ "),
+            ' '..='~' => escaped.push(c),
+            _ => {
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    escaped.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    escaped
+}
+
+/// A value read from `server.properties`, with its escapes resolved
+fn unescape(value: &str) -> String {
+    let mut units = Vec::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            units.extend(c.encode_utf16(&mut [0u16; 2]).iter());
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                if let Ok(unit) = u16::from_str_radix(&hex, 16) {
+                    units.push(unit);
+                }
+                continue;
+            }
+            Some(other) => other,
+            None => break,
+        };
+        units.extend(escaped.encode_utf16(&mut [0u16; 2]).iter());
+    }
+    String::from_utf16_lossy(&units)
+}
+
+/// Text without its codes
+fn plain(text: &str) -> String {
+    let mut plain = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == SECTION {
+            chars.next();
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// Show the MOTD the way the server list renders it, in color on a terminal
+fn preview(motd: &str) {
+    let lines: Vec<&str> = motd.lines().take(2).collect();
+    if !std::io::stdout().is_terminal() {
+        for line in lines {
+            println!("  {}", plain(line));
+        }
+        return;
+    }
+
+    for line in render(&lines) {
+        println!("  {}", line);
+    }
+}
+
+/// Lines of a MOTD with their codes turned into the terminal's
+fn render(lines: &[&str]) -> Vec<String> {
+    let mut rendered_lines = Vec::new();
+    // Codes carry over to the next line
+    let mut style = DEFAULT_COLOR.to_string();
+    for line in lines {
+        let mut rendered = style.clone();
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c != SECTION {
+                rendered.push(c);
+                continue;
+            }
+            let Some(code) = chars.next().map(|code| code.to_ascii_lowercase()) else {
+                break;
+            };
+            match ansi(code) {
+                // A color resets the formats before it
+                Some(ansi) if code.is_ascii_hexdigit() => style = format!("\x1b[0m{}", ansi),
+                Some(ansi) => style.push_str(ansi),
+                None if code == 'r' => style = format!("\x1b[0m{}", DEFAULT_COLOR),
+                None => continue,
+            }
+            rendered.push_str(&style);
+        }
+        rendered.push_str("\x1b[0m");
+        rendered_lines.push(rendered);
+    }
+    rendered_lines
+}
+
+/// The terminal's version of a color or format code
+fn ansi(code: char) -> Option<&'static str> {
+    Some(match code {
+        '0' => "\x1b[30m",
+        '1' => "\x1b[34m",
+        '2' => "\x1b[32m",
+        '3' => "\x1b[36m",
+        '4' => "\x1b[31m",
+        '5' => "\x1b[35m",
+        '6' => "\x1b[33m",
+        '7' => "\x1b[37m",
+        '8' => "\x1b[90m",
+        '9' => "\x1b[94m",
+        'a' => "\x1b[92m",
+        'b' => "\x1b[96m",
+        'c' => "\x1b[91m",
+        'd' => "\x1b[95m",
+        'e' => "\x1b[93m",
+        'f' => "\x1b[97m",
+        'l' => "\x1b[1m",
+        'm' => "\x1b[9m",
+        'n' => "\x1b[4m",
+        'o' => "\x1b[3m",
+        // Obfuscated text cycles through random characters; blinking is the nearest
+        'k' => "\x1b[5m",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ampersand_codes() {
+        assert_eq!(parse("&aWelcome\\n&LSurvival"), "§aWelcome\n§lSurvival");
+        assert_eq!(parse("Fish \\& chips &r"), "Fish & chips §r");
+        // Not codes, so left alone
+        assert_eq!(parse("&z & &"), "&z & &");
+        assert_eq!(parse(""), "");
+    }
+
+    #[test]
+    fn section_codes() {
+        assert_eq!(plain("§aHello §lworld§r!"), "Hello world!");
+        assert_eq!(plain("§zunknown"), "unknown");
+        assert_eq!(plain("dangling §"), "dangling ");
+        assert_eq!(plain(""), "");
+
+        assert_eq!(render(&["§aHi"]), ["\x1b[37m\x1b[0m\x1b[92mHi\x1b[0m"]);
+        // Formats add to the color, and carry over to the next line
+        assert_eq!(
+            render(&["§c§lA", "B§rC"]),
+            [
+                "\x1b[37m\x1b[0m\x1b[91m\x1b[0m\x1b[91m\x1b[1mA\x1b[0m",
+                "\x1b[0m\x1b[91m\x1b[1mB\x1b[0m\x1b[37mC\x1b[0m",
+            ]
+        );
+        // Unknown codes are dropped, as the client does
+        assert_eq!(render(&["§zHi§"]), ["\x1b[37mHi\x1b[0m"]);
+        assert!(render(&[]).is_empty());
+        assert_eq!(render(&[""]), ["\x1b[37m\x1b[0m"]);
+    }
+
+    #[test]
+    fn properties_escapes() {
+        let motd = " §aCafé\n§7Survival \\o/";
+        let escaped = escape(motd);
+        assert_eq!(escaped, "\\ \\u00a7aCaf\\u00e9\\n\\u00a77Survival \\\\o/");
+        assert_eq!(unescape(&escaped), motd);
+        // Outside the BMP, as a surrogate pair
+        assert_eq!(escape("🙂"), "\\ud83d\\ude42");
+        assert_eq!(unescape("\\ud83d\\ude42"), "🙂");
+        assert_eq!(escape(""), "");
+        assert_eq!(unescape(""), "");
+        assert_eq!(unescape("trailing\\"), "trailing");
+    }
+}
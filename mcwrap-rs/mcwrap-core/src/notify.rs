@@ -0,0 +1,330 @@
+//! Notifications about server events
+//!
+//! Events chosen in `[notify] events` (by default starts, stops, crashes,
+//! failed backups and player milestones) are posted to every configured
+//! service: as an embed to a Discord webhook, as a message from a Telegram
+//! bot, or as an attachment to a Slack webhook. For anything else, the event
+//! JSON can be POSTed to plain webhooks, or handed to a script.
+//!
+//! ```toml
+//! [notify]
+//! player_milestones = [10, 25]
+//!
+//! [notify.discord]
+//! webhook = "https://discord.com/api/webhooks/..."
+//!
+//! [notify.telegram]
+//! token = "123456:ABC..."
+//! chat_id = -1001234567890
+//!
+//! [notify.slack]
+//! webhook = "https://hooks.slack.com/services/..."
+//!
+//! [notify.webhook]
+//! urls = ["https://example.com/minecraft-events"]
+//!
+//! [notify.exec]
+//! command = ["./on-event.sh"]
+//! ```
+//!
+//! The webhooks get the event as `mcwrap events --json` shows it, with the
+//! server name added, e.g. `{"at":1700000000,"type":"Crashed","exit_code":1,
+//! "out_of_memory":false,"server":"survival"}`; player milestones come as
+//! `{"type":"PlayerMilestone","online":10,"player":"Steve",...}`. The script
+//! runs in the server directory with each of those fields in an environment
+//! variable (`MCWRAP_TYPE`, `MCWRAP_SERVER`, `MCWRAP_EXIT_CODE`...), the
+//! whole JSON in `MCWRAP_EVENT` and a readable summary in `MCWRAP_MESSAGE`.
+//!
+//! Like all settings this can go in the global config, to cover every server,
+//! or in a server's `mcwrap.toml`. Services are reached with curl.
+
+use anyhow::{bail, Context, Result};
+use crate::config::{Config, NotifyConfig, NotifyEvent, TelegramConfig};
+use crate::events::{Event, EventKind};
+use crate::http;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest a `[notify.exec]` script or trigger hook may run before it is killed
+const EXEC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Embed colors
+const GREEN: u32 = 0x2ecc71;
+const GREY: u32 = 0x95a5a6;
+const RED: u32 = 0xe74c3c;
+const ORANGE: u32 = 0xe67e22;
+const BLUE: u32 = 0x3498db;
+
+/// Sends notifications for one server
+#[derive(Clone)]
+pub struct Notifier {
+    server: String,
+    server_dir: PathBuf,
+    events: Vec<NotifyEvent>,
+    milestones: Vec<usize>,
+    discord: Option<String>,
+    telegram: Option<TelegramConfig>,
+    slack: Option<String>,
+    webhooks: Vec<String>,
+    exec: Vec<String>,
+}
+
+/// What a notification says, whichever service it goes to
+struct Message {
+    title: String,
+    color: u32,
+    /// Details as name and value
+    fields: Vec<(&'static str, String)>,
+    at: u64,
+    /// The event as JSON, for webhooks and scripts
+    data: Value,
+}
+
+impl Notifier {
+    /// The notifier for a server, if any service is configured
+    pub fn from_config(config: NotifyConfig, server_dir: &Path) -> Option<Self> {
+        let discord = config.discord.map(|discord| discord.webhook);
+        let slack = config.slack.map(|slack| slack.webhook);
+        let webhooks = config.webhook.map(|webhook| webhook.urls).unwrap_or_default();
+        let exec = config.exec.map(|exec| exec.command).unwrap_or_default();
+        if discord.is_none()
+            && config.telegram.is_none()
+            && slack.is_none()
+            && webhooks.is_empty()
+            && exec.is_empty()
+        {
+            return None;
+        }
+        let server = config.name.unwrap_or_else(|| crate::server_name(server_dir));
+        Some(Self {
+            server,
+            server_dir: server_dir.to_path_buf(),
+            events: config.events,
+            milestones: config.player_milestones,
+            discord,
+            telegram: config.telegram,
+            slack,
+            webhooks,
+            exec,
+        })
+    }
+
+    /// Notify about an event, if it is one of those asked for
+    ///
+    /// `online` is the number of players online after it, when known. A
+    /// service failing doesn't keep the others from being notified.
+    pub fn notify(&self, event: &Event, online: Option<usize>) -> Result<()> {
+        let mut messages = Vec::new();
+        if topic(&event.kind).is_none_or(|topic| self.events.contains(&topic)) {
+            messages.push(self.message(event));
+        }
+        let milestone = online.filter(|online| self.milestones.contains(online));
+        if let (EventKind::PlayerJoined { player }, Some(online)) = (&event.kind, milestone) {
+            if self.events.contains(&NotifyEvent::PlayerMilestone) {
+                let plural = if online == 1 { "" } else { "s" };
+                messages.push(Message {
+                    title: format!("{} player{} online", online, plural),
+                    color: GREEN,
+                    fields: vec![("Server", self.server.clone()), ("Joined", player.clone())],
+                    at: event.at,
+                    data: json!({
+                        "at": event.at,
+                        "type": "PlayerMilestone",
+                        "online": online,
+                        "player": player,
+                        "server": self.server,
+                    }),
+                });
+            }
+        }
+
+        let mut failures = Vec::new();
+        for message in &messages {
+            for (service, result) in self.send(message) {
+                if let Err(e) = result {
+                    failures.push(format!("{}: {:#}", service, e));
+                }
+            }
+        }
+        if !failures.is_empty() {
+            bail!("{}", failures.join("; "));
+        }
+        Ok(())
+    }
+
+    /// Post a message to every configured service
+    fn send(&self, message: &Message) -> Vec<(&'static str, Result<()>)> {
+        let post_json = |url: &str, body: &Value| {
+            http::post(url, "application/json", body.to_string().as_bytes()).map(drop)
+        };
+        let mut results = Vec::new();
+        if let Some(webhook) = &self.discord {
+            results.push(("Discord", post_json(webhook, &discord_payload(message))));
+        }
+        if let Some(telegram) = &self.telegram {
+            let url = format!("https://api.telegram.org/bot{}/sendMessage", telegram.token);
+            let payload = json!({ "chat_id": telegram.chat_id, "text": message.text() });
+            results.push(("Telegram", post_json(&url, &payload)));
+        }
+        if let Some(webhook) = &self.slack {
+            results.push(("Slack", post_json(webhook, &slack_payload(message))));
+        }
+        for url in &self.webhooks {
+            results.push(("Webhook", post_json(url, &message.data)));
+        }
+        if !self.exec.is_empty() {
+            results.push(("Script", self.run_script(message)));
+        }
+        results
+    }
+
+    /// Run the `[notify.exec]` script with the message in its environment
+    fn run_script(&self, message: &Message) -> Result<()> {
+        let mut env = vec![
+            ("MCWRAP_EVENT".to_string(), message.data.to_string()),
+            ("MCWRAP_MESSAGE".to_string(), message.text()),
+        ];
+        for (key, value) in message.data.as_object().into_iter().flatten() {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            env.push((format!("MCWRAP_{}", key.to_uppercase()), value));
+        }
+        run_script(&self.exec, &self.server_dir, env)
+    }
+
+    fn message(&self, event: &Event) -> Message {
+        let mut fields = vec![("Server", self.server.clone())];
+        let color = match &event.kind {
+            EventKind::ServerStarted { pid } => {
+                fields.push(("PID", pid.to_string()));
+                GREEN
+            }
+            EventKind::ServerStopped { .. } => GREY,
+            EventKind::Crashed { exit_code, .. } => {
+                fields.push(("Exit code", exit_code.to_string()));
+                RED
+            }
+            EventKind::BackupFailed { .. }
+            | EventKind::HighMemory { .. }
+            | EventKind::Alert { .. } => ORANGE,
+            EventKind::PlayerJoined { .. }
+            | EventKind::PlayerLeft { .. }
+            | EventKind::BackupCompleted { .. } => BLUE,
+        };
+        let mut data = serde_json::to_value(event).unwrap_or_default();
+        data["server"] = json!(self.server);
+        Message {
+            title: event.describe(),
+            color,
+            fields,
+            at: event.at,
+            data,
+        }
+    }
+}
+
+/// Notify about an event that happened outside the daemon, from the server's config
+pub fn notify(server_dir: &Path, event: &Event) -> Result<()> {
+    let config = Config::load(server_dir)?;
+    match Notifier::from_config(config.notify, server_dir) {
+        Some(notifier) => notifier.notify(event, None),
+        None => Ok(()),
+    }
+}
+
+impl Message {
+    /// The message as plain text, for services without rich formatting
+    fn text(&self) -> String {
+        let mut text = self.title.clone();
+        for (name, value) in &self.fields {
+            text.push_str(&format!("\n{}: {}", name, value));
+        }
+        text
+    }
+}
+
+/// The `[notify] events` entry that covers an event, or None for alerts, which are always sent
+fn topic(kind: &EventKind) -> Option<NotifyEvent> {
+    Some(match kind {
+        EventKind::ServerStarted { .. } => NotifyEvent::Start,
+        EventKind::ServerStopped { .. } => NotifyEvent::Stop,
+        EventKind::Crashed { .. } => NotifyEvent::Crash,
+        EventKind::PlayerJoined { .. } => NotifyEvent::Join,
+        EventKind::PlayerLeft { .. } => NotifyEvent::Leave,
+        EventKind::BackupCompleted { .. } => NotifyEvent::Backup,
+        EventKind::BackupFailed { .. } => NotifyEvent::BackupFailed,
+        EventKind::HighMemory { .. } => NotifyEvent::HighMemory,
+        EventKind::Alert { .. } => return None,
+    })
+}
+
+/// Run a script in `dir` with extra environment variables, killing it if it hangs
+pub fn run_script(command: &[String], dir: &Path, env: Vec<(String, String)>) -> Result<()> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .current_dir(dir)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {:?}", command[0]))?;
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > EXEC_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{:?} still running after {:?}, killed it", command[0], EXEC_TIMEOUT);
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    if !status.success() {
+        bail!("{:?} failed ({})", command[0], status);
+    }
+    Ok(())
+}
+
+fn discord_payload(message: &Message) -> Value {
+    let fields: Vec<Value> = message
+        .fields
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value, "inline": true }))
+        .collect();
+    let timestamp = chrono::DateTime::from_timestamp(message.at as i64, 0)
+        .map(|at| at.to_rfc3339())
+        .unwrap_or_default();
+    json!({
+        "username": "mcwrap",
+        "embeds": [{
+            "title": message.title,
+            "color": message.color,
+            "fields": fields,
+            "timestamp": timestamp,
+        }],
+    })
+}
+
+fn slack_payload(message: &Message) -> Value {
+    let fields: Vec<Value> = message
+        .fields
+        .iter()
+        .map(|(name, value)| json!({ "title": name, "value": value, "short": true }))
+        .collect();
+    json!({
+        "attachments": [{
+            "fallback": message.text(),
+            "title": message.title,
+            "color": format!("#{:06x}", message.color),
+            "fields": fields,
+            "ts": message.at,
+        }],
+    })
+}
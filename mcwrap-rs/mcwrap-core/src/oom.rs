@@ -0,0 +1,195 @@
+//! Out-of-memory detection
+//!
+//! The daemon spots `java.lang.OutOfMemoryError` in console output, and on a
+//! SIGKILL checks the kernel log for the OOM killer having picked the server.
+//! Either way it saves `oom.json` in the wrap dir with the heap dump the JVM
+//! wrote, if any (`-XX:+HeapDumpOnOutOfMemoryError`). With `[oom] restart`,
+//! the daemon stops the broken JVM and mcwrapd restarts it, optionally with a
+//! bigger heap.
+
+use anyhow::Result;
+use crate::pty::DaemonState;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::Duration;
+
+/// Time a server that ran out of memory gets to stop cleanly
+const STOP_GRACE: Duration = Duration::from_secs(60);
+
+/// Time it then gets to exit on SIGTERM before it is killed
+const TERM_GRACE: Duration = Duration::from_secs(30);
+
+static HEAP_DUMP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"Dumping heap to (\S+) \.\.\.").unwrap());
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum OomKind {
+    /// The JVM ran out of heap (or metaspace, threads, ...)
+    Java,
+    /// The kernel killed the JVM for using too much memory
+    Kernel,
+}
+
+/// An out-of-memory event, as saved in the wrap dir
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Oom {
+    pub kind: OomKind,
+    /// Unix time it was detected
+    pub at: u64,
+    pub message: String,
+    /// As printed by the JVM, relative to the server directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heap_dump: Option<PathBuf>,
+}
+
+impl Oom {
+    pub fn load(path: &Path) -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// One-line description for status output
+    pub fn describe(&self, server_dir: &Path) -> String {
+        let at = crate::local_time(self.at);
+        let source = match self.kind {
+            OomKind::Java => "JVM",
+            OomKind::Kernel => "kernel",
+        };
+        let mut text = format!("{} ({}: {})", at, source, self.message);
+        if let Some(dump) = &self.heap_dump {
+            text.push_str(&format!(", heap dump {}", server_dir.join(dump).display()));
+        }
+        text
+    }
+}
+
+/// Console output watched for out-of-memory errors
+#[derive(Default)]
+pub struct Watch {
+    partial: String,
+    heap_dump: Option<PathBuf>,
+    detected: Option<Oom>,
+}
+
+impl Watch {
+    /// Feed (log-filtered) console output, returning the event when it is
+    /// first detected or learns its heap dump
+    pub fn push(&mut self, output: &[u8]) -> Option<Oom> {
+        self.partial.push_str(&String::from_utf8_lossy(output));
+        let mut changed = false;
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            let line = crate::console::strip_ansi(line.trim());
+
+            if let Some(caps) = HEAP_DUMP.captures(&line) {
+                self.heap_dump = Some(PathBuf::from(&caps[1]));
+                if let Some(oom) = self.detected.as_mut() {
+                    oom.heap_dump = self.heap_dump.clone();
+                    changed = true;
+                }
+            }
+            if self.detected.is_none() {
+                if let Some(start) = line.find("java.lang.OutOfMemoryError") {
+                    self.detected = Some(Oom {
+                        kind: OomKind::Java,
+                        at: crate::unix_now(),
+                        message: line[start..].to_string(),
+                        heap_dump: self.heap_dump.clone(),
+                    });
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.detected.clone()
+        } else {
+            None
+        }
+    }
+}
+
+/// Stop the server, forcibly if it doesn't exit in time, from a background thread
+pub fn stop_server(state: Arc<DaemonState>) {
+    thread::spawn(move || {
+        state.write_input(b"stop\n");
+        if !state.wait_for_exit(STOP_GRACE) {
+            state.terminate(TERM_GRACE);
+        }
+    });
+}
+
+/// The kernel's report of the OOM killer killing a process, if it did
+///
+/// Reading the kernel log may need privileges (`kernel.dmesg_restrict`); when
+/// it can't be read the kill goes unexplained.
+pub fn kernel_oom_kill(pid: i32) -> Option<Oom> {
+    let mut kmsg = OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NONBLOCK)
+        .open("/dev/kmsg")
+        .ok()?;
+    let needle = format!("Killed process {} ", pid);
+    let mut found = None;
+    // Each read returns one record, "<prefix>;<message>"
+    let mut buf = vec![0u8; 8192];
+    loop {
+        match kmsg.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let record = String::from_utf8_lossy(&buf[..n]);
+                let message = record.split_once(';').map_or(&*record, |(_, m)| m);
+                let message = message.lines().next().unwrap_or_default();
+                if message.contains(&needle) {
+                    found = Some(message.to_string());
+                }
+            }
+            // Records were overwritten while reading
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => continue,
+            Err(_) => break,
+        }
+    }
+    Some(Oom {
+        kind: OomKind::Kernel,
+        at: crate::unix_now(),
+        message: found?,
+        heap_dump: None,
+    })
+}
+
+/// `-Xmx` in megabytes, if the arguments set it
+pub fn max_heap_mb(java_args: &[String]) -> Option<u64> {
+    java_args.iter().rev().find_map(|arg| parse_size_mb(arg.strip_prefix("-Xmx")?))
+}
+
+/// Replace `-Xmx` in the arguments
+pub fn set_max_heap_mb(java_args: &mut Vec<String>, mb: u64) {
+    java_args.retain(|arg| !arg.starts_with("-Xmx"));
+    // JVM options must come before `-jar`
+    let at = java_args.iter().position(|arg| arg == "-jar").unwrap_or(0);
+    java_args.insert(at, format!("-Xmx{}M", mb));
+}
+
+/// Parse a JVM memory size ("4G", "4096m", "512k", bytes) into megabytes
+pub fn parse_size_mb(size: &str) -> Option<u64> {
+    let digits = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (digits, unit) = size.split_at(digits);
+    let value: u64 = digits.parse().ok()?;
+    match unit {
+        "g" | "G" => Some(value * 1024),
+        "m" | "M" => Some(value),
+        "k" | "K" => Some(value / 1024),
+        "" => Some(value / (1024 * 1024)),
+        _ => None,
+    }
+}
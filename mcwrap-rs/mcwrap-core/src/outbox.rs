@@ -0,0 +1,121 @@
+//! Output waiting to be written to a console client
+//!
+//! The PTY daemon never waits on a client: what it sends one goes into the
+//! client's outbox, which is written out as fast as the client reads it. A
+//! client that reads slower than the server prints, such as `attach` over a
+//! bad SSH link, has up to `[attach] buffer` of console output queued
+//! (default "1M"). Past that, `slow_client = "drop-oldest"` (the default)
+//! drops the oldest output it hasn't been sent yet, so that it catches up
+//! with a gap, and `"disconnect"` drops the client. Replies and scrollback
+//! are never dropped.
+
+use crate::config::SlowClient;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+/// Messages queued for one client
+pub struct Outbox {
+    /// Largest amount of console output kept
+    limit: usize,
+    policy: SlowClient,
+    messages: VecDeque<Message>,
+    /// How much of the first message has been written
+    written: usize,
+    /// Bytes in `messages` that are console output
+    output: usize,
+    /// Console output was dropped since the client last caught up
+    dropping: bool,
+}
+
+struct Message {
+    data: Vec<u8>,
+    /// Console output, which may be dropped
+    output: bool,
+}
+
+impl Outbox {
+    pub fn new(limit: usize, policy: SlowClient) -> Self {
+        Self {
+            limit,
+            policy,
+            messages: VecDeque::new(),
+            written: 0,
+            output: 0,
+            dropping: false,
+        }
+    }
+
+    /// Queue a message that must arrive, such as a reply
+    pub fn push(&mut self, data: Vec<u8>) {
+        self.messages.push_back(Message {
+            data,
+            output: false,
+        });
+    }
+
+    /// Queue console output, failing if the client is too far behind and is to be dropped
+    ///
+    /// Returns whether this is the first output dropped since the client last caught up.
+    pub fn push_output(&mut self, data: Vec<u8>) -> io::Result<bool> {
+        let mut started_dropping = false;
+        if self.output + data.len() > self.limit {
+            match self.policy {
+                SlowClient::Disconnect => {
+                    let behind = self.output + data.len();
+                    return Err(io::Error::other(format!("too slow, {} bytes behind", behind)));
+                }
+                SlowClient::DropOldest => {
+                    started_dropping = !self.dropping;
+                    self.drop_oldest(self.output + data.len() - self.limit);
+                    self.dropping = true;
+                }
+            }
+        }
+        self.output += data.len();
+        self.messages.push_back(Message { data, output: true });
+        Ok(started_dropping)
+    }
+
+    /// Drop at least `excess` bytes of console output, oldest first
+    fn drop_oldest(&mut self, excess: usize) {
+        let mut freed = 0;
+        // The message being written has to be finished, or the client would get half a frame
+        let first = usize::from(self.written > 0);
+        let mut i = first;
+        while freed < excess && i < self.messages.len() {
+            if self.messages[i].output {
+                let message = self.messages.remove(i).unwrap();
+                freed += message.data.len();
+            } else {
+                i += 1;
+            }
+        }
+        self.output -= freed;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Write as much as the client takes without blocking
+    pub fn flush(&mut self, stream: &mut impl Write) -> io::Result<()> {
+        while let Some(message) = self.messages.front() {
+            match stream.write(&message.data[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            if self.written == message.data.len() {
+                if message.output {
+                    self.output -= message.data.len();
+                }
+                self.messages.pop_front();
+                self.written = 0;
+            }
+        }
+        self.dropping = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! What differs between the Unixes mcwrap runs on
+//!
+//! Processes, their working directories and executables, and the machine's
+//! memory come from sysinfo, which reads `/proc` on Linux and asks the
+//! kernel elsewhere, so FreeBSD jails without `/proc` are covered too.
+//! `top`, `status`, the memory alerts, `jcmd` and `adopt` all go through
+//! here.
+//!
+//! macOS's poll(2) doesn't support devices, terminals included, so the
+//! daemon and `attach` wait on the PTY and stdin with select(2) there.
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::unistd::{getpgid, Pid};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+/// A process, as listed by `processes`
+pub struct Process {
+    pub pid: i32,
+    /// Process group
+    pub pgid: i32,
+    /// Command name, e.g. "java"
+    pub name: String,
+    /// CPU time used, user and system
+    pub cpu: Duration,
+    /// Resident memory in bytes
+    pub memory: u64,
+}
+
+/// Every process on the machine
+pub fn processes() -> io::Result<Vec<Process>> {
+    if !sysinfo::IS_SUPPORTED_SYSTEM {
+        return Err(io::Error::other("Listing processes is not supported here"));
+    }
+    let mut system = System::new();
+    let kind = ProcessRefreshKind::nothing().with_memory().with_cpu();
+    system.refresh_processes_specifics(ProcessesToUpdate::All, true, kind);
+    let processes = system
+        .processes()
+        .values()
+        .filter_map(|process| {
+            let pid = process.pid().as_u32() as i32;
+            // Gone since it was listed
+            let pgid = getpgid(Some(Pid::from_raw(pid))).ok()?;
+            Some(Process {
+                pid,
+                pgid: pgid.as_raw(),
+                name: process.name().to_string_lossy().into_owned(),
+                cpu: Duration::from_millis(process.accumulated_cpu_time()),
+                memory: process.memory(),
+            })
+        })
+        .collect();
+    Ok(processes)
+}
+
+/// Refresh one process with `kind`, and read from it with `read`
+fn with_process<T>(
+    pid: i32,
+    kind: ProcessRefreshKind,
+    read: impl FnOnce(&sysinfo::Process) -> Option<T>,
+) -> Option<T> {
+    let pid = sysinfo::Pid::from_u32(u32::try_from(pid).ok()?);
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), false, kind);
+    system.process(pid).and_then(read)
+}
+
+/// The executable a process runs
+pub fn executable(pid: i32) -> Option<PathBuf> {
+    let kind = ProcessRefreshKind::nothing().with_exe(UpdateKind::Always);
+    with_process(pid, kind, |process| process.exe().map(Path::to_path_buf))
+}
+
+/// The working directory of a process
+pub fn cwd(pid: i32) -> Option<PathBuf> {
+    let kind = ProcessRefreshKind::nothing().with_cwd(UpdateKind::Always);
+    with_process(pid, kind, |process| process.cwd().map(Path::to_path_buf))
+}
+
+/// The machine's memory in bytes
+pub fn total_memory() -> Option<u64> {
+    let mut system = System::new();
+    system.refresh_memory();
+    Some(system.total_memory()).filter(|&bytes| bytes > 0)
+}
+
+/// Wait until one of `fds` is ready or `timeout_ms` (-1 for ever) passes, as
+/// poll(2) does, returning the number of ready fds
+#[cfg(not(target_os = "macos"))]
+pub fn poll(fds: &mut [libc::pollfd], timeout_ms: i32) -> nix::Result<usize> {
+    let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+    Errno::result(ready).map(|ready| ready as usize)
+}
+
+/// Wait until one of `fds` is ready or `timeout_ms` (-1 for ever) passes, as
+/// poll(2) does, returning the number of ready fds
+///
+/// Only `POLLIN` and `POLLOUT` are waited for; a hangup shows as `POLLIN`,
+/// which is what select(2) reports for it.
+#[cfg(target_os = "macos")]
+pub fn poll(fds: &mut [libc::pollfd], timeout_ms: i32) -> nix::Result<usize> {
+    let mut read: libc::fd_set = unsafe { std::mem::zeroed() };
+    let mut write: libc::fd_set = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::FD_ZERO(&mut read);
+        libc::FD_ZERO(&mut write);
+    }
+    let mut highest = -1;
+    for fd in fds.iter() {
+        if fd.fd < 0 || fd.fd >= libc::FD_SETSIZE as libc::c_int {
+            return Err(Errno::EINVAL);
+        }
+        if fd.events & libc::POLLIN != 0 {
+            unsafe { libc::FD_SET(fd.fd, &mut read) };
+        }
+        if fd.events & libc::POLLOUT != 0 {
+            unsafe { libc::FD_SET(fd.fd, &mut write) };
+        }
+        highest = highest.max(fd.fd);
+    }
+    let mut timeout = libc::timeval {
+        tv_sec: (timeout_ms / 1000) as libc::time_t,
+        tv_usec: (timeout_ms % 1000 * 1000) as libc::suseconds_t,
+    };
+    let timeout = if timeout_ms < 0 {
+        std::ptr::null_mut()
+    } else {
+        &mut timeout as *mut libc::timeval
+    };
+    let null = std::ptr::null_mut();
+    Errno::result(unsafe { libc::select(highest + 1, &mut read, &mut write, null, timeout) })?;
+
+    let mut ready = 0;
+    for fd in fds.iter_mut() {
+        fd.revents = 0;
+        if unsafe { libc::FD_ISSET(fd.fd, &read) } {
+            fd.revents |= libc::POLLIN;
+        }
+        if unsafe { libc::FD_ISSET(fd.fd, &write) } {
+            fd.revents |= libc::POLLOUT;
+        }
+        if fd.revents != 0 {
+            ready += 1;
+        }
+    }
+    Ok(ready)
+}
@@ -0,0 +1,170 @@
+//! Tracking who is online from console output
+//!
+//! The daemon watches the server's console for join and leave messages, and
+//! resynchronises from the reply whenever someone runs `list`. The online
+//! players are reported in the daemon's status, and joins and leaves are
+//! passed on as events.
+//!
+//! `mcwrap players <dir>` lists the players the server knows (from its
+//! `usercache.json`); with `--online`, it asks the server with `list uuids`
+//! instead, which works without query or RCON. Each player is printed as
+//! "name uuid", or as a JSON object with `--json`.
+
+use anyhow::{bail, Context, Result};
+use crate::control::ControlClient;
+use crate::events::EventKind;
+use crate::failure::Failure;
+use crate::{is_running, ServerPaths};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// Longest to wait for the server to answer `list uuids`
+const LIST_TIMEOUT_MS: u64 = 5_000;
+
+/// "[12:00:00 INFO]: Steve joined the game"; chat lines start with "<name>" instead
+static JOIN_LEAVE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]: ([A-Za-z0-9_.]{1,16}) (joined|left) the game$").unwrap()
+});
+
+/// The reply to `list`, in its vanilla and older "N/M" forms
+static LIST: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"There are (\d+) (?:of a max of |/)\d+ players online:(.*)$").unwrap()
+});
+
+/// Players online, as seen in console output
+#[derive(Default)]
+pub struct Players {
+    online: BTreeSet<String>,
+    partial: String,
+}
+
+impl Players {
+    /// Feed (log-filtered) console output, returning who joined or left
+    pub fn push(&mut self, output: &[u8]) -> Vec<EventKind> {
+        self.partial.push_str(&String::from_utf8_lossy(output));
+        let mut changes = Vec::new();
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            let line = crate::console::strip_ansi(line.trim_end_matches(['\r', '\n']));
+            changes.extend(self.observe(&line));
+        }
+        changes
+    }
+
+    fn observe(&mut self, line: &str) -> Option<EventKind> {
+        if let Some(caps) = JOIN_LEAVE.captures(line) {
+            let player = caps[1].to_string();
+            if &caps[2] == "joined" {
+                self.online.insert(player.clone());
+                return Some(EventKind::PlayerJoined { player });
+            }
+            self.online.remove(&player);
+            return Some(EventKind::PlayerLeft { player });
+        } else if let Some(caps) = LIST.captures(line) {
+            self.online = parse_list(&caps[2]).into_iter().map(|player| player.name).collect();
+        }
+        None
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.online.iter().cloned().collect()
+    }
+}
+
+/// A player, as `mcwrap players` prints them
+#[derive(Serialize, Deserialize)]
+pub struct Player {
+    pub name: String,
+    pub uuid: Option<String>,
+}
+
+/// The players in the reply to `list`; `list uuids` follows each name with its UUID
+fn parse_list(list: &str) -> Vec<Player> {
+    list.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split_whitespace();
+            let name = parts.next()?.to_string();
+            let uuid = parts
+                .next()
+                .map(|uuid| uuid.trim_matches(['(', ')']).to_string())
+                .filter(|uuid| !uuid.is_empty());
+            Some(Player { name, uuid })
+        })
+        .collect()
+}
+
+pub async fn cmd_players(server_dir: &Path, online: bool, as_json: bool) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let players = if online {
+        online_players(&server_dir).await?
+    } else {
+        known_players(&server_dir)?
+    };
+    for player in &players {
+        if as_json {
+            println!("{}", serde_json::to_string(player)?);
+        } else {
+            println!("{} {}", player.name, player.uuid.as_deref().unwrap_or("-"));
+        }
+    }
+    Ok(())
+}
+
+/// Ask the server who is online
+async fn online_players(server_dir: &Path) -> Result<Vec<Player>> {
+    let paths = ServerPaths::new(server_dir);
+    let Some(mut control) = ControlClient::connect(&paths).await? else {
+        is_running(&paths).context(Failure::not_running())?;
+        bail!("players --online needs a PTY-mode server");
+    };
+    let params = json!({ "command": "list uuids", "capture_ms": LIST_TIMEOUT_MS });
+    let result = control.call("send", params).await?;
+    let output = crate::console::strip_ansi(result["output"].as_str().unwrap_or_default());
+
+    let mut lines = output.lines();
+    while let Some(line) = lines.next() {
+        let Some(caps) = LIST.captures(line) else {
+            continue;
+        };
+        let mut players = parse_list(&caps[2]);
+        // Older Bukkit servers put the names on the next line
+        if players.is_empty() && &caps[1] != "0" {
+            let next = lines.next().unwrap_or_default();
+            players = parse_list(next.split_once("]: ").map_or(next, |(_, names)| names));
+        }
+        return Ok(players);
+    }
+    bail!("The server didn't answer `list uuids` as expected: {}", output.trim())
+}
+
+#[derive(Deserialize)]
+struct CachedPlayer {
+    name: String,
+    uuid: String,
+}
+
+/// The players the server has seen, from its user cache
+fn known_players(server_dir: &Path) -> Result<Vec<Player>> {
+    let path = server_dir.join("usercache.json");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+    };
+    let cached: Vec<CachedPlayer> =
+        serde_json::from_str(&content).with_context(|| format!("Invalid {:?}", path))?;
+    let mut players: Vec<Player> = cached
+        .into_iter()
+        .map(|player| Player {
+            name: player.name,
+            uuid: Some(player.uuid),
+        })
+        .collect();
+    players.sort_by_key(|player| player.name.to_lowercase());
+    Ok(players)
+}
@@ -0,0 +1,185 @@
+//! How long players spend on a server
+//!
+//! The daemon turns the join and leave events into sessions and keeps a
+//! summary per player in `playtime.json` in the wrap dir: when they were
+//! first and last seen, their total playtime, and the sessions of the last
+//! 30 days. Sessions still open when the server stops end with it.
+//! `mcwrap playtime <dir> [player]` shows the summary, with `--json` as one
+//! object per player for scripts that hand out ranks.
+
+use anyhow::{bail, Context, Result};
+use crate::{is_running, local_time, unix_now, ServerPaths};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const DAY_SECS: u64 = 86400;
+
+/// How long sessions are kept for the recent playtime
+const RECENT_SECS: u64 = 30 * DAY_SECS;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Playtime {
+    players: BTreeMap<String, PlayerTime>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlayerTime {
+    /// Unix times
+    first_seen: u64,
+    last_seen: u64,
+    /// Playtime of the finished sessions
+    total_secs: u64,
+    sessions: u64,
+    /// Start of the session in progress
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    online_since: Option<u64>,
+    /// Finished sessions of the last 30 days, as (start, end)
+    #[serde(default)]
+    recent: Vec<(u64, u64)>,
+}
+
+impl PlayerTime {
+    /// Total playtime, including the session in progress
+    fn total(&self, now: u64) -> u64 {
+        self.total_secs + self.online_since.map_or(0, |since| now.saturating_sub(since))
+    }
+
+    /// Playtime within the last `secs`
+    fn within(&self, secs: u64, now: u64) -> u64 {
+        let cutoff = now.saturating_sub(secs);
+        let current = self.online_since.map(|since| (since, now));
+        self.recent
+            .iter()
+            .copied()
+            .chain(current)
+            .map(|(start, end)| end.saturating_sub(start.max(cutoff)))
+            .sum()
+    }
+}
+
+impl Playtime {
+    /// Load the summary; sessions left open by a daemon that died end when they began
+    pub fn load(path: &Path) -> Self {
+        let mut playtime: Self = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        for player in playtime.players.values_mut() {
+            player.online_since = None;
+        }
+        playtime
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).with_context(|| format!("Failed to write {:?}", path))
+    }
+
+    pub fn join(&mut self, name: &str, at: u64) {
+        let player = self.players.entry(name.to_string()).or_insert(PlayerTime {
+            first_seen: at,
+            last_seen: at,
+            total_secs: 0,
+            sessions: 0,
+            online_since: None,
+            recent: Vec::new(),
+        });
+        player.last_seen = at;
+        player.online_since.get_or_insert(at);
+    }
+
+    pub fn leave(&mut self, name: &str, at: u64) {
+        let Some(player) = self.players.get_mut(name) else {
+            return;
+        };
+        player.last_seen = at;
+        let Some(since) = player.online_since.take() else {
+            return;
+        };
+        player.total_secs += at.saturating_sub(since);
+        player.sessions += 1;
+        player.recent.push((since, at));
+        player.recent.retain(|(_, end)| at.saturating_sub(*end) < RECENT_SECS);
+    }
+
+    /// End every session in progress, as when the server stops
+    pub fn leave_all(&mut self, at: u64) {
+        let online: Vec<String> = self
+            .players
+            .iter()
+            .filter(|(_, player)| player.online_since.is_some())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in online {
+            self.leave(&name, at);
+        }
+    }
+}
+
+pub fn cmd_playtime(server_dir: &Path, player: Option<&str>, as_json: bool) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    // Sessions in progress only count while the server runs
+    let playtime = match is_running(&paths) {
+        Some(_) => fs::read_to_string(&paths.playtime_file)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default(),
+        None => Playtime::load(&paths.playtime_file),
+    };
+    let now = unix_now();
+
+    let mut players: Vec<(&String, &PlayerTime)> = match player {
+        Some(name) => {
+            let found = playtime.players.iter().find(|(n, _)| n.eq_ignore_ascii_case(name));
+            let Some(found) = found else {
+                bail!("{} hasn't played on this server (since mcwrap started tracking)", name);
+            };
+            vec![found]
+        }
+        None => playtime.players.iter().collect(),
+    };
+    players.sort_by_key(|(_, time)| std::cmp::Reverse(time.total(now)));
+    if players.is_empty() && !as_json {
+        println!("No playtime recorded yet.");
+    }
+
+    for (name, time) in players {
+        if as_json {
+            let summary = json!({
+                "name": name,
+                "total_secs": time.total(now),
+                "last_7d_secs": time.within(7 * DAY_SECS, now),
+                "last_30d_secs": time.within(30 * DAY_SECS, now),
+                "sessions": time.sessions + time.online_since.is_some() as u64,
+                "first_seen": time.first_seen,
+                "last_seen": time.last_seen,
+                "online": time.online_since.is_some(),
+            });
+            println!("{}", summary);
+            continue;
+        }
+        let last_seen = if time.online_since.is_some() {
+            "online now".to_string()
+        } else {
+            format!("last seen {}", local_time(time.last_seen))
+        };
+        println!("{} ({})", name, last_seen);
+        println!(
+            "  Total: {}  Last 7 days: {}  Last 30 days: {}",
+            hours(time.total(now)),
+            hours(time.within(7 * DAY_SECS, now)),
+            hours(time.within(30 * DAY_SECS, now))
+        );
+        println!("  First seen: {}", local_time(time.first_seen));
+    }
+    Ok(())
+}
+
+/// A duration as "12h 34m"
+fn hours(secs: u64) -> String {
+    format!("{}h {:02}m", secs / 3600, secs / 60 % 60)
+}
@@ -0,0 +1,220 @@
+//! The network ports of servers
+//!
+//! A server's game port (`server-port`), and its query and RCON ports when
+//! enabled, come from its `server.properties`. Before a start, `start` makes
+//! sure none of them is taken by a running registered server or bound by
+//! any other process, and warns about stopped registered servers set up with
+//! the same ports. `mcwrap init --port auto` (or `port = "auto"` in a
+//! template) picks the first port of the global config's range that no
+//! registered server is set up with and nothing listens on:
+//!
+//! ```toml
+//! [ports]
+//! range = "25565-25664"
+//! ```
+
+use anyhow::{bail, Context, Result};
+use crate::config::PortsConfig;
+use crate::hibernate::DEFAULT_PORT;
+use crate::properties::Properties;
+use crate::registry::Registry;
+use crate::{is_running, ServerPaths};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Ports `auto` picks from without `[ports] range`
+const DEFAULT_RANGE: RangeInclusive<u16> = 25565..=25664;
+
+pub const DEFAULT_RCON_PORT: u16 = 25575;
+
+/// A port, or the next free one
+#[derive(Clone, Copy, Deserialize)]
+#[serde(try_from = "PortValue")]
+pub enum PortChoice {
+    Auto,
+    Fixed(u16),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PortValue {
+    Number(u16),
+    Text(String),
+}
+
+impl FromStr for PortChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s == "auto" {
+            return Ok(PortChoice::Auto);
+        }
+        s.parse()
+            .map(PortChoice::Fixed)
+            .map_err(|_| format!("expected a port number or \"auto\", got {:?}", s))
+    }
+}
+
+impl TryFrom<PortValue> for PortChoice {
+    type Error = String;
+
+    fn try_from(value: PortValue) -> Result<Self, String> {
+        match value {
+            PortValue::Number(port) => Ok(PortChoice::Fixed(port)),
+            PortValue::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl PortChoice {
+    /// The port itself, picking the next free one for `Auto`
+    pub fn resolve(self, server_dir: &Path) -> Result<u16> {
+        match self {
+            PortChoice::Fixed(port) => Ok(port),
+            PortChoice::Auto => {
+                let config = crate::config::Config::load_global()?;
+                next_free(range(&config.ports)?, &mut registered(server_dir))
+            }
+        }
+    }
+}
+
+/// A port a server listens on
+pub struct Port {
+    /// The setting it comes from
+    pub setting: &'static str,
+    pub port: u16,
+    /// Query runs over UDP, the rest over TCP
+    pub udp: bool,
+}
+
+impl Port {
+    /// The port as docker publishes it, e.g. "25565:25565" or "25565:25565/udp"
+    pub fn publish(&self) -> String {
+        let protocol = if self.udp { "/udp" } else { "" };
+        format!("{0}:{0}{1}", self.port, protocol)
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let protocol = if self.udp { "UDP" } else { "TCP" };
+        write!(f, "{} {} ({})", protocol, self.port, self.setting)
+    }
+}
+
+/// The ports a server is set up to listen on, and the address it binds them to
+pub fn configured(server_dir: &Path) -> Result<(Vec<Port>, IpAddr)> {
+    let properties = Properties::load(server_dir)?;
+    let port = |key| properties.get(key).and_then(|port| port.parse().ok());
+    let enabled = |key| properties.get(key) == Some("true");
+
+    let game = port("server-port").unwrap_or(DEFAULT_PORT);
+    let mut ports = vec![Port {
+        setting: "server-port",
+        port: game,
+        udp: false,
+    }];
+    if enabled("enable-query") {
+        ports.push(Port {
+            setting: "query.port",
+            port: port("query.port").unwrap_or(game),
+            udp: true,
+        });
+    }
+    if enabled("enable-rcon") {
+        ports.push(Port {
+            setting: "rcon.port",
+            port: port("rcon.port").unwrap_or(DEFAULT_RCON_PORT),
+            udp: false,
+        });
+    }
+    let ip = properties
+        .get("server-ip")
+        .and_then(|ip| ip.parse().ok())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    Ok((ports, ip))
+}
+
+/// Make sure a server's ports are free before it starts, returning warnings
+pub fn check(server_dir: &Path) -> Result<Vec<String>> {
+    if !server_dir.join("server.properties").exists() {
+        return Ok(Vec::new());
+    }
+    let (ports, ip) = configured(server_dir)?;
+    let mut warnings = Vec::new();
+
+    let registry = Registry::load()?;
+    let others = registry
+        .servers
+        .iter()
+        .filter(|entry| entry.dir != server_dir && entry.dir.join("server.properties").exists());
+    for entry in others {
+        let Ok((taken, _)) = configured(&entry.dir) else {
+            continue;
+        };
+        for port in &ports {
+            if !taken.iter().any(|other| other.port == port.port && other.udp == port.udp) {
+                continue;
+            }
+            if is_running(&ServerPaths::new(&entry.dir)).is_some() {
+                bail!("{} is taken by {}, which is running", port, entry.dir.display());
+            }
+            warnings.push(format!("{} is also set up for {}", port, entry.dir.display()));
+        }
+    }
+
+    for port in &ports {
+        let bound = if port.udp {
+            UdpSocket::bind((ip, port.port)).is_err()
+        } else {
+            TcpListener::bind((ip, port.port)).is_err()
+        };
+        if bound {
+            bail!("{} is already in use by another process", port);
+        }
+    }
+    Ok(warnings)
+}
+
+/// Ports the registered servers other than `except` are set up with
+pub fn registered(except: &Path) -> BTreeSet<u16> {
+    let registry = Registry::load().unwrap_or_default();
+    registry
+        .servers
+        .iter()
+        .filter(|entry| entry.dir != except && entry.dir.join("server.properties").exists())
+        .filter_map(|entry| configured(&entry.dir).ok())
+        .flat_map(|(ports, _)| ports.into_iter().map(|port| port.port))
+        .collect()
+}
+
+/// The first of `candidates` that isn't `taken` and can be listened on, which is then taken
+pub fn next_free(
+    candidates: impl IntoIterator<Item = u16>,
+    taken: &mut BTreeSet<u16>,
+) -> Result<u16> {
+    let port = candidates
+        .into_iter()
+        .find(|port| !taken.contains(port) && TcpListener::bind(("0.0.0.0", *port)).is_ok())
+        .context("No free port left")?;
+    taken.insert(port);
+    Ok(port)
+}
+
+/// Ports `auto` picks from
+pub fn range(config: &PortsConfig) -> Result<RangeInclusive<u16>> {
+    let Some(range) = &config.range else {
+        return Ok(DEFAULT_RANGE);
+    };
+    let parsed = range
+        .split_once('-')
+        .and_then(|(first, last)| Some(first.trim().parse().ok()?..=last.trim().parse().ok()?))
+        .filter(|range: &RangeInclusive<u16>| !range.is_empty());
+    parsed.with_context(|| format!("Invalid [ports] range {:?} (expected e.g. 25565-25664)", range))
+}
@@ -0,0 +1,490 @@
+//! Generating a world's chunks ahead of time
+//!
+//! `mcwrap pregen <dir> --radius 5000` generates every chunk within 5000
+//! blocks of the center on a running server, so that players exploring later
+//! don't wait for (and lag) chunk generation. With the Chunky plugin (or mod)
+//! installed, it sets up and starts a Chunky task and follows the progress
+//! Chunky reports in the console; `--install-chunky` downloads Chunky from
+//! Modrinth first. Otherwise the area is force loaded 256 chunks at a time
+//! with `forceload`, waiting for each batch to be generated (which needs
+//! Minecraft 1.19.4 or newer for `execute if loaded`).
+//!
+//! Generation pauses while players are online, unless `--no-pause` is given.
+//! Interrupting `pregen` leaves a Chunky task running in the server; forced
+//! chunks are released.
+
+use anyhow::{bail, Context, Result};
+use crate::control::ControlClient;
+use crate::events::{Event, EventKind};
+use crate::failure::Failure;
+use crate::http;
+use crate::init::fetch_json;
+use crate::upgrade::is_jar;
+use crate::{is_running, ServerPaths};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+/// Chunky's builds on Modrinth
+const CHUNKY_VERSIONS: &str = "https://api.modrinth.com/v2/project/chunky/version";
+
+/// Most chunks `forceload add` takes at once
+const BATCH_CHUNKS: i32 = 16;
+
+/// Longest to wait for a batch of forced chunks to be generated
+const BATCH_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often to check whether players have left again
+const PLAYER_POLL: Duration = Duration::from_secs(10);
+
+/// Longest to wait for the server to answer a command
+const REPLY_MS: u64 = 2_000;
+
+const BAR_WIDTH: usize = 30;
+
+/// "[Chunky] Task running for world. Processed: 1234 chunks (5.67%), ETA: 0:12:34, ..."
+static CHUNKY_RUNNING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"Task running for (\S+)\. Processed: (\d+) chunks \(([\d.,]+)%\), ETA: ([\d:]+)")
+        .unwrap()
+});
+
+/// "[Chunky] Task finished for world. Processed: 40401 chunks (100.00%), Total time: 0:05:00"
+static CHUNKY_FINISHED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"Task finished for (\S+)\. Processed: (\d+) chunks.*Total time: ([\d:]+)").unwrap()
+});
+
+/// Options for `mcwrap pregen`
+pub struct PregenOptions {
+    /// Blocks from the center to generate
+    pub radius: u32,
+    /// Block X and Z of the center
+    pub center: (i32, i32),
+    /// World (for Chunky) or dimension (for forceload) to generate
+    pub world: Option<String>,
+    /// Use forceload even if Chunky is installed
+    pub vanilla: bool,
+    /// Download Chunky if it isn't installed
+    pub install_chunky: bool,
+    /// Pause while players are online
+    pub pause_for_players: bool,
+}
+
+pub async fn cmd_pregen(server_dir: &Path, opts: PregenOptions) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+
+    let chunky = chunky_jar(&server_dir);
+    if chunky.is_none() && opts.install_chunky {
+        let jar = install_chunky(&server_dir).await?;
+        println!("Installed {}", jar.display());
+        println!("Restart the server to load it, then run pregen again.");
+        return Ok(());
+    }
+
+    let Some(control) = ControlClient::connect(&paths).await? else {
+        is_running(&paths).context(Failure::not_running())?;
+        bail!("pregen needs a PTY-mode server");
+    };
+    let mut output = ControlClient::connect(&paths).await?.context("Server stopped")?;
+    output.call("subscribe", json!({ "console": true, "events": true })).await?;
+    let mut pregen = Pregen {
+        control,
+        output,
+        progress: Progress::new(),
+        opts,
+        forced: None,
+    };
+
+    let result = match &chunky {
+        Some(jar) if !pregen.opts.vanilla => {
+            println!("Generating with {}", jar.file_name().unwrap().to_string_lossy());
+            let run = pregen.with_chunky();
+            tokio::select! {
+                result = run => Some(result),
+                _ = tokio::signal::ctrl_c() => None,
+            }
+        }
+        _ => {
+            let run = pregen.with_forceload();
+            tokio::select! {
+                result = run => Some(result),
+                _ = tokio::signal::ctrl_c() => None,
+            }
+        }
+    };
+    pregen.progress.clear();
+    if let Some(result) = result {
+        return result;
+    }
+
+    // The interrupted call may still be waiting for its answer
+    let mut control = ControlClient::connect(&paths).await?.context("Server stopped")?;
+    match pregen.forced.take() {
+        Some(remove) => {
+            send(&mut control, &remove).await?;
+            println!("Stopped; the forced chunks were released.");
+        }
+        None if chunky.is_some() && !pregen.opts.vanilla => {
+            println!("Chunky carries on in the server. Pause it with:");
+            println!("  mcwrap send {} \"chunky pause\"", server_dir.display());
+        }
+        None => println!("Stopped."),
+    }
+    Ok(())
+}
+
+struct Pregen {
+    control: ControlClient,
+    /// Subscribed to the console and events
+    output: ControlClient,
+    progress: Progress,
+    opts: PregenOptions,
+    /// Command releasing the chunks currently force loaded
+    forced: Option<String>,
+}
+
+impl Pregen {
+    async fn with_chunky(&mut self) -> Result<()> {
+        let mut setup = Vec::new();
+        if let Some(world) = &self.opts.world {
+            setup.push(format!("chunky world {}", world));
+        }
+        let (x, z) = self.opts.center;
+        setup.push(format!("chunky center {} {}", x, z));
+        setup.push(format!("chunky radius {}", self.opts.radius));
+        for command in &setup {
+            let reply = send(&mut self.control, command).await?;
+            if reply.contains("Unknown") {
+                bail!("Chunky isn't loaded; restart the server to load it");
+            }
+        }
+
+        let mut started = false;
+        let mut paused = false;
+        if self.players_online().await? == 0 {
+            send(&mut self.control, "chunky start").await?;
+            started = true;
+        } else {
+            self.progress.note("Waiting for the players to leave...");
+            paused = true;
+        }
+
+        let mut partial = String::new();
+        while let Some(message) = self.output.next_notification().await? {
+            if message["method"] == "event" {
+                let Ok(event) = serde_json::from_value::<Event>(message["params"].clone()) else {
+                    continue;
+                };
+                let (EventKind::PlayerJoined { .. } | EventKind::PlayerLeft { .. }) = event.kind
+                else {
+                    continue;
+                };
+                let online = self.players_online().await?;
+                if online > 0 && !paused {
+                    send(&mut self.control, "chunky pause").await?;
+                    self.progress.note(&format!("Paused: {} online", plural(online, "player")));
+                    paused = true;
+                } else if online == 0 && paused {
+                    let command = if started { "chunky continue" } else { "chunky start" };
+                    send(&mut self.control, command).await?;
+                    self.progress.note("Players left; carrying on");
+                    started = true;
+                    paused = false;
+                }
+                continue;
+            }
+
+            partial.push_str(message["params"]["data"].as_str().unwrap_or_default());
+            while let Some(end) = partial.find('\n') {
+                let line: String = partial.drain(..=end).collect();
+                let line = crate::console::strip_ansi(line.trim_end());
+                if let Some(c) = CHUNKY_FINISHED.captures(&line) {
+                    self.progress.clear();
+                    println!("Generated {} chunks of {} in {}", &c[2], &c[1], &c[3]);
+                    return Ok(());
+                }
+                if let Some(c) = CHUNKY_RUNNING.captures(&line) {
+                    let percent = c[3].replace(',', ".").parse().unwrap_or(0.0);
+                    self.progress.show(percent, &format!("{} chunks, ETA {}", &c[2], &c[4]));
+                } else if line.contains("[Chunky] Task stopped") {
+                    bail!("The Chunky task was cancelled");
+                }
+            }
+        }
+        bail!("Server stopped; Chunky picks up where it left off with `chunky continue`")
+    }
+
+    async fn with_forceload(&mut self) -> Result<()> {
+        let dimension = self.opts.world.clone().unwrap_or("minecraft:overworld".to_string());
+        let batches = batches(self.opts.center, self.opts.radius);
+        let total: u64 = batches.iter().map(|batch| batch.chunks()).sum();
+        println!("Generating {} chunks with forceload", total);
+
+        let started = Instant::now();
+        let mut waited = Duration::ZERO;
+        let mut done = 0;
+        for batch in batches {
+            if self.opts.pause_for_players {
+                waited += self.wait_for_no_players().await?;
+            }
+            let (x1, z1, x2, z2) = batch.blocks();
+            let area = format!("{} {} {} {}", x1, z1, x2, z2);
+            let add = format!("execute in {} run forceload add {}", dimension, area);
+            let reply = send(&mut self.control, &add).await?;
+            if !reply.contains("Marked") && !reply.contains("No chunks were marked") {
+                bail!("The server didn't force load the chunks: {}", reply.trim());
+            }
+            self.forced = Some(format!("execute in {} run forceload remove {}", dimension, area));
+
+            self.wait_until_loaded(&dimension, [(x1, z1), (x1, z2), (x2, z1), (x2, z2)])
+                .await?;
+            if let Some(remove) = self.forced.take() {
+                send(&mut self.control, &remove).await?;
+            }
+
+            done += batch.chunks();
+            let working = started.elapsed().saturating_sub(waited);
+            let eta = working.mul_f64((total - done) as f64 / done as f64);
+            let detail = format!("{}/{} chunks, ETA {}", done, total, clock(eta));
+            self.progress.show(done as f64 * 100.0 / total as f64, &detail);
+        }
+        self.progress.clear();
+        println!("Generated {} chunks in {}", total, clock(started.elapsed()));
+        Ok(())
+    }
+
+    /// Wait until the chunks at these block positions are loaded
+    async fn wait_until_loaded(&mut self, dimension: &str, corners: [(i32, i32); 4]) -> Result<()> {
+        let deadline = Instant::now() + BATCH_TIMEOUT;
+        for (x, z) in corners {
+            let test = format!("execute in {} if loaded {} 0 {}", dimension, x, z);
+            loop {
+                let reply = send(&mut self.control, &test).await?;
+                if reply.contains("Test passed") {
+                    break;
+                }
+                if !reply.contains("Test failed") {
+                    bail!("forceload pregeneration needs Minecraft 1.19.4 or newer (or Chunky)");
+                }
+                if Instant::now() > deadline {
+                    bail!("Chunks were not generated within {:?}", BATCH_TIMEOUT);
+                }
+                tokio::time::sleep(Duration::from_millis(250)).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait while players are online, returning how long that was
+    async fn wait_for_no_players(&mut self) -> Result<Duration> {
+        let since = Instant::now();
+        let mut paused = false;
+        loop {
+            let online = self.players_online().await?;
+            if online == 0 {
+                break;
+            }
+            if !paused {
+                self.progress.note(&format!("Paused: {} online", plural(online, "player")));
+                paused = true;
+            }
+            tokio::time::sleep(PLAYER_POLL).await;
+        }
+        if paused {
+            self.progress.note("Players left; carrying on");
+        }
+        Ok(since.elapsed())
+    }
+
+    async fn players_online(&mut self) -> Result<usize> {
+        if !self.opts.pause_for_players {
+            return Ok(0);
+        }
+        let status = self.control.call("status", json!({})).await?;
+        Ok(status["players"].as_array().map_or(0, Vec::len))
+    }
+}
+
+/// Send a command and return what the server answers
+async fn send(control: &mut ControlClient, command: &str) -> Result<String> {
+    let reply = control
+        .call("send", json!({ "command": command, "capture_ms": REPLY_MS }))
+        .await?;
+    Ok(reply["output"].as_str().unwrap_or_default().to_string())
+}
+
+/// A square of chunks, in chunk coordinates
+struct Batch {
+    from: (i32, i32),
+    to: (i32, i32),
+}
+
+impl Batch {
+    fn chunks(&self) -> u64 {
+        ((self.to.0 - self.from.0 + 1) * (self.to.1 - self.from.1 + 1)) as u64
+    }
+
+    /// The block coordinates of its corners
+    fn blocks(&self) -> (i32, i32, i32, i32) {
+        (self.from.0 * 16, self.from.1 * 16, self.to.0 * 16 + 15, self.to.1 * 16 + 15)
+    }
+}
+
+/// The chunks within `radius` blocks of the center, as batches `forceload` takes
+fn batches(center: (i32, i32), radius: u32) -> Vec<Batch> {
+    let radius = radius.div_ceil(16) as i32;
+    let (cx, cz) = (center.0.div_euclid(16), center.1.div_euclid(16));
+    let mut batches = Vec::new();
+    for x in (cx - radius..=cx + radius).step_by(BATCH_CHUNKS as usize) {
+        for z in (cz - radius..=cz + radius).step_by(BATCH_CHUNKS as usize) {
+            let last = |from: i32, center: i32| (from + BATCH_CHUNKS - 1).min(center + radius);
+            batches.push(Batch {
+                from: (x, z),
+                to: (last(x, cx), last(z, cz)),
+            });
+        }
+    }
+    batches
+}
+
+/// The installed Chunky JAR, if any
+fn chunky_jar(server_dir: &Path) -> Option<PathBuf> {
+    ["plugins", "mods"]
+        .iter()
+        .flat_map(|dir| fs::read_dir(server_dir.join(dir)).into_iter().flatten().flatten())
+        .map(|entry| entry.path())
+        .find(|path| {
+            // Not ChunkyBorder and other add-ons
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+            name == "chunky.jar" || (name.starts_with("chunky-") && name.ends_with(".jar"))
+        })
+}
+
+#[derive(Deserialize)]
+struct ModrinthVersion {
+    files: Vec<ModrinthFile>,
+}
+
+#[derive(Deserialize)]
+struct ModrinthFile {
+    url: String,
+    filename: String,
+    primary: bool,
+}
+
+/// Download the latest Chunky build for the server's plugin or mod loader
+async fn install_chunky(server_dir: &Path) -> Result<PathBuf> {
+    let jar = crate::find_jar(server_dir).ok();
+    let jar_name = jar
+        .as_ref()
+        .and_then(|jar| jar.file_name())
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let takes_plugins = ["paper", "purpur", "spigot", "bukkit", "folia", "pufferfish"]
+        .iter()
+        .any(|name| jar_name.contains(name));
+    let (loader, dir) = if jar_name == "fabric-server-launch.jar" {
+        ("fabric", "mods")
+    } else if takes_plugins || server_dir.join("plugins").is_dir() {
+        ("paper", "plugins")
+    } else {
+        bail!("Can't tell which Chunky build the server takes; put it in plugins/ or mods/");
+    };
+    let url = format!("{}?loaders=%5B%22{}%22%5D", CHUNKY_VERSIONS, loader);
+    let versions: Vec<ModrinthVersion> = fetch_json(&url).await?;
+    let file = versions
+        .iter()
+        .flat_map(|version| &version.files)
+        .find(|file| file.primary)
+        .with_context(|| format!("No Chunky build for {} on Modrinth", loader))?;
+
+    println!("Downloading {}...", file.filename);
+    let dest = server_dir.join(dir).join(&file.filename);
+    fs::create_dir_all(server_dir.join(dir))?;
+    http::get(&["-o", &dest.to_string_lossy(), &file.url]).await?;
+    if !is_jar(&dest) {
+        let _ = fs::remove_file(&dest);
+        bail!("The download is not a JAR file");
+    }
+    Ok(dest)
+}
+
+/// Parse a center given as "X,Z"
+pub fn parse_center(s: &str) -> Result<(i32, i32), String> {
+    s.split_once(',')
+        .and_then(|(x, z)| Some((x.trim().parse().ok()?, z.trim().parse().ok()?)))
+        .ok_or_else(|| format!("expected block coordinates X,Z, got {:?}", s))
+}
+
+/// A progress bar, redrawn in place on a terminal
+struct Progress {
+    tty: bool,
+    /// What is on the current line, to redraw after a note
+    line: Option<String>,
+    /// Last whole percentage printed when not on a terminal
+    printed: Option<u32>,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            tty: std::io::stdout().is_terminal(),
+            line: None,
+            printed: None,
+        }
+    }
+
+    fn show(&mut self, percent: f64, detail: &str) {
+        let percent = percent.clamp(0.0, 100.0);
+        let filled = (percent / 100.0 * BAR_WIDTH as f64).round() as usize;
+        let line = format!(
+            "[{}{}] {:5.1}%  {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            percent,
+            detail
+        );
+        if self.tty {
+            print!("\r\x1b[K{}", line);
+            let _ = std::io::stdout().flush();
+            self.line = Some(line);
+        } else if self.printed != Some(percent as u32) {
+            println!("{}", line);
+            self.printed = Some(percent as u32);
+        }
+    }
+
+    /// Print a message above the bar
+    fn note(&mut self, message: &str) {
+        if self.tty && self.line.is_some() {
+            print!("\r\x1b[K");
+        }
+        println!("{}", message);
+        if let Some(line) = &self.line {
+            print!("{}", line);
+            let _ = std::io::stdout().flush();
+        }
+    }
+
+    /// End the bar's line
+    fn clear(&mut self) {
+        if self.line.take().is_some() {
+            println!();
+        }
+    }
+}
+
+/// A duration as H:MM:SS
+fn clock(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn plural(n: usize, noun: &str) -> String {
+    format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" })
+}
@@ -0,0 +1,177 @@
+//! Garbage collector presets for the JVM flags `start` builds
+//!
+//! `mcwrap start --preset <name>` (or `[java] preset` in mcwrap.toml) adds a
+//! tuned set of collector flags in front of `[java] flags`: `aikar` for
+//! Aikar's well-known G1 flags, or for large heaps `zgc`, `generational-zgc`
+//! and `shenandoah`, whose pauses stay short however big the heap is. Those
+//! three pre-touch the whole heap at startup, so they want `[java] memory`
+//! set. The installed `java` is asked whether it has the collector first, as
+//! that depends on the Java version and the vendor (Oracle's builds have no
+//! Shenandoah); when it doesn't, the Aikar flags are used instead. Presets
+//! only apply when mcwrap builds the Java arguments itself.
+
+use crate::config::JavaConfig;
+use crate::disk::parse_size;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+/// Heap above which Aikar's flags use their large-heap values
+const LARGE_HEAP: u64 = 12 << 30;
+
+/// Aikar's G1 flags that don't depend on the heap size
+const AIKAR: &[&str] = &[
+    "-XX:+ParallelRefProcEnabled",
+    "-XX:MaxGCPauseMillis=200",
+    "-XX:+UnlockExperimentalVMOptions",
+    "-XX:G1HeapWastePercent=5",
+    "-XX:G1MixedGCCountTarget=4",
+    "-XX:G1MixedGCLiveThresholdPercent=90",
+    "-XX:G1RSetUpdatingPauseTimePercent=5",
+    "-XX:SurvivorRatio=32",
+    "-XX:MaxTenuringThreshold=1",
+];
+
+/// Flags every preset adds: commit the whole heap up front, ignore plugins calling
+/// `System.gc()`, and keep JVM statistics off the disk
+const COMMON: &[&str] =
+    &["-XX:+AlwaysPreTouch", "-XX:+DisableExplicitGC", "-XX:+PerfDisableSharedMem"];
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// G1 with Aikar's flags
+    Aikar,
+    /// ZGC (Java 15 or later)
+    Zgc,
+    /// ZGC with young and old generations (Java 21 or later)
+    GenerationalZgc,
+    /// Shenandoah (Java 12 or later, not in Oracle's builds)
+    Shenandoah,
+}
+
+impl Preset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Aikar => "aikar",
+            Preset::Zgc => "zgc",
+            Preset::GenerationalZgc => "generational-zgc",
+            Preset::Shenandoah => "shenandoah",
+        }
+    }
+
+    /// The first Java version with the collector ready for production
+    fn min_java(self) -> u32 {
+        match self {
+            Preset::Aikar => 8,
+            Preset::Zgc => 15,
+            Preset::GenerationalZgc => 21,
+            Preset::Shenandoah => 12,
+        }
+    }
+
+    /// The flags that pick the collector, enough to ask the JVM whether it has it
+    fn collector(self, java: u32) -> Vec<&'static str> {
+        match self {
+            Preset::Aikar => vec!["-XX:+UseG1GC"],
+            Preset::Zgc => vec!["-XX:+UseZGC"],
+            // Generational from Java 23, and the only mode left from 24
+            Preset::GenerationalZgc if java >= 23 => vec!["-XX:+UseZGC"],
+            Preset::GenerationalZgc => vec!["-XX:+UseZGC", "-XX:+ZGenerational"],
+            Preset::Shenandoah => vec!["-XX:+UseShenandoahGC"],
+        }
+    }
+
+    fn flags(self, java: u32, heap: u64) -> Vec<String> {
+        let mut flags: Vec<String> = self.collector(java).into_iter().map(String::from).collect();
+        match self {
+            Preset::Aikar => {
+                let (new, max_new, region, reserve, occupancy) = if heap > LARGE_HEAP {
+                    (40, 50, 16, 15, 20)
+                } else {
+                    (30, 40, 8, 20, 15)
+                };
+                // The sized flags are experimental, so they go after the unlock in AIKAR
+                flags.extend(AIKAR.iter().map(|flag| flag.to_string()));
+                flags.extend([
+                    format!("-XX:G1NewSizePercent={}", new),
+                    format!("-XX:G1MaxNewSizePercent={}", max_new),
+                    format!("-XX:G1HeapRegionSize={}M", region),
+                    format!("-XX:G1ReservePercent={}", reserve),
+                    format!("-XX:InitiatingHeapOccupancyPercent={}", occupancy),
+                ]);
+            }
+            // Keep the heap once it has grown; handing memory back to the OS only costs time
+            Preset::Zgc | Preset::GenerationalZgc => flags.push("-XX:-ZUncommit".to_string()),
+            Preset::Shenandoah => {}
+        }
+        flags.extend(COMMON.iter().map(|flag| flag.to_string()));
+        if self == Preset::Aikar {
+            flags.push("-Dusing.aikars.flags=https://mcflags.emc.gs".to_string());
+            flags.push("-Daikars.new.flags=true".to_string());
+        }
+        flags
+    }
+}
+
+/// Add the flags of `preset` (from `start --preset`, or else `[java] preset`) to `java` when
+/// mcwrap builds the Java arguments. Returns what was chosen and why, for `start` to print.
+pub fn apply(
+    java: &mut JavaConfig,
+    preset: Option<Preset>,
+    launcher: &[String],
+    java_args: &[String],
+) -> Option<String> {
+    let preset = preset.or(java.preset)?;
+    let note = if !launcher.is_empty() {
+        format!("{} not applied (the server runs its own command)", preset.name())
+    } else if !java_args.is_empty() {
+        format!("{} not applied (Java arguments were given)", preset.name())
+    } else {
+        add_flags(java, preset)
+    };
+    Some(note)
+}
+
+/// Put the flags of `preset` in front of `[java] flags`, falling back to Aikar's when `java`
+/// lacks the collector
+fn add_flags(java: &mut JavaConfig, preset: Preset) -> String {
+    let Some(version) = java_version(&[]) else {
+        return format!("{} not applied (couldn't run java -version)", preset.name());
+    };
+    let (chosen, note) = if version < preset.min_java() {
+        let note = format!(
+            "{} needs Java {} or later, not {}; using aikar",
+            preset.name(),
+            preset.min_java(),
+            version
+        );
+        (Preset::Aikar, note)
+    } else if java_version(&preset.collector(version)).is_none() {
+        let note = format!("{} isn't in this Java {} build; using aikar", preset.name(), version);
+        (Preset::Aikar, note)
+    } else {
+        (preset, format!("{} (Java {})", preset.name(), version))
+    };
+    // Without [java] memory the heap grows to the default -Xmx4G
+    let heap = java.memory.as_deref().and_then(|memory| parse_size(memory).ok());
+    let heap = heap.unwrap_or(4 << 30);
+    let mut flags = chosen.flags(version, heap);
+    flags.append(&mut java.flags);
+    java.flags = flags;
+    note
+}
+
+/// The major version `java -version` reports, e.g. 17, or 8 for "1.8.0_392"; `None` when java
+/// won't start with `flags`
+pub fn java_version(flags: &[&str]) -> Option<u32> {
+    let output = Command::new("java").args(flags).arg("-version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // The version is quoted on the first line, e.g. `openjdk version "21.0.2" 2024-01-16`
+    let text = String::from_utf8_lossy(&output.stderr);
+    let version = text.lines().next()?.split('"').nth(1)?;
+    let version = version.strip_prefix("1.").unwrap_or(version);
+    version.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()
+}
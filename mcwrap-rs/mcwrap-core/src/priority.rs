@@ -0,0 +1,163 @@
+//! CPU affinity and scheduling priority for the server process
+//!
+//! `start --cpus 0-3 --nice 5 --ionice best-effort:7` pins the server to some
+//! CPUs and adjusts its CPU and IO priority, e.g. to keep a latency-sensitive
+//! server apart from batch workloads. The settings are applied in the forked
+//! child before it execs the server, and remembered in the registry so that
+//! mcwrapd and `boot` start the server the same way. CPU affinity and IO
+//! priority are Linux features; elsewhere only `--nice` is accepted.
+
+use anyhow::{bail, Result};
+use clap::Args;
+use nix::libc;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Highest CPU number a cpu_set_t can hold
+#[cfg(target_os = "linux")]
+const MAX_CPU: usize = libc::CPU_SETSIZE as usize - 1;
+
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+/// Scheduling settings as given on the command line
+#[derive(Args, Serialize, Deserialize, Clone, Default)]
+pub struct Priority {
+    /// CPUs the server may run on (e.g. 0-3,6)
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    /// Niceness, from -20 (highest priority, needs root) to 19
+    #[arg(long, allow_hyphen_values = true)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nice: Option<i32>,
+    /// IO priority: realtime[:0-7], best-effort[:0-7] or idle
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ionice: Option<String>,
+}
+
+impl Priority {
+    /// Validate the settings
+    pub fn resolve(&self) -> Result<Scheduling> {
+        #[cfg(not(target_os = "linux"))]
+        if self.cpus.is_some() || self.ionice.is_some() {
+            bail!("--cpus and --ionice are only supported on Linux");
+        }
+        #[cfg(target_os = "linux")]
+        let cpus = self.cpus.as_deref().map(parse_cpus).transpose()?;
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                bail!("--nice must be between -20 and 19");
+            }
+        }
+        #[cfg(target_os = "linux")]
+        let ioprio = self.ionice.as_deref().map(parse_ionice).transpose()?;
+        Ok(Scheduling {
+            #[cfg(target_os = "linux")]
+            cpus,
+            nice: self.nice,
+            #[cfg(target_os = "linux")]
+            ioprio,
+        })
+    }
+
+    /// The settings as `mcwrap start` arguments
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cpus) = &self.cpus {
+            args.extend(["--cpus".to_string(), cpus.clone()]);
+        }
+        if let Some(nice) = self.nice {
+            args.extend(["--nice".to_string(), nice.to_string()]);
+        }
+        if let Some(ionice) = &self.ionice {
+            args.extend(["--ionice".to_string(), ionice.clone()]);
+        }
+        args
+    }
+}
+
+/// Validated scheduling settings, ready to apply
+#[derive(Default)]
+pub struct Scheduling {
+    #[cfg(target_os = "linux")]
+    cpus: Option<Vec<usize>>,
+    nice: Option<i32>,
+    #[cfg(target_os = "linux")]
+    ioprio: Option<libc::c_int>,
+}
+
+impl Scheduling {
+    /// Apply to the calling process (between fork and exec)
+    ///
+    /// Failures are reported on stderr, which is the server's console, and
+    /// the server starts anyway.
+    pub fn apply(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some(cpus) = &self.cpus {
+            let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for &cpu in cpus {
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+            let size = std::mem::size_of::<libc::cpu_set_t>();
+            if unsafe { libc::sched_setaffinity(0, size, &set) } != 0 {
+                warn("CPU affinity");
+            }
+        }
+        if let Some(nice) = self.nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+                warn("nice");
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(ioprio) = self.ioprio {
+            if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+                warn("IO priority");
+            }
+        }
+    }
+}
+
+fn warn(setting: &str) {
+    eprintln!("mcwrap: failed to set {}: {}", setting, io::Error::last_os_error());
+}
+
+/// Parse a CPU list such as "0-3,6"
+#[cfg(target_os = "linux")]
+fn parse_cpus(list: &str) -> Result<Vec<usize>> {
+    use anyhow::Context;
+
+    let mut cpus = Vec::new();
+    for part in list.split(',') {
+        let part = part.trim();
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let first: usize = first.parse().with_context(|| format!("Invalid CPU list {:?}", list))?;
+        let last: usize = last.parse().with_context(|| format!("Invalid CPU list {:?}", list))?;
+        if first > last || last > MAX_CPU {
+            bail!("Invalid CPU range {:?}", part);
+        }
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+/// Parse an IO priority such as "best-effort:7" into an ioprio value
+#[cfg(target_os = "linux")]
+fn parse_ionice(value: &str) -> Result<libc::c_int> {
+    use anyhow::Context;
+
+    let (class, level) = value.split_once(':').unwrap_or((value, "4"));
+    let class = match class {
+        "realtime" | "rt" => 1,
+        "best-effort" | "be" => 2,
+        "idle" => 3,
+        _ => bail!("Unknown IO class {:?} (use realtime, best-effort or idle)", class),
+    };
+    let level: libc::c_int = level.parse().ok().filter(|l| (0..=7).contains(l)).with_context(|| {
+        format!("Invalid IO priority level in {:?} (use 0-7)", value)
+    })?;
+    Ok((class << IOPRIO_CLASS_SHIFT) | if class == 3 { 0 } else { level })
+}
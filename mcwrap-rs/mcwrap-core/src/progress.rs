@@ -0,0 +1,84 @@
+//! Progress of `mcwrap start --json`
+//!
+//! With `--json`, `start` leaves out its usual report and prints each step
+//! as a JSON line on stdout instead, for GUIs to show real progress:
+//! `jar_resolved` and `jvm_selected` when the server runs on java, `forked`
+//! once the server process exists, then with `--wait` `waiting_ready` and
+//! `ready`. Whatever goes wrong ends it with `failed`, the reason and its
+//! code (see `failure`).
+
+use anyhow::Error;
+use crate::failure;
+use serde::Serialize;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+/// A step of starting a server
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Step {
+    /// The JAR or Forge arguments file the java command was built from
+    JarResolved {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        jar: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        forge_args: Option<String>,
+    },
+    /// The java that will run the server, and where it was found on the PATH
+    JvmSelected { program: String, path: Option<PathBuf> },
+    /// The server process is running
+    Forked {
+        pid: i32,
+        /// Started by mcwrapd rather than by this process
+        supervised: bool,
+    },
+    /// Following the console log until the server is done starting
+    WaitingReady,
+    Ready { secs: f64 },
+    /// With the failure's code and exit code, as `--json` commands report errors
+    Failed {
+        reason: String,
+        code: &'static str,
+        exit_code: i32,
+    },
+}
+
+/// Where `start` reports to: its usual lines, or JSON steps
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Progress {
+    json: bool,
+}
+
+impl Progress {
+    pub fn new(json: bool) -> Self {
+        Self { json }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    /// Report a step, which only JSON progress shows
+    pub fn step(&self, step: Step) {
+        if self.json {
+            println!("{}", serde_json::to_string(&step).expect("steps always serialize"));
+        }
+    }
+
+    /// Report what stopped the start, as a `failed` step
+    pub fn fail(&self, error: &Error) {
+        let kind = failure::kind(error);
+        self.step(Step::Failed {
+            reason: format!("{:#}", error),
+            code: kind.map_or("error", failure::ErrorKind::name),
+            exit_code: failure::exit_code(error),
+        });
+    }
+
+    /// Print a line of the usual report, which JSON progress leaves out
+    pub fn say(&self, line: impl Display) {
+        if !self.json {
+            println!("{}", line);
+        }
+    }
+}
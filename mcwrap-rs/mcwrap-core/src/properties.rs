@@ -0,0 +1,57 @@
+//! Reading and editing `server.properties`
+//!
+//! Edits keep the file's comments, order and other lines as they are, since
+//! the server rewrites the file on its next start anyway.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Properties {
+    path: PathBuf,
+    lines: Vec<String>,
+}
+
+impl Properties {
+    /// Read a server's properties; a missing file reads as empty
+    pub fn load(server_dir: &Path) -> Result<Self> {
+        let path = server_dir.join("server.properties");
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        Ok(Self {
+            path,
+            lines: content.lines().map(str::to_string).collect(),
+        })
+    }
+
+    /// A property's value, unless it is missing or empty
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .find(|(k, _)| k.trim() == key)
+            .map(|(_, value)| value.trim())
+            .filter(|value| !value.is_empty())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        let line = format!("{}={}", key, value);
+        let existing = self.lines.iter_mut().find(|line| {
+            !line.starts_with('#') && line.split_once('=').is_some_and(|(k, _)| k.trim() == key)
+        });
+        match existing {
+            Some(existing) => *existing = line,
+            None => self.lines.push(line),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut content = self.lines.join("\n");
+        content.push('\n');
+        fs::write(&self.path, content).with_context(|| format!("Failed to write {:?}", self.path))
+    }
+}
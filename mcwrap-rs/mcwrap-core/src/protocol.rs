@@ -0,0 +1,344 @@
+//! Framed protocol spoken on a server's `pty.sock`
+//!
+//! Every message is a frame: a 4-byte big-endian length (covering the type
+//! byte and the payload), a 1-byte frame type, then the payload. Console
+//! bytes travel untouched in ConsoleOutput/Input frames, terminal sizes as
+//! two big-endian u16s, and everything else as JSON.
+//!
+//! A client that sets `protocol` in its credentials gets a Response back with
+//! the daemon's `Handshake`: its protocol version and what it can do. Daemons
+//! from before the handshake send nothing, so `authenticate` asks for the
+//! status as well and takes a status reply without a handshake to mean
+//! version 1.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest frame we accept; anything bigger is a protocol error
+pub const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// Version of the protocols spoken on a server's sockets, raised when they change
+pub const VERSION: u32 = 2;
+
+/// What this daemon can do, announced in its handshake
+pub const CAPABILITIES: &[&str] = &[
+    "resize",
+    "scrollback",
+    "take",
+    "events",
+    "exec",
+    "complete",
+    "queue",
+    "send",
+];
+
+/// Version spoken by daemons from before the handshake
+const LEGACY_VERSION: u32 = 1;
+
+const CONSOLE_OUTPUT: u8 = 1;
+const INPUT: u8 = 2;
+const RESIZE: u8 = 3;
+const SUBSCRIBE: u8 = 4;
+const COMMAND: u8 = 5;
+const RESPONSE: u8 = 6;
+const AUTH: u8 = 7;
+const SCROLLBACK: u8 = 8;
+const PRIMARY: u8 = 9;
+
+/// A single protocol message
+#[derive(Debug)]
+pub enum Frame {
+    /// Console output from the server (daemon → client)
+    ConsoleOutput(Vec<u8>),
+    /// Bytes to write to the server's terminal (client → daemon)
+    Input(Vec<u8>),
+    /// Client terminal size (client → daemon)
+    Resize { rows: u16, cols: u16 },
+    /// Choose which streams the client receives (client → daemon)
+    Subscribe(Subscription),
+    /// Control request (client → daemon)
+    Command(Command),
+    /// Reply to a Command or Auth (daemon → client)
+    Response(Response),
+    /// Credentials, required before any other frame (client → daemon)
+    Auth(Credentials),
+    /// Recent console output, log-filtered, sent on subscribing (daemon → client)
+    Scrollback(Vec<u8>),
+    /// Whether this client's keystrokes reach the server (daemon → client)
+    Primary(bool),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Subscription {
+    /// Receive ConsoleOutput frames
+    #[serde(default)]
+    pub console: bool,
+    /// Start with a Scrollback frame of recent output
+    #[serde(default)]
+    pub scrollback: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Credentials {
+    /// Server token or a user's token
+    pub token: String,
+    /// User a relay holding the server token acts for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Protocol version the client speaks; the daemon answers with its handshake if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<u32>,
+}
+
+impl Credentials {
+    pub fn token(token: String) -> Self {
+        Self {
+            token,
+            user: None,
+            protocol: Some(VERSION),
+        }
+    }
+}
+
+/// What a daemon announces about itself once a client has authenticated
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Handshake {
+    #[serde(default = "legacy_version")]
+    pub protocol: u32,
+    #[serde(default = "legacy_capabilities")]
+    pub capabilities: Vec<String>,
+}
+
+impl Handshake {
+    /// This daemon's
+    pub fn current() -> Self {
+        Self {
+            protocol: VERSION,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// A daemon's from before the handshake
+    pub fn legacy() -> Self {
+        Self {
+            protocol: LEGACY_VERSION,
+            capabilities: legacy_capabilities(),
+        }
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+fn legacy_version() -> u32 {
+    LEGACY_VERSION
+}
+
+/// Daemons from before the handshake are taken to do what the last of them did
+fn legacy_capabilities() -> Vec<String> {
+    // The Send command came after the handshake
+    CAPABILITIES.iter().filter(|c| **c != "send").map(|c| c.to_string()).collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    /// Report daemon and server status
+    Status,
+    /// Become the primary client, turning the current one into an observer
+    Take,
+    /// Run a console command, as the control socket's `send` does, without
+    /// being the primary client
+    Send {
+        command: String,
+        /// Wait in the command queue (skipping it needs the admin role)
+        #[serde(default = "default_true")]
+        queue: bool,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub data: serde_json::Value,
+}
+
+impl Response {
+    pub fn ok(data: serde_json::Value) -> Self {
+        Self {
+            ok: true,
+            error: None,
+            data,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(message.into()),
+            data: serde_json::Value::Null,
+        }
+    }
+}
+
+impl Frame {
+    /// Serialize the frame including its length header
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, payload) = match self {
+            Frame::ConsoleOutput(data) => (CONSOLE_OUTPUT, data.clone()),
+            Frame::Input(data) => (INPUT, data.clone()),
+            Frame::Resize { rows, cols } => {
+                let mut payload = rows.to_be_bytes().to_vec();
+                payload.extend_from_slice(&cols.to_be_bytes());
+                (RESIZE, payload)
+            }
+            Frame::Subscribe(sub) => (SUBSCRIBE, to_json(sub)),
+            Frame::Command(cmd) => (COMMAND, to_json(cmd)),
+            Frame::Response(resp) => (RESPONSE, to_json(resp)),
+            Frame::Auth(creds) => (AUTH, to_json(creds)),
+            Frame::Scrollback(data) => (SCROLLBACK, data.clone()),
+            Frame::Primary(primary) => (PRIMARY, vec![*primary as u8]),
+        };
+
+        let mut out = Vec::with_capacity(5 + payload.len());
+        out.extend_from_slice(&(payload.len() as u32 + 1).to_be_bytes());
+        out.push(kind);
+        out.extend_from_slice(&payload);
+        out
+    }
+
+    /// Parse a frame from its type byte and payload
+    pub fn decode(kind: u8, payload: Vec<u8>) -> io::Result<Frame> {
+        Ok(match kind {
+            CONSOLE_OUTPUT => Frame::ConsoleOutput(payload),
+            INPUT => Frame::Input(payload),
+            RESIZE => {
+                if payload.len() != 4 {
+                    return Err(invalid("Resize frame must be 4 bytes"));
+                }
+                Frame::Resize {
+                    rows: u16::from_be_bytes([payload[0], payload[1]]),
+                    cols: u16::from_be_bytes([payload[2], payload[3]]),
+                }
+            }
+            SUBSCRIBE => Frame::Subscribe(from_json(&payload)?),
+            COMMAND => Frame::Command(from_json(&payload)?),
+            RESPONSE => Frame::Response(from_json(&payload)?),
+            AUTH => Frame::Auth(from_json(&payload)?),
+            SCROLLBACK => Frame::Scrollback(payload),
+            PRIMARY => match payload[..] {
+                [flag] => Frame::Primary(flag != 0),
+                _ => return Err(invalid("Primary frame must be 1 byte")),
+            },
+            other => return Err(invalid(&format!("Unknown frame type {}", other))),
+        })
+    }
+}
+
+/// Incremental decoder for frames arriving on a non-blocking stream
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Pop the next complete frame, if one has been fully received
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        if self.buf.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]) as usize;
+        if len == 0 || len > MAX_FRAME {
+            return Err(invalid("Bad frame length"));
+        }
+        if self.buf.len() < 4 + len {
+            return Ok(None);
+        }
+
+        let kind = self.buf[4];
+        let payload = self.buf[5..4 + len].to_vec();
+        self.buf.drain(..4 + len);
+        Frame::decode(kind, payload).map(Some)
+    }
+}
+
+/// Write one frame to an async stream
+pub async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, frame: &Frame) -> io::Result<()> {
+    w.write_all(&frame.encode()).await?;
+    w.flush().await
+}
+
+/// Read one frame from an async stream, consuming nothing after it
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Frame> {
+    let len = r.read_u32().await? as usize;
+    if len == 0 || len > MAX_FRAME {
+        return Err(invalid("Bad frame length"));
+    }
+    let kind = r.read_u8().await?;
+    let mut payload = vec![0; len - 1];
+    r.read_exact(&mut payload).await?;
+    Frame::decode(kind, payload)
+}
+
+/// Present credentials on a newly opened stream and return the daemon's handshake
+pub async fn authenticate<S>(stream: &mut S, creds: Credentials) -> io::Result<Handshake>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_frame(stream, &Frame::Auth(creds)).await?;
+    write_frame(stream, &Frame::Command(Command::Status)).await?;
+    let reply = read_response(stream).await?;
+    if reply.data.get("protocol").is_none() {
+        // The status reply, from a daemon that didn't answer the credentials
+        return Ok(Handshake::legacy());
+    }
+    read_response(stream).await?;
+    serde_json::from_value(reply.data).map_err(|e| invalid(&e.to_string()))
+}
+
+/// Send a Command and wait for its Response, failing if it is an error
+pub async fn request<S>(stream: &mut S, command: Command) -> io::Result<Response>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_frame(stream, &Frame::Command(command)).await?;
+    read_response(stream).await
+}
+
+/// Wait for the next Response, failing if it is an error
+async fn read_response<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Response> {
+    loop {
+        if let Frame::Response(response) = read_frame(r).await? {
+            if !response.ok {
+                let error = response.error.unwrap_or_else(|| "Request failed".to_string());
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, error));
+            }
+            return Ok(response);
+        }
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Vec<u8> {
+    serde_json::to_vec(value).expect("protocol types always serialize")
+}
+
+fn from_json<T: for<'de> Deserialize<'de>>(payload: &[u8]) -> io::Result<T> {
+    serde_json::from_slice(payload).map_err(|e| invalid(&e.to_string()))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
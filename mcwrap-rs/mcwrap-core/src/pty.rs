@@ -0,0 +1,1400 @@
+//! PTY handling for mcwrap
+//!
+//! Spawns the Java process with a real PTY so JLine enables tab completion.
+//! The PTY master is exposed via a Unix socket for clients to connect.
+
+use anyhow::{Context, Result};
+use crate::access::Access;
+use crate::alerts::{self, Alerts};
+use crate::audit;
+use crate::auth;
+use crate::cgroup::Cgroup;
+use crate::config::{LogFormat, QueueConfig, SlowClient};
+use crate::control::{self, ControlWriter};
+use crate::daemon_log::{self, Level};
+use crate::dedup::Dedup;
+use crate::docker;
+use crate::events::{self, Event, EventKind};
+use crate::forward::{Forwarder, Forwarding};
+use crate::history::{self, LineBuffer};
+use crate::jsonl::Encoder;
+use crate::log_index::Indexer;
+use crate::notify::Notifier;
+use crate::oom;
+use crate::outbox::Outbox;
+use crate::platform;
+use crate::players::Players;
+use crate::playtime::Playtime;
+use crate::priority::Scheduling;
+use crate::protocol::{Command, Frame, FrameDecoder, Handshake, Response};
+use crate::queue::CommandQueue;
+use crate::schedule;
+use crate::scripting::{self, Scripts};
+use crate::scrollback::Ring;
+use crate::ship::{Shipper, Shipping};
+use crate::startup;
+use crate::timestamps::{Stamper, Zone};
+use crate::triggers::{self, Triggers};
+use crate::users::{Identity, Role, Users};
+use crate::watchdog::Watchdog;
+use crate::Exit;
+use nix::errno::Errno;
+use nix::libc;
+use nix::pty::{openpty, Winsize};
+use nix::sys::signal::{kill, killpg, signal, SigHandler, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{dup2, execvp, fork, getuid, pipe, setsid, ForkResult, Pid};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read as IoRead, Write as IoWrite};
+use std::os::fd::{AsRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Time helpers get to exit on SIGTERM once the server has exited
+const LEFTOVER_GRACE: Duration = Duration::from_secs(5);
+
+/// How often the main loop checks on the server while its console is quiet, as a
+/// helper it started may keep the terminal open after it exits
+const EXIT_CHECK_MS: i32 = 1000;
+
+pub struct PtySpawnResult {
+    pub child_pid: i32,
+}
+
+/// Settings for the PTY daemon
+pub struct DaemonOptions {
+    pub log_file: PathBuf,
+    pub socket_path: PathBuf,
+    pub control_socket: PathBuf,
+    pub audit_log: PathBuf,
+    pub history_file: PathBuf,
+    /// Where the reason is saved if the server fails to start
+    pub failure_file: PathBuf,
+    /// Where out-of-memory events are saved
+    pub oom_file: PathBuf,
+    /// Server state, where the exit is recorded
+    pub state_file: PathBuf,
+    /// Stop the server once it has run out of memory
+    pub stop_on_oom: bool,
+    /// Secret clients must present before anything else
+    pub token: String,
+    /// Ownership and permissions for the sockets
+    pub access: Access,
+    /// Speak the raw byte protocol of older mcwrap clients on the socket
+    pub legacy_raw: bool,
+    /// Checks that the server hasn't frozen
+    pub watchdog: Option<Watchdog>,
+    /// Resource limits the server runs under
+    pub cgroup: Option<Cgroup>,
+    /// Docker container the server runs in, removed once it has exited
+    pub container: Option<String>,
+    /// CPU affinity and scheduling priority for the server
+    pub scheduling: Scheduling,
+    /// Environment variables from the server config
+    pub env: BTreeMap<String, String>,
+    /// Pacing of commands from `send`
+    pub queue: QueueConfig,
+    /// Console output queued for a client that falls behind
+    pub client_buffer: usize,
+    pub slow_client: SlowClient,
+    /// The daemon's own log
+    pub daemon_log: PathBuf,
+    pub log_level: Level,
+    /// Where events are recorded
+    pub events_file: PathBuf,
+    /// Where the players' playtime is kept
+    pub playtime_file: PathBuf,
+    /// Memory use (bytes) above which a HighMemory event is sent
+    pub high_memory: Option<u64>,
+    /// Where events are sent from `[notify]`
+    pub notifier: Option<Notifier>,
+    /// Alerts about who is online
+    pub alerts: Arc<Alerts>,
+    /// Text or JSON lines
+    pub log_format: LogFormat,
+    /// Time zone of the console log's timestamps, when it has them
+    pub timestamps: Option<Zone>,
+    /// Collapse runs of a repeated line in the console log
+    pub dedupe: bool,
+    /// Keep an index of the console log by time
+    pub log_index: bool,
+    /// Where console lines are forwarded
+    pub forwarding: Option<Forwarding>,
+    /// Where log records are pushed
+    pub shipping: Option<Shipping>,
+    /// Console commands run on a cron schedule
+    pub schedule: Vec<schedule::Task>,
+    /// Actions taken on matching console lines
+    pub triggers: Triggers,
+    /// Automation scripts
+    pub scripts: Scripts,
+}
+
+/// A client connected to the PTY socket
+///
+/// Of the framed clients watching the console, only the primary one types
+/// into it and sets its size; the others observe. The first admin to
+/// subscribe becomes primary, `Command::Take` moves the role, and when the
+/// primary leaves it passes to the longest-attached admin.
+struct Client {
+    /// Tells clients apart in the daemon log
+    id: u64,
+    stream: UnixStream,
+    /// Frame decoder, or None for legacy raw clients
+    decoder: Option<FrameDecoder>,
+    /// Receives console output
+    subscribed: bool,
+    /// Who the client authenticated as, once it has
+    identity: Option<Identity>,
+    /// Unix uid of the client process
+    uid: Option<u32>,
+    /// Keystrokes of the line being typed, for the audit log
+    typed: LineBuffer,
+    /// Keystrokes are forwarded to the server
+    primary: bool,
+    /// What is waiting to be written to the client
+    outbox: Outbox,
+}
+
+/// A client asking for the primary role
+enum Claim {
+    /// Only if no client has it
+    IfVacant,
+    /// Even from the current primary
+    Steal,
+}
+
+/// Spawn a process with a PTY in a detached daemon and expose it via Unix socket
+pub fn spawn_with_pty(
+    server_dir: &Path,
+    command: &[String],
+    opts: &DaemonOptions,
+) -> Result<PtySpawnResult> {
+    // Pipe used by the daemon to report the server PID back to us
+    let (pid_read, pid_write) = pipe().context("Failed to create pipe")?;
+
+    // Double fork to daemonize
+    match unsafe { fork() }.context("Daemon fork failed")? {
+        ForkResult::Parent { child } => {
+            drop(pid_write);
+            // Reap the intermediate process
+            waitpid(child, None).ok();
+
+            let mut buf = [0u8; 4];
+            File::from(pid_read)
+                .read_exact(&mut buf)
+                .context("PTY daemon failed to start the server")?;
+            return Ok(PtySpawnResult {
+                child_pid: i32::from_ne_bytes(buf),
+            });
+        }
+        ForkResult::Child => {
+            // Daemon process
+            drop(pid_read);
+            setsid().ok();
+
+            // Second fork to prevent zombie
+            match unsafe { fork() } {
+                Ok(ForkResult::Parent { .. }) => {
+                    std::process::exit(0);
+                }
+                Ok(ForkResult::Child) => {
+                    // This is the actual daemon
+                }
+                Err(_) => std::process::exit(1),
+            }
+        }
+    }
+
+    // Nobody sees the daemon's output any more, so it goes to its log
+    if daemon_log::init(&opts.daemon_log, opts.log_level).is_ok() {
+        daemon_log::capture_stdio();
+    }
+
+    // The server is forked from the daemon so the daemon can reap it
+    let (master_fd, child_pid) = match spawn_child(server_dir, command, opts) {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            daemon_log::error(format!("Failed to start the server: {:#}", e));
+            std::process::exit(1);
+        }
+    };
+    File::from(pid_write)
+        .write_all(&child_pid.as_raw().to_ne_bytes())
+        .ok();
+
+    run_daemon(master_fd, child_pid, opts);
+    std::process::exit(0);
+}
+
+/// Run the server with a PTY, managing it from the current process
+///
+/// `on_spawn` is called with the server PID right after it is forked.
+/// Returns the server's exit code once it exits.
+pub fn run_foreground(
+    server_dir: &Path,
+    command: &[String],
+    opts: &DaemonOptions,
+    on_spawn: impl FnOnce(i32) -> Result<()>,
+) -> Result<i32> {
+    if let Err(e) = daemon_log::init(&opts.daemon_log, opts.log_level) {
+        eprintln!("Failed to open {:?}: {}", opts.daemon_log, e);
+    }
+    let (master_fd, child_pid) = spawn_child(server_dir, command, opts)?;
+
+    if let Err(e) = on_spawn(child_pid.as_raw()) {
+        kill(child_pid, Signal::SIGKILL).ok();
+        waitpid(child_pid, None).ok();
+        return Err(e);
+    }
+
+    Ok(run_daemon(master_fd, child_pid, opts))
+}
+
+/// Fork the server process attached to a new PTY, returning the master end
+fn spawn_child(
+    server_dir: &Path,
+    command: &[String],
+    opts: &DaemonOptions,
+) -> Result<(RawFd, Pid)> {
+    // Create PTY pair, as big as the terminal starting the server until a client resizes it
+    let (rows, cols) = crate::terminal_size().unwrap_or((24, 80));
+    let winsize = Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    let pty = openpty(Some(&winsize), None).context("Failed to create PTY")?;
+    let master_fd = pty.master;
+    let slave_fd = pty.slave;
+
+    // Fork
+    match unsafe { fork() }.context("Fork failed")? {
+        ForkResult::Parent { child } => {
+            // Parent process
+            // Close slave end
+            drop(slave_fd);
+
+            Ok((master_fd.into_raw_fd(), child))
+        }
+        ForkResult::Child => {
+            // Child process - becomes the server
+            // Close master end
+            drop(master_fd);
+
+            // Create new session
+            setsid().ok();
+
+            // Set slave as controlling terminal
+            let slave_raw = slave_fd.as_raw_fd();
+
+            // Make slave the controlling terminal
+            unsafe {
+                libc::ioctl(slave_raw, libc::TIOCSCTTY as libc::c_ulong, 0);
+            }
+
+            // Redirect stdio to slave PTY
+            dup2(slave_raw, 0).ok(); // stdin
+            dup2(slave_raw, 1).ok(); // stdout
+            dup2(slave_raw, 2).ok(); // stderr
+
+            if slave_raw > 2 {
+                drop(slave_fd);
+            }
+
+            if let Some(cgroup) = &opts.cgroup {
+                cgroup.enter();
+            }
+            opts.scheduling.apply();
+
+            // Change to server directory
+            std::env::set_current_dir(server_dir).ok();
+
+            // Set environment
+            std::env::set_var("TERM", "xterm-256color");
+            std::env::set_var("COLORTERM", "truecolor");
+            for (key, value) in &opts.env {
+                std::env::set_var(key, value);
+            }
+
+            // Build args for execvp
+            let args: Vec<CString> =
+                command.iter().map(|a| CString::new(a.as_str()).unwrap()).collect();
+
+            // Execute the server
+            let _ = execvp(&args[0], &args);
+            eprintln!("execvp failed: {}", std::io::Error::last_os_error());
+            std::process::exit(127)
+        }
+    }
+}
+
+/// State shared between the daemon's threads
+pub struct DaemonState {
+    pub master_fd: RawFd,
+    pub child_pid: Pid,
+    started: Instant,
+    token: String,
+    audit_log: PathBuf,
+    history_file: PathBuf,
+    /// How many clients are connected to the PTY socket
+    client_count: AtomicUsize,
+    /// Control connections receiving console notifications
+    console_subscribers: Mutex<Vec<(u64, ControlWriter)>>,
+    events_file: PathBuf,
+    /// Control connections receiving event notifications
+    event_subscribers: Mutex<Vec<(u64, ControlWriter)>>,
+    playtime: Mutex<Playtime>,
+    playtime_file: PathBuf,
+    notifier: Option<Notifier>,
+    alerts: Arc<Alerts>,
+    /// Notifications still being sent
+    notifications: Mutex<Vec<JoinHandle<()>>>,
+    /// Feeds the console and events to the scripts, if there are any
+    scripts: Option<scripting::Host>,
+    /// In-progress send-and-capture requests
+    captures: Mutex<Vec<Arc<Capture>>>,
+    commands: CommandQueue,
+    /// Recent output for clients that scroll back
+    scrollback: Mutex<Ring>,
+    players: Mutex<Players>,
+    /// Whether the server is Bukkit or a fork, and so answers `tps`
+    bukkit: AtomicBool,
+    last_output: Mutex<Instant>,
+    exit_code: Mutex<Option<i32>>,
+    exited: Condvar,
+}
+
+/// Console output collected for a send-and-capture request
+#[derive(Default)]
+pub struct Capture {
+    output: Mutex<Vec<u8>>,
+    last_output: Mutex<Option<Instant>>,
+}
+
+impl Capture {
+    /// Time since output was last received, if any has been
+    pub fn idle_for(&self) -> Option<Duration> {
+        self.last_output.lock().unwrap().map(|t| t.elapsed())
+    }
+
+    pub fn output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().clone()
+    }
+}
+
+impl DaemonState {
+    /// Write input to the server's terminal
+    pub fn write_input(&self, data: &[u8]) {
+        write_master(self.master_fd, data);
+    }
+
+    /// Write a command line, after those queued before it unless `queued` is false
+    ///
+    /// With `capture`, returns the capture of the output from the moment the
+    /// command is written; the caller finishes it.
+    pub fn send_command(&self, command: &str, queued: bool, capture: bool) -> Option<Arc<Capture>> {
+        let turn = queued.then(|| self.commands.turn());
+        let answer = turn.is_some() && self.commands.waits_for_answer();
+        let output = (capture || answer).then(|| self.start_capture());
+        self.write_input(format!("{}\n", command).as_bytes());
+        if let Some(output) = output.as_ref().filter(|_| answer) {
+            self.commands.wait_for_answer(output);
+        }
+        drop(turn);
+
+        match output {
+            Some(output) if !capture => {
+                self.finish_capture(&output);
+                None
+            }
+            output => output,
+        }
+    }
+
+    /// Wait up to `timeout` for the server to exit, returning whether it did
+    pub fn wait_for_exit(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while kill(self.child_pid, None).is_ok() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+        true
+    }
+
+    /// Send the server SIGTERM, and SIGKILL if it is still running after `grace`
+    pub fn terminate(&self, grace: Duration) {
+        signal_group(self.child_pid, Signal::SIGTERM).ok();
+        if !self.wait_for_exit(grace) {
+            signal_group(self.child_pid, Signal::SIGKILL).ok();
+        }
+    }
+
+    /// Time since the server last wrote to its console
+    pub fn silent_for(&self) -> Duration {
+        self.last_output.lock().unwrap().elapsed()
+    }
+
+    /// Names of the players online
+    pub fn online_players(&self) -> Vec<String> {
+        self.players.lock().unwrap().names()
+    }
+
+    /// Status reported to Status commands and control clients
+    pub fn status(&self) -> serde_json::Value {
+        let players = self.players.lock().unwrap();
+        serde_json::json!({
+            "pid": self.child_pid.as_raw(),
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "clients": self.client_count.load(Ordering::SeqCst),
+            "players": players.names(),
+            "bukkit": self.is_bukkit(),
+        })
+    }
+
+    /// Whether the server has started as Bukkit or a fork, which answer `tps`
+    pub fn is_bukkit(&self) -> bool {
+        self.bukkit.load(Ordering::SeqCst)
+    }
+
+    /// Resolve credentials presented by a client
+    pub fn authenticate(&self, token: &str, as_user: Option<&str>) -> Result<Identity, String> {
+        let server_token_ok = auth::verify(&self.token, token);
+        if server_token_ok && as_user.is_none() {
+            return Ok(Identity::owner());
+        }
+        let users = Users::load().map_err(|e| format!("{:#}", e))?;
+        users.authenticate(server_token_ok, token, as_user)
+    }
+
+    /// Record an action in the audit log
+    pub fn audit(&self, identity: &Identity, via: &str, command: &str) {
+        let entry = audit::Entry::new(&identity.name, identity.uid, via, command);
+        if let Err(e) = audit::append(&self.audit_log, &entry) {
+            eprintln!("{:#}", e);
+        }
+    }
+
+    /// Record a console command in the audit log and command history
+    pub fn record_command(&self, identity: &Identity, via: &str, command: &str) {
+        self.audit(identity, via, command);
+        if let Err(e) = history::append(&self.history_file, command) {
+            eprintln!("{:#}", e);
+        }
+    }
+
+    /// Start collecting console output
+    pub fn start_capture(&self) -> Arc<Capture> {
+        let capture = Arc::new(Capture::default());
+        self.captures.lock().unwrap().push(capture.clone());
+        capture
+    }
+
+    pub fn finish_capture(&self, capture: &Arc<Capture>) {
+        self.captures.lock().unwrap().retain(|c| !Arc::ptr_eq(c, capture));
+    }
+
+    pub fn subscribe_console(&self, id: u64, writer: ControlWriter) {
+        self.unsubscribe_console(id);
+        self.console_subscribers.lock().unwrap().push((id, writer));
+    }
+
+    pub fn unsubscribe_console(&self, id: u64) {
+        self.console_subscribers.lock().unwrap().retain(|(sub_id, _)| *sub_id != id);
+    }
+
+    pub fn subscribe_events(&self, id: u64, writer: ControlWriter) {
+        self.unsubscribe_events(id);
+        self.event_subscribers.lock().unwrap().push((id, writer));
+    }
+
+    pub fn unsubscribe_events(&self, id: u64) {
+        self.event_subscribers.lock().unwrap().retain(|(sub_id, _)| *sub_id != id);
+    }
+
+    /// Record an event, notify about it and send it to the subscribed control clients
+    pub fn emit(&self, kind: EventKind) {
+        let event = Event::now(kind);
+        if let Err(e) = event.record(&self.events_file) {
+            daemon_log::error(format!("Failed to record event: {:#}", e));
+        }
+        self.track_playtime(&event);
+        if let Some(notifier) = self.notifier.clone() {
+            let online = self.online_players().len();
+            let event = event.clone();
+            let sending = thread::spawn(move || {
+                if let Err(e) = notifier.notify(&event, Some(online)) {
+                    daemon_log::warn(format!("Failed to send notification: {:#}", e));
+                }
+            });
+            let mut notifications = self.notifications.lock().unwrap();
+            notifications.retain(|sending| !sending.is_finished());
+            notifications.push(sending);
+        }
+        if let Some(scripts) = &self.scripts {
+            scripts.event(&event);
+        }
+        if let Ok(params) = serde_json::to_value(&event) {
+            let notification = control::notification("event", params);
+            let mut subscribers = self.event_subscribers.lock().unwrap();
+            subscribers.retain(|(_, writer)| control::write_line(writer, &notification).is_ok());
+        }
+        // After the event itself, so that alerts about it come second everywhere
+        for message in self.alerts.check(&event, self.online_players().len()) {
+            self.emit(EventKind::Alert { message });
+        }
+    }
+
+    /// Turn joins and leaves into playtime sessions
+    fn track_playtime(&self, event: &Event) {
+        let mut playtime = self.playtime.lock().unwrap();
+        match &event.kind {
+            EventKind::PlayerJoined { player } => playtime.join(player, event.at),
+            EventKind::PlayerLeft { player } => playtime.leave(player, event.at),
+            EventKind::ServerStopped { .. } | EventKind::Crashed { .. } => {
+                playtime.leave_all(event.at)
+            }
+            _ => return,
+        }
+        if let Err(e) = playtime.save(&self.playtime_file) {
+            daemon_log::error(format!("Failed to save playtime: {:#}", e));
+        }
+    }
+
+    /// Wait for the server to exit, returning its exit code
+    pub fn wait_exit(&self, timeout: Duration) -> Option<i32> {
+        let guard = self.exit_code.lock().unwrap();
+        let (guard, _) = self
+            .exited
+            .wait_timeout_while(guard, timeout, |code| code.is_none())
+            .unwrap();
+        *guard
+    }
+
+    /// Feed PTY output to captures and control subscribers
+    fn publish(&self, filtered: &[u8]) {
+        if filtered.is_empty() {
+            return;
+        }
+        for capture in self.captures.lock().unwrap().iter() {
+            capture.output.lock().unwrap().extend_from_slice(filtered);
+            *capture.last_output.lock().unwrap() = Some(Instant::now());
+        }
+
+        let mut subscribers = self.console_subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let data = String::from_utf8_lossy(filtered);
+        let notification = control::notification("console", serde_json::json!({ "data": data }));
+        subscribers.retain(|(_, writer)| control::write_line(writer, &notification).is_ok());
+    }
+}
+
+/// Manage the PTY master and expose it via socket until the server exits
+///
+/// Returns the server's exit code (128 + signal number if it was killed).
+fn run_daemon(master_fd: RawFd, child_pid: Pid, opts: &DaemonOptions) -> i32 {
+    let socket_path = &opts.socket_path;
+
+    // Remove old sockets if they exist
+    let _ = fs::remove_file(socket_path);
+    let _ = fs::remove_file(&opts.control_socket);
+
+    // Now we're the daemon - manage the PTY
+
+    // Ignore SIGHUP
+    unsafe {
+        signal(Signal::SIGHUP, SigHandler::SigIgn).ok();
+    }
+
+    daemon_log::info(format!(
+        "Daemon {} started for server PID {}",
+        std::process::id(),
+        child_pid
+    ));
+
+    // Open log file
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&opts.log_file)
+        .unwrap_or_else(|e| {
+            daemon_log::error(format!("Failed to open {:?}: {}", opts.log_file, e));
+            File::create("/dev/null").unwrap()
+        });
+
+    // Create Unix socket for clients
+    let listener = UnixListener::bind(socket_path).unwrap_or_else(|e| {
+        daemon_log::error(format!("Failed to bind {:?}: {}", socket_path, e));
+        panic!("Failed to bind socket: {}", e)
+    });
+    listener.set_nonblocking(true).ok();
+    if let Err(e) = opts.access.apply(socket_path) {
+        daemon_log::warn(format!("{:#}", e));
+    }
+
+    let (scripts, script_input) = scripting::host(&opts.scripts).unzip();
+    let state = Arc::new(DaemonState {
+        master_fd,
+        child_pid,
+        started: Instant::now(),
+        token: opts.token.clone(),
+        audit_log: opts.audit_log.clone(),
+        history_file: opts.history_file.clone(),
+        client_count: AtomicUsize::new(0),
+        console_subscribers: Mutex::new(Vec::new()),
+        events_file: opts.events_file.clone(),
+        event_subscribers: Mutex::new(Vec::new()),
+        playtime: Mutex::new(Playtime::load(&opts.playtime_file)),
+        playtime_file: opts.playtime_file.clone(),
+        notifier: opts.notifier.clone(),
+        alerts: opts.alerts.clone(),
+        notifications: Mutex::new(Vec::new()),
+        scripts,
+        captures: Mutex::new(Vec::new()),
+        commands: CommandQueue::new(opts.queue),
+        scrollback: Mutex::new(Ring::default()),
+        players: Mutex::new(Players::default()),
+        bukkit: AtomicBool::new(false),
+        last_output: Mutex::new(Instant::now()),
+        exit_code: Mutex::new(None),
+        exited: Condvar::new(),
+    });
+
+    // Control socket for request/response clients
+    match UnixListener::bind(&opts.control_socket) {
+        Ok(control_listener) => {
+            if let Err(e) = opts.access.apply(&opts.control_socket) {
+                daemon_log::warn(format!("{:#}", e));
+            }
+            control::serve(control_listener, state.clone());
+        }
+        Err(e) => daemon_log::error(format!("Failed to bind {:?}: {}", opts.control_socket, e)),
+    }
+
+    if let Some(watchdog) = &opts.watchdog {
+        watchdog.clone().spawn(state.clone());
+    }
+    events::rotate(&opts.events_file);
+    if let Some(threshold) = opts.high_memory {
+        events::watch_memory(state.clone(), threshold);
+    }
+    alerts::watch_empty(state.clone(), opts.alerts.clone());
+    schedule::spawn(state.clone(), opts.schedule.clone());
+    if let Some(input) = script_input {
+        scripting::spawn(state.clone(), opts.scripts.clone(), input);
+    }
+
+    // Main loop: wait on the PTY, the socket and every client at once, reading
+    // from whichever is ready, and broadcast PTY output to the clients and log
+    let mut clients: Vec<Client> = Vec::new();
+    let mut next_id = 0;
+    let mut accepting = true;
+    let mut buf = [0u8; 4096];
+    let mut exit_status = None;
+    let mut startup = startup::Watch::default();
+    let mut oom = oom::Watch::default();
+    let mut triggers = triggers::Watch::new(opts.triggers.clone());
+    let mut oom_noted = false;
+    // Set while writing the console log fails, so that it is reported once
+    let mut log_failing = false;
+    let mut writer = LogWriter::new(opts.log_format, opts.timestamps);
+    let mut dedup = opts.dedupe.then(Dedup::default);
+    let mut indexer = match opts.log_index.then(|| Indexer::open(&opts.log_file, &log)) {
+        Some(Ok(indexer)) => Some(indexer),
+        Some(Err(e)) => {
+            daemon_log::warn(format!("Failed to index {:?}: {}", opts.log_file, e));
+            None
+        }
+        None => None,
+    };
+    let mut forwarder = opts.forwarding.clone().map(Forwarder::new);
+    let shipper = opts.shipping.clone().map(Shipper::spawn);
+    loop {
+        flush_clients(&mut clients);
+        state.client_count.store(clients.len(), Ordering::SeqCst);
+
+        // Check if child is still alive
+        if exit_status.is_none() {
+            if let Ok(status @ (WaitStatus::Exited(..) | WaitStatus::Signaled(..))) =
+                waitpid(child_pid, Some(WaitPidFlag::WNOHANG))
+            {
+                exit_status = Some(status);
+            }
+        }
+
+        // Once the child has exited, only drain what is left in the PTY without waiting
+        let timeout = match exit_status {
+            Some(_) => 0,
+            None => EXIT_CHECK_MS,
+        };
+        let listening = accepting.then_some(&listener);
+        let ready = match wait_ready(master_fd, listening, &clients, timeout) {
+            Ok(ready) => ready,
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                daemon_log::error(format!("Failed to wait for the PTY: {}", e));
+                break;
+            }
+        };
+        // Clients first, as those accepted next have no place in `ready`
+        read_clients(&mut clients, &ready.clients, &state);
+        if ready.listener {
+            accepting = accept_clients(&listener, &mut clients, &mut next_id, opts);
+        }
+        if !ready.pty {
+            if exit_status.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        // Read from PTY master using libc
+        let n = unsafe { libc::read(master_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+
+        if n == 0 {
+            // EOF
+            break;
+        } else if n > 0 {
+            let data = &buf[..n as usize];
+
+            // Write to log (filter cursor codes but keep colors)
+            let filtered = filter_for_log(data);
+            let lines = match &mut dedup {
+                Some(dedup) => Cow::Owned(dedup.push(&filtered)),
+                None => Cow::Borrowed(filtered.as_slice()),
+            };
+            if let Some(forwarder) = &mut forwarder {
+                forwarder.push(&lines);
+            }
+            if let Some(shipper) = &shipper {
+                shipper.push(&lines);
+            }
+            if let Some(indexer) = &mut indexer {
+                indexer.mark(&log).ok();
+            }
+            match writer.write(&mut log, &lines).and_then(|_| log.flush()) {
+                Ok(()) if log_failing => {
+                    daemon_log::info("Writing the console log works again");
+                    log_failing = false;
+                }
+                Ok(()) => {}
+                Err(e) if !log_failing => {
+                    daemon_log::error(format!("Failed to write {:?}: {}", opts.log_file, e));
+                    log_failing = true;
+                }
+                Err(_) => {}
+            }
+            state.publish(&filtered);
+            state.commands.console_output(data);
+            let changes = state.players.lock().unwrap().push(&filtered);
+            for change in changes {
+                state.emit(change);
+            }
+            *state.last_output.lock().unwrap() = Instant::now();
+            let was_ready = startup.is_ready();
+            startup.push(&filtered);
+            if !was_ready && startup.is_ready() {
+                state.bukkit.store(startup.is_bukkit(), Ordering::SeqCst);
+                state.emit(EventKind::ServerStarted {
+                    pid: child_pid.as_raw(),
+                });
+            }
+            triggers.push(&state, &filtered);
+            if let Some(scripts) = &state.scripts {
+                scripts.console_output(&filtered);
+            }
+            if let Some(event) = oom.push(&filtered) {
+                if let Err(e) = event.save(&opts.oom_file) {
+                    daemon_log::error(format!("Failed to record out-of-memory error: {:#}", e));
+                }
+                if !oom_noted {
+                    oom_noted = true;
+                    writeln!(log, "[mcwrap] Out of memory: {}", event.message).ok();
+                    if opts.stop_on_oom {
+                        writeln!(log, "[mcwrap] Stopping the server").ok();
+                        oom::stop_server(state.clone());
+                    }
+                }
+            }
+
+            // Broadcast to all subscribed clients
+            let framed = Frame::ConsoleOutput(data.to_vec()).encode();
+            state.scrollback.lock().unwrap().push(&filtered);
+            let mut to_remove = Vec::new();
+            for (i, client) in clients.iter_mut().enumerate() {
+                if !client.subscribed {
+                    continue;
+                }
+                let bytes = if client.decoder.is_some() { &framed } else { data };
+                match client.outbox.push_output(bytes.to_vec()) {
+                    Ok(true) => daemon_log::warn(format!(
+                        "Console {} can't keep up, dropping output it hasn't been sent",
+                        client.describe()
+                    )),
+                    Ok(false) => {}
+                    Err(e) => {
+                        daemon_log::warn(format!("Console {} dropped: {}", client.describe(), e));
+                        to_remove.push(i);
+                    }
+                }
+            }
+            for i in to_remove.into_iter().rev() {
+                clients.remove(i);
+            }
+        } else {
+            // Error
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            // EIO just means the server's side of the terminal has closed
+            if err.raw_os_error() != Some(libc::EIO) {
+                daemon_log::error(format!("Failed to read from the PTY: {}", err));
+            }
+            break;
+        }
+    }
+
+    if let Some(dedup) = &mut dedup {
+        let lines = dedup.finish();
+        if let Some(forwarder) = &mut forwarder {
+            forwarder.push(&lines);
+        }
+        if let Some(shipper) = &shipper {
+            shipper.push(&lines);
+        }
+        writer.write(&mut log, &lines).ok();
+    }
+    writer.finish(&mut log).ok();
+
+    // Cleanup
+    unsafe { libc::close(master_fd) };
+    let _ = fs::remove_file(socket_path);
+    let _ = fs::remove_file(&opts.control_socket);
+
+    // Reap the server if it hasn't been already
+    let exit_status = exit_status.or_else(|| waitpid(child_pid, None).ok());
+    let code = match exit_status {
+        Some(WaitStatus::Exited(_, code)) => code,
+        Some(WaitStatus::Signaled(_, sig, _)) => 128 + sig as i32,
+        _ => 1,
+    };
+    let mut out_of_memory = oom_noted;
+    if matches!(exit_status, Some(WaitStatus::Signaled(_, Signal::SIGKILL, _))) {
+        if let Some(event) = oom::kernel_oom_kill(child_pid.as_raw()) {
+            out_of_memory = true;
+            writeln!(log, "[mcwrap] Killed by the kernel: {}", event.message).ok();
+            if let Err(e) = event.save(&opts.oom_file) {
+                daemon_log::error(format!("Failed to record out-of-memory kill: {:#}", e));
+            }
+        }
+    }
+    end_process_group(child_pid);
+    if let Some(cgroup) = &opts.cgroup {
+        cgroup.remove();
+    }
+    if let Some(name) = &opts.container {
+        docker::remove(name);
+    }
+    let exit = match exit_status {
+        Some(WaitStatus::Exited(_, code)) => Exit::reaped(Some(code), None, out_of_memory),
+        Some(WaitStatus::Signaled(_, sig, _)) => Exit::reaped(None, Some(sig), out_of_memory),
+        _ => Exit::default(),
+    };
+    // Being killed on purpose is no failure to start
+    if !exit.is_killed() {
+        if let Some(failure) = startup.failure(code, state.started.elapsed()) {
+            if let Err(e) = failure.save(&opts.failure_file) {
+                daemon_log::error(format!("Failed to record startup failure: {:#}", e));
+            }
+        }
+    }
+    daemon_log::info(format!("Server exited with code {}", code));
+    state.emit(if exit.is_crash() {
+        EventKind::Crashed {
+            exit_code: code,
+            out_of_memory,
+        }
+    } else {
+        EventKind::ServerStopped { exit_code: code }
+    });
+    crate::mark_exited(&opts.state_file, exit);
+    // The daemon exits next, so let the last notifications and records go out first
+    for sending in state.notifications.lock().unwrap().drain(..) {
+        let _ = sending.join();
+    }
+    if let Some(shipper) = shipper {
+        shipper.finish();
+    }
+
+    // Wake control clients waiting on the exit and give them time to reply
+    *state.exit_code.lock().unwrap() = Some(code);
+    state.exited.notify_all();
+    thread::sleep(Duration::from_millis(100));
+
+    code
+}
+
+/// Signal the server's process group, which includes any helpers it started
+///
+/// Servers started in basic mode by older versions share mcwrap's process
+/// group, so those only get the signal themselves.
+pub fn signal_group(pid: Pid, signal: Signal) -> nix::Result<()> {
+    killpg(pid, signal).or_else(|_| kill(pid, signal))
+}
+
+/// Stop helpers that outlived the server: SIGTERM, then SIGKILL after a grace period
+pub fn end_process_group(pgid: Pid) {
+    if killpg(pgid, Signal::SIGTERM).is_err() {
+        return;
+    }
+    let deadline = Instant::now() + LEFTOVER_GRACE;
+    while killpg(pgid, None).is_ok() {
+        if Instant::now() >= deadline {
+            killpg(pgid, Signal::SIGKILL).ok();
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// What the main loop found ready to read
+struct Ready {
+    pty: bool,
+    listener: bool,
+    /// Each client, in order: it may be read from or written to
+    clients: Vec<bool>,
+}
+
+/// Wait until the PTY, the socket (if still `listener`) or a client has something to read,
+/// or a client can take more output
+fn wait_ready(
+    master_fd: RawFd,
+    listener: Option<&UnixListener>,
+    clients: &[Client],
+    timeout_ms: i32,
+) -> nix::Result<Ready> {
+    let pollfd = |fd: RawFd, events: libc::c_short| libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    let mut fds = vec![pollfd(master_fd, libc::POLLIN)];
+    fds.extend(listener.map(|listener| pollfd(listener.as_raw_fd(), libc::POLLIN)));
+    fds.extend(clients.iter().map(|client| {
+        let mut events = libc::POLLIN;
+        // Woken when the client can take more of what is waiting for it
+        if !client.outbox.is_empty() {
+            events |= libc::POLLOUT;
+        }
+        pollfd(client.stream.as_raw_fd(), events)
+    }));
+    platform::poll(&mut fds, timeout_ms)?;
+    // Hangups and errors count too, so that the read finds out what happened
+    let mut ready = fds.iter().map(|fd| fd.revents != 0);
+    Ok(Ready {
+        pty: ready.next().unwrap_or(false),
+        listener: listener.is_some() && ready.next().unwrap_or(false),
+        clients: ready.collect(),
+    })
+}
+
+/// Accept the clients waiting on the socket, returning whether it still accepts more
+fn accept_clients(
+    listener: &UnixListener,
+    clients: &mut Vec<Client>,
+    next_id: &mut u64,
+    opts: &DaemonOptions,
+) -> bool {
+    let legacy_raw = opts.legacy_raw;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(true).ok();
+                let uid = audit::peer_uid(&stream);
+                let id = *next_id;
+                *next_id += 1;
+                // Raw clients always get console output; framed ones authenticate
+                // and subscribe. Raw clients can't present the token, so only
+                // the server's own user may type; others may only watch.
+                let owner = uid == Some(getuid().as_raw());
+                let client = Client {
+                    id,
+                    stream,
+                    decoder: (!legacy_raw).then(FrameDecoder::default),
+                    subscribed: legacy_raw,
+                    identity: (legacy_raw && owner).then(|| Identity { uid, ..Identity::owner() }),
+                    uid,
+                    typed: LineBuffer::default(),
+                    primary: false,
+                    outbox: Outbox::new(opts.client_buffer, opts.slow_client),
+                };
+                daemon_log::debug(format!("Console {} connected", client.describe()));
+                clients.push(client);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                daemon_log::error(format!("Stopped accepting console clients: {}", e));
+                return false;
+            }
+        }
+    }
+}
+
+/// Handle what the `ready` clients sent, dropping those that left
+fn read_clients(clients: &mut Vec<Client>, ready: &[bool], state: &Arc<DaemonState>) {
+    let mut buf = [0u8; 1024];
+    let mut to_remove = Vec::new();
+    let mut claims = Vec::new();
+    for (i, client) in clients.iter_mut().enumerate() {
+        if !ready.get(i).copied().unwrap_or(false) {
+            continue;
+        }
+        let gone = match client.stream.read(&mut buf) {
+            Ok(0) => Some((Level::Debug, "disconnected".to_string())),
+            Ok(n) => match client.handle_input(&buf[..n], state) {
+                Ok(Some(claim)) => {
+                    claims.push((i, claim));
+                    None
+                }
+                Ok(None) => None,
+                Err(e) => Some((Level::Warn, format!("dropped: {}", e))),
+            },
+            Err(ref e)
+                if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) =>
+            {
+                None
+            }
+            Err(e) => Some((Level::Warn, format!("dropped: {}", e))),
+        };
+        if let Some((level, reason)) = gone {
+            daemon_log::log(level, format!("Console {} {}", client.describe(), reason));
+            to_remove.push(i);
+        }
+    }
+    for (i, claim) in claims {
+        if !to_remove.contains(&i) {
+            claim_primary(clients, i, claim);
+        }
+    }
+    // Remove disconnected clients (in reverse order), with a last try at telling them why
+    for i in to_remove.into_iter().rev() {
+        let mut client = clients.remove(i);
+        client.outbox.flush(&mut client.stream).ok();
+    }
+    // Hand a vacated primary role to the longest-attached admin
+    if !clients.iter().any(|c| c.primary) {
+        if let Some(i) = clients.iter().position(Client::may_be_primary) {
+            claim_primary(clients, i, Claim::IfVacant);
+        }
+    }
+}
+
+/// Write what is waiting for each client, as much as it takes, dropping those that are gone
+fn flush_clients(clients: &mut Vec<Client>) {
+    clients.retain_mut(|client| match client.outbox.flush(&mut client.stream) {
+        Ok(()) => true,
+        Err(e) => {
+            daemon_log::warn(format!("Console {} dropped: {}", client.describe(), e));
+            false
+        }
+    });
+}
+
+/// Give the primary role to `clients[index]` if it may have it
+fn claim_primary(clients: &mut [Client], index: usize, claim: Claim) {
+    if !clients[index].may_be_primary() || clients[index].primary {
+        return;
+    }
+    let vacant = !clients.iter().any(|c| c.primary);
+    let granted = vacant || matches!(claim, Claim::Steal);
+    for (i, client) in clients.iter_mut().enumerate() {
+        let primary = if i == index { granted } else { client.primary && !granted };
+        // Tell the claimant either way, and the previous primary if it lost the role
+        if i == index || client.primary != primary {
+            if client.primary != primary {
+                let role = if primary { "now" } else { "no longer" };
+                daemon_log::debug(format!("Console {} is {} primary", client.describe(), role));
+            }
+            client.primary = primary;
+            client.outbox.push(Frame::Primary(primary).encode());
+        }
+    }
+}
+
+impl Client {
+    /// The client as shown in the daemon log, e.g. "client 3 (owner, uid 1000)"
+    fn describe(&self) -> String {
+        let mut who = Vec::new();
+        if let Some(identity) = &self.identity {
+            who.push(identity.name.clone());
+        }
+        if let Some(uid) = self.uid {
+            who.push(format!("uid {}", uid));
+        }
+        if who.is_empty() {
+            format!("client {}", self.id)
+        } else {
+            format!("client {} ({})", self.id, who.join(", "))
+        }
+    }
+
+    /// Framed admins watching the console can hold the primary role
+    fn may_be_primary(&self) -> bool {
+        self.decoder.is_some()
+            && self.subscribed
+            && self.identity.as_ref().is_some_and(|id| id.role == Role::Admin)
+    }
+
+    /// Handle bytes received from the client, returning any claim to the primary role
+    fn handle_input(
+        &mut self,
+        data: &[u8],
+        state: &Arc<DaemonState>,
+    ) -> io::Result<Option<Claim>> {
+        let Some(decoder) = self.decoder.as_mut() else {
+            // Legacy raw client: everything is terminal input
+            if self.identity.is_none() {
+                let error = "Only the server's user can type on the raw socket";
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, error));
+            }
+            state.write_input(data);
+            self.audit_typed(data, state);
+            return Ok(None);
+        };
+
+        decoder.push(data);
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.next_frame()? {
+            frames.push(frame);
+        }
+
+        let mut claim = None;
+        for frame in frames {
+            let Some(identity) = &self.identity else {
+                // The first frame must carry credentials
+                let Frame::Auth(creds) = frame else {
+                    let error = "Not authenticated";
+                    self.outbox.push(Frame::Response(Response::error(error)).encode());
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, error));
+                };
+                match state.authenticate(&creds.token, creds.user.as_deref()) {
+                    Ok(identity) => {
+                        self.identity = Some(Identity { uid: self.uid, ..identity });
+                        let version = creds.protocol.unwrap_or(1);
+                        let message = format!(
+                            "Console {} authenticated (protocol {})",
+                            self.describe(),
+                            version
+                        );
+                        daemon_log::debug(message);
+                        // Clients from before the handshake don't expect an answer
+                        if creds.protocol.is_some() {
+                            let handshake = serde_json::to_value(Handshake::current())
+                                .expect("handshake always serializes");
+                            self.outbox.push(Frame::Response(Response::ok(handshake)).encode());
+                        }
+                    }
+                    Err(e) => {
+                        self.outbox.push(Frame::Response(Response::error(e.clone())).encode());
+                        return Err(io::Error::new(io::ErrorKind::PermissionDenied, e));
+                    }
+                }
+                continue;
+            };
+            // Only the primary client types into the console; others get a read-only view
+            let may_write = self.primary;
+
+            match frame {
+                Frame::Input(input) if may_write => {
+                    state.write_input(&input);
+                    self.audit_typed(&input, state);
+                }
+                Frame::Resize { rows, cols } if may_write => {
+                    set_window_size(state.master_fd, rows, cols)
+                }
+                Frame::Input(_) | Frame::Resize { .. } => {}
+                Frame::Subscribe(sub) => {
+                    if sub.scrollback {
+                        let recent = state.scrollback.lock().unwrap().contents();
+                        self.outbox.push(Frame::Scrollback(recent).encode());
+                    }
+                    self.subscribed = sub.console;
+                    if sub.console {
+                        claim = Some(Claim::IfVacant);
+                    }
+                }
+                Frame::Command(cmd) => {
+                    let response = match cmd {
+                        Command::Status => Response::ok(state.status()),
+                        Command::Take if identity.role == Role::Admin => {
+                            claim = Some(Claim::Steal);
+                            Response::ok(serde_json::Value::Null)
+                        }
+                        Command::Take => Response::error("Only admins can take the console"),
+                        Command::Send { command, queue } => {
+                            match identity.may_send(&command, queue) {
+                                Ok(()) => {
+                                    // The queue may hold it a while, which would stall the console
+                                    let state = Arc::clone(state);
+                                    let identity = identity.clone();
+                                    thread::spawn(move || {
+                                        state.send_command(&command, queue, false);
+                                        state.record_command(&identity, "send", &command);
+                                    });
+                                    Response::ok(serde_json::Value::Null)
+                                }
+                                Err(e) => Response::error(e),
+                            }
+                        }
+                    };
+                    self.outbox.push(Frame::Response(response).encode());
+                }
+                // Daemon-to-client frames and repeated auth are ignored
+                Frame::ConsoleOutput(_)
+                | Frame::Response(_)
+                | Frame::Auth(_)
+                | Frame::Scrollback(_)
+                | Frame::Primary(_) => {}
+            }
+        }
+        Ok(claim)
+    }
+
+    /// Audit each line completed by the client's keystrokes
+    fn audit_typed(&mut self, input: &[u8], state: &DaemonState) {
+        let Some(identity) = &self.identity else {
+            return;
+        };
+        for line in self.typed.push(input) {
+            state.record_command(identity, "attach", &line);
+        }
+    }
+}
+
+/// Write client input to the PTY master
+fn write_master(master_fd: RawFd, data: &[u8]) {
+    unsafe {
+        libc::write(master_fd, data.as_ptr() as *const libc::c_void, data.len());
+    }
+}
+
+/// How console output is written to the log
+pub enum LogWriter {
+    Plain,
+    /// With a timestamp in front of each line
+    Stamped(Stamper),
+    /// As JSON lines
+    Jsonl(Encoder),
+}
+
+impl LogWriter {
+    /// The writer for `format`, which has timestamps when there is a `zone`
+    pub fn new(format: LogFormat, zone: Option<Zone>) -> Self {
+        match (format, zone) {
+            (LogFormat::Jsonl, Some(zone)) => LogWriter::Jsonl(Encoder::new(zone)),
+            (_, Some(zone)) => LogWriter::Stamped(Stamper::new(zone)),
+            (_, None) => LogWriter::Plain,
+        }
+    }
+
+    pub fn write(&mut self, log: &mut impl IoWrite, data: &[u8]) -> io::Result<()> {
+        match self {
+            LogWriter::Plain => log.write_all(data),
+            LogWriter::Stamped(stamper) => log.write_all(&stamper.stamp(data)),
+            LogWriter::Jsonl(encoder) => log.write_all(&encoder.encode(data)),
+        }
+    }
+
+    /// Write what is held back, once the server has exited
+    pub fn finish(&mut self, log: &mut impl IoWrite) -> io::Result<()> {
+        match self {
+            LogWriter::Jsonl(encoder) => log.write_all(&encoder.finish()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Apply a client's terminal size to the PTY
+fn set_window_size(master_fd: RawFd, rows: u16, cols: u16) {
+    let winsize = Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe {
+        libc::ioctl(master_fd, libc::TIOCSWINSZ, &winsize);
+    }
+}
+
+/// Filter ANSI codes for log file - keep colors, remove cursor movement and prompts
+pub fn filter_for_log(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut i = 0;
+    let mut line_start = true;
+
+    while i < data.len() {
+        // Skip Minecraft's "> " prompt at start of lines
+        if line_start && i + 1 < data.len() && data[i] == b'>' && data[i + 1] == b' ' {
+            i += 2;
+            // Skip any following newline
+            if i < data.len() && data[i] == b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+
+        if data[i] == 0x1b && i + 1 < data.len() && data[i + 1] == b'[' {
+            // Start of CSI sequence
+            let start = i;
+            i += 2;
+
+            // Find end of sequence
+            while i < data.len() {
+                let c = data[i];
+                i += 1;
+                if (0x40..=0x7E).contains(&c) {
+                    // End of CSI sequence
+                    // Keep SGR (color) sequences ending in 'm'
+                    if c == b'm' {
+                        result.extend_from_slice(&data[start..i]);
+                    }
+                    // Skip cursor movement: H, f, A, B, C, D, E, F, G, J, K, S, T, s, u
+                    break;
+                }
+            }
+            line_start = false;
+        } else if data[i] == b'\r' {
+            // Skip CR entirely - we'll use LF for newlines
+            i += 1;
+        } else if data[i] == b'\n' {
+            // Only add newline if we have content (avoid double newlines)
+            if !result.is_empty() && result.last() != Some(&b'\n') {
+                result.push(b'\n');
+            }
+            line_start = true;
+            i += 1;
+        } else {
+            result.push(data[i]);
+            line_start = false;
+            i += 1;
+        }
+    }
+
+    result
+}
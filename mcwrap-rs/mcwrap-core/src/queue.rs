@@ -0,0 +1,117 @@
+//! Pacing of commands sent to a PTY-mode server
+//!
+//! Commands from `send` (and everything built on it) are queued in the
+//! daemon, so that a burst from an automation can't garble the console. They
+//! are written one at a time in the order they arrived, at most
+//! `[queue] max_per_sec` a second, and with `wait_for_answer` each only once
+//! the server has answered the previous one. Admins can skip the queue for
+//! trusted bulk operations (`mcwrap send --no-queue`).
+
+use crate::config::QueueConfig;
+use crate::console::strip_ansi;
+use crate::pty::Capture;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The server is taken to have answered once it has been quiet this long
+const ANSWER_IDLE: Duration = Duration::from_millis(150);
+
+/// Quiet time that is enough when the console already shows a prompt again
+const PROMPT_IDLE: Duration = Duration::from_millis(20);
+
+/// Never hold the queue longer than this for one answer
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct CommandQueue {
+    config: QueueConfig,
+    turns: Mutex<Turns>,
+    next: Condvar,
+    /// The console output so far ends with the input prompt
+    prompt: AtomicBool,
+}
+
+#[derive(Default)]
+struct Turns {
+    next_ticket: u64,
+    serving: u64,
+    last_sent: Option<Instant>,
+}
+
+/// The right to write the next command, passed on when dropped
+pub struct Turn<'a> {
+    queue: &'a CommandQueue,
+}
+
+impl CommandQueue {
+    pub fn new(config: QueueConfig) -> Self {
+        Self {
+            config,
+            turns: Mutex::new(Turns::default()),
+            next: Condvar::new(),
+            prompt: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether commands wait for the server to answer the previous one
+    pub fn waits_for_answer(&self) -> bool {
+        self.config.wait_for_answer
+    }
+
+    /// Follow the console's raw output, which still has the prompt in it
+    pub fn console_output(&self, data: &[u8]) {
+        self.prompt.store(ends_with_prompt(data), Ordering::SeqCst);
+    }
+
+    /// Wait until the server has answered a command whose output goes to `capture`
+    pub fn wait_for_answer(&self, capture: &Capture) {
+        let written = Instant::now();
+        self.prompt.store(false, Ordering::SeqCst);
+        while written.elapsed() < ANSWER_TIMEOUT {
+            let idle = capture.idle_for().unwrap_or_else(|| written.elapsed());
+            let prompt = self.prompt.load(Ordering::SeqCst);
+            if idle >= ANSWER_IDLE || (prompt && idle >= PROMPT_IDLE) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Wait until it is this command's turn to be written
+    pub fn turn(&self) -> Turn<'_> {
+        let mut turns = self.turns.lock().unwrap();
+        let ticket = turns.next_ticket;
+        turns.next_ticket += 1;
+        while turns.serving != ticket {
+            turns = self.next.wait(turns).unwrap();
+        }
+        let last_sent = turns.last_sent;
+        drop(turns);
+
+        if let Some(last_sent) = last_sent.filter(|_| self.config.max_per_sec > 0) {
+            let interval = Duration::from_secs(1) / self.config.max_per_sec;
+            thread::sleep(interval.saturating_sub(last_sent.elapsed()));
+        }
+        Turn { queue: self }
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        let mut turns = self.queue.turns.lock().unwrap();
+        turns.serving += 1;
+        turns.last_sent = Some(Instant::now());
+        self.queue.next.notify_all();
+    }
+}
+
+/// Whether console output ends with the server's input prompt ("> ")
+fn ends_with_prompt(output: &[u8]) -> bool {
+    let tail = &output[output.len().saturating_sub(64)..];
+    let tail = strip_ansi(&String::from_utf8_lossy(tail));
+    match tail.trim_end_matches(' ').strip_suffix('>') {
+        Some(line) => line.is_empty() || line.ends_with(['\n', '\r']),
+        None => false,
+    }
+}
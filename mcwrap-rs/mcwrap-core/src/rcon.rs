@@ -0,0 +1,125 @@
+//! A client for the server's RCON console
+//!
+//! Servers mcwrap didn't start (see `adopt`) have no console mcwrap can type
+//! into, so `send` and `stop` reach them over RCON instead, with the port and
+//! password from `server.properties`:
+//!
+//! ```text
+//! enable-rcon=true
+//! rcon.port=25575
+//! rcon.password=…
+//! ```
+
+use anyhow::{bail, Context, Result};
+use crate::failure::{ErrorKind, Failure};
+use crate::ports::DEFAULT_RCON_PORT;
+use crate::properties::Properties;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// How long the server gets to accept the connection and to reply
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Packet types
+const RESPONSE: i32 = 0;
+const COMMAND: i32 = 2;
+const LOGIN: i32 = 3;
+
+/// Largest packet accepted from the server; replies are at most 4 KiB
+const MAX_PACKET: usize = 64 * 1024;
+
+/// A logged-in RCON connection
+pub struct Rcon {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl Rcon {
+    /// Connect to a server's RCON port and log in
+    pub async fn connect(server_dir: &Path) -> Result<Self> {
+        let properties = Properties::load(server_dir)?;
+        if properties.get("enable-rcon") != Some("true") {
+            bail!("RCON is not enabled (set enable-rcon=true in server.properties)");
+        }
+        let password = properties
+            .get("rcon.password")
+            .context("RCON has no password (set rcon.password in server.properties)")?;
+        let port = properties.get("rcon.port").and_then(|port| port.parse().ok());
+        let port = port.unwrap_or(DEFAULT_RCON_PORT);
+        let host = properties.get("server-ip").unwrap_or("127.0.0.1");
+
+        let stream = timeout(TcpStream::connect((host, port)))
+            .await?
+            .with_context(|| format!("Failed to connect to RCON on {}:{}", host, port))?;
+        let mut rcon = Self { stream, next_id: 1 };
+        let id = rcon.write(LOGIN, password).await?;
+        // The server answers a wrong password with an ID of -1
+        let (reply_id, _) = rcon.read().await.context("Failed to log in to RCON")?;
+        if reply_id != id {
+            bail!(Failure::new(ErrorKind::PermissionDenied, "RCON refused the password"));
+        }
+        Ok(rcon)
+    }
+
+    /// Run a command, returning its output
+    pub async fn command(&mut self, command: &str) -> Result<String> {
+        self.write(COMMAND, command).await?;
+        match self.read().await {
+            Ok((_, body)) => Ok(body),
+            // `stop` closes the connection before replying
+            Err(e) if is_eof(&e) => Ok(String::new()),
+            Err(e) => Err(e.context("Failed to read the RCON reply")),
+        }
+    }
+
+    /// Send a packet, returning its ID
+    async fn write(&mut self, kind: i32, body: &str) -> Result<i32> {
+        let id = self.next_id;
+        self.next_id += 1;
+        // ID, type, then the body and an empty string, both NUL-terminated
+        let len = 4 + 4 + body.len() + 2;
+        let mut packet = Vec::with_capacity(4 + len);
+        packet.extend_from_slice(&(len as i32).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&kind.to_le_bytes());
+        packet.extend_from_slice(body.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        self.stream.write_all(&packet).await.context("Failed to write to RCON")?;
+        Ok(id)
+    }
+
+    /// Read a packet: its ID and body
+    async fn read(&mut self) -> Result<(i32, String)> {
+        let mut len = [0; 4];
+        timeout(self.stream.read_exact(&mut len)).await??;
+        let len = i32::from_le_bytes(len) as usize;
+        if !(10..=MAX_PACKET).contains(&len) {
+            bail!("Invalid RCON packet");
+        }
+        let mut packet = vec![0; len];
+        timeout(self.stream.read_exact(&mut packet)).await??;
+        let id = i32::from_le_bytes(packet[0..4].try_into().unwrap());
+        let kind = i32::from_le_bytes(packet[4..8].try_into().unwrap());
+        if kind != RESPONSE && kind != COMMAND {
+            bail!("Unexpected RCON packet");
+        }
+        let body = &packet[8..len - 2];
+        Ok((id, String::from_utf8_lossy(body).into_owned()))
+    }
+}
+
+/// Whether the server closed the connection
+fn is_eof(error: &anyhow::Error) -> bool {
+    let error = error.downcast_ref::<io::Error>();
+    error.is_some_and(|e| e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+/// Wait for `future` until `TIMEOUT`
+async fn timeout<T>(future: impl std::future::Future<Output = T>) -> Result<T> {
+    tokio::time::timeout(TIMEOUT, future)
+        .await
+        .map_err(|_| Failure::new(ErrorKind::Timeout, "RCON did not answer in time").into())
+}
@@ -0,0 +1,133 @@
+//! The console log read as records
+//!
+//! Servers log through log4j, one message per line behind a header:
+//! `[12:00:00 INFO]: ...` on Paper, `[12:00:00] [Server thread/INFO]: ...` on
+//! vanilla, and with the logger after the thread on Forge. A record is such a
+//! line together with the stack trace printed after it, so that `mcwrap log
+//! --level error` and `--json` return whole exceptions rather than their
+//! first line or stray `at ...` lines. Lines without a header start a record
+//! of their own, with no level.
+
+use crate::console::strip_ansi;
+use crate::timestamps;
+use clap::ValueEnum;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+/// A log4j header: the time of day, the level with or without the thread, and on Forge the
+/// logger, e.g. `[12:00:00] [Server thread/WARN] [minecraft/DedicatedServer]: message`
+static HEADER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"^\[(\d\d:\d\d:\d\d)(?: ([A-Z]+))?\]",
+        r"(?: \[([^\]]*)/([A-Z]+)\])?(?: \[([^\]]*)\])?:? ?(.*)$"
+    ))
+    .unwrap()
+});
+
+/// A line of a stack trace, after a `[log] timestamps` timestamp if there is one:
+/// `java.lang.NullPointerException: ...`, `\tat org.bukkit...`, `Caused by: ...`, `... 12 more`
+static TRACE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"^(?:\d{4}-\d\d-\d\dT\S+ )?",
+        r"(?:\s+at \S|Caused by: |Exception in thread |\s+\.\.\. \d+ more$",
+        r"|[\w.$]+(?:Exception|Error)(?:: |$))"
+    ))
+    .unwrap()
+});
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all(serialize = "UPPERCASE", deserialize = "lowercase"))]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl Level {
+    fn parse(level: &str) -> Option<Self> {
+        match level {
+            "TRACE" => Some(Level::Trace),
+            "DEBUG" => Some(Level::Debug),
+            "INFO" => Some(Level::Info),
+            "WARN" | "WARNING" => Some(Level::Warn),
+            "ERROR" | "SEVERE" => Some(Level::Error),
+            "FATAL" => Some(Level::Fatal),
+            _ => None,
+        }
+    }
+}
+
+/// One logged message and the stack trace that goes with it
+#[derive(Serialize)]
+pub struct Record {
+    /// The `[log] timestamps` timestamp
+    pub time: Option<String>,
+    /// The time of day in the server's header
+    pub clock: Option<String>,
+    pub level: Option<Level>,
+    pub thread: Option<String>,
+    /// The logger, which Forge shows after the thread
+    pub logger: Option<String>,
+    pub message: String,
+    /// The stack trace lines after the message
+    pub trace: Vec<String>,
+    /// The lines as they are in the log, colors and all
+    #[serde(skip)]
+    pub lines: Vec<String>,
+}
+
+/// Whether a line is part of a stack trace
+pub fn is_trace(line: &str) -> bool {
+    TRACE.is_match(line)
+}
+
+/// The level in a line's header, if it has one
+pub fn level(line: &str) -> Option<Level> {
+    let caps = HEADER.captures(line)?;
+    Level::parse(caps.get(2).or(caps.get(4))?.as_str())
+}
+
+/// Group log lines into records
+pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<Record> {
+    let mut records: Vec<Record> = Vec::new();
+    for line in lines {
+        let text = strip_ansi(line);
+        let time = timestamps::line_time(&text).map(|time| time.to_rfc3339());
+        // Past the timestamp, which is followed by a single space
+        let text = match time {
+            Some(_) => text.split_once(' ').map_or("", |(_, rest)| rest),
+            None => text.as_str(),
+        };
+        if let Some(record) = records.last_mut().filter(|_| is_trace(text)) {
+            record.trace.push(text.to_string());
+            record.lines.push(line.to_string());
+            continue;
+        }
+        let mut record = Record {
+            time,
+            clock: None,
+            level: None,
+            thread: None,
+            logger: None,
+            message: text.to_string(),
+            trace: Vec::new(),
+            lines: vec![line.to_string()],
+        };
+        if let Some(caps) = HEADER.captures(text) {
+            let level = caps.get(2).or(caps.get(4)).and_then(|level| Level::parse(level.as_str()));
+            if level.is_some() {
+                record.clock = Some(caps[1].to_string());
+                record.level = level;
+                record.thread = caps.get(3).map(|thread| thread.as_str().to_string());
+                record.logger = caps.get(5).map(|logger| logger.as_str().to_string());
+                record.message = caps[6].to_string();
+            }
+        }
+        records.push(record);
+    }
+    records
+}
@@ -0,0 +1,193 @@
+//! Registry of servers managed by mcwrap
+//!
+//! Every server started through mcwrap is recorded in `~/.mcwrap/registry.json`
+//! together with the arguments it was launched with, so it can be brought
+//! back up later (e.g. on boot) without the caller repeating them.
+
+use anyhow::{bail, Context, Result};
+use crate::daemon_log::Level;
+use crate::docker::Container;
+use crate::presets::Preset;
+use crate::priority::Priority;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A single registered server
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RegistryEntry {
+    pub dir: PathBuf,
+    /// Java arguments given on the last start (empty = defaults)
+    #[serde(default)]
+    pub java_args: Vec<String>,
+    /// Started in basic pipe mode
+    #[serde(default)]
+    pub basic: bool,
+    /// Started with the unframed socket protocol
+    #[serde(default)]
+    pub legacy_raw: bool,
+    /// Program run instead of java, with `java_args` as its arguments
+    #[serde(default)]
+    pub exec: Option<String>,
+    /// CPU affinity and scheduling priority given on the last start
+    #[serde(default)]
+    pub priority: Priority,
+    /// Docker container the server was last started in
+    #[serde(default)]
+    pub container: Container,
+    /// Detail of the PTY daemon's own log
+    #[serde(default)]
+    pub log_level: Level,
+    /// Started with the JVM logging garbage collections
+    #[serde(default)]
+    pub gc_log: bool,
+    /// Garbage collector preset given on the last start
+    #[serde(default)]
+    pub preset: Option<Preset>,
+    /// Start this server from `mcwrap boot`
+    #[serde(default)]
+    pub boot: bool,
+    /// Servers that must be started before this one on boot
+    #[serde(default)]
+    pub after: Vec<PathBuf>,
+}
+
+impl RegistryEntry {
+    fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            java_args: Vec::new(),
+            basic: false,
+            legacy_raw: false,
+            exec: None,
+            priority: Priority::default(),
+            container: Container::default(),
+            log_level: Level::default(),
+            gc_log: false,
+            preset: None,
+            boot: false,
+            after: Vec::new(),
+        }
+    }
+
+    /// `mcwrap start` arguments that launch the server the same way again
+    pub fn start_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.basic {
+            args.push("--basic".to_string());
+        }
+        if self.legacy_raw {
+            args.push("--legacy-raw".to_string());
+        }
+        if let Some(exec) = &self.exec {
+            args.extend(["--exec".to_string(), exec.clone()]);
+        }
+        args.extend(self.priority.to_args());
+        args.extend(self.container.to_args());
+        if self.log_level != Level::default() {
+            args.extend(["--log-level".to_string(), self.log_level.name().to_string()]);
+        }
+        if self.gc_log {
+            args.push("--gc-log".to_string());
+        }
+        if let Some(preset) = self.preset {
+            args.extend(["--preset".to_string(), preset.name().to_string()]);
+        }
+        args.push(self.dir.to_string_lossy().into_owned());
+        if !self.java_args.is_empty() {
+            args.push("--".to_string());
+            args.extend(self.java_args.iter().cloned());
+        }
+        args
+    }
+}
+
+/// All registered servers
+#[derive(Serialize, Deserialize, Default)]
+pub struct Registry {
+    #[serde(default)]
+    pub servers: Vec<RegistryEntry>,
+}
+
+impl Registry {
+    /// Location of the registry file
+    pub fn path() -> PathBuf {
+        crate::wrap_base().join("registry.json")
+    }
+
+    /// Load the registry, returning an empty one if none exists yet
+    pub fn load() -> Result<Self> {
+        match fs::read_to_string(Self::path()) {
+            Ok(content) => serde_json::from_str(&content).context("Corrupt registry.json"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).context("Failed to read registry"),
+        }
+    }
+
+    /// Write the registry atomically
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, dir: &Path) -> Option<&RegistryEntry> {
+        self.servers.iter().find(|e| e.dir == dir)
+    }
+
+    /// Get the entry for a server, creating it if needed
+    pub fn entry(&mut self, dir: &Path) -> &mut RegistryEntry {
+        let idx = match self.servers.iter().position(|e| e.dir == dir) {
+            Some(idx) => idx,
+            None => {
+                self.servers.push(RegistryEntry::new(dir));
+                self.servers.len() - 1
+            }
+        };
+        &mut self.servers[idx]
+    }
+
+    /// Boot-enabled servers, ordered so dependencies come first
+    pub fn boot_order(&self) -> Result<Vec<&RegistryEntry>> {
+        let mut order = Vec::new();
+        let mut done = HashSet::new();
+        let mut visiting = HashSet::new();
+
+        for entry in self.servers.iter().filter(|e| e.boot) {
+            self.visit(entry, &mut order, &mut done, &mut visiting)?;
+        }
+
+        Ok(order)
+    }
+
+    fn visit<'a>(
+        &'a self,
+        entry: &'a RegistryEntry,
+        order: &mut Vec<&'a RegistryEntry>,
+        done: &mut HashSet<&'a Path>,
+        visiting: &mut HashSet<&'a Path>,
+    ) -> Result<()> {
+        if done.contains(entry.dir.as_path()) {
+            return Ok(());
+        }
+        if !visiting.insert(&entry.dir) {
+            bail!("Dependency cycle involving {}", entry.dir.display());
+        }
+
+        for dep in &entry.after {
+            // Dependencies that aren't boot-enabled are not started for us
+            if let Some(dep_entry) = self.get(dep).filter(|e| e.boot) {
+                self.visit(dep_entry, order, done, visiting)?;
+            }
+        }
+
+        visiting.remove(entry.dir.as_path());
+        done.insert(&entry.dir);
+        order.push(entry);
+        Ok(())
+    }
+}
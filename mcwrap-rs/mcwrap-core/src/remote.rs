@@ -0,0 +1,386 @@
+//! Remote access over TCP with mutual TLS
+//!
+//! `mcwrap serve` accepts TLS connections from clients holding a certificate
+//! signed by the configured CA. Each connection opens with a JSON line naming
+//! a server directory and a channel (control or console); `serve` connects to
+//! that server's local socket, authenticates with its token, replies with a
+//! JSON line and then relays bytes in both directions. From there the client
+//! speaks the same protocol it would over the Unix socket. The certificate's
+//! common name is the user name checked against the users file.
+//!
+//! The opening line carries the client's protocol version, which `serve`
+//! passes on when it authenticates, and the reply the handshake of the
+//! server's daemon. Clients and relays from before the handshake leave them
+//! out, which means version 1.
+
+use anyhow::{bail, Context, Result};
+use crate::config::Config;
+use crate::control::ControlClient;
+use crate::detach::DetachKeys;
+use crate::failure::Failure;
+use crate::highlight::Highlighter;
+use crate::protocol::{self, Credentials, Handshake};
+use crate::{auth, ServerPaths};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Default port for `mcwrap serve`
+pub const DEFAULT_PORT: u16 = 7077;
+
+/// Longest handshake line we accept
+const MAX_LINE: usize = 64 * 1024;
+
+/// Which of a server's sockets to reach
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    /// JSON-RPC control socket
+    Control,
+    /// Framed console socket
+    Console,
+}
+
+/// First line sent by a client
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    dir: PathBuf,
+    channel: Channel,
+    /// Protocol version the client speaks
+    #[serde(default = "legacy_version")]
+    protocol: u32,
+}
+
+/// Reply to a Hello
+#[derive(Serialize, Deserialize)]
+struct Welcome {
+    ok: bool,
+    #[serde(default)]
+    running: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The daemon's, or the relay's own if it couldn't reach one
+    #[serde(flatten)]
+    handshake: Handshake,
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// Certificate, key and CA used on both ends of a connection
+pub struct TlsFiles {
+    cert: PathBuf,
+    key: PathBuf,
+    ca: PathBuf,
+}
+
+impl TlsFiles {
+    /// Take files from the command line, falling back to `[remote]` in the global config
+    pub fn resolve(
+        cert: Option<PathBuf>,
+        key: Option<PathBuf>,
+        ca: Option<PathBuf>,
+    ) -> Result<Self> {
+        let remote = Config::load_global()?.remote;
+        match (cert.or(remote.tls_cert), key.or(remote.tls_key), ca.or(remote.tls_ca)) {
+            (Some(cert), Some(key), Some(ca)) => Ok(Self { cert, key, ca }),
+            _ => bail!("--tls-cert, --tls-key and --tls-ca are required (or set under [remote])"),
+        }
+    }
+
+    fn certs(&self) -> Result<Vec<CertificateDer<'static>>> {
+        read_certs(&self.cert)
+    }
+
+    fn key(&self) -> Result<PrivateKeyDer<'static>> {
+        let file = File::open(&self.key).with_context(|| format!("Cannot open {:?}", self.key))?;
+        rustls_pemfile::private_key(&mut BufReader::new(file))?
+            .with_context(|| format!("No private key in {:?}", self.key))
+    }
+
+    fn roots(&self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(&self.ca)? {
+            roots.add(cert).context("Invalid CA certificate")?;
+        }
+        Ok(roots)
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {:?}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate in {:?}", path))?;
+    if certs.is_empty() {
+        bail!("No certificates in {:?}", path);
+    }
+    Ok(certs)
+}
+
+/// Accept remote clients until interrupted
+pub async fn cmd_serve(listen: SocketAddr, tls: TlsFiles) -> Result<()> {
+    let verifier = WebPkiClientVerifier::builder(Arc::new(tls.roots()?))
+        .build()
+        .context("Invalid client CA")?;
+    let config = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(tls.certs()?, tls.key()?)
+        .context("Invalid server certificate or key")?;
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", listen))?;
+    println!("Serving on {} (mutual TLS)", listen);
+
+    loop {
+        let (tcp, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(acceptor, tcp).await {
+                eprintln!("{}: {:#}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_client(acceptor: TlsAcceptor, tcp: TcpStream) -> Result<()> {
+    let mut stream = acceptor.accept(tcp).await.context("TLS handshake failed")?;
+    let user = peer_name(stream.get_ref().1.peer_certificates())?;
+    let hello: Hello = serde_json::from_slice(&read_line(&mut stream).await?)?;
+
+    let mut welcome = Welcome {
+        ok: true,
+        running: false,
+        error: None,
+        handshake: Handshake::current(),
+    };
+    let local = match open_local(&hello, &user).await {
+        Ok(Some((local, handshake))) => {
+            welcome.running = true;
+            welcome.handshake = handshake;
+            Some(local)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            welcome.ok = false;
+            welcome.error = Some(format!("{:#}", e));
+            None
+        }
+    };
+    write_line(&mut stream, &welcome).await?;
+
+    if let Some(mut local) = local {
+        tokio::io::copy_bidirectional(&mut stream, &mut local).await.ok();
+    }
+    Ok(())
+}
+
+/// Common name of the client certificate, used as the user name for roles
+fn peer_name(certs: Option<&[CertificateDer<'static>]>) -> Result<String> {
+    let cert = certs.and_then(|certs| certs.first()).context("No client certificate")?;
+    let (_, cert) = X509Certificate::from_der(cert).context("Invalid client certificate")?;
+    let name = cert
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .context("Client certificate has no common name")?;
+    Ok(name.to_string())
+}
+
+/// Connect and authenticate to a local server's socket as `user`, returning the daemon's
+/// handshake, or None if it isn't running
+async fn open_local(hello: &Hello, user: &str) -> Result<Option<(UnixStream, Handshake)>> {
+    let dir = hello.dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&dir);
+    let Some(state) = crate::is_running(&paths) else {
+        return Ok(None);
+    };
+    if state.pty_master.is_none() {
+        bail!("Remote access needs a PTY-mode server");
+    }
+    let token = auth::load(&paths.token_file)?;
+
+    match hello.channel {
+        Channel::Console => {
+            if !state.framed {
+                bail!("Server uses the legacy raw protocol");
+            }
+            let mut stream = UnixStream::connect(&paths.socket_path)
+                .await
+                .context("Failed to connect to PTY socket")?;
+            let creds = Credentials {
+                token,
+                user: Some(user.to_string()),
+                protocol: Some(hello.protocol),
+            };
+            let handshake = protocol::authenticate(&mut stream, creds).await?;
+            Ok(Some((stream, handshake)))
+        }
+        Channel::Control => {
+            let mut stream = UnixStream::connect(&paths.control_socket)
+                .await
+                .context("Failed to connect to control socket")?;
+            let request = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 0,
+                "method": "auth",
+                "params": { "token": token, "user": user, "protocol": hello.protocol },
+            });
+            write_line(&mut stream, &request).await?;
+            let reply: serde_json::Value = serde_json::from_slice(&read_line(&mut stream).await?)?;
+            if let Some(error) = reply.get("error") {
+                bail!("{}", error["message"].as_str().unwrap_or("Authentication failed"));
+            }
+            let handshake = serde_json::from_value(reply["result"].clone())?;
+            Ok(Some((stream, handshake)))
+        }
+    }
+}
+
+/// Open a channel to a server on a remote `mcwrap serve`, with its daemon's handshake, or
+/// None if it isn't running
+pub async fn connect(
+    host: &str,
+    dir: &Path,
+    channel: Channel,
+    tls: &TlsFiles,
+) -> Result<Option<(TlsStream<TcpStream>, Handshake)>> {
+    let config = ClientConfig::builder()
+        .with_root_certificates(tls.roots()?)
+        .with_client_auth_cert(tls.certs()?, tls.key()?)
+        .context("Invalid client certificate or key")?;
+
+    let (name, addr) = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => (name, host.to_string()),
+        _ => (host, format!("{}:{}", host, DEFAULT_PORT)),
+    };
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    let server_name = ServerName::try_from(name.to_string()).context("Invalid host name")?;
+
+    let tcp = TcpStream::connect(&addr)
+        .await
+        .with_context(|| format!("Failed to connect to {}", addr))?;
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await
+        .context("TLS handshake failed")?;
+
+    let hello = Hello {
+        dir: dir.to_path_buf(),
+        channel,
+        protocol: protocol::VERSION,
+    };
+    write_line(&mut stream, &hello).await?;
+    let welcome: Welcome = serde_json::from_slice(&read_line(&mut stream).await?)?;
+    if !welcome.ok {
+        bail!("{}", welcome.error.unwrap_or_else(|| "Remote refused".to_string()));
+    }
+
+    Ok(welcome.running.then_some((stream, welcome.handshake)))
+}
+
+/// `mcwrap attach --host`
+pub async fn cmd_attach(
+    host: &str,
+    dir: &Path,
+    raw: bool,
+    take: bool,
+    history: usize,
+    highlighter: Option<Highlighter>,
+    tls: &TlsFiles,
+) -> Result<()> {
+    let (mut stream, handshake) = connect(host, dir, Channel::Console, tls)
+        .await?
+        .context(Failure::not_running())?;
+    if take && !handshake.supports("take") {
+        bail!("The server's daemon can't hand over the console; restart it to use --take");
+    }
+    crate::subscribe_console(&mut stream, !raw || history > 0, take).await?;
+    let keys = DetachKeys::new(&Config::load_global()?.attach)
+        .context("Invalid [attach] settings")?;
+
+    // The history comes with the daemon's scrollback, as the log is on the remote machine
+    let opts = crate::AttachOptions {
+        raw,
+        framed: true,
+        resize: handshake.supports("resize"),
+        history,
+    };
+    crate::attach_stream(stream, None, None, keys, opts, highlighter).await
+}
+
+/// `mcwrap send --host`
+pub async fn cmd_send(
+    host: &str,
+    dir: &Path,
+    command: &str,
+    queue: bool,
+    tls: &TlsFiles,
+) -> Result<()> {
+    let (stream, handshake) = connect(host, dir, Channel::Control, tls)
+        .await?
+        .context(Failure::not_running())?;
+    let mut control = ControlClient::from_stream(stream, handshake);
+    let params = serde_json::json!({ "command": command, "queue": queue });
+    control.call("send", params).await?;
+    Ok(())
+}
+
+/// `mcwrap status --host`
+pub async fn cmd_status(host: &str, dir: &Path, tls: &TlsFiles) -> Result<()> {
+    let name = dir.file_name().unwrap_or(dir.as_os_str()).to_string_lossy();
+    let Some((stream, handshake)) = connect(host, dir, Channel::Control, tls).await? else {
+        println!("○ {} not running on {}", name, host);
+        return Ok(());
+    };
+    let mut control = ControlClient::from_stream(stream, handshake);
+    let status = control.call("status", serde_json::json!({})).await?;
+
+    println!("● {} running on {}", name, host);
+    println!("  PID: {}", status["pid"]);
+    println!("  Uptime: {}s", status["uptime_secs"]);
+    println!("  Clients: {}", status["clients"]);
+    Ok(())
+}
+
+/// Read one line byte by byte so nothing after it is consumed
+async fn read_line<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut line = Vec::new();
+    loop {
+        let byte = stream.read_u8().await.context("Connection closed during handshake")?;
+        if byte == b'\n' {
+            return Ok(line);
+        }
+        if line.len() >= MAX_LINE {
+            bail!("Handshake line too long");
+        }
+        line.push(byte);
+    }
+}
+
+async fn write_line<S, T>(stream: &mut S, message: &T) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line).await?;
+    stream.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,133 @@
+//! Playing a console log back as it was written
+//!
+//! `mcwrap replay <dir>` prints a console log again with the pauses it was
+//! written with, so that a grief or a crash can be watched as it unfolded
+//! rather than read off a wall of lines. It plays the current run, or the
+//! latest archived one if the server isn't running, or with `--run N` the
+//! one `mcwrap logs` lists as N. `--from 14:00` starts at the first 14:00 in
+//! the log (a date and time or a duration ago, as for `log --since`, work
+//! too) and `--speed 4x` plays it four times as fast.
+//!
+//! Lines are timed by their `[log] timestamps` when the log has them, or else
+//! by the time of day in the server's headers; stack traces and other lines
+//! without either come right after the line before them. A pause of more
+//! than 10 seconds, once scaled, is skipped with a note of how long it was.
+
+use anyhow::{bail, Context, Result};
+use crate::config::Config;
+use crate::errors::{self, Clock};
+use crate::timestamps::{self, Zone};
+use crate::{console, format_uptime, jsonl, logs, read_state, records, ServerPaths};
+use chrono::{DateTime, FixedOffset, Local, NaiveTime, TimeDelta, TimeZone};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// The longest pause played as it was
+const MAX_PAUSE: Duration = Duration::from_secs(10);
+
+/// How long a pause that is skipped lasts instead
+const SKIPPED_PAUSE: Duration = Duration::from_secs(1);
+
+pub fn cmd_replay(
+    server_dir: &Path,
+    run: Option<usize>,
+    from: Option<&str>,
+    speed: f64,
+    plain: bool,
+) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    let runs = logs::runs(&paths.logs_dir);
+    let (path, started) = match run {
+        Some(n) => {
+            let Some(path) = n.checked_sub(1).and_then(|i| runs.get(i)) else {
+                bail!("No archived run {}", n);
+            };
+            (path.clone(), logs::started(path))
+        }
+        None if paths.log_file.exists() => {
+            let started = read_state(&paths)
+                .and_then(|state| DateTime::from_timestamp(state.started_at as i64, 0))
+                .map(|at| at.with_timezone(&Local).naive_local());
+            (paths.log_file.clone(), started)
+        }
+        None => match runs.first() {
+            Some(path) => (path.clone(), logs::started(path)),
+            None => bail!("No console log to replay"),
+        },
+    };
+
+    let content = jsonl::read(&path).with_context(|| format!("Failed to read {:?}", path))?;
+    let records = records::parse(content.lines());
+    let mut clock = Clock::new(started);
+    let times: Vec<_> = records.iter().map(|record| errors::occurred(record, &mut clock)).collect();
+    let from = match from {
+        Some(from) => {
+            let zone = Zone::from_config(&Config::load(&server_dir)?.log)?;
+            let Some(&first) = times.iter().flatten().next() else {
+                bail!("The log has no times to replay it by");
+            };
+            Some(start(from, first, zone)?)
+        }
+        None => None,
+    };
+    let plain = plain || !std::io::stdout().is_terminal();
+
+    // When the record played last was logged
+    let mut last: Option<DateTime<FixedOffset>> = None;
+    let mut time = None;
+    for (record, at) in records.iter().zip(times) {
+        // Records without a time go with the one before them
+        time = at.or(time);
+        if from.is_some_and(|from| time.is_none_or(|time| time < from)) {
+            continue;
+        }
+        if let (Some(last), Some(time)) = (last, time) {
+            let pause = (time - last).to_std().unwrap_or_default();
+            if pause.div_f64(speed) > MAX_PAUSE {
+                println!("-- {} later --", format_uptime(pause.as_secs()));
+                thread::sleep(SKIPPED_PAUSE);
+            } else {
+                thread::sleep(pause.div_f64(speed));
+            }
+        }
+        last = time.or(last);
+        for line in &record.lines {
+            if plain {
+                println!("{}", console::strip_ansi(line));
+            } else {
+                println!("{}", line);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Where `--from` starts: the first time the log reaches a time of day (e.g. "14:00"), or a
+/// time as `--since` takes it
+fn start(from: &str, first: DateTime<FixedOffset>, zone: Zone) -> Result<DateTime<FixedOffset>> {
+    let time_of_day = ["%H:%M", "%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(from, format).ok());
+    let Some(time_of_day) = time_of_day else {
+        return timestamps::parse_since(from, zone);
+    };
+    let local = first.date_naive().and_time(time_of_day);
+    let Some(mut at) = first.offset().from_local_datetime(&local).single() else {
+        bail!("Invalid time {:?}", from);
+    };
+    if at < first {
+        at += TimeDelta::days(1);
+    }
+    Ok(at)
+}
+
+/// How much faster than it was written the log is played, e.g. "4x" or "0.5"
+pub fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.strip_suffix('x').unwrap_or(s).parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("invalid speed {:?} (use e.g. \"4x\" or \"0.5x\")", s)),
+    }
+}
@@ -0,0 +1,330 @@
+//! Console commands run on a cron schedule
+//!
+//! A server's config can list tasks for its PTY daemon to run:
+//!
+//! ```toml
+//! schedule = [
+//!     { cron = "55 */6 * * *", command = "say Backups in 5 minutes" },
+//!     { cron = "@daily", command = "save-all" },
+//! ]
+//! ```
+//!
+//! Cron expressions have the usual five fields (minute, hour, day of month,
+//! month, day of week) in local time, with `*`, lists, ranges, steps and
+//! names like `mon` or `jan`, or are one of `@hourly`, `@daily`, `@weekly`,
+//! `@monthly` and `@yearly`. Commands go through the command queue like
+//! `send`, and are audited as sent by the owner via "schedule". The schedule
+//! is read when the server starts; `mcwrap tasks` shows when each task runs
+//! next and can run one right away.
+
+use anyhow::{bail, Context, Result};
+use crate::config::{Config, ScheduledTask};
+use crate::daemon_log;
+use crate::pty::DaemonState;
+use crate::users::Identity;
+use crate::{is_running, ServerPaths};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeDelta, TimeZone, Timelike};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Longest the scheduler sleeps at once, so that clock changes are noticed
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// How far ahead to look for the next run of an expression
+const MAX_YEARS_AHEAD: i64 = 5;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A parsed cron expression; each field is a bit set of the values it matches
+#[derive(Clone)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day of month and day of week were both restricted, so either matching is enough
+    either_day: bool,
+}
+
+/// A task from the config, ready to run
+#[derive(Clone)]
+pub struct Task {
+    pub spec: String,
+    pub cron: Cron,
+    pub command: String,
+}
+
+impl Cron {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let expanded = match spec.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            spec => spec,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("expected 5 fields (minute hour day month weekday), got {}", fields.len());
+        };
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAYS).context("day of week")?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[]).context("minute")?,
+            hours: parse_field(hour, 0, 23, &[]).context("hour")?,
+            days: parse_field(day, 1, 31, &[]).context("day of month")?,
+            months: parse_field(month, 1, 12, &MONTHS).context("month")?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    /// The first time after `after` (at minute precision) the expression matches
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let after = after.naive_local();
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        let limit = after + TimeDelta::days(366 * MAX_YEARS_AHEAD);
+        while time < limit {
+            if !self.matches_day(&time) {
+                time = time.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !matches(self.hours, time.hour()) {
+                time = time.with_minute(0)? + TimeDelta::hours(1);
+            } else if !matches(self.minutes, time.minute()) {
+                time += TimeDelta::minutes(1);
+            } else if let Some(local) = Local.from_local_datetime(&time).earliest() {
+                return Some(local);
+            } else {
+                // Skipped by a daylight saving change
+                time += TimeDelta::minutes(1);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        if !matches(self.months, time.month()) {
+            return false;
+        }
+        let day = matches(self.days, time.day());
+        let weekday = matches(self.weekdays, time.weekday().num_days_from_sunday());
+        if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+}
+
+fn matches(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parse one field, e.g. "*/15", "1-5", "mon,wed,fri" or "0-30/10"
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let value = |text: &str| -> Result<u32> {
+        let lower = text.to_ascii_lowercase();
+        let value = match names.iter().position(|name| *name == lower) {
+            Some(i) => min + i as u32,
+            None => text.parse().with_context(|| format!("invalid value {:?}", text))?,
+        };
+        if !(min..=max).contains(&value) {
+            bail!("{} is not within {}-{}", value, min, max);
+        }
+        Ok(value)
+    };
+
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().with_context(|| format!("invalid step {:?}", step))?;
+                if step == 0 {
+                    bail!("step must not be 0");
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // A single value with a step runs to the end, as in "5/15"
+            None if step > 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if first > last {
+            bail!("range {} is backwards", range);
+        }
+        for value in (first..=last).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+/// Parse the tasks from a server's config
+pub fn parse_tasks(schedule: &[ScheduledTask]) -> Result<Vec<Task>> {
+    schedule
+        .iter()
+        .map(|task| {
+            let cron = Cron::parse(&task.cron)
+                .with_context(|| format!("Invalid schedule entry {:?}", task.cron))?;
+            Ok(Task {
+                spec: task.cron.clone(),
+                cron,
+                command: task.command.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Run the tasks from a background thread for as long as the daemon lives
+pub fn spawn(state: Arc<DaemonState>, tasks: Vec<Task>) {
+    if tasks.is_empty() {
+        return;
+    }
+    thread::spawn(move || loop {
+        let now = Local::now();
+        let Some(next) = tasks.iter().filter_map(|task| task.cron.next_after(now)).min() else {
+            daemon_log::warn("No scheduled task will ever run again");
+            return;
+        };
+        let wait = (next - Local::now()).to_std().unwrap_or_default();
+        thread::sleep(wait.min(MAX_SLEEP));
+        if Local::now() < next {
+            continue;
+        }
+        for task in tasks.iter().filter(|task| task.cron.next_after(now) == Some(next)) {
+            daemon_log::info(format!("Running scheduled task: {}", task.command));
+            state.send_command(&task.command, true, false);
+            state.record_command(&Identity::owner(), "schedule", &task.command);
+        }
+    });
+}
+
+/// List the scheduled tasks with their next run, or run task N now
+pub async fn cmd_tasks(server_dir: &Path, run_now: Option<usize>) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let tasks = parse_tasks(&Config::load(&server_dir)?.schedule)?;
+
+    if let Some(n) = run_now {
+        let Some(task) = n.checked_sub(1).and_then(|i| tasks.get(i)) else {
+            bail!("No task {}", n);
+        };
+        println!("{}", task.command);
+        return crate::cmd_send(&server_dir, &task.command).await;
+    }
+
+    if tasks.is_empty() {
+        println!("No scheduled tasks. Add them to mcwrap.toml, e.g.:");
+        println!("  schedule = [{{ cron = \"0 */6 * * *\", command = \"save-all\" }}]");
+        return Ok(());
+    }
+    let width = tasks.iter().map(|task| task.spec.len()).max().unwrap_or(0).max(4);
+    println!("{:>3}  {:<16}  {:<width$}  COMMAND", "#", "NEXT RUN", "CRON", width = width);
+    let now = Local::now();
+    for (i, task) in tasks.iter().enumerate() {
+        let next = match task.cron.next_after(now) {
+            Some(next) => next.format("%Y-%m-%d %H:%M").to_string(),
+            None => "never".to_string(),
+        };
+        println!(
+            "{:>3}  {:<16}  {:<width$}  {}",
+            i + 1,
+            next,
+            task.spec,
+            task.command,
+            width = width
+        );
+    }
+    if is_running(&ServerPaths::new(&server_dir)).is_none() {
+        println!("\nServer is not running; tasks run while it is (in PTY mode).");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn next(spec: &str, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        Cron::parse(spec).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn valid_expressions() {
+        let cron = Cron::parse("*/15 9-17 * * *").unwrap();
+        assert_eq!(cron.minutes, 1 << 0 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(cron.hours, 0b111111111 << 9);
+        assert!(!cron.either_day);
+
+        let cron = Cron::parse("5/20 0 1,15 JAN-mar mon-fri").unwrap();
+        assert_eq!(cron.minutes, 1 << 5 | 1 << 25 | 1 << 45);
+        assert_eq!(cron.days, 1 << 1 | 1 << 15);
+        assert_eq!(cron.months, 0b1110);
+        assert_eq!(cron.weekdays, 0b111110);
+        assert!(cron.either_day);
+
+        // Both 0 and 7 are Sunday
+        assert_eq!(Cron::parse("0 0 * * 7").unwrap().weekdays, 1);
+        assert_eq!(Cron::parse("0 0 * * 0,7").unwrap().weekdays, 1);
+        for spec in ["@hourly", "@daily", "@midnight", "@weekly", "@monthly", "@yearly"] {
+            assert!(Cron::parse(spec).is_ok(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        for spec in [
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * 32 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "* * * foo *",
+            "*/0 * * * *",
+            "30-10 * * * *",
+            "-1 * * * *",
+            "* * * *",
+            "* * * * * *",
+            "@fortnightly",
+            "",
+        ] {
+            assert!(Cron::parse(spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn next_fire() {
+        // 14 January 2026 is a Wednesday
+        let wednesday = at(2026, 1, 14, 10, 15);
+        assert_eq!(next("30 * * * *", wednesday), Some(at(2026, 1, 14, 10, 30)));
+        // Strictly after, even when `after` matches
+        assert_eq!(next("15 * * * *", wednesday), Some(at(2026, 1, 14, 11, 15)));
+        assert_eq!(next("*/10 * * * *", wednesday), Some(at(2026, 1, 14, 10, 20)));
+        assert_eq!(next("@daily", wednesday), Some(at(2026, 1, 15, 0, 0)));
+        assert_eq!(next("0 12 * * mon", wednesday), Some(at(2026, 1, 19, 12, 0)));
+        assert_eq!(next("0 0 1 * *", at(2026, 1, 31, 12, 0)), Some(at(2026, 2, 1, 0, 0)));
+        assert_eq!(next("@yearly", at(2026, 12, 31, 23, 59)), Some(at(2027, 1, 1, 0, 0)));
+        // With both days restricted, either one matching is enough
+        assert_eq!(next("0 0 13 * fri", wednesday), Some(at(2026, 1, 16, 0, 0)));
+        // 29 February only comes in leap years
+        assert_eq!(next("0 0 29 2 *", wednesday), Some(at(2028, 2, 29, 0, 0)));
+        assert_eq!(next("0 0 30 2 *", wednesday), None);
+    }
+}
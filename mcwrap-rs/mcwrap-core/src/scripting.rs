@@ -0,0 +1,290 @@
+//! Automation scripts run by the PTY daemon
+//!
+//! For automation beyond trigger rules, a server's config can list Rhai
+//! scripts (https://rhai.rs), relative to the server directory:
+//!
+//! ```toml
+//! scripts = ["scripts/afk.rhai"]
+//! ```
+//!
+//! A script defines any of these handlers, which get its state as `this`:
+//!
+//! - `init()`, once when the daemon starts
+//! - `on_line(line)`, for every console line (without colors)
+//! - `on_event(event)`, for every event, as a map like `mcwrap events --json`
+//!   shows (`event.type`, `event.player`...)
+//! - `on_tick()`, every second
+//!
+//! and can call `send(command)`, `stop()`, `backup()`, `notify(message)`,
+//! `log(message)`, `players()` (the names of those online) and
+//! `captures(text, regex)` (the groups of the first match, or an empty
+//! array). Kicking players who haven't chatted for 30 minutes:
+//!
+//! ```rhai
+//! fn init() { this.active = #{}; }
+//!
+//! fn on_event(event) {
+//!     if event.type == "PlayerJoined" { this.active[event.player] = timestamp(); }
+//!     if event.type == "PlayerLeft" { this.active.remove(event.player); }
+//! }
+//!
+//! fn on_line(line) {
+//!     let chat = captures(line, "<(\\w+)> ");
+//!     if chat.len() > 0 { this.active[chat[1]] = timestamp(); }
+//! }
+//!
+//! fn on_tick() {
+//!     for player in this.active.keys() {
+//!         if this.active[player].elapsed > 1800.0 { send(`kick ${player} AFK`); }
+//!     }
+//! }
+//! ```
+//!
+//! Scripts are compiled when the server starts, so errors in them keep it
+//! from starting; errors while they run go to `daemon.log`. Scripts run one
+//! handler at a time on a thread of their own, and a handler that runs away
+//! is stopped after `MAX_OPERATIONS`.
+
+use anyhow::{Context, Result};
+use crate::daemon_log;
+use crate::events::{Event, EventKind};
+use crate::pty::DaemonState;
+use regex::Regex;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Scope, AST};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval between `on_tick` calls
+const TICK: Duration = Duration::from_secs(1);
+
+/// Operations a single handler call may take before it is stopped
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A compiled script
+#[derive(Clone)]
+struct Script {
+    name: String,
+    ast: AST,
+}
+
+/// The scripts of one server
+#[derive(Clone)]
+pub struct Scripts {
+    server_dir: PathBuf,
+    scripts: Vec<Script>,
+}
+
+/// What scripts are told about
+pub enum Input {
+    Line(String),
+    Event(Event),
+}
+
+/// The daemon's side of the scripts' thread
+pub struct Host {
+    input: Mutex<Sender<Input>>,
+    partial: Mutex<String>,
+}
+
+impl Scripts {
+    /// Compile the scripts from a server's config
+    pub fn compile(server_dir: &Path, paths: &[PathBuf]) -> Result<Self> {
+        let engine = Engine::new();
+        let scripts = paths
+            .iter()
+            .map(|path| {
+                let path = server_dir.join(path);
+                let source = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read script {:?}", path))?;
+                let ast = engine
+                    .compile(source)
+                    .map_err(|e| anyhow::anyhow!("{}", e))
+                    .with_context(|| format!("Invalid script {:?}", path))?;
+                let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                Ok(Script { name, ast })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            server_dir: server_dir.to_path_buf(),
+            scripts,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+}
+
+impl Host {
+    /// Feed (log-filtered) console output
+    pub fn console_output(&self, output: &[u8]) {
+        let mut partial = self.partial.lock().unwrap();
+        partial.push_str(&String::from_utf8_lossy(output));
+        while let Some(end) = partial.find('\n') {
+            let line: String = partial.drain(..=end).collect();
+            let line = crate::console::strip_ansi(line.trim_end_matches(['\r', '\n']));
+            let _ = self.input.lock().unwrap().send(Input::Line(line));
+        }
+    }
+
+    pub fn event(&self, event: &Event) {
+        let _ = self.input.lock().unwrap().send(Input::Event(event.clone()));
+    }
+}
+
+/// The host for a set of scripts, and the receiving end to hand to `spawn`
+pub fn host(scripts: &Scripts) -> Option<(Host, Receiver<Input>)> {
+    if scripts.is_empty() {
+        return None;
+    }
+    let (sender, receiver) = mpsc::channel();
+    let host = Host {
+        input: Mutex::new(sender),
+        partial: Mutex::new(String::new()),
+    };
+    Some((host, receiver))
+}
+
+/// Run the scripts from a background thread for as long as the daemon lives
+pub fn spawn(state: Arc<DaemonState>, scripts: Scripts, input: Receiver<Input>) {
+    thread::spawn(move || {
+        let engine = engine(state, scripts.server_dir);
+        let mut running: Vec<(Script, Dynamic)> = scripts
+            .scripts
+            .into_iter()
+            .map(|script| (script, Dynamic::from_map(Default::default())))
+            .collect();
+        for (script, this) in &mut running {
+            // Top-level statements run first, then init()
+            if let Err(e) = engine.run_ast(&script.ast) {
+                daemon_log::error(format!("Script {}: {}", script.name, e));
+            }
+            call(&engine, script, this, "init", ());
+        }
+
+        let mut next_tick = Instant::now() + TICK;
+        loop {
+            match input.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+                Ok(Input::Line(line)) => {
+                    for (script, this) in &mut running {
+                        call(&engine, script, this, "on_line", (line.clone(),));
+                    }
+                }
+                Ok(Input::Event(event)) => {
+                    let Ok(event) = rhai::serde::to_dynamic(&event) else {
+                        continue;
+                    };
+                    for (script, this) in &mut running {
+                        call(&engine, script, this, "on_event", (event.clone(),));
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    next_tick = Instant::now() + TICK;
+                    for (script, this) in &mut running {
+                        call(&engine, script, this, "on_tick", ());
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    });
+}
+
+/// Call a handler, if the script defines it
+fn call(
+    engine: &Engine,
+    script: &Script,
+    this: &mut Dynamic,
+    handler: &str,
+    args: impl rhai::FuncArgs,
+) {
+    let defined = script.ast.iter_functions().any(|f| f.name == handler);
+    if !defined {
+        return;
+    }
+    let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(this);
+    let mut scope = Scope::new();
+    let result =
+        engine.call_fn_with_options::<Dynamic>(options, &mut scope, &script.ast, handler, args);
+    if let Err(e) = result {
+        daemon_log::error(format!("Script {}: {}: {}", script.name, handler, e));
+    }
+}
+
+/// An engine with the mcwrap functions scripts can call
+fn engine(state: Arc<DaemonState>, server_dir: PathBuf) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+
+    let s = state.clone();
+    engine.register_fn("send", move |command: &str| {
+        s.send_command(command, true, false);
+        s.record_command(&crate::users::Identity::owner(), "script", command);
+    });
+    let s = state.clone();
+    engine.register_fn("stop", move || {
+        daemon_log::info("Script stopping the server");
+        crate::oom::stop_server(s.clone());
+    });
+    let s = state.clone();
+    engine.register_fn("backup", move || backup(&s, &server_dir));
+    let s = state.clone();
+    engine.register_fn("notify", move |message: &str| {
+        s.emit(EventKind::Alert {
+            message: message.to_string(),
+        });
+    });
+    engine.register_fn("log", |message: &str| daemon_log::info(message));
+    let s = state;
+    engine.register_fn("players", move || -> Array {
+        s.online_players().into_iter().map(Dynamic::from).collect()
+    });
+
+    let regexes: Mutex<HashMap<String, Regex>> = Mutex::new(HashMap::new());
+    engine.register_fn("captures", move |text: &str, pattern: &str| -> Array {
+        let mut regexes = regexes.lock().unwrap();
+        if !regexes.contains_key(pattern) {
+            match Regex::new(pattern) {
+                Ok(regex) => regexes.insert(pattern.to_string(), regex),
+                Err(e) => {
+                    daemon_log::error(format!("Script regex {:?}: {}", pattern, e));
+                    return Array::new();
+                }
+            };
+        }
+        let Some(captures) = regexes[pattern].captures(text) else {
+            return Array::new();
+        };
+        captures
+            .iter()
+            .map(|group| group.map_or(Dynamic::UNIT, |group| group.as_str().into()))
+            .collect()
+    });
+    engine
+}
+
+/// Back up the worlds of the running server, with saving paused meanwhile
+fn backup(state: &DaemonState, server_dir: &Path) -> bool {
+    state.send_command("save-off", true, false);
+    state.send_command("save-all flush", true, false);
+    let result = crate::upgrade::backup(server_dir, "script", &[]);
+    state.send_command("save-on", true, false);
+    match result {
+        Ok(archive) => {
+            state.emit(EventKind::BackupCompleted { archive });
+            true
+        }
+        Err(e) => {
+            daemon_log::error(format!("Script backup failed: {:#}", e));
+            state.emit(EventKind::BackupFailed {
+                reason: format!("{:#}", e),
+            });
+            false
+        }
+    }
+}
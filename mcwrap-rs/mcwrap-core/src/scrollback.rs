@@ -0,0 +1,149 @@
+//! Scrolling back through console output while attached
+//!
+//! The daemon keeps the most recent console output in a ring buffer and
+//! sends it to clients that subscribe with `scrollback` set. `attach` shows
+//! its last lines first (`--history`, 30 by default), colors and all, rather
+//! than reading them back from a log that may just have been rotated. It adds
+//! live output to its own copy, and PageUp/PageDown page through it on the
+//! terminal's alternate screen without touching the log file. Output that
+//! arrives meanwhile is held back until the user returns to the live view.
+
+use std::collections::VecDeque;
+
+/// Most console output the daemon keeps for scrollback
+const RING_BYTES: usize = 512 * 1024;
+
+/// Most lines an attached client keeps
+const MAX_LINES: usize = 10_000;
+
+pub const PAGE_UP: &[u8] = b"\x1b[5~";
+pub const PAGE_DOWN: &[u8] = b"\x1b[6~";
+
+/// Recent console output held by the daemon
+#[derive(Default)]
+pub struct Ring {
+    data: VecDeque<u8>,
+}
+
+impl Ring {
+    /// Add (log-filtered) console output, dropping the oldest whole lines
+    pub fn push(&mut self, output: &[u8]) {
+        self.data.extend(output);
+        if self.data.len() <= RING_BYTES {
+            return;
+        }
+        let excess = self.data.len() - RING_BYTES;
+        self.data.drain(..excess);
+        // Don't start in the middle of a line
+        if let Some(newline) = self.data.iter().position(|&b| b == b'\n') {
+            self.data.drain(..=newline);
+        }
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.data.iter().copied().collect()
+    }
+}
+
+/// Client-side pager over the lines seen since attaching
+#[derive(Default)]
+pub struct Pager {
+    lines: VecDeque<String>,
+    partial: String,
+    /// Lines scrolled up from the bottom, while the pager is shown
+    offset: Option<usize>,
+    /// Live output held back while the pager is shown
+    held: Vec<u8>,
+}
+
+impl Pager {
+    /// Record (log-filtered) console output
+    pub fn push(&mut self, output: &[u8]) {
+        self.partial.push_str(&String::from_utf8_lossy(output));
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            self.lines.push_back(line.trim_end_matches(['\r', '\n']).to_string());
+        }
+        while self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+            // Keep the view on the same lines as older ones fall off
+            if let Some(offset) = self.offset.as_mut() {
+                *offset = offset.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Pass live terminal output through, or hold it while the pager is shown
+    pub fn live(&mut self, output: &[u8]) -> Option<Vec<u8>> {
+        if self.offset.is_some() {
+            self.held.extend_from_slice(output);
+            return None;
+        }
+        Some(output.to_vec())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.offset.is_some()
+    }
+
+    /// Scroll up a page, returning what to write to the terminal
+    pub fn page_up(&mut self, rows: u16) -> Vec<u8> {
+        let page = page_size(rows);
+        let max = self.lines.len().saturating_sub(page);
+        let mut out = Vec::new();
+        if self.offset.is_none() {
+            out.extend_from_slice(b"\x1b[?1049h");
+        }
+        self.offset = Some((self.offset.unwrap_or(0) + page).min(max));
+        out.extend(self.render(page));
+        out
+    }
+
+    /// Scroll down a page, returning to the live view at the bottom
+    pub fn page_down(&mut self, rows: u16) -> Vec<u8> {
+        let Some(offset) = self.offset else {
+            return Vec::new();
+        };
+        let page = page_size(rows);
+        if offset <= page {
+            return self.close();
+        }
+        self.offset = Some(offset - page);
+        self.render(page)
+    }
+
+    /// Leave the pager, restoring the live view and any output held back
+    pub fn close(&mut self) -> Vec<u8> {
+        if self.offset.take().is_none() {
+            return Vec::new();
+        }
+        let mut out = b"\x1b[?1049l".to_vec();
+        out.append(&mut self.held);
+        out
+    }
+
+    fn render(&self, page: usize) -> Vec<u8> {
+        let offset = self.offset.unwrap_or(0);
+        let end = self.lines.len() - offset.min(self.lines.len());
+        let start = end.saturating_sub(page);
+
+        let mut out = b"\x1b[H\x1b[2J".to_vec();
+        for line in self.lines.range(start..end) {
+            out.extend_from_slice(line.as_bytes());
+            out.extend_from_slice(b"\x1b[0m\r\n");
+        }
+        let status = format!(
+            "\x1b[7m-- lines {}-{} of {} (PgUp/PgDn to scroll, q to return) --\x1b[0m",
+            start + 1,
+            end,
+            self.lines.len()
+        );
+        out.extend_from_slice(status.as_bytes());
+        out
+    }
+}
+
+/// Lines of output per page, leaving room for the status line
+fn page_size(rows: u16) -> usize {
+    (rows as usize).saturating_sub(1).max(1)
+}
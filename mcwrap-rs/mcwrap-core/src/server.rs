@@ -0,0 +1,125 @@
+//! Servers for programs that link mcwrap
+//!
+//! A `Server` is a server directory, running or not. Starting it, or
+//! connecting to it while it runs, gives a `ServerHandle`: a `Client` of the
+//! daemon that asks for its status, sends commands and stops it, and from
+//! which its console (`ConsoleStream`) and its events (`EventStream`) can be
+//! followed. Only PTY-mode servers have a daemon to connect to.
+//!
+//! Starting a server forks the daemon off the calling process, as `mcwrap
+//! start` does, and prints what it prints.
+
+use anyhow::{bail, Context, Result};
+use crate::client::{Client, ConsoleStream, EventStream, Status};
+use crate::failure::Failure;
+use crate::protocol::Handshake;
+use crate::{cmd_start, is_running, ServerPaths, StartOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub use crate::events::Event as ServerEvent;
+
+/// How long a started server's daemon gets to open its control socket
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server directory
+pub struct Server {
+    dir: PathBuf,
+}
+
+impl Server {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let dir = dir.canonicalize().context("Invalid server directory")?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The server's process, while it runs
+    pub fn pid(&self) -> Option<i32> {
+        is_running(&ServerPaths::new(&self.dir)).map(|state| state.pid)
+    }
+
+    /// Start the server and connect to its daemon
+    ///
+    /// This returns once the server process is running, not once it is ready for players:
+    /// that is the `ServerStarted` event.
+    pub async fn start(&self, java_args: Vec<String>, opts: StartOptions) -> Result<ServerHandle> {
+        if opts.basic || opts.foreground {
+            bail!("Only a server started with a detached PTY daemon can be connected to");
+        }
+        cmd_start(&self.dir, java_args, opts).await?;
+        // The daemon opens its control socket after it has started the server
+        let started = Instant::now();
+        loop {
+            match self.connect().await {
+                Ok(handle) => return Ok(handle),
+                Err(_) if started.elapsed() < CONNECT_TIMEOUT => {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Connect to the running server's daemon
+    pub async fn connect(&self) -> Result<ServerHandle> {
+        let state = is_running(&ServerPaths::new(&self.dir)).context(Failure::not_running())?;
+        let client = Client::connect(&self.dir).await?;
+        Ok(ServerHandle {
+            dir: self.dir.clone(),
+            pid: state.pid,
+            client,
+        })
+    }
+}
+
+/// A connection to a running server's daemon
+pub struct ServerHandle {
+    dir: PathBuf,
+    pid: i32,
+    client: Client,
+}
+
+impl ServerHandle {
+    /// The server's process
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// The daemon's protocol version and capabilities
+    pub fn handshake(&self) -> &Handshake {
+        self.client.handshake()
+    }
+
+    pub async fn status(&mut self) -> Result<Status> {
+        self.client.status().await
+    }
+
+    /// Send a console command, which waits its turn in the command queue
+    pub async fn send(&mut self, command: &str) -> Result<()> {
+        self.client.send(command).await
+    }
+
+    /// Send a console command and return the output it produces within `timeout`
+    pub async fn exec(&mut self, command: &str, timeout: Duration) -> Result<String> {
+        self.client.exec(command, timeout).await
+    }
+
+    /// Stop the server, giving it `timeout` to exit, and return its exit code
+    pub async fn stop(&mut self, timeout: Duration) -> Result<i32> {
+        self.client.stop(timeout).await
+    }
+
+    /// Follow the console output from now on, over a connection of its own
+    pub async fn console(&self) -> Result<ConsoleStream> {
+        Client::connect(&self.dir).await?.subscribe_console().await
+    }
+
+    /// Follow the server's events from now on, over a connection of its own
+    pub async fn events(&self) -> Result<EventStream> {
+        Client::connect(&self.dir).await?.subscribe_events().await
+    }
+}
@@ -0,0 +1,279 @@
+//! Shipping the console log to Grafana Loki or Elasticsearch
+//!
+//! ```toml
+//! [log.ship]
+//! to = "loki"             # or "elasticsearch" (OpenSearch works too)
+//! url = "http://loki:3100"
+//! interval = "5s"
+//! headers = { "X-Scope-OrgID" = "minecraft" }
+//! ```
+//!
+//! For searching the logs of a whole fleet in one place. The daemon reads the
+//! console as records, like `mcwrap log --json` (a message with its stack
+//! trace), and every `interval` pushes those logged since to the service with
+//! curl. Loki gets them in streams labelled `server`, `level` and, on Forge,
+//! `logger`; Elasticsearch gets one document per record in `index` (default
+//! "mcwrap") through the bulk API, with `@timestamp` and `server` added. The
+//! time is the `[log] timestamps` one when there is one, or else when the
+//! daemon read the line. If the service can't be reached, records are kept
+//! for the next try, up to a limit.
+
+use anyhow::{anyhow, bail, Context, Result};
+use crate::config::{ShipConfig, ShipTarget};
+use crate::console::strip_ansi;
+use crate::daemon_log;
+use crate::http;
+use crate::records::{self, Level, Record};
+use crate::timestamps;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Lines kept while the service can't be reached, past which the oldest are dropped
+const MAX_PENDING: usize = 50_000;
+
+/// Where records are pushed
+#[derive(Clone)]
+pub struct Shipping {
+    to: ShipTarget,
+    url: String,
+    server: String,
+    index: String,
+    interval: Duration,
+    headers: Vec<String>,
+}
+
+impl Shipping {
+    pub fn from_config(config: &ShipConfig, server_dir: &Path) -> Result<Self> {
+        let interval = config
+            .interval
+            .as_deref()
+            .map(|interval| crate::parse_duration(interval).map_err(|e| anyhow!(e)))
+            .transpose()
+            .context("Invalid [log.ship] interval")?
+            .unwrap_or(Duration::from_secs(5));
+        Ok(Self {
+            to: config.to,
+            url: config.url.trim_end_matches('/').to_string(),
+            server: config.name.clone().unwrap_or_else(|| crate::server_name(server_dir)),
+            index: config.index.clone().unwrap_or_else(|| "mcwrap".to_string()),
+            interval,
+            headers: config
+                .headers
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect(),
+        })
+    }
+
+    /// Where the records go, e.g. "Loki at http://loki:3100"
+    pub fn describe(&self) -> String {
+        match self.to {
+            ShipTarget::Loki => format!("Loki at {}", self.url),
+            ShipTarget::Elasticsearch => format!("Elasticsearch at {}", self.url),
+        }
+    }
+
+    /// Push records, as a Loki push or an Elasticsearch bulk request
+    fn send(&self, records: &[(Record, DateTime<Utc>)]) -> Result<()> {
+        match self.to {
+            ShipTarget::Loki => {
+                let mut streams: BTreeMap<(Option<Level>, Option<&str>), Vec<Value>> =
+                    BTreeMap::new();
+                for (record, at) in records {
+                    let nanos = at.timestamp_nanos_opt().unwrap_or_default();
+                    streams
+                        .entry((record.level, record.logger.as_deref()))
+                        .or_default()
+                        .push(json!([nanos.to_string(), text(record)]));
+                }
+                let streams: Vec<Value> = streams
+                    .into_iter()
+                    .map(|((level, logger), values)| {
+                        let mut labels = Map::new();
+                        labels.insert("server".into(), json!(self.server));
+                        if let Some(level) = level {
+                            labels.insert("level".into(), json!(level));
+                        }
+                        if let Some(logger) = logger {
+                            labels.insert("logger".into(), json!(logger));
+                        }
+                        json!({ "stream": labels, "values": values })
+                    })
+                    .collect();
+                let body = json!({ "streams": streams }).to_string();
+                let url = format!("{}/loki/api/v1/push", self.url);
+                http::post_with_headers(&url, "application/json", &self.headers, body.as_bytes())?;
+            }
+            ShipTarget::Elasticsearch => {
+                let action = json!({ "index": { "_index": self.index } }).to_string();
+                let mut body = String::new();
+                for (record, at) in records {
+                    let mut document = json!(record);
+                    document["@timestamp"] = json!(at.to_rfc3339());
+                    document["server"] = json!(self.server);
+                    body.push_str(&action);
+                    body.push('\n');
+                    body.push_str(&document.to_string());
+                    body.push('\n');
+                }
+                let url = format!("{}/_bulk", self.url);
+                let content_type = "application/x-ndjson";
+                let response =
+                    http::post_with_headers(&url, content_type, &self.headers, body.as_bytes())?;
+                // A bulk request succeeds as a whole even when documents are refused
+                let response: Value = serde_json::from_slice(&response).unwrap_or_default();
+                if response["errors"] == json!(true) {
+                    let items = response["items"].as_array().into_iter().flatten();
+                    let error = items.filter_map(|item| item["index"]["error"].as_object()).next();
+                    match error.and_then(|error| error.get("reason")) {
+                        Some(reason) => bail!("Records refused: {}", reason),
+                        None => bail!("Records refused"),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+}
+
+/// Sends console output to the shipping thread
+pub struct Shipper {
+    sender: Sender<Vec<u8>>,
+    thread: JoinHandle<()>,
+}
+
+impl Shipper {
+    pub fn spawn(shipping: Shipping) -> Self {
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+        let thread = thread::spawn(move || {
+            let mut batch = Batch::new(shipping);
+            let mut next = Instant::now() + batch.shipping.interval;
+            loop {
+                match receiver.recv_timeout(next.saturating_duration_since(Instant::now())) {
+                    Ok(data) => batch.push(&data),
+                    Err(RecvTimeoutError::Timeout) => {
+                        batch.flush(false);
+                        next = Instant::now() + batch.shipping.interval;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            batch.flush(true);
+        });
+        Self { sender, thread }
+    }
+
+    /// Console output as it is written to the log, which may end mid-line
+    pub fn push(&self, data: &[u8]) {
+        self.sender.send(data.to_vec()).ok();
+    }
+
+    /// Send what is left, once the server has exited
+    pub fn finish(self) {
+        drop(self.sender);
+        self.thread.join().ok();
+    }
+}
+
+/// Lines waiting to be sent
+struct Batch {
+    shipping: Shipping,
+    /// Output after the last complete line
+    partial: Vec<u8>,
+    /// Complete lines, with when they were read
+    lines: Vec<(String, DateTime<Utc>)>,
+    /// Lines came in since the last flush
+    fresh: bool,
+    /// Set while sending fails, so that it is reported once
+    failing: bool,
+}
+
+impl Batch {
+    fn new(shipping: Shipping) -> Self {
+        Self {
+            shipping,
+            partial: Vec::new(),
+            lines: Vec::new(),
+            fresh: false,
+            failing: false,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.partial.extend_from_slice(data);
+        let now = Utc::now();
+        while let Some(end) = self.partial.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+            if !strip_ansi(&line).trim().is_empty() {
+                self.lines.push((line, now));
+                self.fresh = true;
+            }
+        }
+    }
+
+    /// Send the records read so far, except one that may still be getting its stack trace
+    /// unless this is the last flush or the console has gone quiet
+    fn flush(&mut self, last: bool) {
+        let mut records = records::parse(self.lines.iter().map(|(line, _)| line.as_str()));
+        if !last && self.fresh {
+            records.pop();
+        }
+        self.fresh = false;
+        if records.is_empty() {
+            return;
+        }
+        let mut shipped = 0;
+        let records: Vec<(Record, DateTime<Utc>)> = records
+            .into_iter()
+            .map(|record| {
+                let read_at = self.lines[shipped].1;
+                shipped += record.lines.len();
+                let at = record.time.as_deref().and_then(|time| {
+                    DateTime::parse_from_rfc3339(time).ok().map(|time| time.to_utc())
+                });
+                (record, at.unwrap_or(read_at))
+            })
+            .collect();
+        match self.shipping.send(&records) {
+            Ok(()) => {
+                self.lines.drain(..shipped);
+                if self.failing {
+                    let to = self.shipping.describe();
+                    daemon_log::info(format!("Shipping to {} works again", to));
+                    self.failing = false;
+                }
+            }
+            Err(e) => {
+                if !self.failing {
+                    let to = self.shipping.describe();
+                    daemon_log::error(format!("Failed to ship to {}: {:#}", to, e));
+                    self.failing = true;
+                }
+                if self.lines.len() > MAX_PENDING {
+                    let dropped = self.lines.len() - MAX_PENDING;
+                    self.lines.drain(..dropped);
+                    daemon_log::warn(format!("Dropped {} line(s) never shipped", dropped));
+                }
+            }
+        }
+    }
+}
+
+/// A record as it reads in the log, without colors or `[log] timestamps`
+fn text(record: &Record) -> String {
+    let lines = record.lines.iter().map(|line| {
+        let line = strip_ansi(line);
+        match timestamps::line_time(&line) {
+            Some(_) => line.split_once(' ').map_or(String::new(), |(_, rest)| rest.to_string()),
+            None => line,
+        }
+    });
+    lines.collect::<Vec<_>>().join("\n")
+}
@@ -0,0 +1,124 @@
+//! Running mcwrap on another machine over SSH
+//!
+//! `mcwrap --remote user@host:/srv/mc <subcommand> ...` execs
+//! `ssh user@host mcwrap <subcommand> /srv/mc ...`, inheriting stdio so the
+//! console stream of `attach`/`tail` is proxied by ssh itself. This runs
+//! before normal argument parsing because the server directory comes from
+//! the remote spec rather than the command line.
+
+use anyhow::{bail, Context, Result};
+use std::io::IsTerminal;
+use std::process::Command;
+
+/// Subcommands that need a terminal on the remote side
+const INTERACTIVE: &[&str] = &["attach"];
+
+/// If `--remote` was given, run the command over SSH and return its exit code
+///
+/// `cli` is mcwrap's command line, to tell where the server directory goes.
+pub fn forward_if_remote(cli: &clap::Command) -> Result<Option<i32>> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(spec) = take_remote(&mut args)? else {
+        return Ok(None);
+    };
+    let Some((host, dir)) = spec.split_once(':') else {
+        bail!("--remote expects user@host:dir");
+    };
+
+    let name = insert_dir(cli, &mut args, dir)?;
+
+    let mut ssh = Command::new("ssh");
+    if INTERACTIVE.contains(&name.as_str()) && std::io::stdin().is_terminal() {
+        ssh.arg("-t");
+    }
+    // ssh joins its arguments into one remote shell command, so quote each
+    let remote = std::iter::once("mcwrap".to_string())
+        .chain(args.iter().map(|arg| shell_quote(arg)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    ssh.arg(host).arg("--").arg(remote);
+
+    let status = ssh.status().context("Failed to run ssh")?;
+    Ok(Some(status.code().unwrap_or(255)))
+}
+
+/// Put the server directory where the subcommand takes it, returning the
+/// top-level subcommand's name
+///
+/// Subcommands such as `world list` are followed down to the one that runs,
+/// whose `dir` is given after the positional arguments before it.
+fn insert_dir(cli: &clap::Command, args: &mut Vec<String>, dir: &str) -> Result<String> {
+    let mut cmd = cli;
+    let mut idx = 0;
+    let mut top = None;
+    loop {
+        // Find the subcommand, skipping options and their values
+        while idx < args.len() && args[idx].starts_with('-') {
+            idx += if takes_value(cmd, &args[idx]) { 2 } else { 1 };
+        }
+        let Some(name) = args.get(idx) else {
+            match top {
+                // Left for the remote mcwrap to complain about
+                Some(_) => break,
+                None => bail!("--remote needs a subcommand"),
+            }
+        };
+        cmd = cmd
+            .find_subcommand(name)
+            .with_context(|| format!("Unknown subcommand {}", name))?;
+        top.get_or_insert_with(|| name.clone());
+        idx += 1;
+        if !cmd.has_subcommands() {
+            break;
+        }
+    }
+
+    let positionals: Vec<&str> = cmd.get_positionals().map(|arg| arg.get_id().as_str()).collect();
+    if let Some(position) = positionals.iter().position(|&id| id == "dir") {
+        let mut before = position;
+        while before > 0 && idx < args.len() {
+            if args[idx].starts_with('-') {
+                idx += if takes_value(cmd, &args[idx]) { 2 } else { 1 };
+            } else {
+                before -= 1;
+                idx += 1;
+            }
+        }
+        args.insert(idx.min(args.len()), dir.to_string());
+    }
+    Ok(top.unwrap())
+}
+
+/// Whether `arg` is one of `cmd`'s options that takes a separate value
+fn takes_value(cmd: &clap::Command, arg: &str) -> bool {
+    cmd.get_arguments().any(|option| {
+        let long = option.get_long().is_some_and(|long| arg == format!("--{}", long));
+        let short = option.get_short().is_some_and(|short| arg == format!("-{}", short));
+        (long || short) && option.get_action().takes_values()
+    })
+}
+
+/// Remove `--remote SPEC` / `--remote=SPEC` from the arguments, returning SPEC
+fn take_remote(args: &mut Vec<String>) -> Result<Option<String>> {
+    let Some(idx) = args.iter().position(|a| a == "--remote" || a.starts_with("--remote=")) else {
+        return Ok(None);
+    };
+    let arg = args.remove(idx);
+    if let Some(spec) = arg.strip_prefix("--remote=") {
+        return Ok(Some(spec.to_string()));
+    }
+    if idx >= args.len() {
+        bail!("--remote expects user@host:dir");
+    }
+    Ok(Some(args.remove(idx)))
+}
+
+/// Quote an argument for a POSIX shell
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:@,+".contains(c))
+    {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
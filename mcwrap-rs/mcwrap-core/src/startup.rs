@@ -0,0 +1,161 @@
+//! Recognising a server that failed to start
+//!
+//! The daemon watches console output until the server reports `Done (…s)!`,
+//! looking for messages that mean it can't come up. If the server then exits
+//! before it was ready, or soon after, the reason is saved to `failure.json`
+//! in the wrap dir for `status` and `start --wait` to report. It also notes
+//! whether the server is Bukkit or one of its forks, which answer `tps`.
+
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Exiting this soon after launch counts as failing to start, even once ready
+const EARLY_EXIT: Duration = Duration::from_secs(60);
+
+/// Console lines kept with a failure
+const TAIL_LINES: usize = 10;
+
+static READY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"Done \(\d+(\.\d+)?s\)!").unwrap());
+
+/// "This server is running Paper version 1.21.1-119-master@7cd4f2c (…)"
+static BUKKIT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"This server is running \S+ version").unwrap());
+
+static CLASS_VERSION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"class file version (\d+)").unwrap());
+
+/// Console messages that mean the server can't start, and what to report
+static FATAL: LazyLock<Vec<(Regex, &str)>> = LazyLock::new(|| {
+    [
+        (r"(?i)failed to bind to port|address already in use", "the server port is already in use"),
+        (
+            r"You need to agree to the EULA",
+            "the EULA has not been accepted (set eula=true in eula.txt)",
+        ),
+        (r"(?i)(exception|error|failed).*level\.dat", "the world's level.dat could not be read"),
+        (r"Unable to access jarfile", "the server JAR could not be opened"),
+        (
+            r"Could not reserve enough space|Invalid (maximum|initial) heap size",
+            "the JVM could not allocate its heap (check -Xmx)",
+        ),
+        (r"execvp failed", "the server command could not be run (is it installed and on PATH?)"),
+    ]
+    .into_iter()
+    .map(|(pattern, reason)| (Regex::new(pattern).unwrap(), reason))
+    .collect()
+});
+
+/// Whether a console line is the server reporting it has finished starting
+pub fn is_ready(line: &str) -> bool {
+    READY.is_match(line)
+}
+
+/// Why the server can't start, if a console line says so
+fn diagnose(line: &str) -> Option<String> {
+    if line.contains("UnsupportedClassVersionError") {
+        // Class file version 52 is Java 8, and each release adds one
+        let needed = CLASS_VERSION
+            .captures(line)
+            .and_then(|caps| caps[1].parse::<u32>().ok())
+            .map(|version| version.saturating_sub(44));
+        return Some(match needed {
+            Some(java) => format!("the server needs Java {} or newer", java),
+            None => "the server needs a newer Java".to_string(),
+        });
+    }
+    FATAL
+        .iter()
+        .find(|(pattern, _)| pattern.is_match(line))
+        .map(|(_, reason)| reason.to_string())
+}
+
+/// A failed start, as saved in the wrap dir
+#[derive(Serialize, Deserialize)]
+pub struct Failure {
+    pub reason: String,
+    pub exit_code: i32,
+    /// Unix time of the exit
+    pub at: u64,
+    /// Last console lines before the exit
+    pub tail: Vec<String>,
+}
+
+impl Failure {
+    pub fn load(path: &Path) -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Console output seen while the server starts
+#[derive(Default)]
+pub struct Watch {
+    partial: String,
+    ready: bool,
+    bukkit: bool,
+    reason: Option<String>,
+    tail: VecDeque<String>,
+}
+
+impl Watch {
+    /// Feed (log-filtered) console output
+    pub fn push(&mut self, output: &[u8]) {
+        self.partial.push_str(&String::from_utf8_lossy(output));
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            let line = crate::console::strip_ansi(line.trim_end_matches(['\r', '\n']));
+            if !self.ready {
+                self.ready = is_ready(&line);
+                self.bukkit |= BUKKIT.is_match(&line);
+                if self.reason.is_none() {
+                    self.reason = diagnose(&line);
+                }
+            }
+            self.tail.push_back(line);
+            if self.tail.len() > TAIL_LINES {
+                self.tail.pop_front();
+            }
+        }
+    }
+
+    /// Whether the server has reported that it finished starting
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Whether the server announced itself as Bukkit (Spigot, Paper…) while starting
+    pub fn is_bukkit(&self) -> bool {
+        self.bukkit
+    }
+
+    /// The failure to record for an exit, if it was one
+    pub fn failure(&self, exit_code: i32, uptime: Duration) -> Option<Failure> {
+        let reason = match &self.reason {
+            Some(reason) => reason.clone(),
+            None if exit_code == 0 => return None,
+            None if !self.ready => format!("exited with status {} before it was ready", exit_code),
+            None if uptime < EARLY_EXIT => format!(
+                "exited with status {} {}s after starting",
+                exit_code,
+                uptime.as_secs()
+            ),
+            None => return None,
+        };
+        Some(Failure {
+            reason,
+            exit_code,
+            at: crate::unix_now(),
+            tail: self.tail.iter().cloned().collect(),
+        })
+    }
+}
@@ -0,0 +1,40 @@
+//! Lifetime statistics of a server
+//!
+//! `stats.json` in the wrap dir counts starts and mcwrapd restarts and keeps
+//! the time of the last crash across runs, for `status` and `list --verbose`.
+//! Each start folds the previous run's exit into it before the state file is
+//! replaced.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Stats {
+    /// Times the server was started, including restarts
+    pub starts: u64,
+    /// Starts by mcwrapd after the server exited
+    pub restarts: u64,
+    /// Unix time of the last crash or out-of-memory exit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_crash: Option<u64>,
+}
+
+impl Stats {
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Change the saved statistics; they are informational, so failures are ignored
+    pub fn update(path: &Path, change: impl FnOnce(&mut Self)) {
+        let mut stats = Self::load(path);
+        change(&mut stats);
+        if let Ok(json) = serde_json::to_string_pretty(&stats) {
+            let _ = fs::write(path, json);
+        }
+    }
+}
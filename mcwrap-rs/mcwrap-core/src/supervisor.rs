@@ -0,0 +1,668 @@
+//! mcwrapd - central supervisor daemon
+//!
+//! An optional long-running process that starts each server's PTY daemon as
+//! its own child (`mcwrap start --foreground`), restarts it according to the
+//! server's `[supervisor]` config, performs scheduled daily restarts, and
+//! serves a JSON-lines control socket at `~/.mcwrap/mcwrapd.sock`. When
+//! mcwrapd is running, `mcwrap start` and `mcwrap stop` go through it.
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveTime};
+use crate::config::{Config, OomConfig, RestartPolicy};
+use crate::control::ControlClient;
+use crate::daemon_log::{self, Level};
+use crate::failure::{ErrorKind, Failure};
+use crate::hibernate;
+use crate::oom::{self, Oom};
+use crate::registry::Registry;
+use crate::stats::Stats;
+use crate::{is_running, ServerPaths};
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::process::Child;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+/// A run this long resets the consecutive failure count
+const STABLE_RUN: Duration = Duration::from_secs(600);
+
+/// How often to check whether a server waiting to restart has emptied
+const EMPTY_POLL: Duration = Duration::from_secs(15);
+
+/// Requests accepted on the mcwrapd socket
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    /// Start and supervise a server
+    Start { dir: PathBuf },
+    /// A graceful stop is about to be sent; don't restart the server
+    Stopping { dir: PathBuf },
+    /// The server is about to be stopped; start it again once it exits
+    Restarting { dir: PathBuf },
+    /// List supervised servers
+    Status,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<SupervisedInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SupervisedInfo {
+    pub dir: PathBuf,
+    pub state: String,
+    pub pid: Option<i32>,
+    pub restarts: u32,
+}
+
+/// Bookkeeping for one supervised server
+struct Supervised {
+    state: &'static str,
+    pid: Option<i32>,
+    restarts: u32,
+    stop_requested: bool,
+    restart_requested: bool,
+    hibernate_requested: bool,
+    /// Ends hibernation early
+    wake: Arc<Notify>,
+}
+
+type Servers = Arc<Mutex<HashMap<PathBuf, Supervised>>>;
+
+/// A running server instance
+enum Instance {
+    /// Started by us; the child is the PTY daemon
+    Child(Child, i32),
+    /// Already running when we found it; only its PID can be watched
+    Adopted(i32),
+}
+
+impl Instance {
+    fn pid(&self) -> i32 {
+        match self {
+            Instance::Child(_, pid) | Instance::Adopted(pid) => *pid,
+        }
+    }
+
+    /// Wait for the instance to exit, returning whether it exited cleanly
+    async fn wait(&mut self) -> bool {
+        match self {
+            Instance::Child(child, _) => child.wait().await.is_ok_and(|s| s.success()),
+            Instance::Adopted(pid) => {
+                while kill(Pid::from_raw(*pid), None).is_ok() {
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                }
+                // Exit status of a process we didn't spawn is unknown
+                false
+            }
+        }
+    }
+}
+
+/// Path of the mcwrapd control socket
+pub fn socket_path() -> PathBuf {
+    crate::wrap_base().join("mcwrapd.sock")
+}
+
+/// Send a request to mcwrapd, returning None if it isn't running
+pub async fn request(req: &Request) -> Result<Option<Response>> {
+    let Ok(stream) = UnixStream::connect(socket_path()).await else {
+        return Ok(None);
+    };
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = serde_json::to_string(req)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    let mut response = String::new();
+    BufReader::new(reader).read_line(&mut response).await?;
+    let response: Response =
+        serde_json::from_str(&response).context("Invalid response from mcwrapd")?;
+    if let Some(error) = response.error {
+        bail!(error);
+    }
+    Ok(Some(response))
+}
+
+/// Send a request to mcwrapd from a thread without a runtime, like the PTY daemon's
+pub fn request_blocking(req: &Request) -> Result<Option<Response>> {
+    let Ok(mut stream) = std::os::unix::net::UnixStream::connect(socket_path()) else {
+        return Ok(None);
+    };
+    let mut line = serde_json::to_string(req)?;
+    line.push('\n');
+    std::io::Write::write_all(&mut stream, line.as_bytes())?;
+
+    let mut response = String::new();
+    std::io::BufRead::read_line(&mut std::io::BufReader::new(stream), &mut response)?;
+    let response: Response =
+        serde_json::from_str(&response).context("Invalid response from mcwrapd")?;
+    if let Some(error) = response.error {
+        bail!(error);
+    }
+    Ok(Some(response))
+}
+
+/// Whether mcwrapd is holding the server's port while it hibernates
+pub async fn is_hibernating(dir: &Path) -> Result<bool> {
+    let Some(response) = request(&Request::Status).await? else {
+        return Ok(false);
+    };
+    Ok(response
+        .servers
+        .iter()
+        .any(|s| s.dir == dir && s.state == "hibernating"))
+}
+
+/// Print the servers supervised by a running mcwrapd
+pub async fn cmd_status() -> Result<()> {
+    let Some(response) = request(&Request::Status).await? else {
+        println!("mcwrapd is not running.");
+        return Ok(());
+    };
+
+    println!("mcwrapd running");
+    if response.servers.is_empty() {
+        println!("  No supervised servers.");
+    }
+    for server in response.servers {
+        let pid = server.pid.map_or("-".to_string(), |p| p.to_string());
+        println!(
+            "  {} {} (PID: {}, restarts: {})",
+            server.state,
+            server.dir.display(),
+            pid,
+            server.restarts
+        );
+    }
+    Ok(())
+}
+
+/// Run the supervisor in the foreground
+pub async fn run() -> Result<()> {
+    let path = socket_path();
+    if UnixStream::connect(&path).await.is_ok() {
+        bail!("mcwrapd is already running");
+    }
+    let _ = fs::remove_file(&path);
+    fs::create_dir_all(crate::wrap_base())?;
+    let listener = UnixListener::bind(&path).context("Failed to bind mcwrapd socket")?;
+    println!("mcwrapd listening on {:?}", path);
+
+    let servers: Servers = Arc::new(Mutex::new(HashMap::new()));
+
+    // Bring up boot-enabled servers, dependencies first
+    let registry = Registry::load()?;
+    for entry in registry.boot_order()? {
+        match supervise(&servers, entry.dir.clone()).await {
+            Ok(pid) => println!("Supervising {} (PID {})", entry.dir.display(), pid),
+            Err(e) => eprintln!("Failed to start {}: {:#}", entry.dir.display(), e),
+        }
+    }
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                tokio::spawn(handle_client(stream, servers.clone()));
+            }
+            _ = sigterm.recv() => break,
+            _ = sigint.recv() => break,
+        }
+    }
+
+    // Servers keep running; a restarted mcwrapd adopts them again
+    let _ = fs::remove_file(&path);
+    println!("mcwrapd exiting");
+    Ok(())
+}
+
+/// Serve requests from one socket client
+async fn handle_client(stream: UnixStream, servers: Servers) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(req) => handle_request(req, &servers).await,
+            Err(e) => Err(e.into()),
+        };
+        let response = response.unwrap_or_else(|e| Response {
+            error: Some(format!("{:#}", e)),
+            ..Default::default()
+        });
+
+        let Ok(mut out) = serde_json::to_string(&response) else {
+            break;
+        };
+        out.push('\n');
+        if writer.write_all(out.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn handle_request(req: Request, servers: &Servers) -> Result<Response> {
+    match req {
+        Request::Start { dir } => {
+            let pid = supervise(servers, dir).await?;
+            Ok(Response {
+                ok: true,
+                pid: Some(pid),
+                ..Default::default()
+            })
+        }
+        Request::Stopping { dir } => {
+            if let Some(server) = servers.lock().unwrap().get_mut(&dir) {
+                server.stop_requested = true;
+                server.wake.notify_one();
+            }
+            Ok(Response {
+                ok: true,
+                ..Default::default()
+            })
+        }
+        Request::Restarting { dir } => {
+            let mut servers = servers.lock().unwrap();
+            let server = servers.get_mut(&dir).context("Server is not supervised by mcwrapd")?;
+            server.restart_requested = true;
+            Ok(Response {
+                ok: true,
+                ..Default::default()
+            })
+        }
+        Request::Status => {
+            let servers = servers.lock().unwrap();
+            let mut list: Vec<_> = servers
+                .iter()
+                .map(|(dir, s)| SupervisedInfo {
+                    dir: dir.clone(),
+                    state: s.state.to_string(),
+                    pid: s.pid,
+                    restarts: s.restarts,
+                })
+                .collect();
+            list.sort_by(|a, b| a.dir.cmp(&b.dir));
+            Ok(Response {
+                ok: true,
+                servers: list,
+                ..Default::default()
+            })
+        }
+    }
+}
+
+/// Start supervising a server, returning its PID once it is up
+async fn supervise(servers: &Servers, dir: PathBuf) -> Result<i32> {
+    let hibernating = servers
+        .lock()
+        .unwrap()
+        .get(&dir)
+        .filter(|s| s.state == "hibernating")
+        .map(|s| s.wake.clone());
+    if let Some(wake) = hibernating {
+        wake.notify_one();
+        return wait_for_wake(servers, &dir).await;
+    }
+
+    if servers
+        .lock()
+        .unwrap()
+        .get(&dir)
+        .is_some_and(|s| s.pid.is_some())
+    {
+        bail!(Failure::already_running());
+    }
+
+    let instance = launch(&dir).await?;
+    let pid = instance.pid();
+    let restarts = servers.lock().unwrap().get(&dir).map_or(0, |s| s.restarts);
+    servers.lock().unwrap().insert(
+        dir.clone(),
+        Supervised {
+            state: "running",
+            pid: Some(pid),
+            restarts,
+            stop_requested: false,
+            restart_requested: false,
+            hibernate_requested: false,
+            wake: Arc::new(Notify::new()),
+        },
+    );
+
+    tokio::spawn(monitor(servers.clone(), dir, instance));
+    Ok(pid)
+}
+
+/// Wait for a server woken from hibernation to be running again
+async fn wait_for_wake(servers: &Servers, dir: &Path) -> Result<i32> {
+    for _ in 0..150 {
+        let server = servers.lock().unwrap().get(dir).map(|s| (s.state, s.pid));
+        match server {
+            Some(("running", Some(pid))) => return Ok(pid),
+            Some(("hibernating" | "running" | "backoff", _)) => {}
+            _ => {
+                let message = "Server failed to wake from hibernation";
+                bail!(Failure::new(ErrorKind::StartFailed, message))
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    bail!(Failure::new(ErrorKind::Timeout, "Timed out waiting for the server to wake"))
+}
+
+/// Start the server as a child, or adopt it if it is already running
+async fn launch(dir: &Path) -> Result<Instance> {
+    let paths = ServerPaths::new(dir);
+    if let Some(state) = is_running(&paths) {
+        return Ok(Instance::Adopted(state.pid));
+    }
+
+    let registry = Registry::load()?;
+    let entry = registry.get(dir);
+
+    let mut cmd = tokio::process::Command::new(std::env::current_exe()?);
+    cmd.arg("start").arg("--foreground");
+    match entry {
+        Some(entry) => cmd.args(entry.start_args()),
+        None => cmd.arg(dir),
+    };
+    let mut child = cmd.stdin(Stdio::null()).spawn().context("Failed to spawn mcwrap")?;
+
+    // Wait for the server to record its PID
+    for _ in 0..100 {
+        if let Some(state) = is_running(&paths) {
+            return Ok(Instance::Child(child, state.pid));
+        }
+        if let Some(status) = child.try_wait()? {
+            if let Some(failure) = crate::startup::Failure::load(&paths.failure_file) {
+                let message = format!("Server failed to start: {}", failure.reason);
+                bail!(Failure::new(ErrorKind::StartFailed, message));
+            }
+            let message = format!("Server exited during startup ({})", status);
+            bail!(Failure::new(ErrorKind::StartFailed, message));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    child.kill().await.ok();
+    bail!(Failure::new(ErrorKind::Timeout, "Timed out waiting for the server to start"))
+}
+
+/// Watch a server until it stops for good, restarting it as configured
+async fn monitor(servers: Servers, dir: PathBuf, mut instance: Instance) {
+    let config = Config::load(&dir).unwrap_or_else(|e| {
+        eprintln!("{}: {:#}, using defaults", dir.display(), e);
+        Config::default()
+    });
+    let policy = &config.supervisor;
+    let restart_at = policy
+        .restart_at
+        .as_deref()
+        .and_then(|t| NaiveTime::parse_from_str(t, "%H:%M").ok());
+    let max_delay = Duration::from_secs(policy.restart_max_delay_mins * 60);
+    let idle_limit = config.hibernate.idle_mins.map(|mins| Duration::from_secs(mins * 60));
+    let mut failures = 0;
+
+    let paths = ServerPaths::new(&dir);
+    loop {
+        let started = Instant::now();
+        let started_at = crate::unix_now();
+
+        // Wait for exit, triggering the scheduled restart if it comes first
+        let success = loop {
+            let until_restart = restart_at.map_or(Duration::MAX, time_until);
+            let scheduled = async {
+                tokio::time::sleep(until_restart).await;
+                if policy.restart_when_empty {
+                    wait_until_empty(&dir, max_delay).await;
+                }
+            };
+            let idle = async {
+                match idle_limit {
+                    Some(limit) => wait_until_idle(&dir, limit).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                success = instance.wait() => break success,
+                _ = idle => {
+                    println!("{} has been empty for a while, hibernating", dir.display());
+                    set_flag(&servers, &dir, |s| s.hibernate_requested = true);
+                    if let Err(e) = crate::cmd_send(&dir, "stop").await {
+                        eprintln!("{}: failed to send stop: {:#}", dir.display(), e);
+                    }
+                }
+                _ = scheduled => {
+                    println!("Scheduled restart of {}", dir.display());
+                    set_flag(&servers, &dir, |s| s.restart_requested = true);
+                    if let Err(e) = crate::cmd_send(&dir, "stop").await {
+                        eprintln!("{}: failed to send stop: {:#}", dir.display(), e);
+                    }
+                    // Don't fire again for the same minute
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+            }
+        };
+
+        let (stop_requested, mut restart_requested, hibernate_requested, wake) = {
+            let mut map = servers.lock().unwrap();
+            let Some(server) = map.get_mut(&dir) else {
+                return;
+            };
+            server.pid = None;
+            let flags = (
+                server.stop_requested,
+                server.restart_requested,
+                server.hibernate_requested,
+                server.wake.clone(),
+            );
+            server.stop_requested = false;
+            server.restart_requested = false;
+            server.hibernate_requested = false;
+            flags
+        };
+
+        if hibernate_requested && !stop_requested {
+            println!("{} hibernating", dir.display());
+            set_flag(&servers, &dir, |s| s.state = "hibernating");
+            tokio::select! {
+                slept = hibernate::sleep_until_join(&dir, &config.hibernate) => {
+                    if let Err(e) = slept {
+                        eprintln!("{}: {:#}", dir.display(), e);
+                    }
+                }
+                _ = wake.notified() => {}
+            }
+            // `mcwrap stop` ends hibernation for good
+            let stopped = servers
+                .lock()
+                .unwrap()
+                .get_mut(&dir)
+                .is_some_and(|s| std::mem::take(&mut s.stop_requested));
+            if stopped {
+                println!("{} stopped", dir.display());
+                set_flag(&servers, &dir, |s| s.state = "stopped");
+                return;
+            }
+            println!("Waking {}", dir.display());
+            restart_requested = true;
+        }
+
+        // Only an out-of-memory error during this run counts
+        let oom = Oom::load(&paths.oom_file).filter(|oom| oom.at >= started_at);
+        let oom_restart = oom.is_some() && config.oom.restart;
+        if let Some(oom) = &oom {
+            println!("{} ran out of memory: {}", dir.display(), oom.message);
+        }
+        if oom_restart && !stop_requested && config.oom.grow_heap_mb > 0 {
+            match grow_heap(&dir, &config.oom) {
+                Ok(Some(mb)) => println!("Raised the heap of {} to {}M", dir.display(), mb),
+                Ok(None) => println!("{} is already at its maximum heap", dir.display()),
+                Err(e) => eprintln!("{}: failed to raise the heap: {:#}", dir.display(), e),
+            }
+        }
+
+        let by_policy = match policy.restart {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always => true,
+        };
+        let restart = restart_requested || (!stop_requested && (oom_restart || by_policy));
+        if !restart {
+            println!("{} stopped", dir.display());
+            set_flag(&servers, &dir, |s| s.state = "stopped");
+            return;
+        }
+
+        if started.elapsed() >= STABLE_RUN || restart_requested {
+            failures = 0;
+        }
+
+        // Relaunch with exponential backoff on repeated failures
+        instance = loop {
+            if !success || failures > 0 {
+                failures += 1;
+                if policy.max_restarts > 0 && failures > policy.max_restarts {
+                    eprintln!("{}: giving up after {} restarts", dir.display(), failures - 1);
+                    set_flag(&servers, &dir, |s| s.state = "failed");
+                    return;
+                }
+                let delay = Duration::from_secs(5 << (failures - 1).min(6));
+                println!("{} exited, restarting in {}s", dir.display(), delay.as_secs());
+                set_flag(&servers, &dir, |s| s.state = "backoff");
+                tokio::time::sleep(delay).await;
+            }
+
+            match launch(&dir).await {
+                Ok(instance) => break instance,
+                Err(e) => {
+                    eprintln!("{}: restart failed: {:#}", dir.display(), e);
+                    failures = failures.max(1);
+                }
+            }
+        };
+
+        let pid = instance.pid();
+        println!("Restarted {} (PID {})", dir.display(), pid);
+        let paths = ServerPaths::new(&dir);
+        let message = format!("Restarted by mcwrapd (PID {})", pid);
+        daemon_log::append(&paths.daemon_log, Level::Info, message);
+        Stats::update(&paths.stats_file, |stats| stats.restarts += 1);
+        set_flag(&servers, &dir, |s| {
+            s.state = "running";
+            s.pid = Some(pid);
+            s.restarts += 1;
+        });
+    }
+}
+
+/// Wait until nobody has been online for `limit`
+async fn wait_until_idle(dir: &Path, limit: Duration) {
+    let paths = ServerPaths::new(dir);
+    let mut empty_since = Instant::now();
+    loop {
+        tokio::time::sleep(EMPTY_POLL).await;
+        // An unknown player count doesn't count as empty
+        if online_players(&paths).await != Some(0) {
+            empty_since = Instant::now();
+        } else if empty_since.elapsed() >= limit {
+            return;
+        }
+    }
+}
+
+/// Wait until nobody is online, for at most `max_delay`
+async fn wait_until_empty(dir: &Path, max_delay: Duration) {
+    let paths = ServerPaths::new(dir);
+    let deadline = Instant::now() + max_delay;
+    let mut deferred = false;
+    loop {
+        // Without a daemon to ask, there is no player count to wait on
+        let Some(online) = online_players(&paths).await else {
+            return;
+        };
+        if online == 0 {
+            return;
+        }
+        if Instant::now() >= deadline {
+            println!("{}: restarting with {} still online", dir.display(), online);
+            return;
+        }
+        if !deferred {
+            println!("{}: restart deferred until empty ({} online)", dir.display(), online);
+            deferred = true;
+        }
+        tokio::time::sleep(EMPTY_POLL).await;
+    }
+}
+
+/// Number of players online, as tracked by the server's daemon
+async fn online_players(paths: &ServerPaths) -> Option<usize> {
+    let mut control = ControlClient::connect(paths).await.ok()??;
+    let status = control.call("status", serde_json::json!({})).await.ok()?;
+    Some(status["players"].as_array()?.len())
+}
+
+/// Raise `-Xmx` in the server's registered Java arguments, returning the new size
+fn grow_heap(dir: &Path, config: &OomConfig) -> Result<Option<u64>> {
+    let mut registry = Registry::load()?;
+    let entry = registry.entry(dir);
+    let server_config = Config::load(dir)?;
+    if entry.exec.is_some() || !server_config.command.is_empty() {
+        bail!("The server runs a custom command, whose heap mcwrap can't change");
+    }
+    if entry.java_args.is_empty() {
+        let java = &server_config.java;
+        entry.java_args = match crate::find_forge_args(dir) {
+            Some(args_file) => crate::forge_java_args(dir, &args_file, java),
+            None => {
+                let jar = crate::find_jar(dir)?;
+                crate::default_java_args(&jar.file_name().unwrap().to_string_lossy(), java)
+            }
+        };
+    }
+    let current = oom::max_heap_mb(&entry.java_args).context("No -Xmx in the Java arguments")?;
+    let grown = (current + config.grow_heap_mb).min(config.max_heap_mb.unwrap_or(u64::MAX));
+    if grown <= current {
+        return Ok(None);
+    }
+    oom::set_max_heap_mb(&mut entry.java_args, grown);
+    registry.save()?;
+    Ok(Some(grown))
+}
+
+fn set_flag(servers: &Servers, dir: &Path, update: impl FnOnce(&mut Supervised)) {
+    if let Some(server) = servers.lock().unwrap().get_mut(dir) {
+        update(server);
+    }
+}
+
+/// Time until the next occurrence of a local wall-clock time
+fn time_until(at: NaiveTime) -> Duration {
+    let now = Local::now().naive_local();
+    let mut next = now.date().and_time(at);
+    if next <= now {
+        next += chrono::Duration::days(1);
+    }
+    (next - now).to_std().unwrap_or_default()
+}
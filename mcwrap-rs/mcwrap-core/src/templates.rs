@@ -0,0 +1,142 @@
+//! Templates for setting up identical servers
+//!
+//! `mcwrap init --template lobby lobbies/lobby3` takes the answers `init`
+//! would ask for from `~/.config/mcwrap/templates/lobby.toml`:
+//!
+//! ```toml
+//! type = "paper"
+//! version = "1.21.4"
+//! memory = "2G"
+//! port = "auto"
+//! java_flags = ["-XX:+UseG1GC", "-XX:MaxGCPauseMillis=200"]
+//! plugins = ["https://example.com/ViaVersion.jar", "/srv/shared/LuckPerms.jar"]
+//!
+//! # server.properties
+//! [properties]
+//! max-players = 50
+//! motd = "Lobby"
+//!
+//! # The server's mcwrap.toml
+//! [config]
+//! supervisor = { restart = "always" }
+//! ```
+//!
+//! Instead of `type` and `version`, `jar` can give the server JAR itself (a
+//! path or URL). Plugins go to `plugins/` (`mods/` for Fabric). Whatever is in
+//! the `lobby/` directory next to the file, such as plugin configs, is copied
+//! into the new server last. Relative paths are relative to the templates
+//! directory. Flags given to `init` win over the template.
+
+use anyhow::{bail, Context, Result};
+use crate::init::ServerType;
+use crate::ports::PortChoice;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Template {
+    #[serde(rename = "type")]
+    pub server_type: Option<ServerType>,
+    pub version: Option<String>,
+    /// Server JAR to use instead of downloading one (path or URL)
+    pub jar: Option<String>,
+    pub memory: Option<String>,
+    /// A port, or "auto" for the next free one
+    pub port: Option<PortChoice>,
+    /// Extra JVM flags, written to `[java] flags`
+    pub java_flags: Vec<String>,
+    /// Plugin (or mod) JARs, as paths or URLs
+    pub plugins: Vec<String>,
+    /// Settings for `server.properties`
+    pub properties: BTreeMap<String, toml::Value>,
+    /// The server's `mcwrap.toml`
+    pub config: toml::Table,
+    /// Directory copied into the server, if it exists
+    #[serde(skip)]
+    pub files: PathBuf,
+}
+
+impl Template {
+    /// Where templates are kept
+    pub fn dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("mcwrap").join("templates"))
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        let dir = Self::dir().context("No config directory")?;
+        if name.contains('/') || name.starts_with('.') {
+            bail!("Invalid template name {:?}", name);
+        }
+        let path = dir.join(format!("{}.toml", name));
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let available = list(&dir);
+                if available.is_empty() {
+                    bail!("No template {:?} (templates go in {})", name, dir.display());
+                }
+                bail!("No template {:?} (available: {})", name, available.join(", "));
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        let mut template: Self =
+            toml::from_str(&content).with_context(|| format!("Invalid {:?}", path))?;
+        template.files = dir.join(name);
+        Ok(template)
+    }
+
+    /// Resolve a path or URL from the template, relative to the templates directory
+    pub fn source(&self, source: &str) -> String {
+        if source.contains("://") {
+            return source.to_string();
+        }
+        let base = self.files.parent().unwrap_or(Path::new("/"));
+        base.join(source).to_string_lossy().into_owned()
+    }
+}
+
+/// Names of the templates in a directory
+fn list(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "toml" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Copy a directory's contents into another, recursively, leaving out what `skip` picks
+///
+/// Symlinks are copied as symlinks.
+pub fn copy_tree(from: &Path, to: &Path, skip: &dyn Fn(&Path) -> bool) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("Failed to create {:?}", to))?;
+    for entry in fs::read_dir(from).with_context(|| format!("Failed to read {:?}", from))? {
+        let entry = entry?;
+        let path = entry.path();
+        if skip(&path) {
+            continue;
+        }
+        let dest = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree(&path, &dest, skip)?;
+        } else if file_type.is_symlink() {
+            let _ = fs::remove_file(&dest);
+            std::os::unix::fs::symlink(fs::read_link(&path)?, &dest)
+                .with_context(|| format!("Failed to copy {:?}", path))?;
+        } else {
+            fs::copy(&path, &dest).with_context(|| format!("Failed to copy {:?}", path))?;
+        }
+    }
+    Ok(())
+}
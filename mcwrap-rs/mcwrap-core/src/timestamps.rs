@@ -0,0 +1,113 @@
+//! Timestamps on the lines of the console log
+//!
+//! Servers print only the time of day, if anything. With `[log] timestamps
+//! = true` the daemon starts every line it writes to `console.log` with the
+//! full date and time, e.g. `2024-05-01T12:00:00.123+02:00`, in the zone set
+//! by `[log] timezone`. `mcwrap log --since` reads them back to show only
+//! what was logged after a given time.
+
+use anyhow::{bail, Context, Result};
+use crate::config::LogConfig;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+/// Format of the timestamps, RFC 3339 with milliseconds
+const FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+/// Formats `--since` takes besides RFC 3339 and durations, in the log's time zone
+const SINCE_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"];
+
+/// The time zone of the timestamps
+#[derive(Clone, Copy)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl Zone {
+    pub fn from_config(config: &LogConfig) -> Result<Self> {
+        match config.timezone.as_deref() {
+            None | Some("local") => Ok(Zone::Local),
+            Some("UTC" | "utc" | "Z") => Ok(Zone::Fixed(FixedOffset::east_opt(0).unwrap())),
+            Some(offset) => match offset.parse() {
+                Ok(offset) => Ok(Zone::Fixed(offset)),
+                Err(_) => bail!(
+                    "Invalid [log] timezone {:?} (use \"local\", \"UTC\" or e.g. \"+02:00\")",
+                    offset
+                ),
+            },
+        }
+    }
+
+    pub fn now(self) -> String {
+        match self {
+            Zone::Local => Local::now().format(FORMAT).to_string(),
+            Zone::Fixed(offset) => Utc::now().with_timezone(&offset).format(FORMAT).to_string(),
+        }
+    }
+
+    fn localize(self, time: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Zone::Local => Some(Local.from_local_datetime(&time).earliest()?.fixed_offset()),
+            Zone::Fixed(offset) => offset.from_local_datetime(&time).single(),
+        }
+    }
+}
+
+/// Puts a timestamp at the start of each line of console output, across reads that may end
+/// mid-line
+pub struct Stamper {
+    zone: Zone,
+    line_start: bool,
+}
+
+impl Stamper {
+    pub fn new(zone: Zone) -> Self {
+        Self {
+            zone,
+            line_start: true,
+        }
+    }
+
+    pub fn stamp(&mut self, data: &[u8]) -> Vec<u8> {
+        let now = self.zone.now();
+        let mut result = Vec::with_capacity(data.len() + now.len() + 1);
+        for line in data.split_inclusive(|&byte| byte == b'\n') {
+            if self.line_start {
+                result.extend_from_slice(now.as_bytes());
+                result.push(b' ');
+            }
+            result.extend_from_slice(line);
+            self.line_start = line.ends_with(b"\n");
+        }
+        result
+    }
+}
+
+/// The timestamp a log line starts with, if it has one
+pub fn line_time(line: &str) -> Option<DateTime<FixedOffset>> {
+    let (stamp, _) = line.split_once(' ')?;
+    DateTime::parse_from_rfc3339(stamp).ok()
+}
+
+/// The time `--since` means: a date and time in the log's time zone (e.g. "2024-05-01 12:00"),
+/// a date, an RFC 3339 timestamp, or a duration ago (e.g. "2h")
+pub fn parse_since(since: &str, zone: Zone) -> Result<DateTime<FixedOffset>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(since) {
+        return Ok(time);
+    }
+    if let Ok(ago) = crate::parse_duration(since) {
+        let ago = chrono::Duration::from_std(ago).context("Duration too long")?;
+        return Ok((Utc::now() - ago).fixed_offset());
+    }
+    let time = SINCE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(since, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(since, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0));
+    match time.and_then(|time| zone.localize(time)) {
+        Some(time) => Ok(time),
+        None => bail!(
+            "Invalid time {:?} (use e.g. \"2024-05-01 12:00\", \"2024-05-01\" or \"2h\")",
+            since
+        ),
+    }
+}
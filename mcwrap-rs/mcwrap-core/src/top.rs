@@ -0,0 +1,469 @@
+//! Live dashboard of all managed servers
+//!
+//! `mcwrap top` shows every registered server with its state, CPU and memory
+//! use, TPS, player count and last console line, refreshing every couple of
+//! seconds. Keys act on the selected server: `a` attaches to its console, `r`
+//! restarts it and `s` stops it once confirmed with `y`, each by running
+//! mcwrap itself.
+//!
+//! CPU and memory are summed over the server's process group, so wrapper
+//! scripts and their JVM count together. TPS is asked of the daemons of
+//! Bukkit servers (Spigot, Paper and their forks) every half minute, through
+//! a control call that leaves the audit log and command history alone; a
+//! server whose answer can't be read isn't asked again until it restarts.
+
+use anyhow::{Context, Result};
+use crate::console::strip_ansi;
+use crate::control::ControlClient;
+use crate::platform;
+use crate::registry::{Registry, RegistryEntry};
+use crate::supervisor::{self, Request};
+use crate::{format_uptime, is_running, jsonl, read_state, unix_now, ServerPaths};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use regex::Regex;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+/// How often the servers are looked at
+const REFRESH: Duration = Duration::from_secs(2);
+
+/// How often TPS is asked for
+const TPS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How much of the end of the console log is searched for the last line
+const TAIL_BYTES: u64 = 4096;
+
+/// Paper and Spigot: "TPS from last 1m, 5m, 15m: 20.0, 20.0, 20.0"
+static TPS_LINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"TPS from last 1m, 5m, 15m: (?:§.)*\*?([\d.]+)").unwrap());
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Health {
+    Running,
+    Hibernating,
+    Stopped,
+    Crashed,
+}
+
+/// One line of the dashboard
+#[derive(Clone)]
+struct ServerRow {
+    entry: RegistryEntry,
+    name: String,
+    health: Health,
+    state: String,
+    pid: Option<i32>,
+    cpu: Option<f64>,
+    memory: Option<u64>,
+    tps: Option<f64>,
+    players: Option<usize>,
+    uptime: Option<u64>,
+    last_line: String,
+}
+
+/// The last TPS reading of a server
+struct TpsReading {
+    pid: i32,
+    at: Instant,
+    /// None when the answer to `tps` couldn't be read
+    tps: Option<f64>,
+}
+
+/// Gathers the rows, keeping what it needs between refreshes
+#[derive(Default)]
+struct Collector {
+    clients: HashMap<PathBuf, ControlClient>,
+    /// CPU time of each server's process group at the previous refresh
+    cpu: HashMap<PathBuf, (Duration, Instant)>,
+    tps: HashMap<PathBuf, TpsReading>,
+}
+
+impl Collector {
+    async fn collect(&mut self) -> Vec<ServerRow> {
+        let registry = Registry::load().unwrap_or_default();
+        let supervised = match supervisor::request(&Request::Status).await {
+            Ok(Some(response)) => response.servers,
+            _ => Vec::new(),
+        };
+
+        let mut rows = Vec::new();
+        for entry in registry.servers {
+            let paths = ServerPaths::new(&entry.dir);
+            let name = entry.dir.file_name().map_or_else(
+                || entry.dir.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+            let mut row = ServerRow {
+                name,
+                health: Health::Stopped,
+                state: "not running".to_string(),
+                pid: None,
+                cpu: None,
+                memory: None,
+                tps: None,
+                players: None,
+                uptime: None,
+                last_line: last_line(&paths.log_file),
+                entry,
+            };
+
+            if let Some(state) = is_running(&paths) {
+                row.health = Health::Running;
+                row.state = "running".to_string();
+                row.pid = Some(state.pid);
+                row.uptime = Some(unix_now().saturating_sub(state.started_at));
+                if let Some((cpu, memory)) = group_usage(state.pid) {
+                    row.memory = Some(memory);
+                    row.cpu = self.cpu_percent(&row.entry.dir, cpu);
+                }
+                if state.pty_master.is_some() {
+                    self.query(&paths, state.pid, &mut row).await;
+                }
+            } else {
+                self.clients.remove(&row.entry.dir);
+                self.cpu.remove(&row.entry.dir);
+                let hibernating = supervised
+                    .iter()
+                    .any(|s| s.dir == row.entry.dir && s.state == "hibernating");
+                if hibernating {
+                    row.health = Health::Hibernating;
+                    row.state = "hibernating".to_string();
+                } else if let Some(state) = read_state(&paths).filter(|s| s.exited_at.is_some()) {
+                    if state.crashed_at().is_some() {
+                        row.health = Health::Crashed;
+                    }
+                    row.state = state.describe_exit();
+                }
+            }
+            rows.push(row);
+        }
+        rows
+    }
+
+    /// CPU use since the previous refresh, in percent of one core
+    fn cpu_percent(&mut self, dir: &Path, cpu: Duration) -> Option<f64> {
+        let now = Instant::now();
+        let (previous, at) = self.cpu.insert(dir.to_path_buf(), (cpu, now))?;
+        let elapsed = now.duration_since(at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        Some(cpu.saturating_sub(previous).as_secs_f64() / elapsed * 100.0)
+    }
+
+    /// Players and TPS from the daemon's control socket
+    async fn query(&mut self, paths: &ServerPaths, pid: i32, row: &mut ServerRow) {
+        let dir = &row.entry.dir;
+        if !self.clients.contains_key(dir) {
+            let Ok(Some(client)) = ControlClient::connect(paths).await else {
+                return;
+            };
+            self.clients.insert(dir.clone(), client);
+        }
+        let client = self.clients.get_mut(dir).unwrap();
+
+        let bukkit = match client.call("status", json!({})).await {
+            Ok(status) => {
+                row.players = status["players"].as_array().map(Vec::len);
+                status["bukkit"].as_bool().unwrap_or(false)
+            }
+            Err(_) => {
+                self.clients.remove(dir);
+                return;
+            }
+        };
+
+        let reading = self.tps.get(dir).filter(|reading| reading.pid == pid);
+        let due = match reading {
+            None => true,
+            Some(reading) => reading.tps.is_some() && reading.at.elapsed() >= TPS_INTERVAL,
+        };
+        if due && bukkit {
+            let tps = match client.call("tps", json!({})).await {
+                Ok(reply) => {
+                    let output = strip_ansi(reply["output"].as_str().unwrap_or_default());
+                    TPS_LINE.captures(&output).and_then(|caps| caps[1].parse().ok())
+                }
+                Err(_) => None,
+            };
+            let reading = TpsReading {
+                pid,
+                at: Instant::now(),
+                tps,
+            };
+            self.tps.insert(dir.clone(), reading);
+        }
+        row.tps = self.tps.get(dir).and_then(|reading| reading.tps);
+    }
+}
+
+/// CPU time and resident memory (bytes) of every process in a process group,
+/// or of the process `pgid` when it leads none
+pub fn group_usage(pgid: i32) -> Option<(Duration, u64)> {
+    let mut found = false;
+    let (mut cpu, mut memory) = (Duration::ZERO, 0);
+    for process in platform::processes().ok()? {
+        if process.pgid == pgid || process.pid == pgid {
+            cpu += process.cpu;
+            memory += process.memory;
+            found = true;
+        }
+    }
+    found.then_some((cpu, memory))
+}
+
+/// The last non-empty line of the console log
+fn last_line(log_file: &Path) -> String {
+    let Ok(mut file) = File::open(log_file) else {
+        return String::new();
+    };
+    let len = file.metadata().map_or(0, |m| m.len());
+    if file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES))).is_err() {
+        return String::new();
+    }
+    let mut tail = Vec::new();
+    let _ = file.read_to_end(&mut tail);
+    String::from_utf8_lossy(&tail)
+        .lines()
+        .map(|line| strip_ansi(&jsonl::render(line)).trim_matches(['\r', ' ', '>']).to_string())
+        .rfind(|line| !line.is_empty())
+        .unwrap_or_default()
+}
+
+/// Run the dashboard until the user quits
+pub async fn cmd_top() -> Result<()> {
+    let (rows_tx, rows_rx) = watch::channel(None);
+    let collector = tokio::spawn(async move {
+        let mut collector = Collector::default();
+        loop {
+            if rows_tx.send(Some(collector.collect().await)).is_err() {
+                break;
+            }
+            tokio::time::sleep(REFRESH).await;
+        }
+    });
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, rows_rx).await;
+    ratatui::restore();
+    collector.abort();
+    result
+}
+
+async fn run(
+    terminal: &mut DefaultTerminal,
+    rows_rx: watch::Receiver<Option<Vec<ServerRow>>>,
+) -> Result<()> {
+    let (message_tx, mut message_rx) = mpsc::unbounded_channel();
+    let mut message = String::new();
+    let mut table = TableState::default().with_selected(0);
+    // The server `s` was pressed on, until the stop is confirmed or cancelled
+    let mut stopping: Option<RegistryEntry> = None;
+
+    loop {
+        let rows = rows_rx.borrow().clone();
+        // Keep the question on screen while it waits for an answer
+        if stopping.is_none() {
+            while let Ok(update) = message_rx.try_recv() {
+                message = update;
+            }
+        }
+        terminal.draw(|frame| draw(frame, rows.as_deref(), &mut table, &message))?;
+
+        // Keep the runtime free for the collector while waiting for a key
+        if !tokio::task::block_in_place(|| event::poll(Duration::from_millis(200)))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let selected = rows
+            .as_ref()
+            .and_then(|rows| rows.get(table.selected().unwrap_or(0)))
+            .map(|row| row.entry.clone());
+
+        if let Some(entry) = stopping.take() {
+            if key.code == KeyCode::Char('y') {
+                message = format!("Stopping {}...", entry.dir.display());
+                let message_tx = message_tx.clone();
+                tokio::spawn(async move {
+                    let _ = message_tx.send(stop(&entry).await);
+                });
+            } else {
+                message.clear();
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => table.select_previous(),
+            KeyCode::Enter | KeyCode::Char('a') => {
+                if let Some(entry) = selected {
+                    // Hand the terminal to `mcwrap attach` until it detaches
+                    ratatui::restore();
+                    let status = tokio::process::Command::new(std::env::current_exe()?)
+                        .arg("attach")
+                        .arg(&entry.dir)
+                        .status()
+                        .await;
+                    *terminal = ratatui::init();
+                    terminal.clear()?;
+                    message = match status {
+                        Ok(status) if status.success() => String::new(),
+                        Ok(_) => format!("Could not attach to {}", entry.dir.display()),
+                        Err(e) => format!("Could not run mcwrap attach: {}", e),
+                    };
+                }
+            }
+            KeyCode::Char('s') => {
+                if let Some(entry) = selected {
+                    message = format!("Stop {}? (y/n)", entry.dir.display());
+                    stopping = Some(entry);
+                }
+            }
+            KeyCode::Char('r') => {
+                if let Some(entry) = selected {
+                    message = format!("Restarting {}...", entry.dir.display());
+                    let message_tx = message_tx.clone();
+                    tokio::spawn(async move {
+                        let _ = message_tx.send(restart(&entry).await);
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn stop(entry: &RegistryEntry) -> String {
+    let dir = entry.dir.to_string_lossy().into_owned();
+    match mcwrap(&["stop".to_string(), dir]).await {
+        Ok(_) => format!("Stopped {}", entry.dir.display()),
+        Err(e) => format!("{:#}", e),
+    }
+}
+
+async fn restart(entry: &RegistryEntry) -> String {
+    if is_running(&ServerPaths::new(&entry.dir)).is_some() {
+        let dir = entry.dir.to_string_lossy().into_owned();
+        if let Err(e) = mcwrap(&["stop".to_string(), dir]).await {
+            return format!("{:#}", e);
+        }
+    }
+    let mut args = vec!["start".to_string()];
+    args.extend(entry.start_args());
+    match mcwrap(&args).await {
+        Ok(_) => format!("Restarted {}", entry.dir.display()),
+        Err(e) => format!("{:#}", e),
+    }
+}
+
+/// Run mcwrap with its output captured, failing with its last line of output
+async fn mcwrap(args: &[String]) -> Result<()> {
+    let output = tokio::process::Command::new(std::env::current_exe()?)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("Failed to run mcwrap")?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr.lines().rfind(|line| !line.trim().is_empty()).unwrap_or("failed");
+    anyhow::bail!("mcwrap {}: {}", args[0], reason.trim_start_matches("Error: "))
+}
+
+fn draw(frame: &mut Frame, rows: Option<&[ServerRow]>, table: &mut TableState, message: &str) {
+    let [title, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let count = rows.map_or(0, <[ServerRow]>::len);
+    let heading = format!("mcwrap top - {} servers", count);
+    let bold = Style::new().add_modifier(Modifier::BOLD);
+    frame.render_widget(Line::from(heading).style(bold), title);
+
+    let Some(rows) = rows else {
+        frame.render_widget(Paragraph::new("Loading..."), body);
+        return;
+    };
+    if rows.is_empty() {
+        frame.render_widget(Paragraph::new("No servers registered yet."), body);
+    } else {
+        let header = Row::new([
+            "", "SERVER", "STATE", "PID", "CPU", "MEMORY", "TPS", "PLAYERS", "UPTIME", "LAST LINE",
+        ])
+        .style(bold);
+        let lines = rows.iter().map(|row| {
+            let (symbol, color) = match row.health {
+                Health::Running => ("●", Color::Green),
+                Health::Hibernating => ("◐", Color::Blue),
+                Health::Stopped => ("○", Color::Gray),
+                Health::Crashed => ("✗", Color::Red),
+            };
+            let tps_color = match row.tps {
+                Some(tps) if tps < 15.0 => Color::Red,
+                Some(tps) if tps < 19.0 => Color::Yellow,
+                _ => Color::Reset,
+            };
+            Row::new([
+                Cell::from(symbol).style(Style::new().fg(color)),
+                Cell::from(row.name.clone()),
+                Cell::from(row.state.clone()),
+                Cell::from(optional(row.pid)),
+                Cell::from(row.cpu.map_or("-".to_string(), |cpu| format!("{:.0}%", cpu))),
+                Cell::from(row.memory.map_or("-".to_string(), crate::disk::format_size)),
+                Cell::from(row.tps.map_or("-".to_string(), |tps| format!("{:.1}", tps)))
+                    .style(Style::new().fg(tps_color)),
+                Cell::from(optional(row.players)),
+                Cell::from(row.uptime.map_or("-".to_string(), format_uptime)),
+                Cell::from(row.last_line.clone()).style(Style::new().fg(Color::DarkGray)),
+            ])
+        });
+        let widths = [
+            Constraint::Length(1),
+            Constraint::Length(16),
+            Constraint::Length(24),
+            Constraint::Length(7),
+            Constraint::Length(5),
+            Constraint::Length(9),
+            Constraint::Length(5),
+            Constraint::Length(7),
+            Constraint::Length(7),
+            Constraint::Min(10),
+        ];
+        let widget = Table::new(lines, widths)
+            .header(header)
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(widget, body, table);
+    }
+
+    let help = format!("↑/↓ select  a attach  r restart  s stop  q quit   {}", message);
+    frame.render_widget(Line::from(help).style(Style::new().fg(Color::DarkGray)), footer);
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
@@ -0,0 +1,182 @@
+//! Trigger rules: actions taken when console output matches a pattern
+//!
+//! ```toml
+//! [[triggers]]
+//! pattern = "Can't keep up!"
+//! notify = "Server is lagging"
+//! cooldown_secs = 600
+//!
+//! [[triggers]]
+//! pattern = '<(?P<player>\w+)> !whitelist-me'
+//! send = "whitelist add ${player}"
+//! ```
+//!
+//! The PTY daemon matches every console line (without colors) against the
+//! rules. A matching rule sends its command through the command queue, runs
+//! its hook, sends its notification as an `Alert` event and, with `restart`,
+//! has mcwrapd restart the server, in that order. `send` and `notify` can use
+//! the pattern's capture groups (`$1`, `${player}`); hooks get them as
+//! `MCWRAP_MATCH_1`, `MCWRAP_MATCH_PLAYER`... next to `MCWRAP_LINE`. After
+//! firing, a rule ignores further matches for `cooldown_secs` (default 60),
+//! which also keeps a command that prints its own pattern from looping.
+
+use anyhow::{Context, Result};
+use crate::config::TriggerConfig;
+use crate::daemon_log;
+use crate::events::EventKind;
+use crate::pty::DaemonState;
+use crate::supervisor::{self, Request};
+use crate::users::Identity;
+use regex::{Captures, Regex};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A rule from the config, ready to match
+#[derive(Clone)]
+pub struct Rule {
+    regex: Regex,
+    send: Option<String>,
+    exec: Vec<String>,
+    notify: Option<String>,
+    restart: bool,
+    cooldown: Duration,
+}
+
+/// The rules of one server
+#[derive(Clone)]
+pub struct Triggers {
+    server_dir: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl Triggers {
+    pub fn from_config(configs: &[TriggerConfig], server_dir: PathBuf) -> Result<Self> {
+        let rules = configs
+            .iter()
+            .map(|config| {
+                let regex = Regex::new(&config.pattern)
+                    .with_context(|| format!("Invalid trigger pattern {:?}", config.pattern))?;
+                Ok(Rule {
+                    regex,
+                    send: config.send.clone(),
+                    exec: config.exec.clone(),
+                    notify: config.notify.clone(),
+                    restart: config.restart,
+                    cooldown: Duration::from_secs(config.cooldown_secs),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { server_dir, rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// Follows the console output for lines that fire rules
+pub struct Watch {
+    triggers: Arc<Triggers>,
+    fired: Vec<Option<Instant>>,
+    partial: String,
+}
+
+impl Watch {
+    pub fn new(triggers: Triggers) -> Self {
+        Self {
+            fired: vec![None; triggers.rules.len()],
+            triggers: Arc::new(triggers),
+            partial: String::new(),
+        }
+    }
+
+    /// Feed (log-filtered) console output, firing the rules it matches
+    pub fn push(&mut self, state: &Arc<DaemonState>, output: &[u8]) {
+        if self.triggers.is_empty() {
+            return;
+        }
+        self.partial.push_str(&String::from_utf8_lossy(output));
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            let line = crate::console::strip_ansi(line.trim_end_matches(['\r', '\n']));
+            for (i, rule) in self.triggers.rules.iter().enumerate() {
+                if self.fired[i].is_some_and(|at| at.elapsed() < rule.cooldown) {
+                    continue;
+                }
+                if rule.regex.is_match(&line) {
+                    self.fired[i] = Some(Instant::now());
+                    fire(state.clone(), self.triggers.clone(), i, line.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Carry out a rule's actions from a background thread, so that the console keeps flowing
+fn fire(state: Arc<DaemonState>, triggers: Arc<Triggers>, index: usize, line: String) {
+    thread::spawn(move || {
+        let rule = &triggers.rules[index];
+        let Some(captures) = rule.regex.captures(&line) else {
+            return;
+        };
+        daemon_log::info(format!("Trigger {:?} fired on: {}", rule.regex.as_str(), line));
+        let expand = |template: &str| {
+            let mut expanded = String::new();
+            captures.expand(template, &mut expanded);
+            expanded
+        };
+
+        if let Some(command) = &rule.send {
+            let command = expand(command);
+            state.send_command(&command, true, false);
+            state.record_command(&Identity::owner(), "trigger", &command);
+        }
+        if !rule.exec.is_empty() {
+            let env = hook_env(rule, &captures, &line);
+            if let Err(e) = crate::notify::run_script(&rule.exec, &triggers.server_dir, env) {
+                daemon_log::warn(format!("Trigger hook failed: {:#}", e));
+            }
+        }
+        if let Some(message) = &rule.notify {
+            state.emit(EventKind::Alert {
+                message: expand(message),
+            });
+        }
+        if rule.restart {
+            restart(state, &triggers);
+        }
+    });
+}
+
+/// Environment for a rule's hook: the line and its capture groups
+fn hook_env(rule: &Rule, captures: &Captures, line: &str) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("MCWRAP_LINE".to_string(), line.to_string()),
+        ("MCWRAP_PATTERN".to_string(), rule.regex.as_str().to_string()),
+    ];
+    for (i, name) in rule.regex.capture_names().enumerate().skip(1) {
+        let Some(group) = captures.get(i) else {
+            continue;
+        };
+        let key = name.map_or_else(|| i.to_string(), str::to_uppercase);
+        env.push((format!("MCWRAP_MATCH_{}", key), group.as_str().to_string()));
+    }
+    env
+}
+
+/// Have mcwrapd start the server again once it has stopped, then stop it
+fn restart(state: Arc<DaemonState>, triggers: &Triggers) {
+    let request = Request::Restarting {
+        dir: triggers.server_dir.clone(),
+    };
+    match supervisor::request_blocking(&request) {
+        Ok(Some(_)) => {
+            daemon_log::info("Trigger restarting the server");
+            crate::oom::stop_server(state);
+        }
+        Ok(None) => daemon_log::warn("Trigger can't restart the server: mcwrapd is not running"),
+        Err(e) => daemon_log::warn(format!("Trigger can't restart the server: {:#}", e)),
+    }
+}
@@ -0,0 +1,243 @@
+//! Swapping a server's JAR for a new version
+//!
+//! `mcwrap upgrade <dir> --jar new-paper.jar` (or `--to 1.21.4` to download
+//! the latest Paper build) warns the players, stops the server, backs up its
+//! worlds and JAR, renames the new JAR into place and starts the server again
+//! the way it was last started. If the new version doesn't come up, its JAR
+//! is set aside as `<jar>.new`, the old one is put back and started instead.
+
+use anyhow::{bail, Context, Result};
+use crate::events::{Event, EventKind};
+use crate::failure::{ErrorKind, Failure};
+use crate::http;
+use crate::registry::{Registry, RegistryEntry};
+use crate::{
+    cmd_kill, cmd_start, cmd_stop, find_forge_args, find_jar, is_running, unix_now,
+    wait_until_ready, ServerPaths, StartOptions, StopOptions, KILL_GRACE,
+};
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long the server gets to exit after `stop`
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Builds of a Paper version, from the PaperMC API
+const PAPER_BUILDS: &str = "https://api.papermc.io/v2/projects/paper/versions";
+
+/// Options for `mcwrap upgrade`
+pub struct UpgradeOptions {
+    /// The new server JAR
+    pub jar: Option<PathBuf>,
+    /// Minecraft version to download the latest Paper build of
+    pub to: Option<String>,
+    /// Count down in chat for this long before stopping
+    pub warn: Duration,
+    /// Back up the worlds and the old JAR first
+    pub backup: bool,
+    /// How long the new version gets to finish starting
+    pub timeout: Duration,
+}
+
+pub async fn cmd_upgrade(server_dir: &Path, opts: UpgradeOptions) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let paths = ServerPaths::new(&server_dir);
+    let entry = Registry::load()?.entry(&server_dir).clone();
+    if entry.exec.is_some() {
+        bail!("Server is started with --exec; upgrade only swaps server JARs");
+    }
+
+    let current = server_jar(&server_dir, &entry)?;
+    let jar_name = current.file_name().unwrap().to_string_lossy().to_string();
+    // Staged next to the old JAR, so that swapping them is a rename
+    let staged = server_dir.join(format!("{}.new", jar_name));
+    let previous = server_dir.join(format!("{}.previous", jar_name));
+    match (&opts.jar, &opts.to) {
+        (Some(jar), _) => {
+            fs::copy(jar, &staged).with_context(|| format!("Failed to copy {:?}", jar))?;
+        }
+        (None, Some(version)) => download_paper(version, &staged).await?,
+        (None, None) => bail!("Give the new JAR with --jar or a version with --to"),
+    }
+    if !is_jar(&staged) {
+        let _ = fs::remove_file(&staged);
+        bail!("The new server JAR is not a JAR file");
+    }
+
+    let was_running = is_running(&paths).is_some();
+    if was_running {
+        let stop = StopOptions {
+            warn: Some(opts.warn).filter(|warn| !warn.is_zero()),
+            kick: None,
+            timeout: STOP_TIMEOUT,
+            then_kill: false,
+        };
+        cmd_stop(&server_dir, stop).await?;
+    }
+    if opts.backup {
+        println!("Backing up the worlds and {}...", jar_name);
+        let extra = std::slice::from_ref(&jar_name);
+        let (result, kind) = match backup(&server_dir, "upgrade", extra) {
+            Ok(archive) => (Ok(()), EventKind::BackupCompleted { archive }),
+            Err(e) => {
+                let reason = format!("{:#}", e);
+                (Err(e), EventKind::BackupFailed { reason })
+            }
+        };
+        report(&server_dir, Event::now(kind));
+        result?;
+    }
+
+    // The old JAR stays in place until the new one atomically replaces it
+    let _ = fs::remove_file(&previous);
+    fs::hard_link(&current, &previous)
+        .or_else(|_| fs::copy(&current, &previous).map(drop))
+        .context("Failed to keep the old JAR")?;
+    fs::rename(&staged, &current).context("Failed to swap in the new JAR")?;
+    println!("Swapped in the new {} (the old one is kept as {}.previous)", jar_name, jar_name);
+
+    if !was_running {
+        println!("Server was not running; it will use the new JAR on its next start.");
+        return Ok(());
+    }
+
+    let Err(e) = start(&server_dir, &entry, opts.timeout).await else {
+        println!("Upgraded {}", server_dir.display());
+        return Ok(());
+    };
+    eprintln!("The new version failed to start: {:#}", e);
+    if is_running(&paths).is_some() {
+        cmd_kill(&server_dir, KILL_GRACE).await?;
+    }
+    fs::rename(&current, &staged).context("Failed to set the new JAR aside")?;
+    fs::rename(&previous, &current).context("Failed to put the old JAR back")?;
+    println!("Rolled back to the old JAR (the new one is kept as {}.new)", jar_name);
+    start(&server_dir, &entry, opts.timeout)
+        .await
+        .context("The old version failed to start too")?;
+    bail!("Upgrade failed and was rolled back")
+}
+
+/// The JAR the server runs: the one given with `-jar`, or the one `start` would pick
+fn server_jar(server_dir: &Path, entry: &RegistryEntry) -> Result<PathBuf> {
+    let args = &entry.java_args;
+    if let Some(i) = args.iter().position(|arg| arg == "-jar") {
+        let jar = args.get(i + 1).context("-jar without a JAR in the start arguments")?;
+        return Ok(server_dir.join(jar));
+    }
+    if !args.is_empty() || find_forge_args(server_dir).is_some() {
+        bail!("Server is not started from a JAR; upgrade only swaps server JARs");
+    }
+    find_jar(server_dir)
+}
+
+/// Whether a file starts like a ZIP archive, as every JAR does
+pub fn is_jar(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok()
+        && magic == *b"PK\x03\x04"
+}
+
+/// Archive the worlds and `extra` files into `backups/<label>-<time>.tar.gz`
+pub fn backup(server_dir: &Path, label: &str, extra: &[String]) -> Result<PathBuf> {
+    let backups = server_dir.join("backups");
+    fs::create_dir_all(&backups).context("Failed to create the backups directory")?;
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let archive = backups.join(format!("{}-{}.tar.gz", label, stamp));
+
+    let mut contents = extra.to_vec();
+    for world in crate::disk::world_dirs(server_dir) {
+        contents.push(world.file_name().unwrap().to_string_lossy().to_string());
+    }
+    let status = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(server_dir)
+        .arg("--")
+        .args(&contents)
+        .status()
+        .context("Failed to run tar")?;
+    if !status.success() {
+        let _ = fs::remove_file(&archive);
+        bail!("Backup failed ({})", status);
+    }
+    Ok(archive)
+}
+
+/// Record an event and send its notifications, as the daemon would
+fn report(server_dir: &Path, event: Event) {
+    if let Err(e) = event.record(&ServerPaths::new(server_dir).events_file) {
+        eprintln!("Failed to record the event: {:#}", e);
+    }
+    if let Err(e) = crate::notify::notify(server_dir, &event) {
+        eprintln!("Failed to send notifications: {:#}", e);
+    }
+}
+
+/// Start the server as it was last started and wait until it is ready
+pub async fn start(server_dir: &Path, entry: &RegistryEntry, timeout: Duration) -> Result<()> {
+    let opts = StartOptions {
+        basic: entry.basic,
+        legacy_raw: entry.legacy_raw,
+        priority: entry.priority.clone(),
+        container: entry.container.clone(),
+        log_level: entry.log_level,
+        gc_log: entry.gc_log,
+        preset: entry.preset,
+        ..Default::default()
+    };
+    let since = unix_now();
+    let progress = opts.progress;
+    cmd_start(server_dir, entry.java_args.clone(), opts).await?;
+    tokio::time::timeout(timeout, wait_until_ready(server_dir, since, progress))
+        .await
+        .map_err(|_| {
+            let message = format!("Server was not ready after {:?}", timeout);
+            Failure::new(ErrorKind::Timeout, message)
+        })?
+}
+
+#[derive(Deserialize)]
+struct Builds {
+    builds: Vec<Build>,
+}
+
+#[derive(Deserialize)]
+struct Build {
+    build: u32,
+    channel: String,
+    downloads: Downloads,
+}
+
+#[derive(Deserialize)]
+struct Downloads {
+    application: Download,
+}
+
+#[derive(Deserialize)]
+struct Download {
+    name: String,
+}
+
+/// Download the latest stable Paper build of a Minecraft version
+pub async fn download_paper(version: &str, dest: &Path) -> Result<()> {
+    let url = format!("{}/{}/builds", PAPER_BUILDS, version);
+    let response = http::get(&[&url]).await?;
+    let builds: Builds = serde_json::from_slice(&response)
+        .with_context(|| format!("Unexpected answer from {}", url))?;
+    let build = builds
+        .builds
+        .iter()
+        .rfind(|build| build.channel == "default")
+        .or(builds.builds.last())
+        .with_context(|| format!("No Paper builds for {}", version))?;
+
+    let name = &build.downloads.application.name;
+    println!("Downloading {} (Paper build {})...", name, build.build);
+    let url = format!("{}/{}/builds/{}/downloads/{}", PAPER_BUILDS, version, build.build, name);
+    http::get(&["-o", &dest.to_string_lossy(), &url]).await?;
+    Ok(())
+}
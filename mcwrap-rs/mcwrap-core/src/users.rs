@@ -0,0 +1,300 @@
+//! Users and roles for the control API
+//!
+//! Users live in `~/.config/mcwrap/users.toml`, each with a token and a role:
+//!
+//! - viewer: status and console output only
+//! - operator: also `send`, limited to the whitelisted commands
+//! - admin: everything, including `stop`
+//!
+//! The server's own token (from the wrap dir) always acts as admin. Trusted
+//! relays like `mcwrap serve` present the server token together with the name
+//! of the user they authenticated, and get that user's role instead.
+
+use anyhow::{bail, Context, Result};
+use clap::{Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write as IoWrite;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+impl Role {
+    /// Whether this role has at least the permissions of `other`
+    pub fn includes(self, other: Role) -> bool {
+        self as u8 >= other as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct User {
+    pub name: String,
+    pub token: String,
+    pub role: Role,
+}
+
+/// Commands operators may send
+#[derive(Serialize, Deserialize)]
+pub struct OperatorRole {
+    pub commands: Vec<String>,
+}
+
+impl Default for OperatorRole {
+    fn default() -> Self {
+        let commands = ["list", "say", "tell", "msg", "kick", "tps", "whitelist"];
+        Self {
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Users {
+    #[serde(default)]
+    pub operator: OperatorRole,
+    #[serde(default, rename = "user")]
+    pub users: Vec<User>,
+}
+
+/// Who a connection authenticated as
+#[derive(Clone)]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+    /// Unix uid of the connecting process, when known
+    pub uid: Option<u32>,
+}
+
+impl Identity {
+    /// Holder of the server token
+    pub fn owner() -> Self {
+        Self {
+            name: "owner".to_string(),
+            role: Role::Admin,
+            uid: None,
+        }
+    }
+}
+
+impl Identity {
+    /// Check that this identity may send a console command, skipping the queue unless `queue`
+    pub fn may_send(&self, command: &str, queue: bool) -> Result<(), String> {
+        if !self.role.includes(Role::Operator) {
+            return Err("send requires the operator role".to_string());
+        }
+        // Each line reaches the console as a command of its own, unchecked
+        if !self.role.includes(Role::Admin) && command.contains(['\n', '\r']) {
+            return Err("The command must be a single line".to_string());
+        }
+        if self.role == Role::Operator {
+            let users = Users::load().map_err(|e| format!("{:#}", e))?;
+            if !users.operator_may_send(command) {
+                return Err("Command not allowed for operators".to_string());
+            }
+        }
+        if !queue && !self.role.includes(Role::Admin) {
+            return Err("Skipping the queue requires the admin role".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Users {
+    /// Location of the users file
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("mcwrap").join("users.toml"))
+    }
+
+    /// Load the users file, returning an empty one if none exists
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).with_context(|| format!("Invalid {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        }
+    }
+
+    /// Write the users file, readable only by its owner
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().context("No config directory")?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp = path.with_extension("toml.tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp)?;
+        file.write_all(toml::to_string_pretty(self)?.as_bytes())?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Resolve the credentials presented by a client
+    ///
+    /// `server_token_ok` says whether `token` was the server's own token.
+    pub fn authenticate(
+        &self,
+        server_token_ok: bool,
+        token: &str,
+        as_user: Option<&str>,
+    ) -> Result<Identity, String> {
+        if server_token_ok {
+            return match as_user {
+                None => Ok(Identity::owner()),
+                // Without a users file every relayed user is trusted fully
+                Some(name) if self.users.is_empty() => Ok(Identity {
+                    name: name.to_string(),
+                    role: Role::Admin,
+                    uid: None,
+                }),
+                Some(name) => self
+                    .users
+                    .iter()
+                    .find(|u| u.name == name)
+                    .map(|u| u.identity())
+                    .ok_or_else(|| format!("Unknown user {}", name)),
+            };
+        }
+
+        self.users
+            .iter()
+            .find(|u| crate::auth::verify(&u.token, token))
+            .map(|u| u.identity())
+            .ok_or_else(|| "Invalid token".to_string())
+    }
+
+    /// Whether an operator may send this console command
+    pub fn operator_may_send(&self, command: &str) -> bool {
+        if command.contains(['\n', '\r']) {
+            return false;
+        }
+        let name = command.trim_start().trim_start_matches('/');
+        let name = name.split_whitespace().next().unwrap_or("");
+        self.operator.commands.iter().any(|c| c.eq_ignore_ascii_case(name))
+    }
+}
+
+impl User {
+    fn identity(&self) -> Identity {
+        Identity {
+            name: self.name.clone(),
+            role: self.role,
+            uid: None,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum UserAction {
+    /// Add a user (or change their role) and print their token
+    Add {
+        name: String,
+        #[arg(long, value_enum)]
+        role: Role,
+    },
+    /// Remove a user
+    Remove { name: String },
+    /// List users and their roles
+    List,
+}
+
+/// Manage the users file
+pub fn cmd_user(action: UserAction) -> Result<()> {
+    let mut users = Users::load()?;
+
+    match action {
+        UserAction::Add { name, role } => {
+            let token = match users.users.iter_mut().find(|u| u.name == name) {
+                Some(user) => {
+                    user.role = role;
+                    user.token.clone()
+                }
+                None => {
+                    let token = crate::auth::random_token()?;
+                    users.users.push(User {
+                        name: name.clone(),
+                        token: token.clone(),
+                        role,
+                    });
+                    token
+                }
+            };
+            users.save()?;
+            println!("{} is now {}", name, role.name());
+            println!("  Token: {}", token);
+            println!("  Use it with MCWRAP_TOKEN=<token> mcwrap ...");
+        }
+        UserAction::Remove { name } => {
+            let before = users.users.len();
+            users.users.retain(|u| u.name != name);
+            if users.users.len() == before {
+                bail!("No user named {}", name);
+            }
+            users.save()?;
+            println!("Removed {}", name);
+        }
+        UserAction::List => {
+            if users.users.is_empty() {
+                println!("No users configured.");
+            }
+            for user in &users.users {
+                println!("{} ({})", user.name, user.role.name());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operator() -> Identity {
+        Identity {
+            name: "op".to_string(),
+            role: Role::Operator,
+            uid: None,
+        }
+    }
+
+    #[test]
+    fn operator_commands() {
+        let users = Users::default();
+        assert!(users.operator_may_send("list"));
+        assert!(users.operator_may_send("/say hello"));
+        assert!(users.operator_may_send("  KICK steve"));
+        assert!(!users.operator_may_send("op eve"));
+        assert!(!users.operator_may_send(""));
+        assert!(!users.operator_may_send("list\nop eve"));
+        assert!(!users.operator_may_send("say hi\rop eve"));
+    }
+
+    #[test]
+    fn multi_line_commands() {
+        assert!(operator().may_send("list\nop eve", true).is_err());
+        assert!(operator().may_send("say hi\r\nstop", true).is_err());
+        let viewer = Identity {
+            role: Role::Viewer,
+            ..operator()
+        };
+        assert!(viewer.may_send("list", true).is_err());
+        // Admins may send whatever they like, several commands at once included
+        assert!(Identity::owner().may_send("say one\nsay two", true).is_ok());
+    }
+}
@@ -0,0 +1,121 @@
+//! Detecting a server that has frozen while its process stays alive
+//!
+//! When the console has been silent for the configured time, the watchdog
+//! types `list` into it. A server whose main thread still runs answers with
+//! its player list; one that doesn't is reported in the console log and,
+//! if configured, has a thread dump saved to the wrap dir's `dumps` and is killed so
+//! mcwrapd's restart policy can bring it back.
+
+use crate::config::WatchdogConfig;
+use crate::pty::DaemonState;
+use nix::sys::signal::{kill, Signal};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the console's silence is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time the JVM gets to print a thread dump
+const DUMP_WAIT: Duration = Duration::from_secs(3);
+
+/// Time a frozen server gets to exit on SIGTERM before it is killed
+const TERM_GRACE: Duration = Duration::from_secs(30);
+
+/// Settings for one daemon's watchdog
+#[derive(Clone)]
+pub struct Watchdog {
+    silence: Duration,
+    probe_timeout: Duration,
+    restart: bool,
+    log_file: PathBuf,
+    dump_dir: PathBuf,
+}
+
+impl Watchdog {
+    /// The watchdog configured for a server, if any
+    pub fn from_config(
+        config: &WatchdogConfig,
+        log_file: PathBuf,
+        dump_dir: PathBuf,
+    ) -> Option<Self> {
+        Some(Self {
+            silence: Duration::from_secs(config.silent_mins? * 60),
+            probe_timeout: Duration::from_secs(config.probe_timeout_secs),
+            restart: config.restart,
+            log_file,
+            dump_dir,
+        })
+    }
+
+    /// Watch the server from a background thread
+    pub fn spawn(self, state: Arc<DaemonState>) {
+        thread::spawn(move || {
+            // Only report a freeze once, until output resumes
+            let mut reported = false;
+            loop {
+                thread::sleep(CHECK_INTERVAL);
+                if state.silent_for() < self.silence {
+                    reported = false;
+                    continue;
+                }
+                if reported || self.responds(&state) {
+                    continue;
+                }
+                reported = true;
+                self.note(&format!(
+                    "Watchdog: no console output for {} min and no reply to `list`",
+                    self.silence.as_secs() / 60
+                ));
+                if self.restart {
+                    self.restart(&state);
+                }
+            }
+        });
+    }
+
+    /// Whether the server answers a `list` probe in time
+    fn responds(&self, state: &DaemonState) -> bool {
+        let capture = state.start_capture();
+        state.write_input(b"list\n");
+        let deadline = Instant::now() + self.probe_timeout;
+        let mut answered = false;
+        while !answered && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(200));
+            // JLine echoes the command even when the server is stuck
+            answered = String::from_utf8_lossy(&capture.output()).contains("players online");
+        }
+        state.finish_capture(&capture);
+        answered
+    }
+
+    /// Save a thread dump and kill the server so it can be restarted
+    fn restart(&self, state: &DaemonState) {
+        // SIGQUIT makes the JVM print a thread dump to its console
+        let capture = state.start_capture();
+        kill(state.child_pid, Signal::SIGQUIT).ok();
+        thread::sleep(DUMP_WAIT);
+        state.finish_capture(&capture);
+
+        let path = self.dump_dir.join(format!("threaddump-{}.txt", crate::unix_now()));
+        let saved = fs::create_dir_all(&self.dump_dir);
+        match saved.and_then(|()| fs::write(&path, capture.output())) {
+            Ok(()) => self.note(&format!("Watchdog: thread dump saved to {}", path.display())),
+            Err(e) => self.note(&format!("Watchdog: failed to save thread dump: {}", e)),
+        }
+
+        self.note("Watchdog: killing the frozen server");
+        state.terminate(TERM_GRACE);
+    }
+
+    /// Report to the console log and the daemon's stderr
+    fn note(&self, message: &str) {
+        eprintln!("{}", message);
+        if let Ok(mut log) = OpenOptions::new().append(true).open(&self.log_file) {
+            writeln!(log, "[mcwrap] {}", message).ok();
+        }
+    }
+}
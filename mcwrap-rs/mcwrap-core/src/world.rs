@@ -0,0 +1,174 @@
+//! Keeping several worlds in one server
+//!
+//! A server runs the world named by `level-name` in `server.properties`;
+//! other world folders next to it (last season's map, an event map) are left
+//! alone. `mcwrap world list <dir>` shows them, and `mcwrap world activate
+//! <dir> <world>` switches to one: a running server warns its players and
+//! stops (saving the current world), `level-name` is changed, and the server
+//! is started again the way it was last started. With `--new`, the server
+//! generates the world on its next start.
+
+use anyhow::{bail, Context, Result};
+use crate::disk::{du, format_size, level_dirs, DIMENSION_SUFFIXES};
+use crate::properties::Properties;
+use crate::registry::Registry;
+use crate::{cmd_stop, is_running, local_time, parse_duration, ServerPaths, StopOptions};
+use clap::Subcommand;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// How long the server gets to exit after `stop`
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the server gets to come back up with the other world
+const START_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Subcommand)]
+pub enum WorldAction {
+    /// List the worlds in a server directory
+    List {
+        /// Server directory
+        dir: PathBuf,
+    },
+    /// Switch the server to another world, restarting it if it runs
+    Activate {
+        /// Server directory
+        dir: PathBuf,
+        /// Name of the world folder
+        world: String,
+        /// Let the server generate the world, which must not exist yet
+        #[arg(long)]
+        new: bool,
+        /// Count down in chat for this long before stopping (0 to stop right away)
+        #[arg(long, value_name = "DURATION", default_value = "1m", value_parser = parse_duration)]
+        warn: Duration,
+    },
+}
+
+pub async fn cmd_world(action: WorldAction) -> Result<()> {
+    match action {
+        WorldAction::List { dir } => list(&dir),
+        WorldAction::Activate {
+            dir,
+            world,
+            new,
+            warn,
+        } => activate(&dir, &world, new, warn).await,
+    }
+}
+
+fn list(server_dir: &Path) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    let active = level_name(&server_dir)?;
+    let mut worlds = worlds(&server_dir);
+    if !worlds.contains(&active) {
+        worlds.push(active.clone());
+        worlds.sort();
+    }
+
+    for world in &worlds {
+        let marker = if *world == active { "*" } else { " " };
+        let dirs = level_dirs(&server_dir, world);
+        if dirs.is_empty() {
+            println!("{} {} (generated on the next start)", marker, world);
+            continue;
+        }
+        let size: u64 = dirs.iter().map(|dir| du(dir)).sum();
+        let saved = fs::metadata(server_dir.join(world).join("level.dat"))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since| format!(", last saved {}", local_time(since.as_secs())))
+            .unwrap_or_default();
+        println!("{} {} ({}{})", marker, world, format_size(size), saved);
+    }
+    Ok(())
+}
+
+async fn activate(server_dir: &Path, world: &str, new: bool, warn: Duration) -> Result<()> {
+    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
+    if world.is_empty() || world.contains('/') || world.starts_with('.') {
+        bail!("Invalid world name {:?}", world);
+    }
+    let active = level_name(&server_dir)?;
+    if world == active {
+        println!("{} is already the active world", world);
+        return Ok(());
+    }
+    if new && server_dir.join(world).exists() {
+        bail!("{} already exists", server_dir.join(world).display());
+    }
+    if !new && !is_world(&server_dir.join(world)) {
+        let available = worlds(&server_dir);
+        if available.is_empty() {
+            bail!("No world {:?} (add --new to generate it)", world);
+        }
+        bail!(
+            "No world {:?} (worlds: {}; add --new to generate it)",
+            world,
+            available.join(", ")
+        );
+    }
+
+    let paths = ServerPaths::new(&server_dir);
+    let was_running = is_running(&paths).is_some();
+    if was_running {
+        // Stopping saves the current world, and nothing writes to it afterwards
+        let stop = StopOptions {
+            warn: Some(warn).filter(|warn| !warn.is_zero()),
+            kick: None,
+            timeout: STOP_TIMEOUT,
+            then_kill: false,
+        };
+        cmd_stop(&server_dir, stop).await?;
+    }
+
+    let mut properties = Properties::load(&server_dir)?;
+    properties.set("level-name", world);
+    properties.save()?;
+    println!("Switched {} from {} to {}", server_dir.display(), active, world);
+
+    if !was_running {
+        return Ok(());
+    }
+    let entry = Registry::load()?.entry(&server_dir).clone();
+    crate::upgrade::start(&server_dir, &entry, START_TIMEOUT).await?;
+    println!("Server is running {}", world);
+    Ok(())
+}
+
+/// The world the server runs
+fn level_name(server_dir: &Path) -> Result<String> {
+    let properties = Properties::load(server_dir)?;
+    Ok(properties.get("level-name").unwrap_or("world").to_string())
+}
+
+/// A world folder, as opposed to any other directory
+fn is_world(dir: &Path) -> bool {
+    dir.join("level.dat").is_file()
+}
+
+/// Names of the worlds in a server directory, without their separate dimensions
+fn worlds(server_dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(server_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| is_world(&entry.path()))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    // Bukkit-style servers keep world_nether and world_the_end next to world
+    let dimensions: Vec<String> = names
+        .iter()
+        .flat_map(|name| {
+            DIMENSION_SUFFIXES
+                .iter()
+                .filter(|suffix| !suffix.is_empty())
+                .map(move |suffix| format!("{}{}", name, suffix))
+        })
+        .collect();
+    names.retain(|name| !dimensions.contains(name));
+    names.sort();
+    names
+}
@@ -1,35 +1,37 @@
 //! mcwrap - Minecraft server wrapper with PTY support
 //!
-//! Provides session persistence and proper terminal emulation for
-//! interactive console features like tab completion.
-
-use anyhow::{bail, Context, Result};
-use clap::{Parser, Subcommand};
-use nix::libc;
-use nix::sys::signal::{kill, Signal};
-use nix::sys::stat::Mode;
-use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
-use nix::unistd::Pid;
-use serde::{Deserialize, Serialize};
-use std::fs::{self, File, OpenOptions};
-use std::io::{BufRead, BufReader, Read as IoRead, Write as IoWrite};
-use std::os::fd::{AsRawFd, BorrowedFd};
+//! The command line: the arguments are parsed here and handed to
+//! `mcwrap-core`, which does the rest.
+
+use anyhow::{Context, Result};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use mcwrap_core::docker::Container;
+use mcwrap_core::highlight::Highlighter;
+use mcwrap_core::priority::Priority;
+use mcwrap_core::progress::Progress;
+use mcwrap_core::{
+    adopt, audit, boot, clone, completions, console, daemon_log, destroy, disk, dump, errors,
+    events, export, failure, flags, gc, history, icon, init, jfr, logs, macros, motd, players,
+    playtime, ports, pregen, presets, records, remote, replay, schedule, ssh, supervisor, top,
+    upgrade, users, world,
+};
+use mcwrap_core::{
+    cmd_attach, cmd_dry_run, cmd_exec, cmd_expect, cmd_kill, cmd_list, cmd_log, cmd_send_all,
+    cmd_start, cmd_status, cmd_stop, cmd_tail, parse_duration, read_commands, unix_now,
+    wait_until_ready, watch_status, StartOptions, StopOptions, HISTORY_LINES, WATCH_INTERVAL,
+};
+use regex::Regex;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixStream;
-use tokio::signal::unix::{signal, SignalKind};
-
-mod pty;
 
 /// Minecraft server wrapper with PTY support for interactive console
 #[derive(Parser)]
 #[command(name = "mcwrap")]
 #[command(about = "Minecraft server wrapper with PTY support", long_about = None)]
+#[command(after_help = "Run a subcommand on another machine over SSH with --remote USER@HOST:DIR")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -37,15 +39,119 @@ struct Cli {
     /// Use basic pipe-based mode (no PTY, no tab completion)
     #[arg(long, global = true)]
     basic: bool,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+}
+
+/// TLS files for `serve` and `--host` (default to `[remote]` in the global config)
+#[derive(Args)]
+struct TlsArgs {
+    /// Certificate presented to the other side (PEM)
+    #[arg(long, global = true)]
+    tls_cert: Option<PathBuf>,
+    /// Private key for --tls-cert (PEM)
+    #[arg(long, global = true)]
+    tls_key: Option<PathBuf>,
+    /// CA that must have signed the other side's certificate (PEM)
+    #[arg(long, global = true)]
+    tls_ca: Option<PathBuf>,
+}
+
+impl TlsArgs {
+    fn resolve(self) -> Result<remote::TlsFiles> {
+        remote::TlsFiles::resolve(self.tls_cert, self.tls_key, self.tls_ca)
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Set up a new server: download its JAR, accept the EULA and write its config
+    Init {
+        /// Directory for the server (created if missing)
+        dir: PathBuf,
+        /// Server software (default: paper)
+        #[arg(long = "type", value_enum)]
+        server_type: Option<init::ServerType>,
+        /// Minecraft version (default: the latest release)
+        #[arg(long)]
+        version: Option<String>,
+        /// Heap size (e.g. 4G)
+        #[arg(long)]
+        memory: Option<String>,
+        /// Port players connect to, or "auto" for the next free one (default: 25565)
+        #[arg(long)]
+        port: Option<ports::PortChoice>,
+        /// Accept the Minecraft EULA (https://aka.ms/MinecraftEULA)
+        #[arg(long)]
+        accept_eula: bool,
+        /// Take the setup from ~/.config/mcwrap/templates/NAME.toml
+        #[arg(long, value_name = "NAME")]
+        template: Option<String>,
+        /// Start the server once it is set up
+        #[arg(long)]
+        start: bool,
+        /// Don't ask; take the defaults for what isn't given
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Copy a server (configs, plugins and worlds) with its own ports, and register the copy
+    Clone {
+        /// Server directory to copy
+        src: PathBuf,
+        /// Directory for the copy (must be missing or empty)
+        dst: PathBuf,
+        /// Leave the worlds out, so that the copy generates new ones
+        #[arg(long)]
+        without_worlds: bool,
+        /// Port for the copy (default: the next free one)
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Register a server that is already running (in screen, tmux...) without restarting it
+    Adopt {
+        /// Server directory
+        dir: PathBuf,
+        /// PID of the server's java process (default: the java process working in the directory)
+        #[arg(long)]
+        pid: Option<i32>,
+    },
     /// Start a Minecraft server
     Start {
         /// Server directory containing the JAR file
         dir: PathBuf,
-        /// Java arguments (default: -Xms2G -Xmx4G -jar <jar> --nogui)
+        /// Run the PTY daemon in the foreground (used by mcwrapd and service managers)
+        #[arg(long)]
+        foreground: bool,
+        /// Serve the raw byte protocol on the socket for older mcwrap clients
+        #[arg(long)]
+        legacy_raw: bool,
+        /// Wait until the server has finished starting, failing if it exits first
+        #[arg(long, conflicts_with = "foreground")]
+        wait: bool,
+        /// Print the command, directory and environment the server would get, without starting it
+        #[arg(long, conflicts_with_all = ["foreground", "wait"])]
+        dry_run: bool,
+        /// Report each step as a JSON line (jar_resolved, forked, ready, failed...) instead
+        #[arg(long, conflicts_with = "dry_run")]
+        json: bool,
+        /// Run this program instead of java (e.g. ./run.sh), with the trailing arguments
+        #[arg(long, value_name = "PROGRAM")]
+        exec: Option<String>,
+        #[command(flatten)]
+        priority: Priority,
+        #[command(flatten)]
+        container: Container,
+        /// How much the PTY daemon writes to daemon.log in the wrap dir
+        #[arg(long, value_enum, default_value_t)]
+        log_level: daemon_log::Level,
+        /// Log garbage collections to the wrap dir, for `mcwrap gc`
+        #[arg(long)]
+        gc_log: bool,
+        /// Add a garbage collector preset to the Java arguments (overrides [java] preset)
+        #[arg(long, value_enum)]
+        preset: Option<presets::Preset>,
+        /// Java arguments (default: -Xms2G -Xmx4G -jar <jar> --nogui, or [java] memory)
         #[arg(trailing_var_arg = true)]
         java_args: Vec<String>,
     },
@@ -56,660 +162,714 @@ enum Commands {
         /// Raw mode for MCPanel (no decorations)
         #[arg(long)]
         raw: bool,
+        /// Reach the server through `mcwrap serve` on HOST[:PORT]
+        #[arg(long)]
+        host: Option<String>,
+        /// Type into the console even if another client already does
+        #[arg(long)]
+        take: bool,
+        /// Show what this pattern matches in reverse video (repeatable)
+        #[arg(long, value_name = "REGEX")]
+        highlight: Vec<String>,
+        /// Recent lines to show first [default: 30, or 0 with --raw]
+        #[arg(long, value_name = "LINES")]
+        history: Option<usize>,
+    },
+    /// Open a line-editing console (friendlier than a raw attach)
+    Console {
+        /// Server directory
+        dir: PathBuf,
     },
     /// Send a command to the server
     Send {
+        /// Server directory
+        dir: PathBuf,
+        /// Command to send, or - to send each line read from stdin
+        #[arg(required_unless_present = "file", conflicts_with = "file")]
+        command: Option<String>,
+        /// Send each line of FILE (blank lines and lines starting with # are skipped)
+        #[arg(short, long, value_name = "FILE")]
+        file: Option<PathBuf>,
+        /// Wait this long between commands (e.g. 500ms, 2s)
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        delay: Option<Duration>,
+        /// Skip the daemon's command queue and rate limit (needs the admin role)
+        #[arg(long)]
+        no_queue: bool,
+        /// Reach the server through `mcwrap serve` on HOST[:PORT]
+        #[arg(long)]
+        host: Option<String>,
+    },
+    /// Run a macro from the config, or list them
+    Macro {
+        /// Server directory
+        dir: PathBuf,
+        /// Macro to run
+        name: Option<String>,
+        /// Set a variable used by the macro (repeatable)
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = macros::parse_var)]
+        vars: Vec<(String, String)>,
+        /// Print the commands without sending them
+        #[arg(long, requires = "name")]
+        dry_run: bool,
+    },
+    /// Send a command and print the console output it produces
+    Exec {
         /// Server directory
         dir: PathBuf,
         /// Command to send
         command: String,
+        /// Longest to wait for output (e.g. 500ms, 5s, 1m)
+        #[arg(long, default_value = "5s", value_parser = parse_duration)]
+        timeout: Duration,
+    },
+    /// Wait for console output matching a regex, optionally after sending a command
+    Expect {
+        /// Server directory
+        dir: PathBuf,
+        /// Command to send once watching
+        #[arg(long)]
+        send: Option<String>,
+        /// Regex to wait for, matched against each console line
+        #[arg(long)]
+        until: Regex,
+        /// Give up (exiting non-zero) after this long
+        #[arg(long, default_value = "30s", value_parser = parse_duration)]
+        timeout: Duration,
     },
     /// Show server status
     Status {
         /// Server directory
         dir: PathBuf,
+        /// Reach the server through `mcwrap serve` on HOST[:PORT]
+        #[arg(long)]
+        host: Option<String>,
+        /// Warn when free disk space is below SIZE (e.g. 10G; default from [disk] min_free)
+        #[arg(long, value_name = "SIZE", value_parser = disk::parse_size)]
+        threshold: Option<u64>,
+        /// Keep redrawing the status until interrupted
+        #[arg(short, long, conflicts_with = "host")]
+        watch: bool,
+        /// How often --watch redraws (default 2s)
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "watch")]
+        interval: Option<Duration>,
     },
     /// Stop the server gracefully
     Stop {
         /// Server directory
         dir: PathBuf,
+        /// Count down in chat for this long before stopping (e.g. 60, 5m)
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        warn: Option<Duration>,
+        /// Kick remaining players with this message just before stopping
+        #[arg(long, value_name = "MESSAGE")]
+        kick: Option<String>,
+        /// How long to wait for the server to exit after `stop`
+        #[arg(long, default_value = "60s", value_parser = parse_duration)]
+        timeout: Duration,
+        /// If it hasn't exited by then, terminate it (SIGTERM, then SIGKILL)
+        #[arg(long)]
+        then_kill: bool,
+    },
+    /// Terminate the server without a graceful stop: SIGTERM, then SIGKILL
+    Kill {
+        /// Server directory
+        dir: PathBuf,
+        /// How long to wait after SIGTERM before sending SIGKILL
+        #[arg(long, default_value = "10s", value_parser = parse_duration)]
+        grace: Duration,
+    },
+    /// Stop a server and remove everything mcwrap keeps about it
+    Destroy {
+        /// Server directory
+        dir: PathBuf,
+        /// Also delete the server directory, worlds included
+        #[arg(long)]
+        purge: bool,
+        /// Don't ask before deleting the server directory
+        #[arg(short, long, requires = "purge")]
+        yes: bool,
+    },
+    /// Swap the server JAR for a new version, rolling back if it fails to start
+    Upgrade {
+        /// Server directory
+        dir: PathBuf,
+        /// The new server JAR
+        #[arg(long, required_unless_present = "to", conflicts_with = "to")]
+        jar: Option<PathBuf>,
+        /// Download the latest Paper build for this Minecraft version instead
+        #[arg(long, value_name = "VERSION")]
+        to: Option<String>,
+        /// Count down in chat for this long before stopping (0 to stop right away)
+        #[arg(long, value_name = "DURATION", default_value = "1m", value_parser = parse_duration)]
+        warn: Duration,
+        /// Don't back up the worlds and the old JAR to backups/ first
+        #[arg(long)]
+        no_backup: bool,
+        /// How long the new version gets to finish starting
+        #[arg(long, default_value = "5m", value_parser = parse_duration)]
+        timeout: Duration,
+    },
+    /// List a server's worlds or switch it to another one
+    World {
+        #[command(subcommand)]
+        action: world::WorldAction,
+    },
+    /// Set or check the icon shown in the multiplayer server list
+    Icon {
+        #[command(subcommand)]
+        action: icon::IconAction,
+    },
+    /// Set the message shown in the multiplayer server list, or show the current one
+    Motd {
+        /// Server directory
+        dir: PathBuf,
+        /// New MOTD; \n breaks the line, & starts a color or format code (\& for a plain &)
+        text: Option<String>,
+        /// Only show how the new MOTD renders
+        #[arg(long, requires = "text")]
+        dry_run: bool,
+    },
+    /// List the players the server knows, or who is online, as "name uuid" lines
+    Players {
+        /// Server directory
+        dir: PathBuf,
+        /// Ask the server who is online right now
+        #[arg(long)]
+        online: bool,
+        /// Print each player as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show how long players have played: in total, recently, first and last seen
+    Playtime {
+        /// Server directory
+        dir: PathBuf,
+        /// Only this player
+        player: Option<String>,
+        /// Print each player as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Generate the chunks around the center ahead of time, with Chunky or forceload
+    Pregen {
+        /// Server directory
+        dir: PathBuf,
+        /// Blocks from the center to generate
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        radius: u32,
+        /// Block coordinates of the center
+        #[arg(long, value_name = "X,Z", default_value = "0,0", allow_hyphen_values = true,
+              value_parser = pregen::parse_center)]
+        center: (i32, i32),
+        /// World to generate (for forceload, a dimension such as minecraft:the_nether)
+        #[arg(long)]
+        world: Option<String>,
+        /// Use forceload even if Chunky is installed
+        #[arg(long)]
+        vanilla: bool,
+        /// Download Chunky from Modrinth if it isn't installed
+        #[arg(long, conflicts_with = "vanilla")]
+        install_chunky: bool,
+        /// Keep generating while players are online
+        #[arg(long)]
+        no_pause: bool,
+    },
+    /// Sum up the GC log of a server started with --gc-log
+    Gc {
+        /// Server directory
+        dir: PathBuf,
+    },
+    /// Record the running server with Java Flight Recorder
+    Jfr {
+        #[command(subcommand)]
+        action: jfr::JfrAction,
+    },
+    /// Save a thread or heap dump of the running server
+    Dump {
+        #[command(subcommand)]
+        action: dump::DumpAction,
+    },
+    /// Print a compose service or Containerfile that runs a server like mcwrap does
+    Export {
+        #[command(subcommand)]
+        action: export::ExportAction,
+    },
+    /// Check a server's JVM flags, or compare two servers'
+    Flags {
+        #[command(subcommand)]
+        action: flags::FlagsAction,
     },
     /// Show last N lines of console log
     Log {
         /// Server directory
         dir: PathBuf,
-        /// Number of lines (default: 100)
-        #[arg(default_value = "100")]
+        /// Number of lines, or of messages with --level or --json (default: 100, or all with
+        /// --since)
+        lines: Option<usize>,
+        /// Only lines logged since then, e.g. "2024-05-01 12:00" or "2h" (needs [log] timestamps)
+        #[arg(long, value_name = "TIME")]
+        since: Option<String>,
+        /// Strip colors (the default when the output isn't a terminal)
+        #[arg(long)]
+        plain: bool,
+        /// Only messages at this level or above, with their stack traces
+        #[arg(long, value_enum)]
+        level: Option<records::Level>,
+        /// Print each message as a JSON object, with its stack trace
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the console logs of previous runs, or show one
+    Logs {
+        /// Server directory
+        dir: PathBuf,
+        /// Show run N (1 = the most recent)
+        #[arg(value_name = "N")]
+        run: Option<usize>,
+    },
+    /// Play a console log back with the pauses it was written with
+    Replay {
+        /// Server directory
+        dir: PathBuf,
+        /// Play archived run N (1 = the most recent) instead of the current one
+        #[arg(long, value_name = "N")]
+        run: Option<usize>,
+        /// Start at this time of day, e.g. "14:00", or at a time as for `log --since`
+        #[arg(long, value_name = "TIME")]
+        from: Option<String>,
+        /// How much faster to play it than it was written, e.g. "4x"
+        #[arg(long, value_name = "FACTOR", value_parser = replay::parse_speed)]
+        speed: Option<f64>,
+        /// Strip colors (the default when the output isn't a terminal)
+        #[arg(long)]
+        plain: bool,
+    },
+    /// Count the exceptions in the console logs, by class and top stack frame
+    Errors {
+        /// Server directory
+        dir: PathBuf,
+        /// Only exceptions since then, e.g. "24h" or "2024-05-01 12:00"
+        #[arg(long, value_name = "TIME")]
+        since: Option<String>,
+    },
+    /// List recent console commands, or re-send one
+    History {
+        /// Server directory
+        dir: PathBuf,
+        /// Number of entries (default: 50)
+        #[arg(default_value = "50")]
+        lines: usize,
+        /// Re-send entry N
+        #[arg(long, value_name = "N")]
+        run: Option<usize>,
+    },
+    /// List the scheduled tasks and when they run next
+    Tasks {
+        /// Server directory
+        dir: PathBuf,
+        /// Run task N now instead of waiting for its schedule
+        #[arg(long, value_name = "N")]
+        run_now: Option<usize>,
+    },
+    /// Show who sent which commands to the console
+    Audit {
+        /// Server directory
+        dir: PathBuf,
+        /// Number of entries (default: 50)
+        #[arg(default_value = "50")]
+        lines: usize,
+    },
+    /// Show what happened to the server: starts, stops, crashes, players joining and leaving
+    Events {
+        /// Server directory
+        dir: PathBuf,
+        /// Keep printing new events as they happen
+        #[arg(short, long)]
+        follow: bool,
+        /// Print each event as a JSON object
+        #[arg(long)]
+        json: bool,
+        /// Number of past events (default: 20)
+        #[arg(default_value = "20")]
         lines: usize,
     },
     /// Follow console log (read-only)
     Tail {
         /// Server directory
         dir: PathBuf,
+        /// Strip colors (the default when the output isn't a terminal)
+        #[arg(long)]
+        plain: bool,
+        /// Show what this pattern matches in reverse video (repeatable)
+        #[arg(long, value_name = "REGEX", conflicts_with = "plain")]
+        highlight: Vec<String>,
+        /// Only show lines this pattern matches, e.g. "Steve|WorldGuard"
+        #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+        grep: Option<Regex>,
+        /// Leave out lines this pattern matches
+        #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+        exclude: Option<Regex>,
     },
     /// List all managed servers
-    List,
-}
-
-/// Server state persisted to disk
-#[derive(Serialize, Deserialize)]
-struct ServerState {
-    pid: i32,
-    pty_master: Option<String>, // Path to PTY master (for basic mode: None)
-    started_at: u64,
-    server_dir: PathBuf,
-}
-
-/// Get the wrap directory for a server
-fn get_wrap_dir(server_dir: &Path) -> PathBuf {
-    let wrap_base = dirs::home_dir()
-        .unwrap_or_else(|| PathBuf::from("/tmp"))
-        .join(".mcwrap");
-
-    // Create unique ID from server path
-    let id = format!("{:x}", md5::compute(server_dir.to_string_lossy().as_bytes()));
-    let short_id = &id[..12];
-
-    wrap_base.join(short_id)
-}
-
-/// Paths for a server's state files
-struct ServerPaths {
-    wrap_dir: PathBuf,
-    state_file: PathBuf,
-    log_file: PathBuf,
-    socket_path: PathBuf,
-}
-
-impl ServerPaths {
-    fn new(server_dir: &Path) -> Self {
-        let wrap_dir = get_wrap_dir(server_dir);
-        Self {
-            state_file: wrap_dir.join("state.json"),
-            log_file: wrap_dir.join("console.log"),
-            socket_path: wrap_dir.join("pty.sock"),
-            wrap_dir,
-        }
-    }
-
-    fn ensure_dir(&self) -> Result<()> {
-        fs::create_dir_all(&self.wrap_dir)?;
-        Ok(())
-    }
-}
-
-/// Check if a server is running
-fn is_running(paths: &ServerPaths) -> Option<ServerState> {
-    let state: ServerState = serde_json::from_reader(File::open(&paths.state_file).ok()?).ok()?;
-
-    // Check if process is still alive
-    if kill(Pid::from_raw(state.pid), None).is_ok() {
-        Some(state)
-    } else {
-        // Clean up stale state
-        let _ = fs::remove_dir_all(&paths.wrap_dir);
-        None
-    }
+    List {
+        /// Show a table with uptime, start counts and the last crash
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Live dashboard of all managed servers
+    Top,
+    /// Start a server automatically on boot
+    Enable {
+        /// Server directory
+        dir: PathBuf,
+        /// Start these servers first (repeatable)
+        #[arg(long)]
+        after: Vec<PathBuf>,
+    },
+    /// Stop starting a server on boot
+    Disable {
+        /// Server directory
+        dir: PathBuf,
+    },
+    /// Start all boot-enabled servers (run by the installed boot hook)
+    Boot,
+    /// Run the mcwrapd supervisor daemon in the foreground
+    Daemon {
+        /// Show the servers supervised by a running mcwrapd instead
+        #[arg(long)]
+        status: bool,
+    },
+    /// Manage users and roles for the control API
+    User {
+        #[command(subcommand)]
+        action: users::UserAction,
+    },
+    /// Accept remote attach/send/status over mutually-authenticated TLS
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:7077")]
+        listen: SocketAddr,
+    },
+    /// Print a completion script for a shell (e.g. source <(mcwrap completions bash))
+    Completions {
+        #[arg(value_enum)]
+        shell: completions::Shell,
+    },
+    /// List the registered server directories, for completion scripts
+    #[command(name = "__servers", hide = true)]
+    Servers,
 }
 
-/// Find the server JAR file
-fn find_jar(server_dir: &Path) -> Result<PathBuf> {
-    // Look for common jar names
-    let candidates = ["paper.jar", "server.jar", "spigot.jar", "bukkit.jar"];
-
-    for name in candidates {
-        let path = server_dir.join(name);
-        if path.exists() {
-            return Ok(path);
-        }
-    }
-
-    // Look for any .jar file
-    for entry in fs::read_dir(server_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().map_or(false, |e| e == "jar") {
-            return Ok(path);
-        }
+impl Commands {
+    /// Whether the command prints JSON, and so its errors too
+    fn prints_json(&self) -> bool {
+        matches!(
+            self,
+            Commands::Players { json: true, .. }
+                | Commands::Playtime { json: true, .. }
+                | Commands::Log { json: true, .. }
+                | Commands::Events { json: true, .. }
+        )
     }
-
-    bail!("No server JAR found in {:?}", server_dir)
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
-
-    match cli.command {
-        Commands::Start { dir, java_args } => cmd_start(&dir, java_args, cli.basic).await,
-        Commands::Attach { dir, raw } => cmd_attach(&dir, raw, cli.basic).await,
-        Commands::Send { dir, command } => cmd_send(&dir, &command).await,
-        Commands::Status { dir } => cmd_status(&dir),
-        Commands::Stop { dir } => cmd_stop(&dir).await,
-        Commands::Log { dir, lines } => cmd_log(&dir, lines),
-        Commands::Tail { dir } => cmd_tail(&dir).await,
-        Commands::List => cmd_list(),
+async fn main() {
+    // Scripts tell failures apart by the exit code (see mcwrap_core::failure)
+    if let Err(e) = run().await {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(failure::exit_code(&e));
     }
 }
 
-/// Start the Minecraft server with PTY
-async fn cmd_start(server_dir: &Path, java_args: Vec<String>, basic_mode: bool) -> Result<()> {
-    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
-    let paths = ServerPaths::new(&server_dir);
-
-    if is_running(&paths).is_some() {
-        bail!("Server is already running");
+async fn run() -> Result<()> {
+    // Installed as `mcwrapd` (e.g. a symlink), act as the supervisor directly
+    let argv0 = std::env::args_os().next().map(PathBuf::from);
+    if argv0.as_deref().and_then(Path::file_stem).is_some_and(|s| s == "mcwrapd") {
+        return supervisor::run().await;
     }
 
-    // Clean up old state
-    let _ = fs::remove_dir_all(&paths.wrap_dir);
-    paths.ensure_dir()?;
-
-    let jar = find_jar(&server_dir)?;
-    let jar_name = jar.file_name().unwrap().to_string_lossy();
-
-    // Build Java command
-    let java_args = if java_args.is_empty() {
-        vec![
-            "-Dnet.kyori.ansi.colorLevel=truecolor".to_string(),
-            "-Xms2G".to_string(),
-            "-Xmx4G".to_string(),
-            "-jar".to_string(),
-            jar_name.to_string(),
-            "--nogui".to_string(),
-        ]
-    } else {
-        java_args
-    };
-
-    println!("Starting server...");
-    println!("  Directory: {:?}", server_dir);
-    println!("  JAR: {}", jar_name);
-    println!("  Mode: {}", if basic_mode { "basic (pipe)" } else { "PTY" });
-
-    if basic_mode {
-        start_basic_mode(&server_dir, &paths, &java_args).await
-    } else {
-        start_pty_mode(&server_dir, &paths, &java_args).await
+    // `--remote` replaces the server directory, so it is handled before parsing
+    if let Some(code) = ssh::forward_if_remote(&Cli::command())? {
+        std::process::exit(code);
     }
-}
-
-/// Start server in basic pipe mode (no PTY)
-async fn start_basic_mode(
-    server_dir: &Path,
-    paths: &ServerPaths,
-    java_args: &[String],
-) -> Result<()> {
-    // Create FIFO for input
-    let input_fifo = paths.wrap_dir.join("input");
-    nix::unistd::mkfifo(&input_fifo, Mode::from_bits_truncate(0o600))?;
-
-    // Spawn Java process
-    let mut cmd = Command::new("java");
-    cmd.args(java_args)
-        .current_dir(server_dir)
-        .env("TERM", "xterm-256color")
-        .env("COLORTERM", "truecolor")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-
-    let mut child = cmd.spawn().context("Failed to start Java")?;
-    let pid = child.id() as i32;
-
-    // Save state
-    let state = ServerState {
-        pid,
-        pty_master: None,
-        started_at: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs(),
-        server_dir: server_dir.to_path_buf(),
-    };
-    fs::write(&paths.state_file, serde_json::to_string(&state)?)?;
-
-    // Handle output in background
-    let log_path = paths.log_file.clone();
-    let stdout = child.stdout.take().unwrap();
-    let stderr = child.stderr.take().unwrap();
 
-    thread::spawn(move || {
-        let mut log_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&log_path)
-            .unwrap();
-
-        let stdout_reader = BufReader::new(stdout);
-        for line in stdout_reader.lines().map_while(Result::ok) {
-            writeln!(log_file, "{}", line).ok();
-        }
-    });
-
-    thread::spawn(move || {
-        let stderr_reader = BufReader::new(stderr);
-        for line in stderr_reader.lines().map_while(Result::ok) {
-            eprintln!("{}", line);
-        }
-    });
-
-    // Handle input from FIFO
-    let stdin = child.stdin.take().unwrap();
-    let input_fifo_clone = input_fifo.clone();
-    thread::spawn(move || {
-        let mut stdin = stdin;
-        loop {
-            if let Ok(fifo) = File::open(&input_fifo_clone) {
-                let reader = BufReader::new(fifo);
-                for line in reader.lines().map_while(Result::ok) {
-                    writeln!(stdin, "{}", line).ok();
-                    stdin.flush().ok();
-                }
-            }
-            thread::sleep(Duration::from_millis(100));
+    let cli = Cli::parse();
+    let json = cli.command.prints_json();
+    execute(cli).await.inspect_err(|e| {
+        if json {
+            println!("{}", failure::to_json(e));
         }
-    });
-
-    println!("Started (PID {})", pid);
-    Ok(())
-}
-
-/// Start server with PTY for full terminal emulation
-async fn start_pty_mode(
-    server_dir: &Path,
-    paths: &ServerPaths,
-    java_args: &[String],
-) -> Result<()> {
-    // Fork and create PTY
-    let pty_result = pty::spawn_with_pty(server_dir, java_args, &paths.log_file, &paths.socket_path)?;
-
-    // Save state
-    let state = ServerState {
-        pid: pty_result.child_pid,
-        pty_master: Some(paths.socket_path.to_string_lossy().to_string()),
-        started_at: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs(),
-        server_dir: server_dir.to_path_buf(),
-    };
-    fs::write(&paths.state_file, serde_json::to_string(&state)?)?;
-
-    println!("Started (PID {})", pty_result.child_pid);
-    println!("  Socket: {:?}", paths.socket_path);
-    Ok(())
+    })
 }
 
-/// Attach to server console
-async fn cmd_attach(server_dir: &Path, raw: bool, _basic_mode: bool) -> Result<()> {
-    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
-    let paths = ServerPaths::new(&server_dir);
-
-    let state = is_running(&paths).context("Server is not running")?;
-
-    if state.pty_master.is_some() {
-        // PTY mode - connect to socket
-        attach_pty(&paths, raw).await
-    } else {
-        // Basic mode - tail log + send to FIFO
-        attach_basic(&paths, raw).await
-    }
-}
-
-/// Attach to PTY-based server
-async fn attach_pty(paths: &ServerPaths, raw: bool) -> Result<()> {
-    let mut stream = UnixStream::connect(&paths.socket_path)
-        .await
-        .context("Failed to connect to PTY socket")?;
-
-    if !raw {
-        println!("Attached to server (Ctrl+C to detach)");
-        println!("─────────────────────────────────────────");
-
-        // Show recent history
-        if let Ok(content) = fs::read_to_string(&paths.log_file) {
-            let lines: Vec<&str> = content.lines().collect();
-            let start = lines.len().saturating_sub(30);
-            for line in &lines[start..] {
-                println!("{}", line);
-            }
+async fn execute(cli: Cli) -> Result<()> {
+    match cli.command {
+        Commands::Init {
+            dir,
+            server_type,
+            version,
+            memory,
+            port,
+            accept_eula,
+            template,
+            start,
+            yes,
+        } => {
+            let opts = init::InitOptions {
+                server_type,
+                version,
+                memory,
+                port,
+                accept_eula,
+                template,
+                start,
+                yes,
+            };
+            init::cmd_init(&dir, opts).await
         }
-
-        println!("─────────────────────────────────────────");
-    }
-
-    // Set terminal to raw mode
-    let stdin = std::io::stdin();
-    let stdin_fd = stdin.as_raw_fd();
-    let stdin_borrowed = unsafe { BorrowedFd::borrow_raw(stdin_fd) };
-    let original_termios = tcgetattr(&stdin_borrowed).ok();
-    if let Some(ref orig) = original_termios {
-        let mut raw_termios = orig.clone();
-        cfmakeraw(&mut raw_termios);
-        tcsetattr(&stdin_borrowed, SetArg::TCSANOW, &raw_termios)?;
-    }
-
-    // Setup cleanup
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-
-    // Handle Ctrl+C
-    let mut sigint = signal(SignalKind::interrupt())?;
-    let r2 = running.clone();
-    tokio::spawn(async move {
-        sigint.recv().await;
-        r2.store(false, Ordering::SeqCst);
-    });
-
-    // Bidirectional I/O
-    let (mut reader, mut writer) = stream.into_split();
-
-    // Read from PTY, write to stdout
-    let r3 = running.clone();
-    let stdout_handle = tokio::spawn(async move {
-        let mut stdout = tokio::io::stdout();
-        let mut buf = [0u8; 4096];
-        while r3.load(Ordering::SeqCst) {
-            match tokio::time::timeout(Duration::from_millis(100), reader.read(&mut buf)).await {
-                Ok(Ok(0)) => break,
-                Ok(Ok(n)) => {
-                    stdout.write_all(&buf[..n]).await.ok();
-                    stdout.flush().await.ok();
-                }
-                Ok(Err(_)) => break,
-                Err(_) => continue, // timeout, check running flag
+        Commands::Clone {
+            src,
+            dst,
+            without_worlds,
+            port,
+        } => clone::cmd_clone(&src, &dst, without_worlds, port).await,
+        Commands::Adopt { dir, pid } => adopt::cmd_adopt(&dir, pid).await,
+        Commands::Start {
+            dir,
+            foreground,
+            legacy_raw,
+            wait,
+            dry_run,
+            json,
+            exec,
+            priority,
+            container,
+            log_level,
+            gc_log,
+            preset,
+            java_args,
+        } => {
+            let progress = Progress::new(json);
+            let opts = StartOptions {
+                basic: cli.basic,
+                foreground,
+                legacy_raw,
+                exec,
+                priority,
+                container,
+                log_level,
+                gc_log,
+                preset,
+                progress,
+            };
+            if dry_run {
+                return cmd_dry_run(&dir, java_args, &opts);
             }
-        }
-    });
-
-    // Read from stdin, write to PTY
-    let stdin_handle = tokio::spawn(async move {
-        let mut stdin = tokio::io::stdin();
-        let mut buf = [0u8; 1024];
-        while r.load(Ordering::SeqCst) {
-            match tokio::time::timeout(Duration::from_millis(100), stdin.read(&mut buf)).await {
-                Ok(Ok(0)) => break,
-                Ok(Ok(n)) => {
-                    writer.write_all(&buf[..n]).await.ok();
-                    writer.flush().await.ok();
+            let since = unix_now();
+            let start = async {
+                cmd_start(&dir, java_args, opts).await?;
+                if wait {
+                    wait_until_ready(&dir, since, progress).await?;
                 }
-                Ok(Err(_)) => break,
-                Err(_) => continue,
-            }
+                Ok(())
+            };
+            start.await.inspect_err(|e| progress.fail(e))
         }
-    });
-
-    // Wait for either to finish
-    tokio::select! {
-        _ = stdout_handle => {},
-        _ = stdin_handle => {},
-    }
-
-    // Restore terminal
-    if let Some(orig) = original_termios {
-        tcsetattr(&stdin_borrowed, SetArg::TCSANOW, &orig)?;
-    }
-
-    if !raw {
-        println!("\nDetached.");
-    }
-
-    Ok(())
-}
-
-/// Attach to basic pipe-based server
-async fn attach_basic(paths: &ServerPaths, raw: bool) -> Result<()> {
-    let input_fifo = paths.wrap_dir.join("input");
-
-    if !raw {
-        println!("Attached to server (Ctrl+C to detach)");
-        println!("─────────────────────────────────────────");
-
-        // Show recent history
-        if let Ok(content) = fs::read_to_string(&paths.log_file) {
-            let lines: Vec<&str> = content.lines().collect();
-            let start = lines.len().saturating_sub(30);
-            for line in &lines[start..] {
-                println!("{}", line);
-            }
+        Commands::Attach {
+            dir,
+            raw,
+            host: Some(host),
+            take,
+            highlight,
+            history,
+        } => {
+            let highlighter = (!raw).then(|| Highlighter::new(&highlight)).transpose()?;
+            let history = history.unwrap_or(if raw { 0 } else { HISTORY_LINES });
+            let tls = cli.tls.resolve()?;
+            remote::cmd_attach(&host, &dir, raw, take, history, highlighter, &tls).await
         }
-
-        println!("─────────────────────────────────────────");
-    }
-
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-
-    // Handle Ctrl+C
-    let mut sigint = signal(SignalKind::interrupt())?;
-    let r2 = running.clone();
-    tokio::spawn(async move {
-        sigint.recv().await;
-        r2.store(false, Ordering::SeqCst);
-    });
-
-    // Tail log file
-    let log_path = paths.log_file.clone();
-    let r3 = running.clone();
-    tokio::spawn(async move {
-        let mut last_pos = 0u64;
-        while r3.load(Ordering::SeqCst) {
-            if let Ok(mut file) = File::open(&log_path) {
-                use std::io::Seek;
-                let len = file.metadata().map(|m| m.len()).unwrap_or(0);
-                if len > last_pos {
-                    file.seek(std::io::SeekFrom::Start(last_pos)).ok();
-                    let mut buf = String::new();
-                    file.read_to_string(&mut buf).ok();
-                    print!("{}", buf);
-                    std::io::stdout().flush().ok();
-                    last_pos = len;
-                }
-            }
-            tokio::time::sleep(Duration::from_millis(100)).await;
+        Commands::Attach {
+            dir,
+            raw,
+            host: None,
+            take,
+            highlight,
+            history,
+        } => {
+            let highlighter = (!raw).then(|| Highlighter::new(&highlight)).transpose()?;
+            let history = history.unwrap_or(if raw { 0 } else { HISTORY_LINES });
+            cmd_attach(&dir, raw, take, history, highlighter).await
         }
-    });
-
-    // Read commands from stdin
-    let input = tokio::io::stdin();
-    let mut reader = tokio::io::BufReader::new(input);
-    let mut line = String::new();
-
-    while r.load(Ordering::SeqCst) {
-        line.clear();
-        match tokio::time::timeout(Duration::from_millis(100), reader.read_line(&mut line)).await {
-            Ok(Ok(0)) => break,
-            Ok(Ok(_)) => {
-                // Write to FIFO
-                if let Ok(mut fifo) = OpenOptions::new().write(true).open(&input_fifo) {
-                    write!(fifo, "{}", line).ok();
+        Commands::Console { dir } => console::cmd_console(&dir).await,
+        Commands::Send {
+            dir,
+            command,
+            file,
+            delay,
+            no_queue,
+            host,
+        } => {
+            let commands = match file {
+                Some(file) => {
+                    let file =
+                        File::open(&file).with_context(|| format!("Failed to open {:?}", file))?;
+                    read_commands(BufReader::new(file))?
                 }
-            }
-            Ok(Err(_)) => break,
-            Err(_) => continue,
+                None if command.as_deref() == Some("-") => read_commands(std::io::stdin().lock())?,
+                None => command.into_iter().collect(),
+            };
+            let remote = match host {
+                Some(host) => Some((host, cli.tls.resolve()?)),
+                None => None,
+            };
+            cmd_send_all(&dir, &commands, delay, !no_queue, remote.as_ref()).await
         }
-    }
-
-    if !raw {
-        println!("\nDetached.");
-    }
-
-    Ok(())
-}
-
-/// Send a command to the server
-async fn cmd_send(server_dir: &Path, command: &str) -> Result<()> {
-    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
-    let paths = ServerPaths::new(&server_dir);
-
-    let state = is_running(&paths).context("Server is not running")?;
-
-    if state.pty_master.is_some() {
-        // PTY mode
-        let mut stream = UnixStream::connect(&paths.socket_path)
-            .await
-            .context("Failed to connect to PTY socket")?;
-        stream.write_all(command.as_bytes()).await?;
-        stream.write_all(b"\n").await?;
-    } else {
-        // Basic mode
-        let input_fifo = paths.wrap_dir.join("input");
-        let mut fifo = OpenOptions::new()
-            .write(true)
-            .open(&input_fifo)
-            .context("Failed to open input FIFO")?;
-        writeln!(fifo, "{}", command)?;
-    }
-
-    Ok(())
-}
-
-/// Show server status
-fn cmd_status(server_dir: &Path) -> Result<()> {
-    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
-    let paths = ServerPaths::new(&server_dir);
-
-    if let Some(state) = is_running(&paths) {
-        let mode = if state.pty_master.is_some() { "PTY" } else { "basic" };
-        println!("● {} running", server_dir.file_name().unwrap().to_string_lossy());
-        println!("  PID: {}", state.pid);
-        println!("  Mode: {}", mode);
-        println!("  Log: {:?}", paths.log_file);
-
-        // Count log lines
-        if let Ok(content) = fs::read_to_string(&paths.log_file) {
-            println!("  Lines: {}", content.lines().count());
+        Commands::Macro {
+            dir,
+            name,
+            vars,
+            dry_run,
+        } => macros::cmd_macro(&dir, name.as_deref(), vars, dry_run).await,
+        Commands::Exec { dir, command, timeout } => cmd_exec(&dir, &command, timeout).await,
+        Commands::Expect {
+            dir,
+            send,
+            until,
+            timeout,
+        } => cmd_expect(&dir, send.as_deref(), &until, timeout).await,
+        Commands::Status { dir, host: Some(host), .. } => {
+            remote::cmd_status(&host, &dir, &cli.tls.resolve()?).await
         }
-    } else {
-        println!("○ {} not running", server_dir.file_name().unwrap().to_string_lossy());
-    }
-
-    Ok(())
-}
-
-/// Stop the server gracefully
-async fn cmd_stop(server_dir: &Path) -> Result<()> {
-    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
-    let paths = ServerPaths::new(&server_dir);
-
-    let state = is_running(&paths).context("Server is not running")?;
-
-    println!("Stopping server...");
-
-    // Send stop command
-    cmd_send(&server_dir, "stop").await?;
-
-    // Wait for process to exit (up to 60 seconds)
-    for _ in 0..60 {
-        if kill(Pid::from_raw(state.pid), None).is_err() {
-            println!("Server stopped.");
-            let _ = fs::remove_dir_all(&paths.wrap_dir);
-            return Ok(());
+        Commands::Status {
+            dir,
+            host: None,
+            threshold,
+            watch: true,
+            interval,
+        } => watch_status(&dir, threshold, interval.unwrap_or(WATCH_INTERVAL)).await,
+        Commands::Status {
+            dir,
+            host: None,
+            threshold,
+            ..
+        } => cmd_status(&dir, threshold).await,
+        Commands::Stop {
+            dir,
+            warn,
+            kick,
+            timeout,
+            then_kill,
+        } => {
+            let opts = StopOptions {
+                warn,
+                kick,
+                timeout,
+                then_kill,
+            };
+            cmd_stop(&dir, opts).await
         }
-        tokio::time::sleep(Duration::from_secs(1)).await;
-    }
-
-    // Force kill if still running
-    println!("Force killing...");
-    kill(Pid::from_raw(state.pid), Signal::SIGKILL)?;
-    let _ = fs::remove_dir_all(&paths.wrap_dir);
-
-    Ok(())
-}
-
-/// Show last N lines of log
-fn cmd_log(server_dir: &Path, lines: usize) -> Result<()> {
-    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
-    let paths = ServerPaths::new(&server_dir);
-
-    if !paths.log_file.exists() {
-        bail!("No log file found");
-    }
-
-    let content = fs::read_to_string(&paths.log_file)?;
-    let all_lines: Vec<&str> = content.lines().collect();
-    let start = all_lines.len().saturating_sub(lines);
-
-    for line in &all_lines[start..] {
-        println!("{}", line);
-    }
-
-    Ok(())
-}
-
-/// Tail the log file
-async fn cmd_tail(server_dir: &Path) -> Result<()> {
-    let server_dir = server_dir.canonicalize().context("Invalid server directory")?;
-    let paths = ServerPaths::new(&server_dir);
-
-    if !paths.log_file.exists() {
-        bail!("No log file found");
-    }
-
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-
-    // Handle Ctrl+C
-    let mut sigint = signal(SignalKind::interrupt())?;
-    tokio::spawn(async move {
-        sigint.recv().await;
-        r.store(false, Ordering::SeqCst);
-    });
-
-    let mut last_pos = 0u64;
-    while running.load(Ordering::SeqCst) {
-        if let Ok(mut file) = File::open(&paths.log_file) {
-            use std::io::Seek;
-            let len = file.metadata().map(|m| m.len()).unwrap_or(0);
-            if len > last_pos {
-                file.seek(std::io::SeekFrom::Start(last_pos))?;
-                let mut buf = String::new();
-                file.read_to_string(&mut buf)?;
-                print!("{}", buf);
-                std::io::stdout().flush()?;
-                last_pos = len;
-            }
+        Commands::Kill { dir, grace } => cmd_kill(&dir, grace).await,
+        Commands::Destroy { dir, purge, yes } => destroy::cmd_destroy(&dir, purge, yes).await,
+        Commands::Upgrade {
+            dir,
+            jar,
+            to,
+            warn,
+            no_backup,
+            timeout,
+        } => {
+            let opts = upgrade::UpgradeOptions {
+                jar,
+                to,
+                warn,
+                backup: !no_backup,
+                timeout,
+            };
+            upgrade::cmd_upgrade(&dir, opts).await
         }
-        tokio::time::sleep(Duration::from_millis(100)).await;
-    }
-
-    Ok(())
-}
-
-/// List all managed servers
-fn cmd_list() -> Result<()> {
-    let wrap_base = dirs::home_dir()
-        .unwrap_or_else(|| PathBuf::from("/tmp"))
-        .join(".mcwrap");
-
-    if !wrap_base.exists() {
-        println!("No servers managed.");
-        return Ok(());
-    }
-
-    let mut found = false;
-    for entry in fs::read_dir(&wrap_base)? {
-        let entry = entry?;
-        let state_file = entry.path().join("state.json");
-        if let Ok(file) = File::open(&state_file) {
-            if let Ok(state) = serde_json::from_reader::<_, ServerState>(file) {
-                let is_alive = kill(Pid::from_raw(state.pid), None).is_ok();
-                let status = if is_alive { "●" } else { "○" };
-                let mode = if state.pty_master.is_some() { "PTY" } else { "basic" };
-                println!(
-                    "{} {} (PID: {}, {})",
-                    status,
-                    state.server_dir.display(),
-                    state.pid,
-                    mode
-                );
-                found = true;
-            }
+        Commands::World { action } => world::cmd_world(action).await,
+        Commands::Icon { action } => icon::cmd_icon(action),
+        Commands::Motd {
+            dir,
+            text,
+            dry_run,
+        } => motd::cmd_motd(&dir, text.as_deref(), dry_run),
+        Commands::Players { dir, online, json } => players::cmd_players(&dir, online, json).await,
+        Commands::Playtime { dir, player, json } => {
+            playtime::cmd_playtime(&dir, player.as_deref(), json)
         }
+        Commands::Pregen {
+            dir,
+            radius,
+            center,
+            world,
+            vanilla,
+            install_chunky,
+            no_pause,
+        } => {
+            let opts = pregen::PregenOptions {
+                radius,
+                center,
+                world,
+                vanilla,
+                install_chunky,
+                pause_for_players: !no_pause,
+            };
+            pregen::cmd_pregen(&dir, opts).await
+        }
+        Commands::Gc { dir } => gc::cmd_gc(&dir),
+        Commands::Jfr { action } => jfr::cmd_jfr(action),
+        Commands::Dump { action } => dump::cmd_dump(action).await,
+        Commands::Export { action } => export::cmd_export(action),
+        Commands::Flags { action } => flags::cmd_flags(action),
+        Commands::Log {
+            dir,
+            lines,
+            since,
+            plain,
+            level,
+            json,
+        } => cmd_log(&dir, lines, since.as_deref(), plain, level, json),
+        Commands::Logs { dir, run } => logs::cmd_logs(&dir, run),
+        Commands::Replay {
+            dir,
+            run,
+            from,
+            speed,
+            plain,
+        } => replay::cmd_replay(&dir, run, from.as_deref(), speed.unwrap_or(1.0), plain),
+        Commands::Errors { dir, since } => errors::cmd_errors(&dir, since.as_deref()),
+        Commands::History { dir, lines, run } => history::cmd_history(&dir, lines, run).await,
+        Commands::Tasks { dir, run_now } => schedule::cmd_tasks(&dir, run_now).await,
+        Commands::Audit { dir, lines } => audit::cmd_audit(&dir, lines),
+        Commands::Events {
+            dir,
+            follow,
+            json,
+            lines,
+        } => events::cmd_events(&dir, follow, json, lines).await,
+        Commands::Tail {
+            dir,
+            plain,
+            highlight,
+            grep,
+            exclude,
+        } => {
+            let highlighter = Highlighter::new(&highlight)?;
+            cmd_tail(&dir, plain, highlighter, grep, exclude).await
+        }
+        Commands::List { verbose } => cmd_list(verbose),
+        Commands::Top => top::cmd_top().await,
+        Commands::Enable { dir, after } => boot::cmd_enable(&dir, after),
+        Commands::Disable { dir } => boot::cmd_disable(&dir),
+        Commands::Boot => boot::cmd_boot().await,
+        Commands::Daemon { status: true } => supervisor::cmd_status().await,
+        Commands::Daemon { status: false } => supervisor::run().await,
+        Commands::User { action } => users::cmd_user(action),
+        Commands::Serve { listen } => remote::cmd_serve(listen, cli.tls.resolve()?).await,
+        Commands::Completions { shell } => completions::cmd_completions(&Cli::command(), shell),
+        Commands::Servers => completions::cmd_servers(),
     }
-
-    if !found {
-        println!("No servers managed.");
-    }
-
-    Ok(())
 }
@@ -0,0 +1,36 @@
+//! `mcwrap icon set` converting images
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Width and height from a PNG's IHDR chunk
+fn png_size(path: &Path) -> (u32, u32) {
+    let png = fs::read(path).unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    let field = |at: usize| u32::from_be_bytes(png[at..at + 4].try_into().unwrap());
+    (field(16), field(20))
+}
+
+#[test]
+fn set_resizes_to_64x64() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/icon-96x48.png");
+    let name = format!("mcwrap-test-icon-{}", std::process::id());
+    let server_dir = std::env::temp_dir().join(name);
+    fs::create_dir_all(&server_dir).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mcwrap"))
+        .args(["icon", "set"])
+        .arg(&server_dir)
+        .arg(&fixture)
+        .env("HOME", &server_dir)
+        .output()
+        .unwrap();
+    let icon = server_dir.join("server-icon.png");
+    let size = icon.exists().then(|| png_size(&icon));
+    fs::remove_dir_all(&server_dir).ok();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("resized from 96x48"));
+    assert_eq!(size, Some((64, 64)));
+}
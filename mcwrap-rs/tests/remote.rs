@@ -0,0 +1,47 @@
+//! `mcwrap --remote` forwarding commands over ssh
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+
+/// The remote command line `mcwrap --remote u@h:/srv/mc <args>` hands to ssh
+fn forwarded(args: &[&str]) -> String {
+    let name = format!("mcwrap-test-remote-{}-{}", std::process::id(), args.join("-"));
+    let bin = std::env::temp_dir().join(name);
+    fs::create_dir_all(&bin).unwrap();
+    let ssh = bin.join("ssh");
+    let out = bin.join("argv");
+    let script = format!("#!/bin/sh\nprintf '%s\\n' \"$@\" > '{}'\n", out.display());
+    fs::write(&ssh, script).unwrap();
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+    let status = Command::new(env!("CARGO_BIN_EXE_mcwrap"))
+        .args(["--remote", "u@h:/srv/mc"])
+        .args(args)
+        .env("PATH", path)
+        .status()
+        .unwrap();
+    let argv = fs::read_to_string(&out).unwrap_or_default();
+    fs::remove_dir_all(&bin).ok();
+
+    assert!(status.success());
+    // ssh gets the host, `--` and the remote command as one argument
+    let argv: Vec<&str> = argv.lines().collect();
+    assert_eq!(argv[..2], ["u@h", "--"]);
+    argv[2..].join("\n")
+}
+
+#[test]
+fn forwards_the_server_directory() {
+    assert_eq!(forwarded(&["status"]), "mcwrap status /srv/mc");
+    assert_eq!(forwarded(&["send", "say hi"]), "mcwrap send /srv/mc 'say hi'");
+    assert_eq!(forwarded(&["world", "list"]), "mcwrap world list /srv/mc");
+    assert_eq!(forwarded(&["jfr", "start"]), "mcwrap jfr start /srv/mc");
+    assert_eq!(forwarded(&["dump", "threads"]), "mcwrap dump threads /srv/mc");
+    assert_eq!(
+        forwarded(&["icon", "set", "icon.png"]),
+        "mcwrap icon set /srv/mc icon.png"
+    );
+    assert_eq!(forwarded(&["list"]), "mcwrap list");
+}
@@ -0,0 +1,84 @@
+//! `mcwrap send` against a running daemon
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A scratch home with one server, killed and removed on drop
+struct Sandbox {
+    root: PathBuf,
+    server_dir: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Self {
+        let name = format!("mcwrap-test-{}-{}", name, std::process::id());
+        let root = std::env::temp_dir().join(name);
+        let server_dir = root.join("server");
+        fs::create_dir_all(root.join("home")).unwrap();
+        fs::create_dir_all(&server_dir).unwrap();
+        fs::write(server_dir.join("mcwrap.toml"), "command = [\"cat\"]\n").unwrap();
+        Self { root, server_dir }
+    }
+
+    /// Run `mcwrap <command> <server dir> <args>`
+    fn mcwrap(&self, command: &str, args: &[&str]) -> Output {
+        let home = self.root.join("home");
+        Command::new(env!("CARGO_BIN_EXE_mcwrap"))
+            .arg(command)
+            .arg(&self.server_dir)
+            .args(args)
+            .env("HOME", &home)
+            .env("XDG_CONFIG_HOME", home.join(".config"))
+            .output()
+            .unwrap()
+    }
+
+    /// The server's wrap directory, once `start` has made it
+    fn wrap_dir(&self) -> PathBuf {
+        let base = self.root.join("home").join(".mcwrap");
+        let mut dirs = fs::read_dir(&base).unwrap().flatten().map(|entry| entry.path());
+        dirs.find(|dir| dir.join("state.json").exists()).unwrap()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        self.mcwrap("kill", &["--grace", "1s"]);
+        fs::remove_dir_all(&self.root).ok();
+    }
+}
+
+/// Wait up to five seconds for `ready`
+fn wait_for(mut ready: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !ready() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    true
+}
+
+fn contains(path: &Path, text: &str) -> bool {
+    fs::read_to_string(path).is_ok_and(|content| content.contains(text))
+}
+
+#[test]
+fn send_without_control_socket() {
+    let sandbox = Sandbox::new("send");
+    let output = sandbox.mcwrap("start", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let wrap_dir = sandbox.wrap_dir();
+    assert!(wait_for(|| wrap_dir.join("control.sock").exists()));
+    // As with a daemon whose control socket is gone, send falls back to pty.sock
+    fs::remove_file(wrap_dir.join("control.sock")).unwrap();
+
+    let output = sandbox.mcwrap("send", &["say hello"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(wait_for(|| contains(&wrap_dir.join("console.log"), "say hello")));
+}